@@ -35,7 +35,6 @@ fn decompress_stream_bench<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: u
     compressed: &[u8],
     b: &mut Bencher,
 ) {
-    use std::io::Write;
     b.iter(|| {
         let mut sink = Vec::new();
         let mut stream = lzma_rs::decompress::Stream::<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>::new();
@@ -62,28 +61,43 @@ fn decompress_stream_bench_file(compfile: &str, b: &mut Bencher) {
 
 #[bench]
 fn decompress_after_compress_empty(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
     decompress_after_compress_bench(b"", b);
 }
 
 #[bench]
 fn decompress_after_compress_hello(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
     decompress_after_compress_bench(b"Hello world", b);
 }
 
 #[bench]
 fn decompress_after_compress_65536(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
     decompress_after_compress_bench(&[0; 0x10000], b);
 }
 
+// Incompressible data, where decoded bits are close to random
+#[bench]
+fn decompress_after_compress_random_65536(b: &mut Bencher) {
+    #[cfg(feature = "log")]
+    let _ = env_logger::try_init();
+    let mut x = 1u32;
+    let data: Vec<u8> = (0..0x10000)
+        .map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (x >> 24) as u8
+        })
+        .collect();
+    decompress_after_compress_bench(&data, b);
+}
+
 #[bench]
 fn decompress_big_file(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
     decompress_bench_file("tests/files/foo.txt.lzma", b);
 }
@@ -91,19 +105,19 @@ fn decompress_big_file(b: &mut Bencher) {
 #[cfg(feature = "stream")]
 #[bench]
 fn decompress_stream_big_file(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
     decompress_stream_bench_file("tests/files/foo.txt.lzma", b);
 }
 
 #[bench]
 fn decompress_huge_dict(b: &mut Bencher) {
-    #[cfg(feature = "enable_logging")]
+    #[cfg(feature = "log")]
     let _ = env_logger::try_init();
     let compressed: &[u8] = b"\x5d\x00\x40\x00\x00\xff\xff\xff\
                               \xff\xff\xff\xff\xff\x00\x24\x19\
                               \x49\x98\x6f\x10\x19\xc6\xd7\x31\
                               \xeb\x36\x50\xb2\x98\x48\xff\xfe\
                               \xa5\xb0\x00";
-    decompress_bench::<16384, 8>(&compressed, b);
+    decompress_bench::<16384, 8>(compressed, b);
 }
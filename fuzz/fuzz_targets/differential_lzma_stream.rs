@@ -0,0 +1,57 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use lzma_rs::decompress::raw::{
+    DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+};
+use lzma_rs::error::Result;
+
+type Decoder = DecoderState<LzCircularBuffer<65536>, 16>;
+
+fn new_decoder(input: &mut &[u8]) -> Result<Box<Decoder>> {
+    let params = LzmaParams::read_header(input, &Default::default())?;
+    let mut decoder = Box::new(Decoder::new());
+    decoder.reset();
+    decoder.set_params(params)?;
+    Ok(decoder)
+}
+
+// Decode in one call to `process`, in `Finish` mode
+fn decode_one_shot(mut input: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = new_decoder(&mut input)?;
+    let mut decomp = Vec::new();
+    let mut rangecoder = RangeDecoder::new(&mut input)?;
+    decoder.process(&mut decomp, &mut rangecoder)?;
+    decoder.output.finish(&mut decomp)?;
+    Ok(decomp)
+}
+
+// Decode in `Partial` mode, feeding the input one byte at a time so that
+// every symbol straddles calls, then signal the end of the input
+fn decode_byte_by_byte(mut input: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = new_decoder(&mut input)?;
+    let mut decomp = Vec::new();
+    let (mut init, rest) = input.split_at(input.len().min(5));
+    let rangecoder = RangeDecoder::new(&mut init)?;
+    let (mut range, mut code) = (rangecoder.range, rangecoder.code);
+    for mut byte in rest.chunks(1) {
+        let mut rangecoder = RangeDecoder::from_parts(&mut byte, range, code);
+        decoder.process_stream(&mut decomp, &mut rangecoder)?;
+        range = rangecoder.range;
+        code = rangecoder.code;
+    }
+    let mut empty: &[u8] = &[];
+    let mut rangecoder = RangeDecoder::from_parts(&mut empty, range, code);
+    decoder.signal_eof(&mut decomp, &mut rangecoder)?;
+    Ok(decomp)
+}
+
+fuzz_target!(|data: &[u8]| {
+    match (decode_one_shot(data), decode_byte_by_byte(data)) {
+        (Err(_), Err(_)) => (), // both failed, so behavior matches
+        (Ok(_), Err(e)) => panic!("one-shot decoding succeeded but streaming failed: {}", e),
+        (Err(e), Ok(_)) => panic!("streaming decoding succeeded but one-shot failed: {}", e),
+        (Ok(a), Ok(b)) => assert!(a == b),
+    }
+});
@@ -0,0 +1,196 @@
+//! Integrity checks of the XZ format.
+//!
+//! [`Crc32`] and [`Crc64`] are the CRCs of XZ, as computed by liblzma:
+//! CRC-32 (IEEE 802.3) protects the headers, the index and optionally the
+//! blocks, and CRC-64 (ECMA-182) optionally the blocks. Both are computed
+//! incrementally, so data can be checked as it is produced.
+
+use core::fmt;
+
+/// Reversed CRC-32 polynomial of IEEE 802.3.
+const CRC32_POLY: u32 = 0xEDB8_8320;
+/// Reversed CRC-64 polynomial of ECMA-182.
+const CRC64_POLY: u64 = 0xC96C_5795_D787_0F42;
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+static CRC64_TABLE: [u64; 256] = crc64_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC32_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const fn crc64_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Incremental CRC-32, as used by XZ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc32 {
+    // Inverted running value
+    state: u32,
+}
+
+impl Crc32 {
+    /// Create a hasher over no data.
+    pub const fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Add `data` to the hashed data.
+    pub fn update(&mut self, data: &[u8]) {
+        let mut state = self.state;
+        for &byte in data {
+            state = CRC32_TABLE[((state as u8) ^ byte) as usize] ^ (state >> 8);
+        }
+        self.state = state;
+    }
+
+    /// CRC-32 of the data hashed so far. More data may be added afterwards.
+    pub fn finalize(&self) -> u32 {
+        !self.state
+    }
+
+    /// CRC-32 of `data`.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finalize()
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Incremental CRC-64, as used by XZ.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crc64 {
+    // Inverted running value
+    state: u64,
+}
+
+impl Crc64 {
+    /// Create a hasher over no data.
+    pub const fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    /// Add `data` to the hashed data.
+    pub fn update(&mut self, data: &[u8]) {
+        let mut state = self.state;
+        for &byte in data {
+            state = CRC64_TABLE[((state as u8) ^ byte) as usize] ^ (state >> 8);
+        }
+        self.state = state;
+    }
+
+    /// CRC-64 of the data hashed so far. More data may be added afterwards.
+    pub fn finalize(&self) -> u64 {
+        !self.state
+    }
+
+    /// CRC-64 of `data`.
+    pub fn checksum(data: &[u8]) -> u64 {
+        let mut crc = Self::new();
+        crc.update(data);
+        crc.finalize()
+    }
+}
+
+impl Default for Crc64 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Value of the check of an XZ block, as stored after the block or as
+/// computed from its uncompressed data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckValue {
+    /// No check.
+    None,
+    /// CRC-32 of the data.
+    Crc32(u32),
+    /// CRC-64 of the data.
+    Crc64(u64),
+    /// SHA-256 of the data.
+    Sha256([u8; 32]),
+}
+
+impl fmt::Display for CheckValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckValue::None => write!(f, "none"),
+            CheckValue::Crc32(crc) => write!(f, "CRC32 {:08x}", crc),
+            CheckValue::Crc64(crc) => write!(f, "CRC64 {:016x}", crc),
+            CheckValue::Sha256(hash) => {
+                write!(f, "SHA-256 ")?;
+                hash.iter().try_for_each(|b| write!(f, "{:02x}", b))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn crc_check_values() {
+        // Check values of the CRC catalogue, for the ASCII "123456789"
+        assert_eq!(Crc32::checksum(b"123456789"), 0xCBF4_3926);
+        assert_eq!(Crc64::checksum(b"123456789"), 0x995D_C9BB_DF19_39FA);
+        assert_eq!(Crc32::checksum(b""), 0);
+        assert_eq!(Crc64::checksum(b""), 0);
+    }
+
+    #[test]
+    fn crc_incremental() {
+        let data = b"The quick brown fox jumps over the lazy dog";
+        for split in 0..=data.len() {
+            let (head, tail) = data.split_at(split);
+            let mut crc32 = Crc32::new();
+            let mut crc64 = Crc64::new();
+            crc32.update(head);
+            crc64.update(head);
+            crc32.update(tail);
+            crc64.update(tail);
+            assert_eq!(crc32.finalize(), 0x414F_A339);
+            assert_eq!(crc64.finalize(), Crc64::checksum(data));
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Decoding from an asynchronous source through [`tokio::io::AsyncRead`].
+
+use crate::decode::stream::Stream;
+use crate::decompress::Options;
+use crate::error;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// Number of compressed bytes requested from the source at once.
+const INPUT_BUF_LEN: usize = 4096;
+
+/// An [`AsyncRead`] adapter decoding LZMA data read from an asynchronous
+/// `input`, e.g. an HTTP body, without blocking the runtime.
+///
+/// Compressed bytes are passed to a [`Stream`] as they arrive, which buffers
+/// incomplete symbols until the rest of their input is available, so the
+/// source may return [`Poll::Pending`] at any point: the reader then returns
+/// `Pending` as well, and decoding resumes where it stopped on the next poll.
+/// The end of the source finishes the stream and checks its end.
+///
+/// Decoded bytes are available once flushed from the dictionary, i.e. every
+/// `DICT_MEM_LIMIT` bytes and at the end of the stream, and are kept in an
+/// internal buffer until read.
+///
+/// Decoding errors fail the read with an I/O error of kind
+/// [`ErrorKind::InvalidData`](io::ErrorKind::InvalidData), the original
+/// error being kept aside (see [`take_error`](AsyncLzmaReader::take_error)),
+/// and I/O errors of the input are returned as is. After an error, the
+/// stream cannot be resumed and every read fails.
+///
+/// The reader holds the whole decoder state, about `DICT_MEM_LIMIT` bytes;
+/// box it on stack-limited targets.
+pub struct AsyncLzmaReader<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
+    input: R,
+    stream: Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>,
+    input_buf: Vec<u8>,
+    // Decoded bytes, read from `output_pos`
+    output: Vec<u8>,
+    output_pos: usize,
+    finished: bool,
+    failed: bool,
+    error: Option<error::Error>,
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    AsyncLzmaReader<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: AsyncRead + Unpin,
+{
+    /// Decode the LZMA data of `input` with the given `options`, starting
+    /// with its header. Nothing is read until the first poll.
+    pub fn new(input: R, options: &Options) -> Self {
+        let mut stream = Stream::new_with_options(options);
+        stream.reset();
+        Self {
+            input,
+            stream,
+            input_buf: vec![0; INPUT_BUF_LEN],
+            output: Vec::new(),
+            output_pos: 0,
+            finished: false,
+            failed: false,
+            error: None,
+        }
+    }
+
+    /// Get a reference to the input.
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+
+    /// Unwrap the input, positioned wherever reading stopped.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    /// Take the decoding error that failed a read, if any.
+    pub fn take_error(&mut self) -> Option<error::Error> {
+        self.error.take()
+    }
+
+    fn fail(&mut self, e: error::Error) -> io::Error {
+        self.failed = true;
+        match e {
+            error::Error::IoError(e) => e,
+            e => {
+                self.error = Some(e);
+                io::Error::new(io::ErrorKind::InvalidData, "LZMA decoding failed")
+            }
+        }
+    }
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> AsyncRead
+    for AsyncLzmaReader<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: AsyncRead + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.failed {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "LZMA decoding failed",
+                )));
+            }
+            if this.output_pos < this.output.len() {
+                let count = buf.remaining().min(this.output.len() - this.output_pos);
+                buf.put_slice(&this.output[this.output_pos..this.output_pos + count]);
+                this.output_pos += count;
+                return Poll::Ready(Ok(()));
+            }
+            if this.finished || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            this.output.clear();
+            this.output_pos = 0;
+
+            let mut input_buf = ReadBuf::new(&mut this.input_buf);
+            match Pin::new(&mut this.input).poll_read(cx, &mut input_buf) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Err(e)) => {
+                    this.failed = true;
+                    return Poll::Ready(Err(e));
+                }
+                Poll::Ready(Ok(())) => {}
+            }
+            let res = match input_buf.filled() {
+                [] => {
+                    this.finished = true;
+                    this.stream.finish(&mut this.output)
+                }
+                data => this.stream.write_all(&mut this.output, data),
+            };
+            if let Err(e) = res {
+                return Poll::Ready(Err(this.fail(e)));
+            }
+        }
+    }
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> core::fmt::Debug
+    for AsyncLzmaReader<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("AsyncLzmaReader")
+            .field("available", &(self.output.len() - self.output_pos))
+            .field("finished", &self.finished)
+            .field("failed", &self.failed)
+            .field("error", &self.error)
+            .finish()
+    }
+}
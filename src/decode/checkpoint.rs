@@ -0,0 +1,97 @@
+//! Serializable snapshots of the decoder, to resume decoding later or
+//! elsewhere.
+
+use crate::decode::lzma::LzmaParams;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::io;
+use crate::option::GuaranteedOption as Option;
+use serde::{Deserialize, Serialize};
+
+/// State of a [`DecoderState`](crate::decompress::raw::DecoderState) and of
+/// its [`RangeDecoder`] between two symbols, taken with
+/// [`DecoderState::checkpoint`](crate::decompress::raw::DecoderState::checkpoint).
+///
+/// It holds the parameters, the probabilities, the LZMA state and LRU
+/// distances, the settings, the range coder `range` and `code`, and the
+/// part of the dictionary that later matches may reference, i.e. up to the
+/// dictionary size. Once serialized, it can be deserialized into another
+/// process or machine and given to
+/// [`DecoderState::restore`](crate::decompress::raw::DecoderState::restore)
+/// to finish decoding, with the compressed input positioned at
+/// [`total_in`](Checkpoint::total_in) bytes past the header and a range
+/// decoder created by [`range_decoder`](Checkpoint::range_decoder).
+///
+/// Callbacks, trace sinks, statistics and events are not part of a
+/// checkpoint.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub(crate) params: LzmaParams,
+    pub(crate) finished: bool,
+    pub(crate) literal_probs: Vec<u16>,
+    // Other probabilities, see `DecoderState::checkpoint`
+    pub(crate) probs: Vec<u16>,
+    pub(crate) state: usize,
+    pub(crate) rep: [usize; 4],
+    pub(crate) range: u32,
+    pub(crate) code: u32,
+    pub(crate) pending_input: Vec<u8>,
+    pub(crate) window: Window,
+    pub(crate) eos_marker_offset: Option<u64>,
+    pub(crate) concatenated: bool,
+    pub(crate) strict_canonical: bool,
+    pub(crate) verify_end_after_size: bool,
+    pub(crate) reject_trailing_data: bool,
+    pub(crate) max_reference_distance: Option<usize>,
+    pub(crate) max_output: Option<u64>,
+    pub(crate) allow_truncated: bool,
+    pub(crate) len_after_marker: Option<u64>,
+    pub(crate) total_input_len: Option<u64>,
+    pub(crate) input_consumed: u64,
+}
+
+/// Content of the dictionary buffer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Window {
+    // Last bytes of the dictionary, ending at the write position
+    pub(crate) bytes: Vec<u8>,
+    // Number of trailing bytes not flushed to the output sink yet
+    pub(crate) unflushed: usize,
+    pub(crate) len: u64,
+    pub(crate) discard_from: u64,
+    pub(crate) discard_to: u64,
+    pub(crate) fill: Option<u8>,
+    pub(crate) preset_len: usize,
+}
+
+impl Checkpoint {
+    /// Parameters of the stream being decoded.
+    pub fn params(&self) -> &LzmaParams {
+        &self.params
+    }
+
+    /// Number of compressed bytes consumed, past the header, as
+    /// [`DecoderState::total_in`](crate::decompress::raw::DecoderState::total_in):
+    /// decoding resumes at this offset of the compressed data.
+    pub fn total_in(&self) -> u64 {
+        self.input_consumed
+    }
+
+    /// Number of bytes decoded, as
+    /// [`DecoderState::total_out`](crate::decompress::raw::DecoderState::total_out).
+    pub fn total_out(&self) -> u64 {
+        self.window.len
+    }
+
+    /// Number of decoded bytes, including skipped ones, that were flushed
+    /// to the output sink when the checkpoint was taken. The restored
+    /// decoder writes the following ones.
+    pub fn flushed_out(&self) -> u64 {
+        self.window.len - self.window.unflushed as u64
+    }
+
+    /// Create a range decoder resuming at the checkpoint, reading from
+    /// `stream` positioned at [`total_in`](Checkpoint::total_in).
+    pub fn range_decoder<'a, R: io::BufRead>(&self, stream: &'a mut R) -> RangeDecoder<'a, R> {
+        RangeDecoder::from_parts(stream, self.range, self.code)
+    }
+}
@@ -0,0 +1,145 @@
+//! Lazy decoding into owned chunks through [`Iterator`].
+
+use crate::decode::lzbuffer::LzBuffer;
+use crate::decode::lzma::{self, DecoderState};
+use crate::decode::probs::ProbModel;
+use crate::decode::rangecoder::RangeDecoder;
+use crate::error;
+use crate::io::BufRead;
+use crate::option::GuaranteedOption;
+
+/// Longest match, i.e. the most a symbol can decode past the requested
+/// offset.
+const MAX_MATCH_LEN: usize = 273;
+
+/// An [`Iterator`] decoding a stream lazily into owned chunks of
+/// `chunk_len` bytes, the last one being shorter, e.g. to forward the
+/// output to a socket without holding all of it in memory.
+///
+/// As with `LzmaReader`, each call to [`next`](Iterator::next) decodes
+/// whole symbols until at least a chunk is available, and copies the chunk back from the dictionary. Bytes decoded
+/// past the chunk are yielded at the start of the next one. The decoder is
+/// driven in [`process_to_offset`](DecoderState::process_to_offset) steps,
+/// so the end of the stream is checked as by
+/// [`process`](DecoderState::process); it does not write to any sink.
+///
+/// Decoding errors are yielded once, after which the iterator is done.
+pub struct DecoderChunks<'a, 'b, LZB, R, const PROBS_MEM_LIMIT: usize, PM>
+where
+    LZB: LzBuffer,
+    R: BufRead,
+    PM: ProbModel,
+{
+    decoder: &'a mut DecoderState<LZB, PROBS_MEM_LIMIT, PM>,
+    rangecoder: RangeDecoder<'b, R>,
+    chunk_len: usize,
+    // Decoded bytes not yielded yet, at the end of the dictionary
+    available: usize,
+    finished: bool,
+    failed: bool,
+}
+
+impl<'a, 'b, LZB, R, const PROBS_MEM_LIMIT: usize, PM>
+    DecoderChunks<'a, 'b, LZB, R, PROBS_MEM_LIMIT, PM>
+where
+    LZB: LzBuffer,
+    R: BufRead,
+    PM: ProbModel,
+{
+    /// Iterate over the output of `decoder`, whose parameters must be set,
+    /// reading the compressed data from `rangecoder`.
+    ///
+    /// All the bytes of a chunk must fit in the dictionary along with a
+    /// longest match, so `chunk_len` is capped to the dictionary size minus
+    /// 273 bytes.
+    pub fn new(
+        decoder: &'a mut DecoderState<LZB, PROBS_MEM_LIMIT, PM>,
+        rangecoder: RangeDecoder<'b, R>,
+        chunk_len: usize,
+    ) -> error::Result<Self> {
+        let dict_size = match decoder.params() {
+            GuaranteedOption::Some(params) => lzma::dict_len(params.dict_size)?,
+            GuaranteedOption::None => return Err(error::lzma::LzmaError::ParamsNotSet.into()),
+        };
+        Ok(Self {
+            decoder,
+            rangecoder,
+            chunk_len: chunk_len
+                .min(dict_size.saturating_sub(MAX_MATCH_LEN))
+                .max(1),
+            available: 0,
+            finished: false,
+            failed: false,
+        })
+    }
+
+    /// Length of the chunks, after capping.
+    pub fn chunk_len(&self) -> usize {
+        self.chunk_len
+    }
+
+    /// Unwrap the range decoder, positioned wherever decoding stopped.
+    pub fn into_rangecoder(self) -> RangeDecoder<'b, R> {
+        self.rangecoder
+    }
+
+    fn next_chunk(&mut self) -> error::Result<Vec<u8>> {
+        if self.available < self.chunk_len && !self.finished {
+            let start = self.decoder.output.len();
+            let target = start + self.chunk_len - self.available;
+            self.finished = self.decoder.process_to_offset(
+                &mut std::io::sink(),
+                &mut self.rangecoder,
+                target as u64,
+            )?;
+            self.available += self.decoder.output.len() - start;
+        }
+        let count = self.available.min(self.chunk_len);
+        let chunk = (0..count)
+            .map(|i| self.decoder.output.last_n(self.available - i))
+            .collect::<error::Result<Vec<u8>>>()?;
+        self.available -= count;
+        Ok(chunk)
+    }
+}
+
+impl<'a, 'b, LZB, R, const PROBS_MEM_LIMIT: usize, PM> Iterator
+    for DecoderChunks<'a, 'b, LZB, R, PROBS_MEM_LIMIT, PM>
+where
+    LZB: LzBuffer,
+    R: BufRead,
+    PM: ProbModel,
+{
+    type Item = error::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || (self.finished && self.available == 0) {
+            return None;
+        }
+        match self.next_chunk() {
+            Ok(chunk) if chunk.is_empty() => None,
+            Ok(chunk) => Some(Ok(chunk)),
+            Err(e) => {
+                self.failed = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a, 'b, LZB, R, const PROBS_MEM_LIMIT: usize, PM> core::fmt::Debug
+    for DecoderChunks<'a, 'b, LZB, R, PROBS_MEM_LIMIT, PM>
+where
+    LZB: LzBuffer,
+    R: BufRead,
+    PM: ProbModel,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("DecoderChunks")
+            .field("chunk_len", &self.chunk_len)
+            .field("available", &self.available)
+            .field("finished", &self.finished)
+            .field("failed", &self.failed)
+            .finish()
+    }
+}
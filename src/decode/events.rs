@@ -0,0 +1,145 @@
+//! Record of the last symbols decoded, for post-mortem debugging.
+
+/// Number of events kept by a [`DecoderState`](super::lzma::DecoderState).
+pub const EVENT_RING_LEN: usize = 32;
+
+/// Kind of a decoded symbol. Distances are given in bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    /// A literal byte.
+    Literal(u8),
+    /// A match with a newly coded distance.
+    Match {
+        /// Length of the match.
+        len: usize,
+        /// Distance of the match.
+        distance: usize,
+    },
+    /// A match repeating one of the last 4 distances.
+    Rep {
+        /// Length of the match.
+        len: usize,
+        /// Distance of the match.
+        distance: usize,
+    },
+    /// A single byte repeating the last distance.
+    ShortRep {
+        /// Distance of the match.
+        distance: usize,
+    },
+    /// The end-of-stream marker.
+    EndMarker,
+    /// A symbol which failed to decode.
+    Error,
+}
+
+/// A symbol decoded by a [`DecoderState`](super::lzma::DecoderState).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    /// Output position of the symbol.
+    pub offset: u64,
+    /// State of the decoder state machine before the symbol (below 12).
+    pub state: u8,
+    /// What the symbol decoded to.
+    pub kind: EventKind,
+}
+
+impl Event {
+    const EMPTY: Self = Self {
+        offset: 0,
+        state: 0,
+        kind: EventKind::Error,
+    };
+}
+
+/// Fixed-size ring keeping the last `N` [`Event`]s recorded.
+#[derive(Clone, Debug)]
+pub struct EventRing<const N: usize> {
+    events: [Event; N],
+    next: usize,
+    len: usize,
+}
+
+impl<const N: usize> EventRing<N> {
+    /// Create an empty ring.
+    pub const fn new() -> Self {
+        Self {
+            events: [Event::EMPTY; N],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    /// Forget all events.
+    pub fn reset(&mut self) {
+        self.next = 0;
+        self.len = 0;
+    }
+
+    /// Number of events kept, at most `N`.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no event was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The events kept, from the oldest to the most recent.
+    pub fn iter(&self) -> impl Iterator<Item = &Event> {
+        let start = (self.next + N - self.len) % N.max(1);
+        self.events.iter().cycle().skip(start).take(self.len)
+    }
+
+    pub(crate) fn record(&mut self, event: Event) {
+        if N == 0 {
+            return;
+        }
+        self.events[self.next] = event;
+        self.next = (self.next + 1) % N;
+        self.len = (self.len + 1).min(N);
+    }
+}
+
+impl<const N: usize> Default for EventRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn literal(byte: u8) -> Event {
+        Event {
+            offset: byte as u64,
+            state: 0,
+            kind: EventKind::Literal(byte),
+        }
+    }
+
+    #[test]
+    fn keeps_last_events() {
+        let mut ring = EventRing::<3>::new();
+        assert!(ring.is_empty());
+        ring.record(literal(1));
+        ring.record(literal(2));
+        assert!(ring.iter().eq(&[literal(1), literal(2)]));
+
+        ring.record(literal(3));
+        ring.record(literal(4));
+        ring.record(literal(5));
+        assert_eq!(ring.len(), 3);
+        assert!(ring.iter().eq(&[literal(3), literal(4), literal(5)]));
+
+        ring.reset();
+        assert_eq!(ring.iter().count(), 0);
+
+        let mut empty = EventRing::<0>::new();
+        empty.record(literal(1));
+        assert!(empty.is_empty());
+        assert_eq!(empty.iter().count(), 0);
+    }
+}
@@ -0,0 +1,281 @@
+//! Filters of the XZ filter chain, run on the output of LZMA2.
+
+use crate::error;
+use crate::error::xz::XzError;
+
+/// A filter reversing, in place, a transformation applied by the encoder
+/// before compression.
+pub trait Filter {
+    /// Decode `buf` in place and return the number of bytes that were fully
+    /// processed.
+    ///
+    /// Filters that need to look ahead may leave a few trailing bytes
+    /// untouched. They must be passed again at the start of the next call,
+    /// or used as they are once the stream has ended.
+    fn apply(&mut self, buf: &mut [u8]) -> usize;
+}
+
+/// Reverse x86 branch-call-jump (BCJ) filter, XZ filter ID `0x04`.
+///
+/// Encoders turn the relative targets of x86 `CALL` (`E8`) and `JMP` (`E9`)
+/// instructions into absolute addresses to improve compression; this filter
+/// converts them back. It runs on the LZMA2 output of a block and keeps a
+/// small amount of state across calls.
+#[derive(Clone, Debug)]
+pub struct BcjX86 {
+    prev_mask: u32,
+    prev_pos: u32,
+    pos: u32,
+}
+
+impl BcjX86 {
+    /// Filter ID of the x86 BCJ filter in an XZ block header.
+    pub const ID: u64 = 0x04;
+
+    /// Create a filter for a stream starting at offset 0.
+    pub const fn new() -> Self {
+        Self::with_start_offset(0)
+    }
+
+    /// Create a filter for a stream starting at `start_offset`, as given by
+    /// the optional filter properties.
+    pub const fn with_start_offset(start_offset: u32) -> Self {
+        Self {
+            prev_mask: 0,
+            prev_pos: 0u32.wrapping_sub(5),
+            pos: start_offset,
+        }
+    }
+}
+
+// An instruction needs 5 bytes to be converted, so up to 4 trailing bytes
+// may be left untouched
+impl Filter for BcjX86 {
+    fn apply(&mut self, buf: &mut [u8]) -> usize {
+        const MASK_TO_ALLOWED_STATUS: [bool; 8] =
+            [true, true, true, false, true, false, false, false];
+        const MASK_TO_BIT_NUMBER: [u32; 8] = [0, 1, 2, 2, 3, 3, 3, 3];
+
+        fn test_ms_byte(b: u8) -> bool {
+            b == 0x00 || b == 0xFF
+        }
+
+        if buf.len() < 5 {
+            return 0;
+        }
+
+        let mut prev_mask = self.prev_mask;
+        let mut prev_pos = self.prev_pos;
+        if self.pos.wrapping_sub(prev_pos) > 5 {
+            prev_pos = self.pos.wrapping_sub(5);
+        }
+
+        let limit = buf.len() - 5;
+        let mut i = 0;
+        while i <= limit {
+            if buf[i] != 0xE8 && buf[i] != 0xE9 {
+                i += 1;
+                continue;
+            }
+
+            let pos = self.pos.wrapping_add(i as u32);
+            let offset = pos.wrapping_sub(prev_pos);
+            prev_pos = pos;
+
+            if offset > 5 {
+                prev_mask = 0;
+            } else {
+                for _ in 0..offset {
+                    prev_mask &= 0x77;
+                    prev_mask <<= 1;
+                }
+            }
+
+            let b = buf[i + 4];
+            if test_ms_byte(b)
+                && MASK_TO_ALLOWED_STATUS[((prev_mask >> 1) & 0x7) as usize]
+                && (prev_mask >> 1) < 0x10
+            {
+                let mut src = u32::from_le_bytes([buf[i + 1], buf[i + 2], buf[i + 3], b]);
+                let mut dest;
+                loop {
+                    dest = src.wrapping_sub(pos.wrapping_add(5));
+                    if prev_mask == 0 {
+                        break;
+                    }
+                    let index = MASK_TO_BIT_NUMBER[(prev_mask >> 1) as usize];
+                    if !test_ms_byte((dest >> (24 - index * 8)) as u8) {
+                        break;
+                    }
+                    src = dest ^ ((1 << (32 - index * 8)) - 1);
+                }
+
+                let dest = dest.to_le_bytes();
+                buf[i + 1] = dest[0];
+                buf[i + 2] = dest[1];
+                buf[i + 3] = dest[2];
+                buf[i + 4] = if dest[3] & 1 != 0 { 0xFF } else { 0x00 };
+                i += 5;
+                prev_mask = 0;
+            } else {
+                i += 1;
+                prev_mask |= 1;
+                if test_ms_byte(b) {
+                    prev_mask |= 0x10;
+                }
+            }
+        }
+
+        self.prev_mask = prev_mask;
+        self.prev_pos = prev_pos;
+        self.pos = self.pos.wrapping_add(i as u32);
+        i
+    }
+}
+
+impl Default for BcjX86 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reverse delta filter, XZ filter ID `0x03`.
+///
+/// Encoders replace each byte with its difference to the byte `distance`
+/// positions earlier, which helps with fixed-size samples such as audio or
+/// images; this filter adds them back. The last `distance` decoded bytes are
+/// kept across calls.
+#[derive(Clone, Debug)]
+pub struct DeltaFilter {
+    distance: usize,
+    history: [u8; 256],
+    pos: u8,
+}
+
+impl DeltaFilter {
+    /// Filter ID of the delta filter in an XZ block header.
+    pub const ID: u64 = 0x03;
+
+    /// Create a filter for samples `distance` bytes apart, from 1 to 256.
+    pub fn new(distance: usize) -> error::Result<Self> {
+        if !(1..=256).contains(&distance) {
+            return Err(XzError::InvalidDeltaDistance { distance }.into());
+        }
+        Ok(Self {
+            distance,
+            history: [0; 256],
+            pos: 0,
+        })
+    }
+
+    /// Create a filter from its properties byte in a block header, holding
+    /// the distance minus 1.
+    pub fn from_props(props: u8) -> Self {
+        Self {
+            distance: props as usize + 1,
+            history: [0; 256],
+            pos: 0,
+        }
+    }
+
+    /// Distance between samples, in bytes.
+    pub fn distance(&self) -> usize {
+        self.distance
+    }
+}
+
+impl Filter for DeltaFilter {
+    fn apply(&mut self, buf: &mut [u8]) -> usize {
+        // As in liblzma, the history grows towards lower indices, so the
+        // byte `distance` positions back is at `pos + distance`, modulo 256
+        for byte in buf.iter_mut() {
+            let index = self.pos.wrapping_add(self.distance as u8);
+            *byte = byte.wrapping_add(self.history[index as usize]);
+            self.history[self.pos as usize] = *byte;
+            self.pos = self.pos.wrapping_sub(1);
+        }
+        buf.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bcj_x86_decode() {
+        let expected = include_bytes!("../../tests/files/bcj-x86.bin");
+        let mut buf = *include_bytes!("../../tests/files/bcj-x86.bin.filtered");
+        assert_ne!(&buf[..], &expected[..]);
+
+        let processed = BcjX86::new().apply(&mut buf);
+        assert!(processed + 5 > buf.len());
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn bcj_x86_decode_split() {
+        let expected = include_bytes!("../../tests/files/bcj-x86.bin");
+
+        // Instructions straddle the boundaries, and with 1-byte increments
+        // every call but the last ones sees a partial instruction
+        for step in [1, 3, 1000] {
+            let mut buf = *include_bytes!("../../tests/files/bcj-x86.bin.filtered");
+            let mut filter = BcjX86::new();
+            let mut start = 0;
+            for end in (step..buf.len())
+                .step_by(step)
+                .chain(core::iter::once(buf.len()))
+            {
+                start += filter.apply(&mut buf[start..end]);
+            }
+            assert!(start + 5 > buf.len());
+            assert_eq!(&buf[..], &expected[..], "step {}", step);
+        }
+    }
+
+    fn delta_encode(data: &[u8], distance: usize) -> [u8; 1000] {
+        let mut out = [0; 1000];
+        for (i, &b) in data.iter().enumerate() {
+            let prev = if i >= distance { data[i - distance] } else { 0 };
+            out[i] = b.wrapping_sub(prev);
+        }
+        out
+    }
+
+    #[test]
+    fn delta_decode() {
+        let mut data = [0u8; 1000];
+        let mut x = 1u32;
+        for b in data.iter_mut() {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *b = (x >> 24) as u8;
+        }
+        for distance in [1, 2, 4, 255, 256] {
+            let mut buf = delta_encode(&data, distance);
+            let mut filter = DeltaFilter::new(distance).unwrap();
+            assert_eq!(filter.apply(&mut buf), buf.len());
+            assert_eq!(buf, data, "distance {}", distance);
+
+            // Split at arbitrary points, including within the first samples
+            let mut buf = delta_encode(&data, distance);
+            let mut filter = DeltaFilter::from_props((distance - 1) as u8);
+            for chunk in buf.chunks_mut(7) {
+                filter.apply(chunk);
+            }
+            assert_eq!(buf, data, "distance {}", distance);
+        }
+    }
+
+    #[test]
+    fn delta_distance() {
+        assert_eq!(DeltaFilter::from_props(0).distance(), 1);
+        assert_eq!(DeltaFilter::from_props(255).distance(), 256);
+        for distance in [0, 257] {
+            assert!(matches!(
+                DeltaFilter::new(distance),
+                Err(error::Error::XzError(XzError::InvalidDeltaDistance { .. }))
+            ));
+        }
+    }
+}
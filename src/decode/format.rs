@@ -0,0 +1,135 @@
+//! Detection of the format of compressed data.
+
+use crate::decode::lzip::MAGIC as LZIP_MAGIC;
+use crate::decode::lzma::read_properties;
+use crate::decode::xz::MAGIC as XZ_MAGIC;
+use crate::option::GuaranteedOption as Option;
+use crate::option::GuaranteedOption::*;
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Formats recognized by [`detect_format`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `.lzma` data, also known as LZMA-alone: a 13-byte header followed by
+    /// an LZMA stream.
+    Lzma,
+    /// Raw LZMA2 chunks, without a container.
+    Lzma2,
+    /// XZ container, starting with `FD 37 7A 58 5A 00`.
+    Xz,
+    /// LZIP container, starting with `LZIP`.
+    Lzip,
+}
+
+/// Guess the format of `input` from its first bytes, or `None` when it does
+/// not look like any supported format.
+///
+/// An `.lzma` header must have valid properties, a dictionary size of
+/// `2^n` or `2^n + 2^(n-1)` bytes (or `0xFFFF_FFFF`) and a known unpacked
+/// size below 256 GiB, as checked by liblzma. Raw LZMA2 data must start
+/// with a chunk resetting the dictionary, or be an empty stream. Data
+/// passing both checks is reported as `.lzma`.
+pub fn detect_format(input: &[u8]) -> Option<Format> {
+    if input.starts_with(&XZ_MAGIC) {
+        Some(Format::Xz)
+    } else if input.starts_with(&LZIP_MAGIC) {
+        Some(Format::Lzip)
+    } else if is_lzma_header(input) {
+        Some(Format::Lzma)
+    } else if is_lzma2_start(input) {
+        Some(Format::Lzma2)
+    } else {
+        None
+    }
+}
+
+fn is_lzma_header(input: &[u8]) -> bool {
+    if input.len() < 13 || read_properties(&mut &input[..1]).is_err() {
+        return false;
+    }
+    let dict_size = LittleEndian::read_u32(&input[1..5]);
+    let unpacked_size = LittleEndian::read_u64(&input[5..13]);
+    let dict_size_ok = dict_size == 0xFFFF_FFFF
+        || (dict_size != 0 && matches!(dict_size >> dict_size.trailing_zeros(), 1 | 3));
+    let unpacked_size_ok = unpacked_size == 0xFFFF_FFFF_FFFF_FFFF || unpacked_size < 1 << 38;
+    dict_size_ok && unpacked_size_ok
+}
+
+fn is_lzma2_start(input: &[u8]) -> bool {
+    match input {
+        // End of an empty stream
+        [0x00, ..]
+        // Uncompressed chunk resetting the dictionary
+        | [0x01, _, _, ..] => true,
+        // LZMA chunk resetting the dictionary and setting new properties
+        [0xE0..=0xFF, _, _, _, _, props, ..] => match read_properties(&mut &[*props][..]) {
+            Ok((lc, lp, _)) => lc + lp <= 4,
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lzma_header(props: u8, dict_size: u32, unpacked_size: u64) -> [u8; 13] {
+        let mut header = [0; 13];
+        header[0] = props;
+        LittleEndian::write_u32(&mut header[1..5], dict_size);
+        LittleEndian::write_u64(&mut header[5..13], unpacked_size);
+        header
+    }
+
+    #[test]
+    fn lzma_headers() {
+        for (props, dict_size, unpacked_size, expected) in [
+            (0x5D, 1 << 23, u64::MAX, Some(Format::Lzma)),
+            (0x5D, 3 << 20, 1000, Some(Format::Lzma)),
+            (0x5D, 0xFFFF_FFFF, 0, Some(Format::Lzma)),
+            (224, 4096, 0, Some(Format::Lzma)),
+            // Invalid properties, implausible sizes
+            (225, 4096, u64::MAX, None),
+            (0x5D, 5 << 20, 0, None),
+            (0x5D, 0, 0, None),
+            (0x5D, 4096, 1 << 38, None),
+        ] {
+            let header = lzma_header(props, dict_size, unpacked_size);
+            assert_eq!(detect_format(&header), expected, "{:02x?}", header);
+        }
+        // Too short
+        assert_eq!(detect_format(&lzma_header(0x5D, 4096, 0)[..12]), None);
+    }
+
+    #[test]
+    fn lzma2_starts() {
+        assert_eq!(detect_format(&[0x00]), Some(Format::Lzma2));
+        assert_eq!(detect_format(&[0x01, 0x00, 0x00]), Some(Format::Lzma2));
+        assert_eq!(
+            detect_format(&[0xE0, 0x00, 0x00, 0x00, 0x00, 0x5D]),
+            Some(Format::Lzma2)
+        );
+        // lc + lp > 4, no dictionary reset, too short
+        assert_eq!(detect_format(&[0xE0, 0x00, 0x00, 0x00, 0x00, 0x5F]), None);
+        assert_eq!(detect_format(&[0x02, 0x00, 0x00]), None);
+        assert_eq!(detect_format(&[0xA0, 0x00, 0x00, 0x00, 0x00]), None);
+        assert_eq!(detect_format(&[0x01, 0x00]), None);
+        assert_eq!(detect_format(&[]), None);
+    }
+
+    #[test]
+    fn xz_magic() {
+        assert_eq!(
+            detect_format(&[0xFD, b'7', b'z', b'X', b'Z', 0x00]),
+            Some(Format::Xz)
+        );
+        assert_eq!(detect_format(&[0xFD, b'7', b'z', b'X', b'Z']), None);
+    }
+
+    #[test]
+    fn lzip_magic() {
+        assert_eq!(detect_format(b"LZIP\x01\x0C"), Some(Format::Lzip));
+        assert_eq!(detect_format(b"LZI"), None);
+    }
+}
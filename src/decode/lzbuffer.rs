@@ -1,63 +1,325 @@
+#[cfg(feature = "serde")]
+use crate::decode::checkpoint;
+#[cfg(feature = "std")]
+use crate::decode::util;
 use crate::error;
 use crate::io;
 use crate::option::GuaranteedOption as Option;
 use crate::option::GuaranteedOption::*;
+use core::cmp::Ordering;
 
+/// Sliding dictionary used by [`DecoderState`](super::lzma::DecoderState) to
+/// resolve LZ back-references and to buffer output.
 pub trait LzBuffer {
+    /// Set the dictionary size; fails if it does not fit in the buffer.
     fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()>;
+    /// Total number of bytes sent through the buffer.
     fn len(&self) -> usize;
-    // Retrieve the last byte or return a default
+    /// Whether no byte has been sent through the buffer yet.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Retrieve the last byte or return a default
     fn last_or(&self, lit: u8) -> u8;
-    // Retrieve the n-th last byte
+    /// Retrieve the n-th last byte
     fn last_n(&self, dist: usize) -> error::Result<u8>;
-    // Append a literal
+    /// Append a literal
     fn append_literal(&mut self, stream: &mut dyn io::Write, lit: u8) -> error::Result<()>;
-    // Fetch an LZ sequence (length, distance) from inside the buffer
+    /// Fetch an LZ sequence (length, distance) from inside the buffer. The
+    /// decoder passes lengths of 1 (short reps) to 273, never 0.
     fn append_lz(
         &mut self,
         stream: &mut dyn io::Write,
         len: usize,
         dist: usize,
     ) -> error::Result<()>;
-    // Consumes this buffer and flushes any data
+    /// Drop the next `count` appended bytes instead of flushing them to the
+    /// output; they remain available for back-references.
+    fn discard_next(&mut self, count: usize);
+    /// Consumes this buffer and flushes any data
     fn finish(&mut self, stream: &mut dyn io::Write) -> io::Result<()>;
+    /// Assume the dictionary holds `fill` before the start of the output:
+    /// [`last_or`](LzBuffer::last_or) is unaffected, but the other methods
+    /// accept distances up to the dictionary size before any data was
+    /// appended. `None` restores the default of rejecting such distances.
+    fn set_fill(&mut self, fill: Option<u8>);
+    /// Preload the dictionary with the last bytes of `dict`, up to the
+    /// dictionary size, as if they preceded the output, and return how many
+    /// were kept. Must be called after
+    /// [`set_dict_size`](LzBuffer::set_dict_size) and before any byte is
+    /// appended.
+    fn set_preset(&mut self, dict: &[u8]) -> error::Result<usize>;
+    /// Clear the buffer and forget the dictionary size and fill.
     fn reset(&mut self);
 }
 
-// A circular buffer for LZ sequences
-pub struct LzCircularBuffer<const MEM_LIMIT: usize> {
-    buf: [u8; MEM_LIMIT],     // Circular buffer
+/// A circular buffer for LZ sequences, stored in `S`.
+///
+/// See [`LzCircularBuffer`] for a buffer stored inline and [`LzSliceBuffer`]
+/// for a buffer borrowing its storage, e.g. from an arena.
+pub struct CircularBuffer<S> {
+    buf: S,                   // Circular buffer
     dict_size: Option<usize>, // Length of the buffer
     cursor: usize,            // Current position
     len: usize,               // Total number of bytes sent through the buffer
+    discard_from: usize,      // Start of the output range not to be flushed
+    discard_to: usize,        // End of the output range not to be flushed
+    fill: Option<u8>,         // Assumed content of the dictionary before the output
+    preset_len: usize,        // Length of the preset dictionary, before the output
+    #[cfg(feature = "std")]
+    on_wrap: Option<util::Callback<dyn FnMut(u64) + Send>>,
 }
 
-impl<const MEM_LIMIT: usize> LzCircularBuffer<MEM_LIMIT> {
+/// A circular buffer for LZ sequences holding up to `MEM_LIMIT` bytes inline.
+pub type LzCircularBuffer<const MEM_LIMIT: usize> = CircularBuffer<[u8; MEM_LIMIT]>;
+
+/// A circular buffer for LZ sequences stored in a borrowed byte slice, whose
+/// length bounds the dictionary size.
+pub type LzSliceBuffer<'a> = CircularBuffer<&'a mut [u8]>;
+
+impl<const MEM_LIMIT: usize> CircularBuffer<[u8; MEM_LIMIT]> {
+    /// Create an empty buffer; [`LzBuffer::set_dict_size`] must be called
+    /// before use.
     pub const fn new() -> Self {
+        Self::with_storage([0_u8; MEM_LIMIT])
+    }
+}
+
+impl<'a> CircularBuffer<&'a mut [u8]> {
+    /// Create an empty buffer stored in `buf`; [`LzBuffer::set_dict_size`]
+    /// must be called before use.
+    pub fn from_slice(buf: &'a mut [u8]) -> Self {
+        buf.iter_mut().for_each(|v| *v = 0);
+        Self::with_storage(buf)
+    }
+}
+
+impl<S> CircularBuffer<S> {
+    const fn with_storage(buf: S) -> Self {
         Self {
-            buf: [0_u8; MEM_LIMIT],
+            buf,
             dict_size: None,
             cursor: 0,
             len: 0,
+            discard_from: 0,
+            discard_to: 0,
+            fill: None,
+            preset_len: 0,
+            #[cfg(feature = "std")]
+            on_wrap: None,
         }
     }
 
+    /// Call `f` each time the write position wraps around the end of the
+    /// dictionary, i.e. each time `dict_size` more bytes have been
+    /// appended, with the total number of bytes appended so far.
+    ///
+    /// The callback is kept across [`reset`](LzBuffer::reset).
+    #[cfg(feature = "std")]
+    pub fn set_wrap_callback(&mut self, f: impl FnMut(u64) + Send + 'static) {
+        self.on_wrap = Some(util::Callback::new(Box::new(f)));
+    }
+
+    /// Remove the callback set with
+    /// [`set_wrap_callback`](CircularBuffer::set_wrap_callback).
+    #[cfg(feature = "std")]
+    pub fn clear_wrap_callback(&mut self) {
+        self.on_wrap = None;
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> CircularBuffer<S> {
     fn get(&self, index: usize) -> u8 {
-        *self.buf.get(index).unwrap_or(&0)
+        *self.buf.as_ref().get(index).unwrap_or(&0)
     }
 
     fn set(&mut self, index: usize, value: u8) {
-        self.buf[index] = value;
+        self.buf.as_mut()[index] = value;
+    }
+
+    /// The bytes back-references may reach, oldest first: the last bytes
+    /// of the output, preceded by the preset dictionary if any, up to the
+    /// dictionary size, or the whole dictionary with a
+    /// [fill](LzBuffer::set_fill). As with `VecDeque::as_slices`, the window
+    /// is split where the ring wraps around, the first slice followed by the
+    /// second being in logical order. See [`LzBuffer::last_n`] for a single
+    /// byte.
+    ///
+    /// The window is empty until the dictionary size is set.
+    pub fn window(&self) -> (&[u8], &[u8]) {
+        let dict_size = match self.dict_size {
+            Some(v) => v,
+            None => return (&[], &[]),
+        };
+        let count = match self.fill {
+            Some(_) => dict_size,
+            None => (self.len + self.preset_len).min(dict_size),
+        };
+        let buf = &self.buf.as_ref()[..dict_size];
+        if count <= self.cursor {
+            (&buf[self.cursor - count..self.cursor], &[])
+        } else {
+            (&buf[dict_size + self.cursor - count..], &buf[..self.cursor])
+        }
+    }
+
+    // Fail unless `distance` reaches a byte of the window: a distance of 0
+    // would read the stale byte under the cursor
+    fn check_distance(&self, distance: usize, dict_size: usize) -> error::Result<()> {
+        let available = match self.fill {
+            Some(_) => dict_size,
+            None => (self.len + self.preset_len).min(dict_size),
+        };
+        if distance == 0 || distance > available {
+            return Err(error::lzma::LzmaError::InvalidMatchDistance {
+                distance,
+                available,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    // Account for `count` bytes written at the cursor, which must not go past
+    // the end of the dictionary, flushing the buffer when it is full
+    fn advance(
+        &mut self,
+        stream: &mut dyn io::Write,
+        count: usize,
+        dict_size: usize,
+    ) -> io::Result<()> {
+        self.cursor += count;
+        self.len += count;
+
+        // Flush the circular buffer to the output
+        if self.cursor >= dict_size {
+            self.flush(stream)?;
+            self.cursor = 0;
+            #[cfg(feature = "std")]
+            if let Some(on_wrap) = &mut self.on_wrap {
+                (on_wrap.get_mut())(self.len as u64);
+            }
+        }
+        Ok(())
+    }
+
+    // Write the buffered bytes to the output, leaving out the discarded range
+    fn flush(&mut self, stream: &mut dyn io::Write) -> io::Result<()> {
+        let base = self.len - self.cursor;
+        let start = self.discard_from.clamp(base, self.len) - base;
+        let end = self.discard_to.clamp(base, self.len) - base;
+        stream.write_all(&self.buf.as_ref()[..start])?;
+        stream.write_all(&self.buf.as_ref()[end..self.cursor])
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<S: AsRef<[u8]> + AsMut<[u8]>> CircularBuffer<S> {
+    // Save the bytes matches may still reference
+    pub(crate) fn save_window(&self) -> error::Result<checkpoint::Window> {
+        if let None = self.dict_size {
+            return Err(error::lzma::LzmaError::DictionaryUninitialized.into());
+        }
+        let (older, newer) = self.window();
+        Ok(checkpoint::Window {
+            bytes: [older, newer].concat(),
+            unflushed: self.cursor,
+            len: self.len as u64,
+            discard_from: self.discard_from as u64,
+            discard_to: self.discard_to as u64,
+            fill: self.fill,
+            preset_len: self.preset_len,
+        })
+    }
+
+    // Load a saved window, after `set_dict_size`
+    pub(crate) fn load_window(&mut self, window: &checkpoint::Window) -> error::Result<()> {
+        let dict_size = match self.dict_size {
+            Some(v) => v,
+            None => return Err(error::lzma::LzmaError::DictionaryUninitialized.into()),
+        };
+        let count = window.bytes.len();
+        if count > dict_size
+            || window.unflushed >= dict_size
+            || window.unflushed > count
+            || (window.unflushed as u64) > window.len
+        {
+            return Err(error::lzma::LzmaError::InvalidCheckpoint.into());
+        }
+        let start = dict_size + window.unflushed - count;
+        for (i, &byte) in window.bytes.iter().enumerate() {
+            self.set((start + i) % dict_size, byte);
+        }
+        self.cursor = window.unflushed;
+        self.len = window.len as usize;
+        self.discard_from = window.discard_from as usize;
+        self.discard_to = window.discard_to as usize;
+        self.fill = window.fill;
+        self.preset_len = window.preset_len;
+        Ok(())
+    }
+}
+
+// The wrap callback is not cloned
+impl<S: Clone> Clone for CircularBuffer<S> {
+    fn clone(&self) -> Self {
+        Self {
+            buf: self.buf.clone(),
+            dict_size: self.dict_size,
+            cursor: self.cursor,
+            len: self.len,
+            discard_from: self.discard_from,
+            discard_to: self.discard_to,
+            fill: self.fill,
+            preset_len: self.preset_len,
+            #[cfg(feature = "std")]
+            on_wrap: None,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.buf.clone_from(&source.buf);
+        self.dict_size = source.dict_size;
+        self.cursor = source.cursor;
+        self.len = source.len;
+        self.discard_from = source.discard_from;
+        self.discard_to = source.discard_to;
+        self.fill = source.fill;
+        self.preset_len = source.preset_len;
+        #[cfg(feature = "std")]
+        {
+            self.on_wrap = None;
+        }
     }
 }
 
-impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
+impl<const MEM_LIMIT: usize> Default for CircularBuffer<[u8; MEM_LIMIT]> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S> core::fmt::Debug for CircularBuffer<S> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("LzCircularBuffer")
+            .field("dict_size", &self.dict_size)
+            .field("cursor", &self.cursor)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<S: AsRef<[u8]> + AsMut<[u8]>> LzBuffer for CircularBuffer<S> {
     fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()> {
         lzma_info!("Dict size in LZ buffer: {}", dict_size);
-        if dict_size > MEM_LIMIT {
+        if dict_size == 0 {
+            return Err(error::lzma::LzmaError::InvalidDictionarySize { dict_size }.into());
+        }
+        let available = self.buf.as_ref().len();
+        if dict_size > available {
             return Err(error::Error::DictionaryBufferTooSmall {
                 needed: dict_size,
-                available: MEM_LIMIT,
+                available,
             });
         }
         self.dict_size = Some(dict_size);
@@ -70,12 +332,12 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
 
     // Retrieve the last byte or return a default
     fn last_or(&self, lit: u8) -> u8 {
-        // TODO: resolve optional dict_size in a different way
+        // Nothing can have been appended without a dictionary size
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => return lit,
         };
-        if self.len == 0 {
+        if self.len == 0 && self.preset_len == 0 {
             lit
         } else {
             self.get((dict_size + self.cursor - 1) % dict_size)
@@ -85,25 +347,10 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
     // Retrieve the n-th last byte
     fn last_n(&self, distance: usize) -> error::Result<u8> {
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => return Err(error::lzma::LzmaError::DictionaryUninitialized.into()),
         };
-        if distance > dict_size {
-            return Err(
-                error::lzma::LzmaError::MatchDistanceIsBeyondDictionarySize {
-                    distance,
-                    dict_size,
-                }
-                .into(),
-            );
-        }
-        if distance > self.len {
-            return Err(error::lzma::LzmaError::MatchDistanceIsBeyondOutputSize {
-                distance,
-                output_len: self.len,
-            }
-            .into());
-        }
+        self.check_distance(distance, dict_size)?;
 
         let offset = (dict_size + self.cursor - distance) % dict_size;
         Ok(self.get(offset))
@@ -112,19 +359,11 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
     // Append a literal
     fn append_literal(&mut self, stream: &mut dyn io::Write, lit: u8) -> error::Result<()> {
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => return Err(error::lzma::LzmaError::DictionaryUninitialized.into()),
         };
         self.set(self.cursor, lit);
-        self.cursor += 1;
-        self.len += 1;
-
-        // Flush the circular buffer to the output
-        if self.cursor == dict_size {
-            stream.write_all(&self.buf[..self.cursor])?;
-            self.cursor = 0;
-        }
-
+        self.advance(stream, 1, dict_size)?;
         Ok(())
     }
 
@@ -136,30 +375,40 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
         distance: usize,
     ) -> error::Result<()> {
         let dict_size = match self.dict_size {
-            Some(v) => v.clone(),
-            None => panic!("LzCircularBuffer::dict_size is not initialized"),
+            Some(v) => v,
+            None => return Err(error::lzma::LzmaError::DictionaryUninitialized.into()),
         };
         lzma_debug!("LZ {{ len: {}, distance: {} }}", len, distance);
-        if distance > dict_size {
-            return Err(error::lzma::LzmaError::LzDistanceIsBeyondDictionarySize {
-                distance,
-                dict_size,
-            }
-            .into());
-        }
-        if distance > self.len {
-            return Err(error::lzma::LzmaError::LzDistanceIsBeyondOutputSize {
-                distance,
-                output_len: self.len,
-            }
-            .into());
-        }
+        self.check_distance(distance, dict_size)?;
 
+        // Copy in chunks that neither wrap around the end of the dictionary
+        // nor overlap, so that every source byte is written before being read
         let mut offset = (dict_size + self.cursor - distance) % dict_size;
-        for _ in 0..len {
-            let x = self.get(offset);
-            self.append_literal(stream, x)?;
-            offset += 1;
+        let mut remaining = len;
+        while remaining > 0 {
+            let count = remaining
+                .min(dict_size - offset)
+                .min(dict_size - self.cursor);
+            let count = if offset + 1 == self.cursor {
+                // Run of the last byte
+                let x = self.get(offset);
+                self.buf.as_mut()[self.cursor..self.cursor + count].fill(x);
+                count
+            } else {
+                let count = match offset.cmp(&self.cursor) {
+                    Ordering::Less => count.min(self.cursor - offset),
+                    Ordering::Greater => count.min(offset - self.cursor),
+                    // A distance of `dict_size` copies each byte onto itself
+                    Ordering::Equal => count,
+                };
+                self.buf
+                    .as_mut()
+                    .copy_within(offset..offset + count, self.cursor);
+                count
+            };
+            self.advance(stream, count, dict_size)?;
+            remaining -= count;
+            offset += count;
             if offset == dict_size {
                 offset = 0
             }
@@ -167,20 +416,70 @@ impl<const MEM_LIMIT: usize> LzBuffer for LzCircularBuffer<MEM_LIMIT> {
         Ok(())
     }
 
+    fn discard_next(&mut self, count: usize) {
+        if self.discard_to < self.len {
+            self.discard_from = self.len;
+            self.discard_to = self.len;
+        }
+        self.discard_to = self.discard_to.saturating_add(count);
+    }
+
     // Consumes this buffer and flushes any data
     fn finish(&mut self, stream: &mut dyn io::Write) -> io::Result<()> {
         if self.cursor > 0 {
-            stream.write_all(&self.buf[..self.cursor])?;
+            self.flush(stream)?;
             stream.flush()?;
         }
         self.reset();
         Ok(())
     }
 
+    // Until the dictionary wraps around, its unwritten tail is read by
+    // distances reaching before the start of the output
+    fn set_fill(&mut self, fill: Option<u8>) {
+        let byte = match fill {
+            Some(v) => v,
+            None => 0,
+        };
+        // The preset dictionary, if any, ends the tail
+        let end = match self.dict_size {
+            Some(v) => v - self.preset_len,
+            None => self.buf.as_ref().len(),
+        };
+        let start = self.len.min(end);
+        self.buf.as_mut()[start..end]
+            .iter_mut()
+            .for_each(|v| *v = byte);
+        self.fill = fill;
+    }
+
+    // The preset dictionary ends the buffer, so that distances reaching
+    // before the start of the output wrap around into it
+    fn set_preset(&mut self, dict: &[u8]) -> error::Result<usize> {
+        let dict_size = match self.dict_size {
+            Some(v) => v,
+            None => return Err(error::lzma::LzmaError::DictionaryUninitialized.into()),
+        };
+        if self.len > 0 {
+            return Err(error::lzma::LzmaError::PresetDictionaryAfterOutput {
+                output_len: self.len,
+            }
+            .into());
+        }
+        let kept = &dict[dict.len().saturating_sub(dict_size)..];
+        self.buf.as_mut()[dict_size - kept.len()..dict_size].copy_from_slice(kept);
+        self.preset_len = kept.len();
+        Ok(kept.len())
+    }
+
     fn reset(&mut self) {
-        self.buf.iter_mut().for_each(|v| *v = 0);
+        self.buf.as_mut().iter_mut().for_each(|v| *v = 0);
         self.dict_size = None;
         self.cursor = 0;
         self.len = 0;
+        self.discard_from = 0;
+        self.discard_to = 0;
+        self.fill = None;
+        self.preset_len = 0;
     }
 }
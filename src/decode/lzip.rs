@@ -0,0 +1,212 @@
+//! LZIP container: member header and trailer around an LZMA stream.
+
+use crate::check::Crc32;
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{DecoderState, LzmaParams};
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::util;
+use crate::error::lzip::LzipError;
+use crate::error::{self, HeaderField};
+use crate::io::{self, BufRead, Write};
+use crate::option::GuaranteedOption::*;
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Magic bytes starting a member header.
+pub(crate) const MAGIC: [u8; 4] = *b"LZIP";
+/// Size of the member header: magic, version and coded dictionary size.
+const HEADER_SIZE: u64 = 6;
+/// Size of the member trailer: CRC32, data size and member size.
+const TRAILER_SIZE: u64 = 20;
+
+/// LZIP decoder, parsing the member framing and delegating the LZMA stream
+/// of each member to a [`DecoderState`].
+///
+/// A member is a 6-byte header (`LZIP`, the version 1 and the coded
+/// dictionary size), an LZMA stream with `lc = 3`, `lp = 0`, `pb = 2`
+/// ending with an end marker, and a 20-byte trailer holding the CRC32 and
+/// the size of the decompressed data, and the size of the whole member,
+/// all of which are validated.
+///
+/// Concatenated members, as produced by `plzip` or by concatenating files,
+/// are decoded into one output.
+pub struct LzipDecoder<LZB, const PROBS_MEM_LIMIT: usize>
+where
+    LZB: LzBuffer,
+{
+    lzma: DecoderState<LZB, PROBS_MEM_LIMIT>,
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    LzipDecoder<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    /// Create a decoder with an inline dictionary of `DICT_MEM_LIMIT` bytes.
+    pub const fn new() -> Self {
+        Self {
+            lzma: DecoderState::new(),
+        }
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for LzipDecoder<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize> core::fmt::Debug for LzipDecoder<LZB, PROBS_MEM_LIMIT>
+where
+    LZB: LzBuffer + core::fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("LzipDecoder")
+            .field("lzma", &self.lzma)
+            .finish()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize> LzipDecoder<LZB, PROBS_MEM_LIMIT>
+where
+    LZB: LzBuffer,
+{
+    /// Create a decoder delegating the LZMA streams to `lzma`, e.g. to use
+    /// another dictionary buffer. The decoder is reset before each member.
+    pub fn with_decoder(lzma: DecoderState<LZB, PROBS_MEM_LIMIT>) -> Self {
+        Self { lzma }
+    }
+
+    /// Decompress LZIP data made of one or more members, up to the end of
+    /// `input`.
+    pub fn decompress<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+    ) -> error::Result<()> {
+        loop {
+            self.decompress_member(input, output)?;
+            if util::is_eof(input)? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn decompress_member<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+    ) -> error::Result<()> {
+        let dict_size = read_header(input)?;
+        self.lzma.reset();
+        self.lzma
+            .set_params(LzmaParams::from_fields(3, 0, 2, dict_size, None))?;
+        // The trailer follows the end marker
+        self.lzma.set_concatenated(true);
+
+        let mut sink = CrcWriter {
+            inner: output,
+            crc: Crc32::new(),
+            len: 0,
+        };
+        let mut rangecoder = RangeDecoder::new(&mut *input)
+            .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+        self.lzma.process(&mut sink, &mut rangecoder)?;
+        let compressed_size = rangecoder.bytes_consumed();
+        self.lzma.output.finish(&mut sink)?;
+
+        let mut trailer = [0u8; TRAILER_SIZE as usize];
+        input.read_exact(&mut trailer)?;
+        let expected = LittleEndian::read_u32(&trailer[..4]);
+        let found = sink.crc.finalize();
+        if expected != found {
+            return Err(LzipError::CrcMismatch { expected, found }.into());
+        }
+        let expected = LittleEndian::read_u64(&trailer[4..12]);
+        if expected != sink.len {
+            return Err(LzipError::DataSizeMismatch {
+                expected,
+                found: sink.len,
+            }
+            .into());
+        }
+        let expected = LittleEndian::read_u64(&trailer[12..]);
+        let found = HEADER_SIZE + compressed_size + TRAILER_SIZE;
+        if expected != found {
+            return Err(LzipError::MemberSizeMismatch { expected, found }.into());
+        }
+        Ok(())
+    }
+}
+
+// Returns the dictionary size
+fn read_header<R: BufRead>(input: &mut R) -> error::Result<u32> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    input
+        .read_exact(&mut header)
+        .map_err(error::Error::header_too_short(HeaderField::StreamHeader))?;
+    if header[..4] != MAGIC {
+        return Err(LzipError::InvalidMagic.into());
+    }
+    if header[4] != 1 {
+        return Err(LzipError::UnsupportedVersion { version: header[4] }.into());
+    }
+    let dict_size = dict_size_from_byte(header[5])?;
+    lzma_info!("LZIP dictionary size: {}", dict_size);
+    Ok(dict_size)
+}
+
+/// Decode the coded dictionary size of an LZIP header: the low 5 bits are
+/// the base-2 logarithm of a power of two, from which the high 3 bits
+/// subtract as many sixteenths. The result must be between 4 KiB and
+/// 512 MiB.
+pub fn dict_size_from_byte(byte: u8) -> error::Result<u32> {
+    let base = 1u32 << (byte & 0x1F);
+    let dict_size = base - (base / 16) * (byte >> 5) as u32;
+    if !(1 << 12..=1 << 29).contains(&dict_size) {
+        return Err(LzipError::InvalidDictionarySize { byte }.into());
+    }
+    Ok(dict_size)
+}
+
+/// A [`Write`] computing the CRC32 and the length of the data written.
+struct CrcWriter<'a> {
+    inner: &'a mut dyn io::Write,
+    crc: Crc32,
+    len: u64,
+}
+
+impl<'a> Write for CrcWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.crc.update(&buf[..count]);
+        self.len += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dict_sizes() {
+        assert_eq!(dict_size_from_byte(0x0C).unwrap(), 4096);
+        assert_eq!(dict_size_from_byte(0x17).unwrap(), 8 << 20);
+        // 32 MiB - 2 * 2 MiB
+        assert_eq!(dict_size_from_byte(0x59).unwrap(), 28 << 20);
+        assert_eq!(dict_size_from_byte(0x1D).unwrap(), 512 << 20);
+        // Below 4 KiB, above 512 MiB
+        for byte in [0x0B, 0x2C, 0x1E, 0x1F] {
+            assert!(matches!(
+                dict_size_from_byte(byte),
+                Err(error::Error::LzipError(
+                    LzipError::InvalidDictionarySize { .. }
+                ))
+            ));
+        }
+    }
+}
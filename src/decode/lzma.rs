@@ -1,25 +1,49 @@
-use crate::decode::lzbuffer;
+#[cfg(feature = "serde")]
+use crate::decode::checkpoint;
+#[cfg(feature = "events")]
+use crate::decode::events;
+use crate::decode::lzbuffer::{self, LzBuffer};
+#[cfg(feature = "std")]
+use crate::decode::metrics;
+use crate::decode::probs::{self, ProbModel};
 use crate::decode::rangecoder;
+#[cfg(feature = "stats")]
+use crate::decode::stats;
+use crate::decode::trace::{self, TraceSink};
+use crate::decode::util;
 use crate::decompress::Options;
 use crate::decompress::UnpackedSize;
-use crate::error;
+use crate::error::{self, HeaderField};
 use crate::io;
 use crate::option::GuaranteedOption as Option;
 use crate::option::GuaranteedOption::*;
 use byteorder::LittleEndian;
-use io::ReadBytesExt;
+use core::convert::TryFrom;
+use io::{ReadBytesExt, WriteBytesExt};
 
-/// Maximum input data that can be processed in one iteration.
+/// Maximum input data that can be processed in one iteration, and so the
+/// minimum size of the partial input buffer of a
+/// [`DecoderState`].
 /// Libhtp uses the following equation to define the maximum number of bits
 /// for the worst case scenario:
 ///   log2((2^11 / 31) ^ 22) + 26 < 134 + 26 = 160
-const MAX_REQUIRED_INPUT: usize = 20;
+pub const MAX_REQUIRED_INPUT: usize = 20;
+
+// Compile-time check of the `INPUT_BUF_SIZE` of a `DecoderState`
+struct AssertInputBufSize<const INPUT_BUF_SIZE: usize>;
+
+impl<const INPUT_BUF_SIZE: usize> AssertInputBufSize<INPUT_BUF_SIZE> {
+    const OK: () = assert!(
+        INPUT_BUF_SIZE >= MAX_REQUIRED_INPUT,
+        "INPUT_BUF_SIZE must be at least MAX_REQUIRED_INPUT"
+    );
+}
 
 /// Processing mode for decompression.
 ///
 /// Tells the decompressor if we should expect more data after parsing the
 /// current input.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum ProcessingMode {
     /// Streaming mode. Process the input bytes but assume there will be more
     /// chunks of input data to receive in future calls to `process_mode()`.
@@ -31,6 +55,24 @@ enum ProcessingMode {
     Finish,
 }
 
+/// Where to pause decoding before the end of the stream, at a symbol
+/// boundary.
+#[derive(Clone, Copy, Debug)]
+enum StopAt {
+    /// Decode up to the end of the stream.
+    End,
+    /// Pause once the output reaches the given length.
+    Offset(u64),
+    /// Pause once the given time has passed.
+    #[cfg(feature = "std")]
+    Deadline(std::time::Instant),
+}
+
+/// Number of symbols decoded between two reads of the clock with
+/// [`StopAt::Deadline`].
+#[cfg(feature = "std")]
+const DEADLINE_CHECK_INTERVAL: u32 = 1024;
+
 /// Result of the next iteration of processing.
 ///
 /// Indicates whether processing should continue or is finished.
@@ -42,45 +84,138 @@ pub(crate) enum ProcessingStatus {
     Finished,
 }
 
+/// LZMA stream parameters, as found in the `.lzma` header.
 #[repr(C)]
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LzmaParams {
-    // most lc significant bits of previous byte are part of the literal context
-    pub lc: u32, // 0..8
-    pub lp: u32, // 0..4
-    // context for literal/match is plaintext offset modulo 2^pb
-    pub pb: u32, // 0..4
+    /// Number of high bits of the previous byte used as literal context
+    /// (0..8).
+    pub lc: u32,
+    /// Number of low bits of the output position used as literal context
+    /// (0..4).
+    pub lp: u32,
+    /// Number of low bits of the output position used as match/literal
+    /// context (0..4).
+    pub pb: u32,
+    /// Dictionary size in bytes, as used by the decoder: at least `0x1000`.
+    ///
+    /// Match distances are coded on 32 bits, so no stream references data
+    /// more than 4 GiB back and a larger dictionary would never be used.
     pub dict_size: u32,
+    // Dictionary size in bytes as found in the header, before raising it to
+    // the `0x1000` minimum
+    dict_size_provided: u32,
+    /// Expected number of decompressed bytes; `None` means the stream is
+    /// terminated by an end marker.
     pub unpacked_size: Option<u64>,
 }
 
 impl LzmaParams {
+    /// Parameters supplied out of band, e.g. for headerless streams such as
+    /// those of `xz --format=raw`. As with [`read_header`](LzmaParams::read_header),
+    /// a dictionary size below `0x1000` is raised to it.
+    ///
+    /// `lc` must be <= 8, `lp` and `pb` <= 4, and `dict_size` > 0.
+    pub fn new(
+        lc: u32,
+        lp: u32,
+        pb: u32,
+        dict_size: u32,
+        unpacked_size: Option<u64>,
+    ) -> error::Result<LzmaParams> {
+        if lc > 8 || lp > 4 || pb > 4 {
+            return Err(error::lzma::LzmaError::InvalidProperties { lc, lp, pb }.into());
+        }
+        if dict_size == 0 {
+            return Err(error::lzma::LzmaError::InvalidDictionarySize { dict_size: 0 }.into());
+        }
+        Ok(LzmaParams::from_fields(
+            lc,
+            lp,
+            pb,
+            dict_size,
+            unpacked_size,
+        ))
+    }
+
+    // Parameters with the given header fields, which are not validated,
+    // raising the dictionary size to the `0x1000` minimum
+    pub(crate) fn from_fields(
+        lc: u32,
+        lp: u32,
+        pb: u32,
+        dict_size: u32,
+        unpacked_size: Option<u64>,
+    ) -> LzmaParams {
+        LzmaParams {
+            lc,
+            lp,
+            pb,
+            dict_size: dict_size.max(0x1000),
+            dict_size_provided: dict_size,
+            unpacked_size,
+        }
+    }
+
+    /// Dictionary size found in the header, which may be below the minimum
+    /// of `0x1000` bytes.
+    pub fn provided_dict_size(&self) -> u32 {
+        self.dict_size_provided
+    }
+
+    /// Dictionary size used by the decoder, i.e. the
+    /// [provided](LzmaParams::provided_dict_size) one raised to `0x1000`.
+    pub fn effective_dict_size(&self) -> u32 {
+        self.dict_size
+    }
+
+    /// Number of bytes the stream decodes to, when known from the header or
+    /// provided, e.g. to reserve the output up front. The header of
+    /// untrusted data may declare any size, so bound it before allocating,
+    /// e.g. by [`max_expansion`] of the compressed length.
+    pub fn expected_output_size(&self) -> Option<u64> {
+        self.unpacked_size
+    }
+
+    /// Memory needed to decode a stream with these parameters, in bytes:
+    /// the size of a [`DecoderState`] with a dictionary of exactly
+    /// `dict_size` bytes and exactly `1 << (lc + lp)` literal coder
+    /// contexts of `0x600` bytes each, plus a fixed overhead for the other
+    /// probabilities, the partial input buffer and the settings. See
+    /// [`DecoderState::memory_footprint`] for a given decoder type.
+    pub fn memory_required(&self) -> usize {
+        let literal_probs = 1_usize
+            .checked_shl(self.lc + self.lp)
+            .unwrap_or(usize::MAX)
+            .saturating_mul(0x300 * core::mem::size_of::<u16>());
+        usize::try_from(self.dict_size)
+            .unwrap_or(usize::MAX)
+            .saturating_add(literal_probs)
+            .saturating_add(DecoderState::<lzbuffer::LzCircularBuffer<0>, 0>::memory_footprint())
+    }
+
+    /// Read the `.lzma` header from `input`, handling the unpacked size as
+    /// requested by `options`.
     pub fn read_header<R>(input: &mut R, options: &Options) -> error::Result<LzmaParams>
     where
         R: io::BufRead,
     {
-        // Properties
-        let props = input.read_u8().map_err(error::Error::HeaderTooShort)?;
-
-        let mut pb = props as u32;
-        if pb >= 225 {
-            return Err(error::lzma::LzmaError::InvalidHeader {
-                invalid_properties: pb,
+        let (lc, lp, pb) = match options.properties {
+            Some(properties) => {
+                input
+                    .read_u8()
+                    .map_err(error::Error::header_too_short(HeaderField::Properties))?;
+                lzma_info!("Properties overridden: {:?}", properties);
+                properties
             }
-            .into());
-        }
-
-        let lc: u32 = pb % 9;
-        pb /= 9;
-        let lp: u32 = pb % 5;
-        pb /= 5;
-
-        lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", lc, lp, pb);
+            None => read_header_properties(input)?,
+        };
 
         // Dictionary
         let dict_size_provided = input
             .read_u32::<LittleEndian>()
-            .map_err(error::Error::HeaderTooShort)?;
+            .map_err(error::Error::header_too_short(HeaderField::DictSize))?;
         let dict_size = if dict_size_provided < 0x1000 {
             0x1000
         } else {
@@ -94,7 +229,7 @@ impl LzmaParams {
             UnpackedSize::ReadFromHeader => {
                 let unpacked_size_provided = input
                     .read_u64::<LittleEndian>()
-                    .map_err(error::Error::HeaderTooShort)?;
+                    .map_err(error::Error::header_too_short(HeaderField::UnpackedSize))?;
                 let marker_mandatory: bool = unpacked_size_provided == 0xFFFF_FFFF_FFFF_FFFF;
                 if marker_mandatory {
                     None
@@ -105,10 +240,16 @@ impl LzmaParams {
             UnpackedSize::ReadHeaderButUseProvided(x) => {
                 input
                     .read_u64::<LittleEndian>()
-                    .map_err(error::Error::HeaderTooShort)?;
-                x.into()
+                    .map_err(error::Error::header_too_short(HeaderField::UnpackedSize))?;
+                x
             }
             UnpackedSize::UseProvided(x) => x,
+            UnpackedSize::VerifyAfterMarker(_) => {
+                input
+                    .read_u64::<LittleEndian>()
+                    .map_err(error::Error::header_too_short(HeaderField::UnpackedSize))?;
+                None
+            }
         };
 
         lzma_info!("Unpacked size: {:?}", unpacked_size);
@@ -118,129 +259,1152 @@ impl LzmaParams {
             lp,
             pb,
             dict_size,
+            dict_size_provided,
             unpacked_size,
         };
 
         Ok(params)
     }
+
+    /// Parse the `.lzma` header at the start of `input` without consuming
+    /// anything, e.g. to check the dictionary and unpacked sizes before
+    /// allocating a decoder. The unpacked size is read from the header, as
+    /// with the default [`Options`].
+    pub fn peek_header(input: &[u8]) -> error::Result<LzmaParams> {
+        let mut input = input;
+        Self::read_header(&mut input, &Options::default())
+    }
+
+    /// Write the `.lzma` header of these parameters to `output`: the
+    /// properties byte, the [provided](LzmaParams::provided_dict_size)
+    /// dictionary size and the unpacked size, all ones when it is `None`.
+    pub fn write_header<W>(&self, output: &mut W) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        let props = (self.pb * 5 + self.lp) * 9 + self.lc;
+        output.write_u8(props as u8)?;
+        output.write_u32::<LittleEndian>(self.dict_size_provided)?;
+        let unpacked_size = match self.unpacked_size {
+            Some(x) => x,
+            None => 0xFFFF_FFFF_FFFF_FFFF,
+        };
+        output.write_u64::<LittleEndian>(unpacked_size)
+    }
+
+    /// Read a compact header made of the properties byte and a dictionary
+    /// size byte as found in LZMA2 (see [`lzma2_dict_size_from_byte`]), as
+    /// used by space-constrained containers. The unpacked size is not part
+    /// of the header and is given as `unpacked_size`.
+    pub fn read_compact_header<R>(
+        input: &mut R,
+        unpacked_size: Option<u64>,
+    ) -> error::Result<LzmaParams>
+    where
+        R: io::BufRead,
+    {
+        let (lc, lp, pb) = read_header_properties(input)?;
+        let dict_byte = input
+            .read_u8()
+            .map_err(error::Error::header_too_short(HeaderField::DictSize))?;
+        let dict_size = lzma2_dict_size_from_byte(dict_byte)?;
+
+        lzma_info!("Dict size: {}", dict_size);
+
+        Ok(LzmaParams {
+            lc,
+            lp,
+            pb,
+            dict_size,
+            dict_size_provided: dict_size,
+            unpacked_size,
+        })
+    }
 }
 
-pub struct DecoderState<LZB, const PROBS_MEM_LIMIT: usize>
-where
+/// Read and split the properties byte of a header.
+pub(crate) fn read_properties<R: io::BufRead>(input: &mut R) -> error::Result<(u32, u32, u32)> {
+    let props = input
+        .read_u8()
+        .map_err(error::Error::header_too_short(HeaderField::Properties))?;
+    if props >= 225 {
+        return Err(error::lzma::LzmaError::InvalidHeader {
+            invalid_properties: props as u32,
+        }
+        .into());
+    }
+    let (lc, lp, pb) = split_properties(props);
+
+    lzma_info!("Properties {{ lc: {}, lp: {}, pb: {} }}", lc, lp, pb);
+    Ok((lc, lp, pb))
+}
+
+/// Read the properties byte of a header, rejecting `lc + lp > 4` as liblzma
+/// does. [`DecoderState::set_params`] accepts up to `lc = 8` and `lp = 4`
+/// for parameters given out of band.
+fn read_header_properties<R: io::BufRead>(input: &mut R) -> error::Result<(u32, u32, u32)> {
+    let (lc, lp, pb) = read_properties(input)?;
+    if lc + lp > 4 {
+        return Err(error::lzma::LzmaError::InvalidLiteralContext { lc, lp }.into());
+    }
+    Ok((lc, lp, pb))
+}
+
+/// Dictionary size encoded by an LZMA2 dictionary size byte: `2` or `3`
+/// (for even and odd bytes) shifted left by `11 + byte / 2`, or
+/// `0xFFFF_FFFF` for the byte `40`. Bytes above `40` are invalid.
+pub fn lzma2_dict_size_from_byte(byte: u8) -> error::Result<u32> {
+    match byte {
+        0..=39 => Ok((2 | (byte as u32 & 1)) << (byte / 2 + 11)),
+        40 => Ok(0xFFFF_FFFF),
+        _ => Err(error::lzma::LzmaError::InvalidDictionarySizeByte { byte }.into()),
+    }
+}
+
+/// Dictionary size as a buffer length, failing on targets whose address space
+/// is smaller than the dictionary.
+pub(crate) fn dict_len(dict_size: u32) -> error::Result<usize> {
+    usize::try_from(dict_size).map_err(|_| {
+        error::lzma::LzmaError::DictionaryTooLarge {
+            dict_size: dict_size.into(),
+        }
+        .into()
+    })
+}
+
+/// Header and location of one member of concatenated `.lzma` data, as found
+/// by [`lzma_index_members`](crate::lzma_index_members).
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemberInfo {
+    /// Parameters read from the header of the member.
+    pub params: LzmaParams,
+    /// Offset of the first byte of the member (i.e. of its header) in the
+    /// input.
+    pub start: u64,
+    /// Offset just past the last byte of the member in the input.
+    pub end: u64,
+}
+
+/// Kind of the next symbol, as returned by
+/// [`DecoderState::peek_next_symbol_kind`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// A literal byte.
+    Literal,
+    /// A match with a newly coded distance, or the end-of-stream marker.
+    Match,
+    /// A match repeating the given one of the last 4 distances.
+    Rep(u8),
+    /// A single byte repeating the last distance.
+    ShortRep,
+}
+
+/// Split a properties byte below 225 into `(lc, lp, pb)`.
+fn split_properties(props: u8) -> (u32, u32, u32) {
+    let mut pb = props as u32;
+    let lc: u32 = pb % 9;
+    pb /= 9;
+    let lp: u32 = pb % 5;
+    pb /= 5;
+    (lc, lp, pb)
+}
+
+/// Every valid properties byte of the `.lzma` header, with the
+/// `(props, lc, lp, pb)` it decodes to, in increasing `props` order: the
+/// bytes below 225 with `lc + lp <= 4`.
+pub fn enumerate_properties() -> impl Iterator<Item = (u8, u32, u32, u32)> {
+    (0..225u8)
+        .map(|props| {
+            let (lc, lp, pb) = split_properties(props);
+            (props, lc, lp, pb)
+        })
+        .filter(|&(_, lc, lp, _)| lc + lp <= 4)
+}
+
+/// Upper bound on the number of bytes `compressed_len` bytes of `.lzma` data
+/// can decode to, e.g. to size an output buffer or cap the output of an
+/// untrusted stream.
+///
+/// The bound follows from the range coder. A probability never exceeds
+/// `2017 / 2048` of the range, so each decoded bit shrinks the range by a
+/// factor of at least `2048 / 2017`, i.e. costs at least 0.022 bits of input,
+/// and a byte of input pays for at most 364 decoded bits. The most output
+/// per decoded bit comes from a `rep[0]` match of the maximal length 273,
+/// which takes 14 bits (4 for the match kind, then 2 choice bits and 8 tree
+/// bits for the length). The 13-byte header and the 5 bytes initializing the
+/// range coder cover the first 364 bits, so the output is at most
+/// `compressed_len * 364 * 273 / 14 = compressed_len * 7098` bytes. This
+/// also holds for concatenated members.
+pub const fn max_expansion(compressed_len: u64) -> u64 {
+    compressed_len.saturating_mul(364 * 273 / 14)
+}
+
+/// LZMA decoder state: probability model, dictionary and LRU distances.
+///
+/// A decoder must be [`reset`](DecoderState::reset) and given its
+/// [`LzmaParams`] via [`set_params`](DecoderState::set_params) before
+/// processing any data.
+///
+/// The probability tables are stored in a [`ProbModel`], by default an
+/// [`ArrayProbModel`](probs::ArrayProbModel) with room for
+/// `PROBS_MEM_LIMIT` literal coder contexts.
+///
+/// In streaming mode, input ending in the middle of a symbol is held in a
+/// partial input buffer of `INPUT_BUF_SIZE` bytes, which must be at least
+/// [`MAX_REQUIRED_INPUT`]; a smaller size fails to compile. A larger buffer
+/// waits for more input before decoding from it.
+///
+/// All the state is held inline: with an
+/// [`LzCircularBuffer`](lzbuffer::LzCircularBuffer) a decoder takes roughly
+/// `DICT_MEM_LIMIT + 0x600 * PROBS_MEM_LIMIT + 4.5 KiB` bytes, so on
+/// stack-limited targets place it in a `static` or on the heap. Decoding
+/// never recurses and needs a few hundred bytes of stack on top of that.
+///
+/// A configured decoder is `Send` and `Sync`; callbacks stored in it must be
+/// `Send`. The output sink is only borrowed for the duration of each call.
+pub struct DecoderState<
+    LZB,
+    const PROBS_MEM_LIMIT: usize,
+    PM = probs::ArrayProbModel<PROBS_MEM_LIMIT>,
+    TS = trace::NoTrace,
+    const INPUT_BUF_SIZE: usize = MAX_REQUIRED_INPUT,
+> where
     LZB: lzbuffer::LzBuffer,
+    PM: ProbModel,
+    TS: TraceSink,
 {
     processing_status: ProcessingStatus,
+    /// Parameters of the stream being decoded, as given to
+    /// [`set_params`](DecoderState::set_params).
+    pub params: Option<LzmaParams>,
+    // Fields of `params` used for every symbol, cached by `set_params`:
+    // `lc`, and the masks of the position bits of `lp` and `pb`
+    lc: u32,
+    lp_mask: usize,
+    pb_mask: usize,
     // Buffer input data here if we need more for decompression. Up to
     // MAX_REQUIRED_INPUT bytes can be consumed during one iteration.
-    pub params: Option<LzmaParams>,
-    partial_input_buf: io::Cursor<[u8; MAX_REQUIRED_INPUT]>,
+    partial_input_buf: io::Cursor<[u8; INPUT_BUF_SIZE]>,
+    /// Dictionary buffer; decoded data is flushed from it to the output sink.
     pub output: LZB,
-    literal_probs: [[u16; 0x300]; PROBS_MEM_LIMIT],
+    probs: PM,
     pos_slot_decoder: [rangecoder::BitTree<64>; 4],
     align_decoder: rangecoder::BitTree<16>,
-    pos_decoders: [u16; 115],
-    is_match: [u16; 192], // true = LZ, false = literal
-    is_rep: [u16; 12],
-    is_rep_g0: [u16; 12],
-    is_rep_g1: [u16; 12],
-    is_rep_g2: [u16; 12],
-    is_rep_0long: [u16; 192],
     state: usize,
     rep: [usize; 4],
+    eos_marker_offset: Option<u64>,
+    concatenated: bool,
+    strict_canonical: bool,
+    verify_end_after_size: bool,
+    reject_trailing_data: bool,
+    dict_fill: Option<u8>,
+    max_reference_distance: Option<usize>,
+    max_output: Option<u64>,
+    allow_truncated: bool,
+    // Whether decoding stopped at the end of a truncated input
+    truncated: bool,
+    // Length of the preset dictionary, which counts in the position bits
+    preset_len: usize,
+    len_after_marker: Option<u64>,
+    total_input_len: Option<u64>,
+    // Compressed bytes consumed since the last reset, and consumed counter
+    // of the range decoder at the start of the current call.
+    input_consumed: u64,
+    input_base: u64,
     len_decoder: rangecoder::LenDecoder,
     rep_len_decoder: rangecoder::LenDecoder,
+    trace: TS,
+    #[cfg(feature = "stats")]
+    stats: stats::MatchStats,
+    #[cfg(feature = "events")]
+    events: events::EventRing<{ events::EVENT_RING_LEN }>,
+    #[cfg(feature = "std")]
+    metrics: Option<metrics::MetricsReporter>,
+    #[cfg(feature = "literal-hook")]
+    literal_hook: Option<util::Callback<dyn FnMut(u8) -> u8 + Send>>,
 }
 
 impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     DecoderState<lzbuffer::LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
 {
+    /// Create an uninitialized decoder; call [`DecoderState::reset`] before
+    /// use.
     pub const fn new() -> Self {
+        Self::with_trace_sink(trace::NoTrace)
+    }
+
+    /// Size of this decoder type in bytes, wherever it is stored:
+    /// `DICT_MEM_LIMIT` bytes of dictionary, `0x600` bytes per literal coder
+    /// context up to `PROBS_MEM_LIMIT`, and a fixed overhead including the
+    /// partial input buffer. Callbacks are allocated separately.
+    pub const fn memory_footprint() -> usize {
+        core::mem::size_of::<Self>()
+    }
+
+    /// Like [`DecoderState::new`], for streams whose `lc` and `lp` are known
+    /// ahead of time: the literal probabilities are sized for exactly
+    /// `1 << (LC + LP)` contexts, and a `PROBS_MEM_LIMIT` other than
+    /// [`probs_mem_limit(LC, LP)`](probs::probs_mem_limit) fails to
+    /// compile. [`DecoderState::set_params`] still rejects streams needing
+    /// more contexts.
+    pub const fn with_props<const LC: u32, const LP: u32>() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = probs::AssertProps::<PROBS_MEM_LIMIT, LC, LP>::OK;
+        Self::new()
+    }
+}
+
+impl<
+        const DICT_MEM_LIMIT: usize,
+        const PROBS_MEM_LIMIT: usize,
+        TS: TraceSink,
+        const INPUT_BUF_SIZE: usize,
+    >
+    DecoderState<
+        lzbuffer::LzCircularBuffer<DICT_MEM_LIMIT>,
+        PROBS_MEM_LIMIT,
+        probs::ArrayProbModel<PROBS_MEM_LIMIT>,
+        TS,
+        INPUT_BUF_SIZE,
+    >
+{
+    /// Like [`DecoderState::new`], reporting the decoded symbols to `trace`.
+    pub const fn with_trace_sink(trace: TS) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertInputBufSize::<INPUT_BUF_SIZE>::OK;
         Self {
             processing_status: ProcessingStatus::Uninitialized,
             output: lzbuffer::LzCircularBuffer::new(),
-            partial_input_buf: io::Cursor::new([0; MAX_REQUIRED_INPUT]),
+            partial_input_buf: io::Cursor::new([0; INPUT_BUF_SIZE]),
             params: None,
-            literal_probs: [[0; 0x300]; PROBS_MEM_LIMIT],
+            lc: 0,
+            lp_mask: 0,
+            pb_mask: 0,
+            probs: probs::ArrayProbModel::new(),
             pos_slot_decoder: [rangecoder::BitTree::new(); 4],
             align_decoder: rangecoder::BitTree::new(),
-            pos_decoders: [0; 115],
-            is_match: [0; 192],
-            is_rep: [0; 12],
-            is_rep_g0: [0; 12],
-            is_rep_g1: [0; 12],
-            is_rep_g2: [0; 12],
-            is_rep_0long: [0; 192],
             state: 0,
             rep: [0; 4],
+            eos_marker_offset: None,
+            concatenated: false,
+            strict_canonical: false,
+            verify_end_after_size: false,
+            reject_trailing_data: false,
+            dict_fill: None,
+            max_reference_distance: None,
+            max_output: None,
+            allow_truncated: false,
+            truncated: false,
+            preset_len: 0,
+            len_after_marker: None,
+            total_input_len: None,
+            input_consumed: 0,
+            input_base: 0,
             len_decoder: rangecoder::LenDecoder::new(),
             rep_len_decoder: rangecoder::LenDecoder::new(),
+            trace,
+            #[cfg(feature = "stats")]
+            stats: stats::MatchStats::new(),
+            #[cfg(feature = "events")]
+            events: events::EventRing::new(),
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "literal-hook")]
+            literal_hook: None,
         }
     }
 }
 
-impl<LZB, const PROBS_MEM_LIMIT: usize> DecoderState<LZB, PROBS_MEM_LIMIT>
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for DecoderState<lzbuffer::LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize, PM, TS, const INPUT_BUF_SIZE: usize> core::fmt::Debug
+    for DecoderState<LZB, PROBS_MEM_LIMIT, PM, TS, INPUT_BUF_SIZE>
+where
+    LZB: lzbuffer::LzBuffer + core::fmt::Debug,
+    PM: ProbModel,
+    TS: TraceSink,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("DecoderState")
+            .field("processing_status", &self.processing_status)
+            .field("params", &self.params)
+            .field("partial_input_buf", &self.partial_input_buf.position())
+            .field("output", &self.output)
+            .field("state", &self.state)
+            .field("rep", &self.rep)
+            .finish()
+    }
+}
+
+/// Clones are independent decoders, e.g. to try decoding from a known point
+/// and fall back to the clone on failure. The callbacks (metrics, literal
+/// hook, and the wrap callback of a [`CircularBuffer`](lzbuffer::CircularBuffer))
+/// are not cloned.
+///
+/// The clone is as big as the decoder: to save or restore one held on the
+/// heap, prefer [`clone_from`](Clone::clone_from) on an existing decoder,
+/// which copies in place.
+impl<LZB, const PROBS_MEM_LIMIT: usize, PM, TS, const INPUT_BUF_SIZE: usize> Clone
+    for DecoderState<LZB, PROBS_MEM_LIMIT, PM, TS, INPUT_BUF_SIZE>
+where
+    LZB: lzbuffer::LzBuffer + Clone,
+    PM: ProbModel + Clone,
+    TS: TraceSink + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            processing_status: self.processing_status,
+            params: self.params.clone(),
+            lc: self.lc,
+            lp_mask: self.lp_mask,
+            pb_mask: self.pb_mask,
+            partial_input_buf: self.partial_input_buf.clone(),
+            output: self.output.clone(),
+            probs: self.probs.clone(),
+            pos_slot_decoder: self.pos_slot_decoder,
+            align_decoder: self.align_decoder,
+            state: self.state,
+            rep: self.rep,
+            eos_marker_offset: self.eos_marker_offset,
+            concatenated: self.concatenated,
+            strict_canonical: self.strict_canonical,
+            verify_end_after_size: self.verify_end_after_size,
+            reject_trailing_data: self.reject_trailing_data,
+            dict_fill: self.dict_fill,
+            max_reference_distance: self.max_reference_distance,
+            max_output: self.max_output,
+            allow_truncated: self.allow_truncated,
+            truncated: self.truncated,
+            preset_len: self.preset_len,
+            len_after_marker: self.len_after_marker,
+            total_input_len: self.total_input_len,
+            input_consumed: self.input_consumed,
+            input_base: self.input_base,
+            len_decoder: self.len_decoder.clone(),
+            rep_len_decoder: self.rep_len_decoder.clone(),
+            trace: self.trace.clone(),
+            #[cfg(feature = "stats")]
+            stats: self.stats.clone(),
+            #[cfg(feature = "events")]
+            events: self.events.clone(),
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "literal-hook")]
+            literal_hook: None,
+        }
+    }
+
+    fn clone_from(&mut self, source: &Self) {
+        self.processing_status = source.processing_status;
+        self.params.clone_from(&source.params);
+        self.lc = source.lc;
+        self.lp_mask = source.lp_mask;
+        self.pb_mask = source.pb_mask;
+        self.partial_input_buf.clone_from(&source.partial_input_buf);
+        self.output.clone_from(&source.output);
+        self.probs.clone_from(&source.probs);
+        self.pos_slot_decoder = source.pos_slot_decoder;
+        self.align_decoder = source.align_decoder;
+        self.state = source.state;
+        self.rep = source.rep;
+        self.eos_marker_offset = source.eos_marker_offset;
+        self.concatenated = source.concatenated;
+        self.strict_canonical = source.strict_canonical;
+        self.verify_end_after_size = source.verify_end_after_size;
+        self.reject_trailing_data = source.reject_trailing_data;
+        self.dict_fill = source.dict_fill;
+        self.max_reference_distance = source.max_reference_distance;
+        self.max_output = source.max_output;
+        self.allow_truncated = source.allow_truncated;
+        self.truncated = source.truncated;
+        self.preset_len = source.preset_len;
+        self.len_after_marker = source.len_after_marker;
+        self.total_input_len = source.total_input_len;
+        self.input_consumed = source.input_consumed;
+        self.input_base = source.input_base;
+        self.len_decoder.clone_from(&source.len_decoder);
+        self.rep_len_decoder.clone_from(&source.rep_len_decoder);
+        self.trace.clone_from(&source.trace);
+        #[cfg(feature = "stats")]
+        self.stats.clone_from(&source.stats);
+        #[cfg(feature = "events")]
+        self.events.clone_from(&source.events);
+        #[cfg(feature = "std")]
+        {
+            self.metrics = None;
+        }
+        #[cfg(feature = "literal-hook")]
+        {
+            self.literal_hook = None;
+        }
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize, PM> DecoderState<LZB, PROBS_MEM_LIMIT, PM>
 where
     LZB: lzbuffer::LzBuffer,
+    PM: ProbModel,
 {
+    /// Create an uninitialized decoder around the given dictionary buffer
+    /// and probability model; call [`DecoderState::reset`] before use.
+    pub fn with_prob_model(output: LZB, probs: PM) -> Self {
+        Self::with_prob_model_and_trace_sink(output, probs, trace::NoTrace)
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize, PM, TS, const INPUT_BUF_SIZE: usize>
+    DecoderState<LZB, PROBS_MEM_LIMIT, PM, TS, INPUT_BUF_SIZE>
+where
+    LZB: lzbuffer::LzBuffer,
+    PM: ProbModel,
+    TS: TraceSink,
+{
+    /// Like [`DecoderState::with_prob_model`], reporting the decoded symbols
+    /// to `trace`.
+    pub fn with_prob_model_and_trace_sink(output: LZB, probs: PM, trace: TS) -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = AssertInputBufSize::<INPUT_BUF_SIZE>::OK;
+        Self {
+            processing_status: ProcessingStatus::Uninitialized,
+            output,
+            partial_input_buf: io::Cursor::new([0; INPUT_BUF_SIZE]),
+            params: None,
+            lc: 0,
+            lp_mask: 0,
+            pb_mask: 0,
+            probs,
+            pos_slot_decoder: [rangecoder::BitTree::new(); 4],
+            align_decoder: rangecoder::BitTree::new(),
+            state: 0,
+            rep: [0; 4],
+            eos_marker_offset: None,
+            concatenated: false,
+            strict_canonical: false,
+            verify_end_after_size: false,
+            reject_trailing_data: false,
+            dict_fill: None,
+            max_reference_distance: None,
+            max_output: None,
+            allow_truncated: false,
+            truncated: false,
+            preset_len: 0,
+            len_after_marker: None,
+            total_input_len: None,
+            input_consumed: 0,
+            input_base: 0,
+            len_decoder: rangecoder::LenDecoder::new(),
+            rep_len_decoder: rangecoder::LenDecoder::new(),
+            trace,
+            #[cfg(feature = "stats")]
+            stats: stats::MatchStats::new(),
+            #[cfg(feature = "events")]
+            events: events::EventRing::new(),
+            #[cfg(feature = "std")]
+            metrics: None,
+            #[cfg(feature = "literal-hook")]
+            literal_hook: None,
+        }
+    }
+
+    /// The probability model of the decoder.
+    pub fn prob_model(&self) -> &PM {
+        &self.probs
+    }
+
+    /// The trace sink of the decoder.
+    pub fn trace_sink(&self) -> &TS {
+        &self.trace
+    }
+
+    /// Mutable access to the trace sink, e.g. to drain what it recorded.
+    pub fn trace_sink_mut(&mut self) -> &mut TS {
+        &mut self.trace
+    }
+
     #[allow(dead_code)]
     pub(crate) fn get_processing_status(&self) -> ProcessingStatus {
         self.processing_status
     }
 
+    // Count input read by a range decoder that is not passed to this decoder,
+    // e.g. the initial bytes read when `Stream` parses the header.
+    #[cfg(feature = "stream")]
+    pub(crate) fn add_input_consumed(&mut self, len: u64) {
+        self.input_consumed += len;
+    }
+
+    /// The parameters given to [`set_params`](DecoderState::set_params),
+    /// if any.
+    pub fn params(&self) -> Option<&LzmaParams> {
+        self.params.as_ref()
+    }
+
+    /// Apply the stream parameters, sizing the dictionary accordingly.
     pub fn set_params(&mut self, params: LzmaParams) -> error::Result<()> {
         if let ProcessingStatus::Uninitialized = self.processing_status {
-            panic!("DecoderState is uninitialized; call `DecoderState::reset` first");
+            return Err(error::lzma::LzmaError::DecoderUninitialized.into());
         }
-        if (1 << (params.lc + params.lp)) > PROBS_MEM_LIMIT {
+        if params.lc > 8 || params.lp > 4 || params.pb > 4 {
+            return Err(error::lzma::LzmaError::InvalidProperties {
+                lc: params.lc,
+                lp: params.lp,
+                pb: params.pb,
+            }
+            .into());
+        }
+        if (1 << (params.lc + params.lp)) > self.probs.literal_states() {
             return Err(error::Error::ProbabilitiesBufferTooSmall {
                 needed: 1 << (params.lc + params.lp),
-                available: PROBS_MEM_LIMIT,
+                available: self.probs.literal_states(),
             });
         }
-        self.output.set_dict_size(params.dict_size as usize)?;
+        self.output.set_dict_size(dict_len(params.dict_size)?)?;
+        self.trace.on_params(&params);
+        self.lc = params.lc;
+        self.lp_mask = (1 << params.lp) - 1;
+        self.pb_mask = (1 << params.pb) - 1;
         self.params = Some(params);
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub(crate) fn reset(&mut self) {
-        self.processing_status = ProcessingStatus::Continue;
-        self.output.reset();
-        self.partial_input_buf = io::Cursor::new([0; MAX_REQUIRED_INPUT]);
-        self.params = None;
-        self.literal_probs
-            .iter_mut()
-            .for_each(|v| v.iter_mut().for_each(|v| *v = 0x400));
-        self.pos_slot_decoder.iter_mut().for_each(|v| v.reset());
-        self.align_decoder.reset();
-        self.pos_decoders.iter_mut().for_each(|v| *v = 0x400);
-        self.is_match.iter_mut().for_each(|v| *v = 0x400);
-        self.is_rep.iter_mut().for_each(|v| *v = 0x400);
-        self.is_rep_g0.iter_mut().for_each(|v| *v = 0x400);
-        self.is_rep_g1.iter_mut().for_each(|v| *v = 0x400);
-        self.is_rep_g2.iter_mut().for_each(|v| *v = 0x400);
-        self.is_rep_0long.iter_mut().for_each(|v| *v = 0x400);
-        self.state = 0;
-        self.rep = [0; 4];
-        self.len_decoder.reset();
-        self.rep_len_decoder.reset();
+    /// Preload the dictionary with `dict`, for streams compressed against a
+    /// preset dictionary shared by the encoder and the decoder, e.g. the
+    /// `preset_dict` of liblzma. If `dict` is longer than the dictionary
+    /// size, only its tail is kept. Matches may reach into it, and as in
+    /// liblzma, its length counts in the position bits.
+    ///
+    /// Must be called after [`set_params`](DecoderState::set_params) and
+    /// before decoding. Cleared by [`reset`](DecoderState::reset).
+    pub fn set_preset_dict(&mut self, dict: &[u8]) -> error::Result<()> {
+        if let ProcessingStatus::Uninitialized = self.processing_status {
+            return Err(error::lzma::LzmaError::DecoderUninitialized.into());
+        }
+        if matches!(self.params, None) {
+            return Err(error::lzma::LzmaError::ParamsNotSet.into());
+        }
+        self.preset_len = self.output.set_preset(dict)?;
+        Ok(())
+    }
+
+    /// Accept more input after the end-of-stream marker, as found between
+    /// concatenated members. Cleared by [`reset`](DecoderState::reset).
+    pub fn set_concatenated(&mut self, concatenated: bool) {
+        self.concatenated = concatenated;
+    }
+
+    /// Return [`LzmaError::NonCanonicalEncoding`](error::lzma::LzmaError)
+    /// on the first construct a canonical encoder would not produce.
+    /// Cleared by [`reset`](DecoderState::reset).
+    pub fn set_strict_canonical(&mut self, strict_canonical: bool) {
+        self.strict_canonical = strict_canonical;
+    }
+
+    /// In [`process`](DecoderState::process), once the declared unpacked
+    /// size is reached, check that the input ends or that the next symbol is
+    /// the end marker, returning
+    /// [`LzmaError::TrailingDataAfterDeclaredSize`](error::lzma::LzmaError)
+    /// otherwise. Cleared by [`reset`](DecoderState::reset).
+    pub fn set_verify_end_after_size(&mut self, verify_end_after_size: bool) {
+        self.verify_end_after_size = verify_end_after_size;
+    }
+
+    /// In [`process`](DecoderState::process) and the other methods
+    /// decoding to the end, once the declared unpacked size is reached,
+    /// possibly followed by an end marker, return
+    /// [`LzmaError::TrailingData`](error::lzma::LzmaError) if input is left.
+    /// Input after an end marker is always rejected, unless
+    /// [concatenated](DecoderState::set_concatenated), and reported with the
+    /// same error when this is set. Cleared by
+    /// [`reset`](DecoderState::reset).
+    pub fn set_reject_trailing_data(&mut self, reject_trailing_data: bool) {
+        self.reject_trailing_data = reject_trailing_data;
+    }
+
+    /// Assume the dictionary is filled with `fill` before the start of the
+    /// output, as described for
+    /// [`Options::dict_fill`](crate::decompress::Options::dict_fill). Cleared
+    /// by [`reset`](DecoderState::reset).
+    pub fn set_dict_fill(&mut self, fill: Option<u8>) {
+        self.dict_fill = fill;
+        self.output.set_fill(fill);
+    }
+
+    /// Return [`LzmaError::InvalidBackReference`](error::lzma::LzmaError)
+    /// for matches reaching back more than `max_distance` bytes, or accept
+    /// any distance within the dictionary with `None`. Cleared by
+    /// [`reset`](DecoderState::reset).
+    pub fn set_max_reference_distance(&mut self, max_distance: Option<usize>) {
+        self.max_reference_distance = max_distance;
+    }
+
+    /// Return [`Error::OutputTooLarge`](error::Error::OutputTooLarge) once
+    /// more than `limit` bytes are decoded, or decode any length with
+    /// `None`. Cleared by [`reset`](DecoderState::reset).
+    pub fn set_max_output(&mut self, limit: Option<u64>) {
+        self.max_output = limit;
+    }
+
+    /// In [`process`](DecoderState::process), once a stream without a
+    /// declared unpacked size ends, check that `len` bytes were decoded,
+    /// returning
+    /// [`LzmaError::ProcessedDataDoesNotMatchUnpackedSize`](error::lzma::LzmaError)
+    /// otherwise. Cleared by [`reset`](DecoderState::reset).
+    pub fn set_len_after_marker(&mut self, len: Option<u64>) {
+        self.len_after_marker = len;
+    }
+
+    /// In [`process`](DecoderState::process) and the other methods
+    /// decoding to the end, stop without error when the input ends in the
+    /// middle of the stream, keeping the output decoded so far. The symbol
+    /// being decoded when the input ended is dropped, and the decoder must
+    /// be reset before reuse. Cleared by [`reset`](DecoderState::reset).
+    pub fn set_allow_truncated(&mut self, allow_truncated: bool) {
+        self.allow_truncated = allow_truncated;
+    }
+
+    /// Whether decoding stopped early at the end of a truncated input, as
+    /// allowed by [`set_allow_truncated`](DecoderState::set_allow_truncated).
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Total length of the compressed data, for
+    /// [`input_progress_fraction`](DecoderState::input_progress_fraction).
+    /// Cleared by [`reset`](DecoderState::reset).
+    pub fn set_total_input_len(&mut self, len: Option<u64>) {
+        self.total_input_len = len;
+    }
+
+    // Apply the per-stream settings of `options`, after a reset
+    pub(crate) fn set_options(&mut self, options: &Options) {
+        self.set_concatenated(options.concatenated);
+        self.set_strict_canonical(options.strict_canonical);
+        self.set_verify_end_after_size(options.verify_end_after_size);
+        self.set_reject_trailing_data(options.reject_trailing_data);
+        self.set_dict_fill(options.dict_fill);
+        self.set_max_reference_distance(options.max_reference_distance);
+        self.set_max_output(options.max_output);
+        self.set_allow_truncated(options.allow_truncated);
+        self.set_total_input_len(options.total_input_len);
+        if let UnpackedSize::VerifyAfterMarker(len) = options.unpacked_size {
+            self.set_len_after_marker(Some(len));
+        }
+    }
+
+    /// Reset the decoder to its initial state, clearing the dictionary and
+    /// the parameters.
+    pub fn reset(&mut self) {
+        self.reset_probs(0x400, 0x400);
+    }
+
+    /// Like [`reset`](DecoderState::reset), but with the literal coder
+    /// probabilities initialized to `literal_init` and all other
+    /// probabilities to `init`, instead of `0x400` for both, to decode
+    /// streams from encoders using another initialization.
+    ///
+    /// Both values must be > 0 and < `0x800`.
+    pub fn reset_with_initial_probs(&mut self, literal_init: u16, init: u16) -> error::Result<()> {
+        for prob in [literal_init, init] {
+            if prob == 0 || prob >= 0x800 {
+                return Err(error::lzma::LzmaError::InvalidInitialProbability { prob }.into());
+            }
+        }
+        self.reset_probs(literal_init, init);
+        Ok(())
+    }
+
+    /// Reset the probabilities, the state and the LRU distances to those of
+    /// a new stream, but keep the dictionary, the parameters and the
+    /// settings, as done by the LZMA2 chunks resetting the state only.
+    ///
+    /// Unlike [`reset`](DecoderState::reset), this does not initialize a
+    /// new decoder.
+    pub fn reset_keeping_dict(&mut self) {
+        if let ProcessingStatus::Finished = self.processing_status {
+            self.processing_status = ProcessingStatus::Continue;
+        }
+        self.reset_coder(0x400, 0x400);
+    }
+
+    fn reset_probs(&mut self, literal_init: u16, init: u16) {
+        self.processing_status = ProcessingStatus::Continue;
+        self.output.reset();
+        self.params = None;
+        self.reset_coder(literal_init, init);
+        self.concatenated = false;
+        self.strict_canonical = false;
+        self.verify_end_after_size = false;
+        self.reject_trailing_data = false;
+        self.dict_fill = None;
+        self.max_reference_distance = None;
+        self.max_output = None;
+        self.allow_truncated = false;
+        self.truncated = false;
+        self.preset_len = 0;
+        self.len_after_marker = None;
+        self.total_input_len = None;
+        self.input_consumed = 0;
+        self.input_base = 0;
+        #[cfg(feature = "stats")]
+        self.stats.reset();
+        #[cfg(feature = "events")]
+        self.events.reset();
+        #[cfg(feature = "std")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.reset();
+        }
+    }
+
+    fn reset_coder(&mut self, literal_init: u16, init: u16) {
+        self.partial_input_buf = io::Cursor::new([0; INPUT_BUF_SIZE]);
+        self.probs.reset(literal_init, init);
+        self.pos_slot_decoder.iter_mut().for_each(|v| v.reset(init));
+        self.align_decoder.reset(init);
+        self.state = 0;
+        self.rep = [0; 4];
+        self.eos_marker_offset = None;
+        self.len_decoder.reset(init);
+        self.rep_len_decoder.reset(init);
+    }
+
+    /// Histograms of the literals, match lengths and distances decoded since
+    /// the last [`reset`](DecoderState::reset).
+    #[cfg(feature = "stats")]
+    pub fn match_stats(&self) -> &stats::MatchStats {
+        &self.stats
+    }
+
+    /// The last symbols decoded since the last
+    /// [`reset`](DecoderState::reset), including a symbol which failed to
+    /// decode, to inspect the lead-up to an error.
+    #[cfg(feature = "events")]
+    pub fn events(&self) -> &events::EventRing<{ events::EVENT_RING_LEN }> {
+        &self.events
+    }
+
+    /// Call `f` roughly every `interval` while decoding, with the number of
+    /// bytes consumed and decoded and the time elapsed since decoding
+    /// started. The clock is only read every thousand symbols or so, and
+    /// the callback only runs from the decoding methods, so reports may be
+    /// late or, for short calls, skipped.
+    ///
+    /// Unlike the other settings, the callback is kept across
+    /// [`reset`](DecoderState::reset), which only restarts the counts.
+    #[cfg(feature = "std")]
+    pub fn set_metrics_callback(
+        &mut self,
+        interval: std::time::Duration,
+        f: impl FnMut(metrics::DecodeMetrics) + Send + 'static,
+    ) {
+        self.metrics = Some(metrics::MetricsReporter::new(interval, f));
+    }
+
+    /// Remove the callback set with
+    /// [`set_metrics_callback`](DecoderState::set_metrics_callback).
+    #[cfg(feature = "std")]
+    pub fn clear_metrics_callback(&mut self) {
+        self.metrics = None;
+    }
+
+    /// Pass each decoded literal through `f` and use the returned byte
+    /// instead, both in the output and in the dictionary, so that later
+    /// back-references copy the rewritten bytes. This intentionally changes
+    /// the decoded data: the output no longer matches what was compressed.
+    ///
+    /// The literal coder predicts each literal from the preceding byte and,
+    /// after a match, from the byte at the last distance, both read from the
+    /// dictionary. Unless the rewritten bytes leave these predictions
+    /// unchanged, e.g. by keeping the `lc` high bits of each literal in a
+    /// stream without matches, the rest of the stream decodes to garbage or
+    /// fails.
+    ///
+    /// Like the metrics callback, the hook is kept across
+    /// [`reset`](DecoderState::reset).
+    #[cfg(feature = "literal-hook")]
+    pub fn set_literal_hook(&mut self, f: impl FnMut(u8) -> u8 + Send + 'static) {
+        self.literal_hook = Some(util::Callback::new(Box::new(f)));
+    }
+
+    /// Remove the hook set with
+    /// [`set_literal_hook`](DecoderState::set_literal_hook).
+    #[cfg(feature = "literal-hook")]
+    pub fn clear_literal_hook(&mut self) {
+        self.literal_hook = None;
+    }
+
+    /// Ratio of compressed bytes consumed ([`total_in`](DecoderState::total_in))
+    /// to bytes decoded so far, or `None` if nothing has been decoded yet.
+    pub fn observed_ratio(&self) -> Option<f32> {
+        match self.output.len() {
+            0 => None,
+            len => Some(self.total_in() as f32 / len as f32),
+        }
+    }
+
+    /// Fraction of the compressed data consumed since the last
+    /// [`reset`](DecoderState::reset), between 0 and 1, or `None` if no
+    /// [total length](DecoderState::set_total_input_len) was given. Unlike
+    /// the output length, this gives the progress of streams of unknown
+    /// unpacked size.
+    ///
+    /// Only the bytes read by range decoders passed to this decoder are
+    /// counted, so the total must not include the header.
+    pub fn input_progress_fraction(&self) -> Option<f32> {
+        match self.total_input_len {
+            Some(0) | None => None,
+            Some(total) => Some((self.input_consumed as f32 / total as f32).min(1.0)),
+        }
+    }
+
+    /// Number of compressed bytes read from the range decoders passed to this
+    /// decoder since the last [`reset`](DecoderState::reset), across calls
+    /// in streaming mode. The 5 initial bytes of the range coder are counted
+    /// if the range decoder that read them is passed to this decoder.
+    ///
+    /// This includes the bytes held in the partial input buffer (see
+    /// [`pending_input_len`](DecoderState::pending_input_len)), as they were
+    /// taken from the input. Once a stream is fully decoded, this is the
+    /// length of its compressed data after the header, i.e. the offset
+    /// where parsing of the enclosing data resumes.
+    pub fn total_in(&self) -> u64 {
+        self.input_consumed
+    }
+
+    /// Number of bytes decoded since the last
+    /// [`reset`](DecoderState::reset), including those not flushed to the
+    /// output sink yet.
+    pub fn total_out(&self) -> u64 {
+        self.output.len() as u64
+    }
+
+    /// Number of compressed bytes held in the partial input buffer, waiting
+    /// for more input to complete a symbol in streaming mode.
+    pub fn pending_input_len(&self) -> usize {
+        self.partial_input_buf.position() as usize
+    }
+
+    /// Offset in the compressed data, relative to the start of the range
+    /// coder input (i.e. after the header), just past the end-of-stream
+    /// marker, or `None` if no marker has been decoded.
+    pub fn eos_marker_offset(&self) -> Option<u64> {
+        self.eos_marker_offset
+    }
+
+    /// Keep decoding normally but do not write the next `count` decoded bytes
+    /// to the output sink. Skipped bytes still feed the dictionary, so later
+    /// back-references into them are resolved correctly.
+    pub fn skip_output(&mut self, count: u64) {
+        self.output
+            .discard_next(count.min(usize::MAX as u64) as usize);
+    }
+
+    /// Decode the whole stream, checking that it is properly terminated.
+    pub fn process<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+    ) -> error::Result<()> {
+        self.process_mode(output, rangecoder, ProcessingMode::Finish, StopAt::End)?;
+        Ok(())
+    }
+
+    /// Decode until [`output.len()`](LzBuffer::len) reaches `offset` or the
+    /// stream ends, whichever comes first, and return whether the stream
+    /// ended. Decoding stops at a symbol boundary, so the length may exceed
+    /// `offset` by less than the longest match (273 bytes). The decoder can
+    /// then be resumed with another call to this method or to
+    /// [`process`](DecoderState::process).
+    ///
+    /// Decoded bytes are only written to `output` when the dictionary wraps
+    /// around or on [`finish`](LzBuffer::finish).
+    pub fn process_to_offset<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        offset: u64,
+    ) -> error::Result<bool> {
+        self.process_mode(
+            output,
+            rangecoder,
+            ProcessingMode::Finish,
+            StopAt::Offset(offset),
+        )
+    }
+
+    /// Like [`process_to_offset`](DecoderState::process_to_offset), but
+    /// decode for about `budget` at most instead, to bound the latency of
+    /// each call. The clock is only read every thousand symbols or so, and
+    /// at least that many symbols are decoded per call, so calls may exceed
+    /// the budget by the time taken to decode them.
+    #[cfg(feature = "std")]
+    pub fn process_timed<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        budget: std::time::Duration,
+    ) -> error::Result<bool> {
+        let deadline = std::time::Instant::now() + budget;
+        self.process_mode(
+            output,
+            rangecoder,
+            ProcessingMode::Finish,
+            StopAt::Deadline(deadline),
+        )
+    }
+
+    /// Kind of the next symbol, without decoding it. The decoder, the range
+    /// decoder and its input are left untouched.
+    ///
+    /// The few bits coding the kind of the symbol are decoded, without
+    /// updating the probabilities, by a copy of the range decoder's `range`
+    /// and `code`. That copy reads from a lookahead of the next input bytes,
+    /// taken from the bytes buffered in streaming mode then from
+    /// [`fill_buf`](io::BufRead::fill_buf) without consuming them. If fewer
+    /// bytes are available than the bits need, an error is returned.
+    pub fn peek_next_symbol_kind<'a, R: io::BufRead>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+    ) -> error::Result<SymbolKind> {
+        if let ProcessingStatus::Uninitialized = self.processing_status {
+            return Err(error::lzma::LzmaError::DecoderUninitialized.into());
+        }
+        if matches!(self.params, None) {
+            return Err(error::lzma::LzmaError::ParamsNotSet.into());
+        }
+        let pos_state = self.position() & self.pb_mask;
+
+        // Decoding a bit reads at most one byte, and at most 5 bits are
+        // needed, for `Rep(2)` and `Rep(3)`
+        let mut lookahead = [0u8; 5];
+        let buffered = (self.partial_input_buf.position() as usize).min(lookahead.len());
+        lookahead[..buffered].copy_from_slice(&self.partial_input_buf.get_ref()[..buffered]);
+        let buf = util::fill_buf(rangecoder.stream)?;
+        let len = buf.len().min(lookahead.len() - buffered);
+        lookahead[buffered..buffered + len].copy_from_slice(&buf[..len]);
+
+        let mut input = io::Cursor::new(&lookahead[..buffered + len]);
+        let mut rc =
+            rangecoder::RangeDecoder::from_parts(&mut input, rangecoder.range, rangecoder.code);
+        let state = self.state;
+        let kind = if !rc.decode_bit(self.probs.is_match(state, pos_state), false)? {
+            SymbolKind::Literal
+        } else if !rc.decode_bit(self.probs.is_rep(state), false)? {
+            SymbolKind::Match
+        } else if !rc.decode_bit(self.probs.is_rep_g0(state), false)? {
+            if rc.decode_bit(self.probs.is_rep_0long(state, pos_state), false)? {
+                SymbolKind::Rep(0)
+            } else {
+                SymbolKind::ShortRep
+            }
+        } else if !rc.decode_bit(self.probs.is_rep_g1(state), false)? {
+            SymbolKind::Rep(1)
+        } else if !rc.decode_bit(self.probs.is_rep_g2(state), false)? {
+            SymbolKind::Rep(2)
+        } else {
+            SymbolKind::Rep(3)
+        };
+        Ok(kind)
+    }
+
+    /// Decode the next `sample_len` output bytes, or fewer if the stream
+    /// ends first, and return them, e.g. to sniff the content of a stream
+    /// before committing to decompress it.
+    ///
+    /// Bytes the dictionary flushes while sampling are dropped, so this is
+    /// meant to be followed by a [`reset`](DecoderState::reset). To resume
+    /// decoding afterwards, use
+    /// [`decode_prefix_sample_into`](DecoderState::decode_prefix_sample_into)
+    /// instead.
+    #[cfg(feature = "std")]
+    pub fn decode_prefix_sample<'a, R: io::BufRead>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        sample_len: usize,
+    ) -> error::Result<Vec<u8>> {
+        let mut sample = vec![0; sample_len];
+        let len = self.decode_prefix_sample_into(&mut std::io::sink(), rangecoder, &mut sample)?;
+        sample.truncate(len);
+        Ok(sample)
     }
 
-    pub fn process<'a, R: io::BufRead>(
+    /// Like [`decode_prefix_sample`](DecoderState::decode_prefix_sample),
+    /// but copy the sample into `sample` and write the bytes flushed from
+    /// the dictionary to `output`. Returns the number of bytes copied.
+    ///
+    /// As with [`process_to_offset`](DecoderState::process_to_offset),
+    /// decoding stops at a symbol boundary and can be resumed; to discard the
+    /// stream instead, [`reset`](DecoderState::reset) the decoder.
+    pub fn decode_prefix_sample_into<'a, R: io::BufRead>(
         &mut self,
         output: &mut dyn io::Write,
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
-    ) -> error::Result<()> {
-        self.process_mode(output, rangecoder, ProcessingMode::Finish)
+        sample: &mut [u8],
+    ) -> error::Result<usize> {
+        let start = self.output.len();
+        let end = start + sample.len();
+        // The sample is read back from the dictionary, which only holds its
+        // last `dict_size` bytes: decode in steps that the longest match
+        // cannot push out of it. Unless the sample is larger than the
+        // dictionary, this takes a single step.
+        let step = match &self.params {
+            Some(params) => (params.dict_size as usize).saturating_sub(273).max(1),
+            None => 1,
+        };
+        let mut len = start;
+        while len < end {
+            let finished =
+                self.process_to_offset(output, rangecoder, end.min(len + step) as u64)?;
+            let new_len = self.output.len();
+            for pos in len..new_len.min(end) {
+                sample[pos - start] = self.output.last_n(new_len - pos)?;
+            }
+            len = new_len;
+            if finished {
+                break;
+            }
+        }
+        Ok(len.min(end) - start)
     }
 
+    /// Decode as much of the available input as possible, buffering an
+    /// incomplete trailing symbol until more input is provided.
     #[cfg(feature = "stream")]
     pub fn process_stream<'a, R: io::BufRead>(
         &mut self,
         output: &mut dyn io::Write,
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
     ) -> error::Result<()> {
-        self.process_mode(output, rangecoder, ProcessingMode::Partial)
+        self.process_mode(output, rangecoder, ProcessingMode::Partial, StopAt::End)?;
+        Ok(())
+    }
+
+    /// Signal that no more input will follow what `rangecoder` holds, after
+    /// calls to [`process_stream`](DecoderState::process_stream): decode the
+    /// remaining input, including any buffered incomplete symbol, check that
+    /// the stream is properly terminated and flush the dictionary to
+    /// `output`.
+    ///
+    /// Fails with an [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) I/O
+    /// error if the input, buffered or not, ends in the middle of a symbol,
+    /// unless [truncated input](DecoderState::set_allow_truncated) is allowed.
+    ///
+    /// The decoder must be [`reset`](DecoderState::reset) before decoding
+    /// another stream.
+    #[cfg(feature = "stream")]
+    pub fn signal_eof<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+    ) -> error::Result<()> {
+        self.process_mode(output, rangecoder, ProcessingMode::Finish, StopAt::End)?;
+        self.output.finish(output)?;
+        Ok(())
     }
 
     /// Process the next iteration of the loop.
@@ -255,25 +1419,38 @@ where
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
         update: bool,
     ) -> error::Result<ProcessingStatus> {
-        let params = match &self.params {
-            Some(v) => v.clone(),
-            None => panic!(
-                "DecoderState::params is not initialized; call `DecoderState::set_params` first"
-            ),
-        };
-        let pos_state = self.output.len() & ((1 << params.pb) - 1);
+        if matches!(self.params, None) {
+            return Err(error::lzma::LzmaError::ParamsNotSet.into());
+        }
+        let pos_state = self.position() & self.pb_mask;
+        #[cfg(feature = "events")]
+        let (offset, state) = (self.output.len() as u64, self.state as u8);
 
         // Literal
         if !rangecoder.decode_bit(
             // TODO: assumes pb = 2 ??
-            &mut self.is_match[(self.state << 4) + pos_state],
+            self.probs.is_match(self.state, pos_state),
             update,
         )? {
-            let byte: u8 = self.decode_literal(rangecoder, update)?;
+            #[cfg_attr(not(feature = "literal-hook"), allow(unused_mut))]
+            let mut byte: u8 = self.decode_literal(rangecoder, update)?;
 
             if update {
+                #[cfg(feature = "literal-hook")]
+                if let Some(hook) = &mut self.literal_hook {
+                    byte = (hook.get_mut())(byte);
+                }
                 lzma_debug!("Literal: {}", byte);
                 self.output.append_literal(output, byte)?;
+                self.trace.on_literal(byte);
+                #[cfg(feature = "stats")]
+                self.stats.record_literal();
+                #[cfg(feature = "events")]
+                self.events.record(events::Event {
+                    offset,
+                    state,
+                    kind: events::EventKind::Literal(byte),
+                });
 
                 self.state = if self.state < 4 {
                     0
@@ -289,33 +1466,53 @@ where
         // LZ
         let mut len: usize;
         // Distance is repeated from LRU
-        if rangecoder.decode_bit(&mut self.is_rep[self.state], update)? {
+        if rangecoder.decode_bit(self.probs.is_rep(self.state), update)? {
             // dist = rep[0]
-            if !rangecoder.decode_bit(&mut self.is_rep_g0[self.state], update)? {
+            if !rangecoder.decode_bit(self.probs.is_rep_g0(self.state), update)? {
                 // len = 1
-                if !rangecoder.decode_bit(
-                    &mut self.is_rep_0long[(self.state << 4) + pos_state],
-                    update,
-                )? {
+                if !rangecoder.decode_bit(self.probs.is_rep_0long(self.state, pos_state), update)? {
                     // update state (short rep)
                     if update {
                         self.state = if self.state < 7 { 9 } else { 11 };
                         let dist = self.rep[0] + 1;
+                        self.check_reference_distance(dist)?;
+                        // The only copy shorter than a match
                         self.output.append_lz(output, 1, dist)?;
+                        self.trace.on_match(1, dist);
+                        #[cfg(feature = "stats")]
+                        {
+                            self.stats.record_match(1);
+                            self.stats.record_rep(0);
+                        }
+                        #[cfg(feature = "events")]
+                        self.events.record(events::Event {
+                            offset,
+                            state,
+                            kind: events::EventKind::ShortRep { distance: dist },
+                        });
                     }
                     return Ok(ProcessingStatus::Continue);
                 }
+                #[cfg(feature = "stats")]
+                if update {
+                    self.stats.record_rep(0);
+                }
             // dist = rep[i]
             } else {
                 let idx: usize;
-                if !rangecoder.decode_bit(&mut self.is_rep_g1[self.state], update)? {
+                if !rangecoder.decode_bit(self.probs.is_rep_g1(self.state), update)? {
                     idx = 1;
-                } else if !rangecoder.decode_bit(&mut self.is_rep_g2[self.state], update)? {
+                } else if !rangecoder.decode_bit(self.probs.is_rep_g2(self.state), update)? {
                     idx = 2;
                 } else {
                     idx = 3;
                 }
                 if update {
+                    if self.strict_canonical {
+                        self.check_rep_canonical(idx)?;
+                    }
+                    #[cfg(feature = "stats")]
+                    self.stats.record_rep(idx);
                     // Update LRU
                     let dist = self.rep[idx];
                     for i in (0..idx).rev() {
@@ -333,6 +1530,7 @@ where
             }
         // New distance
         } else {
+            let rep_3 = self.rep[3];
             if update {
                 // Update LRU
                 self.rep[3] = self.rep[2];
@@ -352,20 +1550,62 @@ where
             if update {
                 self.rep[0] = rep_0;
                 if self.rep[0] == 0xFFFF_FFFF {
-                    if rangecoder.is_finished_ok()? {
+                    self.eos_marker_offset = Some(rangecoder.bytes_consumed());
+                    #[cfg(feature = "events")]
+                    self.events.record(events::Event {
+                        offset,
+                        state,
+                        kind: events::EventKind::EndMarker,
+                    });
+                    if rangecoder.is_finished_ok()? || (self.concatenated && rangecoder.code == 0) {
                         self.processing_status = ProcessingStatus::Finished;
                         return Ok(ProcessingStatus::Finished);
                     }
-                    return Err(error::lzma::LzmaError::EosFoundButMoreBytesAvailable.into());
+                    return Err(error::lzma::LzmaError::EosFoundButMoreBytesAvailable {
+                        input_offset: self.input_offset(rangecoder),
+                        output_offset: self.output.len() as u64,
+                    }
+                    .into());
+                }
+                #[cfg(feature = "stats")]
+                self.stats.record_distance(rep_0);
+                if self.strict_canonical {
+                    // The previous reps have been shifted by one
+                    let reps = [self.rep[1], self.rep[2], self.rep[3], rep_3];
+                    self.check_new_distance_canonical(rep_0, &reps)?;
                 }
             }
         }
 
         if update {
+            // The length coders decode 0 to 271, so matches copy 2 to 273
+            // bytes: no input, however corrupt, yields an empty copy
             len += 2;
+            debug_assert!(len >= 1);
 
             let dist = self.rep[0] + 1;
+            self.check_reference_distance(dist)?;
             self.output.append_lz(output, len, dist)?;
+            self.trace.on_match(len, dist);
+            #[cfg(feature = "stats")]
+            self.stats.record_match(len);
+            // Only new distances lead to states 7 and 10
+            #[cfg(feature = "events")]
+            self.events.record(events::Event {
+                offset,
+                state,
+                kind: if self.state == 7 || self.state == 10 {
+                    events::EventKind::Match {
+                        len,
+                        distance: dist,
+                    }
+                } else {
+                    events::EventKind::Rep {
+                        len,
+                        distance: dist,
+                    }
+                },
+            });
         }
 
         Ok(ProcessingStatus::Continue)
@@ -376,7 +1616,72 @@ where
         output: &mut dyn io::Write,
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
     ) -> error::Result<ProcessingStatus> {
-        self.process_next_inner(output, rangecoder, true)
+        let range_normalized = rangecoder.range >= 0x0100_0000;
+        #[cfg(feature = "events")]
+        let (offset, state) = (self.output.len() as u64, self.state as u8);
+        let status = self.process_next_inner(output, rangecoder, true);
+        #[cfg(feature = "events")]
+        if status.is_err() {
+            self.events.record(events::Event {
+                offset,
+                state,
+                kind: events::EventKind::Error,
+            });
+        }
+        let status = status?;
+        if status == ProcessingStatus::Continue {
+            self.debug_check_invariants(rangecoder, range_normalized);
+        }
+        Ok(status)
+    }
+
+    /// Check the decoder invariants after a symbol was decoded, in debug
+    /// builds only:
+    /// - the state machine is in one of its 12 states,
+    /// - the cached `lc` and position masks are within the header limits,
+    ///   and the literal coder contexts they select exist in the
+    ///   probability model,
+    /// - every rep distance fits in the dictionary,
+    /// - the range stays normalized (at least `0x0100_0000`) if it was before
+    ///   the symbol, which is the case unless the range decoder was built
+    ///   from bogus parts.
+    ///
+    /// These hold for any input, provided the decoder is reset after an
+    /// error; a failure is a bug in the decoder.
+    #[inline]
+    fn debug_check_invariants<'a, R: io::BufRead>(
+        &self,
+        rangecoder: &rangecoder::RangeDecoder<'a, R>,
+        range_normalized: bool,
+    ) {
+        debug_assert!(self.state < 12, "invalid state {}", self.state);
+        debug_assert!(
+            self.lc <= 8 && self.lp_mask < 1 << 4 && self.pb_mask < 1 << 4,
+            "invalid lc {} or position masks {:#x}, {:#x}",
+            self.lc,
+            self.lp_mask,
+            self.pb_mask
+        );
+        debug_assert!(
+            1 << (self.lc + self.lp_mask.count_ones()) <= self.probs.literal_states(),
+            "literal contexts beyond the {} of the probability model",
+            self.probs.literal_states()
+        );
+        if let Some(params) = &self.params {
+            debug_assert!(
+                self.rep
+                    .iter()
+                    .all(|&rep| (rep as u64) < u64::from(params.dict_size)),
+                "rep distances {:?} beyond dictionary size {}",
+                self.rep,
+                params.dict_size
+            );
+        }
+        debug_assert!(
+            !range_normalized || rangecoder.range >= 0x0100_0000,
+            "range {:08x} not normalized",
+            rangecoder.range
+        );
     }
 
     /// Try to process the next iteration of the loop.
@@ -411,22 +1716,72 @@ where
         Ok(())
     }
 
+    // Number of compressed bytes taken from the input since the last reset,
+    // while `rangecoder` is being used by `process_mode`.
+    fn input_offset<'a, R: io::BufRead>(
+        &self,
+        rangecoder: &rangecoder::RangeDecoder<'a, R>,
+    ) -> u64 {
+        self.input_consumed + rangecoder.bytes_consumed().saturating_sub(self.input_base)
+    }
+
     fn process_mode<'a, R: io::BufRead>(
         &mut self,
         output: &mut dyn io::Write,
-        mut rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
         mode: ProcessingMode,
-    ) -> error::Result<()> {
+        stop_at: StopAt,
+    ) -> error::Result<bool> {
+        // The first range decoder after a reset is counted from its start,
+        // to include the bytes read when creating it.
+        self.input_base = match self.input_consumed {
+            0 => 0,
+            _ => rangecoder.bytes_consumed(),
+        };
+        #[cfg(feature = "std")]
+        if let Some(metrics) = &mut self.metrics {
+            metrics.begin();
+        }
+        let finish = mode == ProcessingMode::Finish;
+        let res = self.process_mode_inner(output, rangecoder, mode, stop_at);
+        self.input_consumed += rangecoder.bytes_consumed().saturating_sub(self.input_base);
+        self.input_base = rangecoder.bytes_consumed();
+        match res {
+            Err(error::Error::IoError(e))
+                if self.allow_truncated && finish && e.kind() == io::ErrorKind::UnexpectedEof =>
+            {
+                self.truncated = true;
+                self.processing_status = ProcessingStatus::Finished;
+                Ok(true)
+            }
+            res => res,
+        }
+    }
+
+    fn process_mode_inner<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        mode: ProcessingMode,
+        stop_at: StopAt,
+    ) -> error::Result<bool> {
         if let ProcessingStatus::Uninitialized = self.processing_status {
-            panic!("DecoderState is uninitialized; call `DecoderState::reset` first");
+            return Err(error::lzma::LzmaError::DecoderUninitialized.into());
         }
         let params = match &self.params {
             Some(v) => v.clone(),
-            None => panic!(
-                "DecoderState::params is not initialized; call `DecoderState::set_params` first"
-            ),
+            None => return Err(error::lzma::LzmaError::ParamsNotSet.into()),
         };
+        // Decode a batch of symbols before the first check, so that every
+        // call makes progress
+        #[cfg(feature = "std")]
+        let mut deadline_countdown = DEADLINE_CHECK_INTERVAL;
         loop {
+            if let Some(limit) = self.max_output {
+                if self.output.len() as u64 > limit {
+                    return Err(error::Error::OutputTooLarge { limit });
+                }
+            }
             if let Some(unpacked_size) = params.unpacked_size {
                 if self.output.len() as u64 >= unpacked_size {
                     break;
@@ -441,6 +1796,29 @@ where
             } {
                 break;
             }
+            match stop_at {
+                StopAt::End => {}
+                StopAt::Offset(offset) => {
+                    if self.output.len() as u64 >= offset {
+                        return Ok(false);
+                    }
+                }
+                #[cfg(feature = "std")]
+                StopAt::Deadline(deadline) => {
+                    if deadline_countdown > 0 {
+                        deadline_countdown -= 1;
+                    } else if std::time::Instant::now() >= deadline {
+                        return Ok(false);
+                    } else {
+                        deadline_countdown = DEADLINE_CHECK_INTERVAL;
+                    }
+                }
+            }
+            #[cfg(feature = "std")]
+            if let Some(metrics) = &mut self.metrics {
+                let consumed = rangecoder.bytes_consumed().saturating_sub(self.input_base);
+                metrics.tick(self.input_consumed + consumed, self.output.len() as u64);
+            }
 
             if self.partial_input_buf.position() as usize > 0 {
                 self.read_partial_input_buf(rangecoder)?;
@@ -448,7 +1826,7 @@ where
 
                 // Check if we need more data to advance the decompressor
                 if mode == ProcessingMode::Partial
-                    && (self.partial_input_buf.position() as usize) < MAX_REQUIRED_INPUT
+                    && (self.partial_input_buf.position() as usize) < INPUT_BUF_SIZE
                     && self
                         .try_process_next(
                             output,
@@ -458,7 +1836,7 @@ where
                         )
                         .is_err()
                 {
-                    return Ok(());
+                    return Ok(false);
                 }
 
                 // Run the decompressor on the tmp buffer
@@ -469,7 +1847,39 @@ where
                     rangecoder.range,
                     rangecoder.code,
                 );
-                let res = self.process_next(output, &mut tmp_rangecoder)?;
+                let eos_marker_found = matches!(self.eos_marker_offset, Some(_));
+                let res = self.process_next(output, &mut tmp_rangecoder);
+
+                // The temporary range decoder only counts from the start of
+                // the partial input buffer. Part of the buffer may have been
+                // read by an earlier range decoder, in which case the offset
+                // cannot go below the start of `rangecoder`.
+                if !eos_marker_found {
+                    if let Some(offset) = self.eos_marker_offset.as_mut() {
+                        *offset += rangecoder
+                            .bytes_consumed()
+                            .saturating_sub(self.partial_input_buf.position());
+                    }
+                }
+                // Likewise, the input offset of an error is made relative to
+                // the actual range decoder, minus the unread buffered bytes.
+                let res = match res {
+                    Err(error::Error::LzmaError(
+                        error::lzma::LzmaError::EosFoundButMoreBytesAvailable {
+                            output_offset, ..
+                        },
+                    )) => {
+                        let code = tmp_rangecoder.code;
+                        let unread = self.partial_input_buf.position() - tmp_reader.position();
+                        self.check_no_data_after_marker(rangecoder, mode, code, unread)?;
+                        return Err(error::lzma::LzmaError::EosFoundButMoreBytesAvailable {
+                            input_offset: self.input_offset(rangecoder) - unread,
+                            output_offset,
+                        }
+                        .into());
+                    }
+                    res => res?,
+                };
 
                 // Update the actual rangecoder
                 rangecoder.set(tmp_rangecoder.range, tmp_rangecoder.code);
@@ -485,55 +1895,222 @@ where
                     break;
                 };
             } else {
-                let buf: &[u8] = rangecoder.stream.fill_buf()?;
+                let buf: &[u8] = util::fill_buf(rangecoder.stream)?;
                 if mode == ProcessingMode::Partial
-                    && buf.len() < MAX_REQUIRED_INPUT
+                    && buf.len() < INPUT_BUF_SIZE
                     && self
                         .try_process_next(output, buf, rangecoder.range, rangecoder.code)
                         .is_err()
                 {
-                    return self.read_partial_input_buf(rangecoder);
+                    self.read_partial_input_buf(rangecoder)?;
+                    return Ok(false);
                 }
 
-                if self.process_next(output, &mut rangecoder)? == ProcessingStatus::Finished {
+                if self.process_next_checked(output, rangecoder, mode)?
+                    == ProcessingStatus::Finished
+                {
                     break;
                 };
             }
         }
 
+        if let (None, Some(len)) = (&params.unpacked_size, self.len_after_marker) {
+            if mode == ProcessingMode::Finish && len != self.output.len() as u64 {
+                return Err(
+                    error::lzma::LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
+                        unpacked_size: len,
+                        decompressed_data: self.output.len(),
+                        input_offset: self.input_offset(rangecoder),
+                    }
+                    .into(),
+                );
+            }
+        }
         if let Some(unpacked_size) = params.unpacked_size {
+            if mode == ProcessingMode::Finish
+                && self.verify_end_after_size
+                && unpacked_size == self.output.len() as u64
+            {
+                self.verify_end(output, rangecoder, unpacked_size)?;
+            }
             if mode == ProcessingMode::Finish && unpacked_size != self.output.len() as u64 {
                 return Err(
                     error::lzma::LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
                         unpacked_size,
                         decompressed_data: self.output.len(),
+                        input_offset: self.input_offset(rangecoder),
                     }
                     .into(),
                 );
             }
+            if mode == ProcessingMode::Finish && self.reject_trailing_data && !self.concatenated {
+                if self.partial_input_buf.position() == 0
+                    && rangecoder.code != 0
+                    && !rangecoder.is_eof()?
+                {
+                    // An end marker may follow the declared size
+                    self.verify_end(output, rangecoder, unpacked_size)?;
+                }
+                self.check_no_trailing_data(rangecoder)?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    // Check that the stream ends after `unpacked_size` bytes, possibly with
+    // an end marker
+    fn verify_end<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        unpacked_size: u64,
+    ) -> error::Result<()> {
+        if self.partial_input_buf.position() == 0 && rangecoder.is_finished_ok()? {
+            return Ok(());
+        }
+        if self.partial_input_buf.position() == 0
+            && self.process_next_checked(output, rangecoder, ProcessingMode::Finish)?
+                == ProcessingStatus::Finished
+        {
+            return Ok(());
+        }
+        Err(error::lzma::LzmaError::TrailingDataAfterDeclaredSize { unpacked_size }.into())
+    }
+
+    // Decode the next symbol, checking input left after an end marker with
+    // `check_no_data_after_marker`
+    fn process_next_checked<'a, R: io::BufRead>(
+        &mut self,
+        output: &mut dyn io::Write,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        mode: ProcessingMode,
+    ) -> error::Result<ProcessingStatus> {
+        match self.process_next(output, rangecoder) {
+            Err(
+                err @ error::Error::LzmaError(
+                    error::lzma::LzmaError::EosFoundButMoreBytesAvailable { .. },
+                ),
+            ) => {
+                self.check_no_data_after_marker(rangecoder, mode, rangecoder.code, 0)?;
+                Err(err)
+            }
+            res => res,
+        }
+    }
+
+    // With `reject_trailing_data`, report input left after an end marker as
+    // `TrailingData` when decoding to the end, as after the declared size.
+    // `code` is the state of the range decoder after the marker, and
+    // `unread` the number of bytes left in the partial input buffer.
+    fn check_no_data_after_marker<'a, R: io::BufRead>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        mode: ProcessingMode,
+        code: u32,
+        unread: u64,
+    ) -> error::Result<()> {
+        if mode == ProcessingMode::Finish
+            && self.reject_trailing_data
+            && !self.concatenated
+            && code == 0
+        {
+            self.partial_input_buf.set_position(unread);
+            self.check_no_trailing_data(rangecoder)?;
         }
+        Ok(())
+    }
 
+    // Fail if input is left, counting it by reading it to the end
+    fn check_no_trailing_data<'a, R: io::BufRead>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+    ) -> error::Result<()> {
+        let mut bytes_remaining = self.partial_input_buf.position();
+        loop {
+            let len = util::fill_buf(rangecoder.stream)?.len();
+            if len == 0 {
+                break;
+            }
+            rangecoder.stream.consume(len);
+            bytes_remaining += len as u64;
+        }
+        if bytes_remaining > 0 {
+            return Err(error::lzma::LzmaError::TrailingData { bytes_remaining }.into());
+        }
         Ok(())
     }
 
+    fn check_rep_canonical(&self, idx: usize) -> error::Result<()> {
+        let earlier: Option<usize> = self.rep[..idx]
+            .iter()
+            .position(|&rep| rep == self.rep[idx])
+            .into();
+        match earlier {
+            Some(earlier) => Err(error::lzma::LzmaError::NonCanonicalEncoding(
+                error::lzma::NonCanonical::RedundantRep {
+                    distance: self.rep[idx] + 1,
+                    index: idx,
+                    earlier,
+                },
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    fn check_new_distance_canonical(&self, rep_0: usize, reps: &[usize; 4]) -> error::Result<()> {
+        // Reps are only meaningful once they point inside the output
+        let index: Option<usize> = reps
+            .iter()
+            .position(|&rep| rep == rep_0 && rep < self.position())
+            .into();
+        match index {
+            Some(index) => Err(error::lzma::LzmaError::NonCanonicalEncoding(
+                error::lzma::NonCanonical::NewDistanceIsRep {
+                    distance: rep_0 + 1,
+                    index,
+                },
+            )
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    // Position in the dictionary, counted from the start of the preset
+    // dictionary
+    fn position(&self) -> usize {
+        self.output.len() + self.preset_len
+    }
+
+    fn check_reference_distance(&self, distance: usize) -> error::Result<()> {
+        match self.max_reference_distance {
+            Some(max_distance) if distance > max_distance => {
+                Err(error::lzma::LzmaError::InvalidBackReference {
+                    distance,
+                    max_distance,
+                }
+                .into())
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn decode_literal<'a, R: io::BufRead>(
         &mut self,
         rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
         update: bool,
     ) -> error::Result<u8> {
-        let params = match &self.params {
-            Some(v) => v.clone(),
-            None => panic!(
-                "DecoderState::params is not initialized; call `DecoderState::set_params` first"
-            ),
+        let def_prev_byte = match self.dict_fill {
+            Some(fill) => fill,
+            None => 0u8,
         };
-        let def_prev_byte = 0u8;
         let prev_byte = self.output.last_or(def_prev_byte) as usize;
 
         let mut result: usize = 1;
-        let lit_state = ((self.output.len() & ((1 << params.lp) - 1)) << params.lc)
-            + (prev_byte >> (8 - params.lc));
-        let probs = &mut self.literal_probs[lit_state];
+        let lit_state =
+            ((self.position() & self.lp_mask) << self.lc) + (prev_byte >> (8 - self.lc));
+        let probs = self.probs.literal_probs(lit_state);
 
         if self.state >= 7 {
             let mut match_byte = self.output.last_n(self.rep[0] + 1)? as usize;
@@ -571,13 +2148,15 @@ where
             return Ok(pos_slot);
         }
 
+        // Slots from 4 come in pairs sharing a number of direct bits, the
+        // low bit of the slot being the bit below the leading one
         let num_direct_bits = (pos_slot >> 1) - 1;
-        let mut result = (2 ^ (pos_slot & 1)) << num_direct_bits;
+        let mut result = (2 | (pos_slot & 1)) << num_direct_bits;
 
         if pos_slot < 14 {
             result += rangecoder.parse_reverse_bit_tree(
                 num_direct_bits,
-                &mut self.pos_decoders,
+                self.probs.pos_decoders(),
                 result - pos_slot,
                 update,
             )? as usize;
@@ -589,3 +2168,542 @@ where
         Ok(result)
     }
 }
+
+impl<S, const PROBS_MEM_LIMIT: usize, PM, TS, const INPUT_BUF_SIZE: usize>
+    DecoderState<lzbuffer::CircularBuffer<S>, PROBS_MEM_LIMIT, PM, TS, INPUT_BUF_SIZE>
+where
+    S: AsRef<[u8]> + AsMut<[u8]>,
+    PM: ProbModel,
+    TS: TraceSink,
+{
+    /// Like [`process`](DecoderState::process) followed by
+    /// [`finish`](LzBuffer::finish), but hand the decoded bytes to `f`
+    /// instead of writing them to a [`Write`](io::Write). Each time the
+    /// dictionary fills up, and once at the end, its new bytes are passed to
+    /// `f` as slices borrowed from it, without copying. The bytes and their
+    /// order are the same as with the `Write` path:
+    /// [skipped](DecoderState::skip_output) bytes are left out.
+    pub fn process_with<'a, R: io::BufRead, F: FnMut(&[u8])>(
+        &mut self,
+        rangecoder: &mut rangecoder::RangeDecoder<'a, R>,
+        f: F,
+    ) -> error::Result<()> {
+        let mut sink = FnWriter(f);
+        self.process(&mut sink, rangecoder)?;
+        self.output.finish(&mut sink)?;
+        Ok(())
+    }
+}
+
+// Sink of `process_with`, handing the slices flushed from the dictionary to
+// the closure
+struct FnWriter<F>(F);
+
+impl<F: FnMut(&[u8])> io::Write for FnWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            (self.0)(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<S, const PROBS_MEM_LIMIT: usize, TS, const INPUT_BUF_SIZE: usize>
+    DecoderState<
+        lzbuffer::CircularBuffer<S>,
+        PROBS_MEM_LIMIT,
+        probs::ArrayProbModel<PROBS_MEM_LIMIT>,
+        TS,
+        INPUT_BUF_SIZE,
+    >
+where
+    S: AsRef<[u8]> + AsMut<[u8]>,
+    TS: TraceSink,
+{
+    /// Take a [`Checkpoint`](checkpoint::Checkpoint) of the decoder and of
+    /// `rangecoder`, the range decoder last passed to it, from which
+    /// decoding can be resumed with [`restore`](DecoderState::restore).
+    ///
+    /// Decoding stops between symbols, so a checkpoint can be taken after
+    /// any call to [`process_to_offset`](DecoderState::process_to_offset)
+    /// or [`process_stream`](DecoderState::process_stream). The parameters
+    /// must be set.
+    pub fn checkpoint<'a, R: io::BufRead>(
+        &self,
+        rangecoder: &rangecoder::RangeDecoder<'a, R>,
+    ) -> error::Result<checkpoint::Checkpoint> {
+        if let ProcessingStatus::Uninitialized = self.processing_status {
+            return Err(error::lzma::LzmaError::DecoderUninitialized.into());
+        }
+        let params = match &self.params {
+            Some(params) => params.clone(),
+            None => return Err(error::lzma::LzmaError::ParamsNotSet.into()),
+        };
+        let mut literal_probs = Vec::new();
+        let mut probs = Vec::new();
+        self.save_probs(&params, &mut literal_probs, &mut probs);
+        let (range, code) = rangecoder.state();
+        let pending = self.partial_input_buf.position() as usize;
+        Ok(checkpoint::Checkpoint {
+            params,
+            finished: self.processing_status == ProcessingStatus::Finished,
+            literal_probs,
+            probs,
+            state: self.state,
+            rep: self.rep,
+            range,
+            code,
+            pending_input: self.partial_input_buf.get_ref()[..pending].to_vec(),
+            window: self.output.save_window()?,
+            eos_marker_offset: self.eos_marker_offset,
+            concatenated: self.concatenated,
+            strict_canonical: self.strict_canonical,
+            verify_end_after_size: self.verify_end_after_size,
+            reject_trailing_data: self.reject_trailing_data,
+            max_reference_distance: self.max_reference_distance,
+            max_output: self.max_output,
+            allow_truncated: self.allow_truncated,
+            len_after_marker: self.len_after_marker,
+            total_input_len: self.total_input_len,
+            input_consumed: self.input_offset(rangecoder),
+        })
+    }
+
+    /// Reset the decoder to the state saved in `checkpoint`, e.g. after
+    /// deserializing it. Decoding resumes with a range decoder created by
+    /// [`Checkpoint::range_decoder`](checkpoint::Checkpoint::range_decoder).
+    ///
+    /// Returns [`LzmaError::InvalidCheckpoint`](error::lzma::LzmaError) if
+    /// the checkpoint is inconsistent, and the errors of
+    /// [`set_params`](DecoderState::set_params) if its parameters do not
+    /// fit in this decoder. The decoder must then be reset before reuse.
+    pub fn restore(&mut self, checkpoint: &checkpoint::Checkpoint) -> error::Result<()> {
+        self.reset();
+        self.set_params(checkpoint.params.clone())?;
+
+        // The probabilities saved by a fresh decoder with the same
+        // parameters give the expected lengths
+        let mut literal_probs = Vec::new();
+        let mut probs = Vec::new();
+        self.save_probs(&checkpoint.params, &mut literal_probs, &mut probs);
+        if checkpoint.literal_probs.len() != literal_probs.len()
+            || checkpoint.probs.len() != probs.len()
+            || checkpoint
+                .literal_probs
+                .iter()
+                .chain(checkpoint.probs.iter())
+                .any(|&prob| prob >= 0x800)
+            || checkpoint.state >= 12
+            || checkpoint.pending_input.len() > INPUT_BUF_SIZE
+        {
+            return Err(error::lzma::LzmaError::InvalidCheckpoint.into());
+        }
+        self.output.load_window(&checkpoint.window)?;
+
+        let mut literal_probs = checkpoint.literal_probs.iter();
+        let mut probs = checkpoint.probs.iter();
+        self.probs.load_probs(&mut literal_probs, &mut probs);
+        self.pos_slot_decoder
+            .iter_mut()
+            .for_each(|v| v.load_probs(&mut probs));
+        self.align_decoder.load_probs(&mut probs);
+        self.len_decoder.load_probs(&mut probs);
+        self.rep_len_decoder.load_probs(&mut probs);
+
+        if checkpoint.finished {
+            self.processing_status = ProcessingStatus::Finished;
+        }
+        self.state = checkpoint.state;
+        self.rep = checkpoint.rep;
+        let pending = checkpoint.pending_input.len();
+        self.partial_input_buf.get_mut()[..pending].copy_from_slice(&checkpoint.pending_input);
+        self.partial_input_buf.set_position(pending as u64);
+        self.eos_marker_offset = checkpoint.eos_marker_offset;
+        self.concatenated = checkpoint.concatenated;
+        self.strict_canonical = checkpoint.strict_canonical;
+        self.verify_end_after_size = checkpoint.verify_end_after_size;
+        self.reject_trailing_data = checkpoint.reject_trailing_data;
+        self.dict_fill = checkpoint.window.fill;
+        self.max_reference_distance = checkpoint.max_reference_distance;
+        self.max_output = checkpoint.max_output;
+        self.allow_truncated = checkpoint.allow_truncated;
+        self.preset_len = checkpoint.window.preset_len;
+        self.len_after_marker = checkpoint.len_after_marker;
+        self.total_input_len = checkpoint.total_input_len;
+        self.input_consumed = checkpoint.input_consumed;
+        Ok(())
+    }
+
+    // Save the probabilities of the literal coder contexts used with
+    // `params`, then the other probabilities in declaration order
+    fn save_probs(&self, params: &LzmaParams, literal: &mut Vec<u16>, other: &mut Vec<u16>) {
+        self.probs
+            .save_probs(1 << (params.lc + params.lp), literal, other);
+        self.pos_slot_decoder
+            .iter()
+            .for_each(|v| v.save_probs(other));
+        self.align_decoder.save_probs(other);
+        self.len_decoder.save_probs(other);
+        self.rep_len_decoder.save_probs(other);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::decode::lzbuffer::LzBuffer;
+    use crate::encode::rangecoder::RangeEncoder;
+
+    // Probabilities mirroring the decoder's distance coders for `len_state`
+    // 0, as the encoder has to update them the same way.
+    struct DistanceProbs {
+        pos_slot: [u16; 64],
+        pos_decoders: [u16; 115],
+        align: [u16; 16],
+    }
+
+    fn pos_slot(distance: u32) -> u32 {
+        if distance < 4 {
+            return distance;
+        }
+        let bits = 31 - distance.leading_zeros();
+        2 * bits + ((distance >> (bits - 1)) & 1)
+    }
+
+    fn encode_distance<W: io::Write>(
+        encoder: &mut RangeEncoder<W>,
+        probs: &mut DistanceProbs,
+        distance: u32,
+    ) -> io::Result<()> {
+        let slot = pos_slot(distance);
+        let mut tmp = 1;
+        for i in (0..6).rev() {
+            let bit = (slot >> i) & 1;
+            encoder.encode_bit(&mut probs.pos_slot[tmp], bit != 0)?;
+            tmp = (tmp << 1) | bit as usize;
+        }
+        if slot < 4 {
+            return Ok(());
+        }
+
+        let num_direct_bits = (slot >> 1) as usize - 1;
+        let base = (2 | (slot & 1)) << num_direct_bits;
+        let (rem, num_bits, probs, offset) = if slot < 14 {
+            let offset = (base - slot) as usize;
+            (
+                distance - base,
+                num_direct_bits,
+                &mut probs.pos_decoders[..],
+                offset,
+            )
+        } else {
+            encoder.encode_direct_bits((distance - base) >> 4, num_direct_bits - 4)?;
+            (distance & 0xF, 4, &mut probs.align[..], 0)
+        };
+        let mut tmp = 1;
+        for i in 0..num_bits {
+            let bit = (rem >> i) & 1;
+            encoder.encode_bit(&mut probs[offset + tmp], bit != 0)?;
+            tmp = (tmp << 1) | bit as usize;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn decode_largest_distances() {
+        let mut distances = vec![
+            0x7FFF_FFFF,
+            0x8000_0000,
+            0xBFFF_FFFF,
+            0xC000_0000,
+            0xFFFF_FFF0,
+            0xFFFF_FFFE,
+            0xFFFF_FFFF,
+        ];
+        // First and last distance of every slot.
+        for slot in 0..64u32 {
+            if slot < 4 {
+                distances.push(slot);
+            } else {
+                let num_direct_bits = (slot >> 1) - 1;
+                let base = (2u64 | (slot & 1) as u64) << num_direct_bits;
+                distances.push(base as u32);
+                distances.push((base + (1 << num_direct_bits) - 1) as u32);
+            }
+        }
+
+        let mut compressed = Vec::new();
+        let mut probs = DistanceProbs {
+            pos_slot: [0x400; 64],
+            pos_decoders: [0x400; 115],
+            align: [0x400; 16],
+        };
+        let mut encoder = RangeEncoder::new(&mut compressed);
+        for &distance in &distances {
+            encode_distance(&mut encoder, &mut probs, distance).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let mut decoder = DecoderState::<lzbuffer::LzCircularBuffer<0>, 1>::new();
+        decoder.reset();
+        let mut input = compressed.as_slice();
+        let mut rangecoder = rangecoder::RangeDecoder::new(&mut input).unwrap();
+        for &distance in &distances {
+            let decoded = decoder.decode_distance(&mut rangecoder, 0, true).unwrap();
+            assert_eq!(decoded, distance as usize, "{:#x}", distance);
+        }
+    }
+
+    #[test]
+    fn distance_slot_bases() {
+        // The first distance of every slot follows the last one of the
+        // previous slot, up to 2^32 - 1 for the last slot.
+        let mut next = 4u64;
+        for slot in 4..64usize {
+            let num_direct_bits = (slot >> 1) - 1;
+            let base = (2 | (slot & 1) as u64) << num_direct_bits;
+            assert_eq!(base, next, "slot {}", slot);
+            assert_eq!(pos_slot(base as u32), slot as u32);
+            next = base + (1 << num_direct_bits);
+        }
+        assert_eq!(next, 1 << 32);
+    }
+
+    #[test]
+    fn decode_distance_slots_from_stream() {
+        // Slots of the distances decoded from a stream of liblzma
+        struct Slots([bool; 64]);
+
+        impl TraceSink for Slots {
+            fn on_match(&mut self, _len: usize, dist: usize) {
+                self.0[pos_slot(dist as u32 - 1) as usize] = true;
+            }
+        }
+
+        let compressed = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut input = &compressed[..];
+        let params = LzmaParams::read_header(&mut input, &Options::default()).unwrap();
+        let mut decoder =
+            DecoderState::<lzbuffer::LzCircularBuffer<4096>, 8, _, _>::with_trace_sink(Slots(
+                [false; 64],
+            ));
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        let mut rangecoder = rangecoder::RangeDecoder::new(&mut input).unwrap();
+        let mut output = Vec::new();
+        decoder.process(&mut output, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut output).unwrap();
+        assert_eq!(output, &expected[..]);
+
+        // Every slot up to the dictionary size is used, including both
+        // slots of every pair from 14, which code the low bits with the
+        // align decoder
+        let slots = &decoder.trace_sink().0;
+        assert!(slots[..24].iter().all(|&used| used));
+    }
+
+    #[test]
+    fn append_lz_at_dictionary_size() {
+        const DICT_SIZE: usize = 1 << 16;
+        let mut buffer = lzbuffer::LzCircularBuffer::<DICT_SIZE>::new();
+        buffer.set_dict_size(DICT_SIZE).unwrap();
+        let mut output = Vec::new();
+        for i in 0..DICT_SIZE + 7 {
+            buffer.append_literal(&mut output, (i % 251) as u8).unwrap();
+        }
+
+        // The oldest byte still in the dictionary.
+        let oldest = buffer.last_n(DICT_SIZE).unwrap();
+        assert_eq!(oldest, 7);
+        buffer.append_lz(&mut output, 3, DICT_SIZE).unwrap();
+        assert_eq!(buffer.last_n(3).unwrap(), oldest);
+
+        for distance in [DICT_SIZE + 1, 0xFFFF_FFFF, usize::MAX] {
+            assert!(buffer.append_lz(&mut output, 1, distance).is_err());
+            assert!(buffer.last_n(distance).is_err());
+        }
+    }
+
+    #[test]
+    fn window_wraps_around() {
+        let mut buffer = lzbuffer::LzCircularBuffer::<8>::new();
+        assert_eq!(buffer.window(), (&[][..], &[][..]));
+        buffer.set_dict_size(6).unwrap();
+        let mut output = Vec::new();
+        buffer.append_literal(&mut output, b'a').unwrap();
+        buffer.append_literal(&mut output, b'b').unwrap();
+        buffer.append_lz(&mut output, 3, 2).unwrap();
+        assert_eq!(buffer.window(), (&b"ababa"[..], &[][..]));
+
+        // The ring wraps around after 6 bytes
+        buffer.append_lz(&mut output, 3, 2).unwrap();
+        assert_eq!(output, b"ababab");
+        assert_eq!(buffer.window(), (&b"abab"[..], &b"ab"[..]));
+        let (older, newer) = buffer.window();
+        let window = [older, newer].concat();
+        for (dist, &byte) in window.iter().rev().enumerate() {
+            assert_eq!(buffer.last_n(dist + 1).unwrap(), byte);
+        }
+
+        // With a fill, the whole dictionary can be referenced
+        buffer.reset();
+        buffer.set_dict_size(4).unwrap();
+        buffer.set_fill(Some(b'x'));
+        buffer.append_literal(&mut output, b'c').unwrap();
+        assert_eq!(buffer.window(), (&b"xxx"[..], &b"c"[..]));
+    }
+
+    // Records the length of every copy
+    struct CopyLens {
+        inner: lzbuffer::LzCircularBuffer<4096>,
+        lens: Vec<usize>,
+    }
+
+    impl LzBuffer for CopyLens {
+        fn set_dict_size(&mut self, dict_size: usize) -> error::Result<()> {
+            self.inner.set_dict_size(dict_size)
+        }
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+        fn last_or(&self, lit: u8) -> u8 {
+            self.inner.last_or(lit)
+        }
+        fn last_n(&self, dist: usize) -> error::Result<u8> {
+            self.inner.last_n(dist)
+        }
+        fn append_literal(&mut self, stream: &mut dyn io::Write, lit: u8) -> error::Result<()> {
+            self.inner.append_literal(stream, lit)
+        }
+        fn append_lz(
+            &mut self,
+            stream: &mut dyn io::Write,
+            len: usize,
+            dist: usize,
+        ) -> error::Result<()> {
+            self.lens.push(len);
+            self.inner.append_lz(stream, len, dist)
+        }
+        fn discard_next(&mut self, count: usize) {
+            self.inner.discard_next(count)
+        }
+        fn finish(&mut self, stream: &mut dyn io::Write) -> io::Result<()> {
+            self.inner.finish(stream)
+        }
+        fn set_fill(&mut self, fill: Option<u8>) {
+            self.inner.set_fill(fill)
+        }
+        fn set_preset(&mut self, dict: &[u8]) -> error::Result<usize> {
+            self.inner.set_preset(dict)
+        }
+        fn reset(&mut self) {
+            self.inner.reset()
+        }
+    }
+
+    fn copy_lens_decoder() -> DecoderState<CopyLens, 8> {
+        let mut decoder = DecoderState::with_prob_model(
+            CopyLens {
+                inner: lzbuffer::LzCircularBuffer::new(),
+                lens: Vec::new(),
+            },
+            probs::ArrayProbModel::new(),
+        );
+        decoder.reset();
+        decoder
+    }
+
+    #[test]
+    fn shortest_copies() {
+        // A match of the shortest length (0 from the length coder) at
+        // distance 1, then a short rep. Every probability is used for the
+        // first time, so each bit is coded with a fresh one.
+        let bits = [
+            true, false, false, false, false, false, // new match of length 2
+            false, false, false, false, false, false, // distance 1
+            true, true, false, false, // short rep
+        ];
+        let mut compressed = Vec::new();
+        let mut encoder = RangeEncoder::new(&mut compressed);
+        for &bit in &bits {
+            encoder.encode_bit(&mut 0x400, bit).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let mut decoder = copy_lens_decoder();
+        decoder
+            .set_params(LzmaParams {
+                lc: 0,
+                lp: 0,
+                pb: 0,
+                dict_size: 4096,
+                dict_size_provided: 4096,
+                unpacked_size: Some(3),
+            })
+            .unwrap();
+        // The match reaches before the start of the output
+        decoder.set_dict_fill(Some(b'x'));
+        let mut input = compressed.as_slice();
+        let mut rangecoder = rangecoder::RangeDecoder::new(&mut input).unwrap();
+        let mut output = Vec::new();
+        decoder.process(&mut output, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut output).unwrap();
+        assert_eq!(output, b"xxx");
+        assert_eq!(decoder.output.lens, [2, 1]);
+    }
+
+    #[test]
+    fn no_empty_copy_from_corrupt_input() {
+        let compressed = include_bytes!("../../tests/files/dict-wraparound.lzma");
+        let mut rejected = 0;
+        for pos in (13..compressed.len()).step_by(7) {
+            let mut corrupt = compressed.to_vec();
+            corrupt[pos] ^= 0xA5;
+            let mut input = corrupt.as_slice();
+            let params = LzmaParams::read_header(&mut input, &Options::default()).unwrap();
+            let mut decoder = copy_lens_decoder();
+            decoder.set_params(params).unwrap();
+            let mut rangecoder = rangecoder::RangeDecoder::new(&mut input).unwrap();
+            if decoder.process(&mut Vec::new(), &mut rangecoder).is_err() {
+                rejected += 1;
+            }
+            assert!(!decoder.output.lens.contains(&0), "corrupt byte {}", pos);
+        }
+        assert!(rejected > 0);
+    }
+
+    #[test]
+    fn peek_rep3_with_minimum_input() {
+        let mut decoder = DecoderState::<lzbuffer::LzCircularBuffer<4096>, 8>::new();
+        decoder.reset();
+        decoder
+            .set_params(LzmaParams::new(3, 0, 2, 4096, None).unwrap())
+            .unwrap();
+        // With probabilities this skewed against them, each of the 5 bits of
+        // a `Rep(3)` shrinks the range below 2^24, so it reads a byte
+        *decoder.probs.is_match(0, 0) = 0x7FF;
+        *decoder.probs.is_rep(0) = 0x7FF;
+        *decoder.probs.is_rep_g0(0) = 0x7FF;
+        *decoder.probs.is_rep_g1(0) = 0x7FF;
+        *decoder.probs.is_rep_g2(0) = 0x7FF;
+        let input = [0xFF; 5];
+        let mut short = &input[..4];
+        let mut rangecoder =
+            rangecoder::RangeDecoder::from_parts(&mut short, 0x0100_0000, 0x00FF_FFFF);
+        assert!(decoder.peek_next_symbol_kind(&mut rangecoder).is_err());
+        let mut exact = &input[..];
+        let mut rangecoder =
+            rangecoder::RangeDecoder::from_parts(&mut exact, 0x0100_0000, 0x00FF_FFFF);
+        assert_eq!(
+            decoder.peek_next_symbol_kind(&mut rangecoder).unwrap(),
+            SymbolKind::Rep(3)
+        );
+        assert_eq!(exact.len(), 5);
+    }
+}
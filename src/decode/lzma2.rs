@@ -0,0 +1,356 @@
+//! LZMA2 chunk framing, as found in XZ blocks and 7z archives.
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{self, DecoderState, LzmaParams};
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::util;
+use crate::error;
+use crate::error::lzma2::Lzma2Error;
+use crate::io::{self, BufRead, Read};
+use crate::option::GuaranteedOption::*;
+use byteorder::BigEndian;
+use io::ReadBytesExt;
+
+/// LZMA2 decoder, parsing the chunk framing and delegating the LZMA chunks
+/// to a [`DecoderState`].
+///
+/// An LZMA2 stream is a sequence of chunks, each starting with a control
+/// byte:
+/// - `0x00` ends the stream,
+/// - `0x01` and `0x02` start an uncompressed chunk, with and without a
+///   dictionary reset, copied as is to the output and the dictionary,
+/// - `0x80` to `0xFF` start an LZMA chunk. Bits 5 and 6 tell what is reset
+///   before it: nothing, the state, the state and the properties, or all of
+///   them and the dictionary. The low 5 bits are the high bits of the
+///   unpacked size.
+///
+/// The dictionary size is not part of the stream but of its container, e.g.
+/// the filter properties of an XZ block (see
+/// [`lzma2_dict_size_from_byte`](lzma::lzma2_dict_size_from_byte)).
+pub struct Lzma2Decoder<LZB, const PROBS_MEM_LIMIT: usize>
+where
+    LZB: LzBuffer,
+{
+    lzma: DecoderState<LZB, PROBS_MEM_LIMIT>,
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    Lzma2Decoder<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    /// Create a decoder with an inline dictionary of `DICT_MEM_LIMIT` bytes.
+    pub const fn new() -> Self {
+        Self {
+            lzma: DecoderState::new(),
+        }
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for Lzma2Decoder<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize> core::fmt::Debug for Lzma2Decoder<LZB, PROBS_MEM_LIMIT>
+where
+    LZB: LzBuffer + core::fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("Lzma2Decoder")
+            .field("lzma", &self.lzma)
+            .finish()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize> Lzma2Decoder<LZB, PROBS_MEM_LIMIT>
+where
+    LZB: LzBuffer,
+{
+    /// Create a decoder delegating the LZMA chunks to `lzma`, e.g. to use
+    /// another dictionary buffer. The decoder is reset before each stream.
+    pub fn with_decoder(lzma: DecoderState<LZB, PROBS_MEM_LIMIT>) -> Self {
+        Self { lzma }
+    }
+
+    /// Decompress an LZMA2 stream up to and including its end chunk, with a
+    /// dictionary of `dict_size` bytes.
+    ///
+    /// The input is left right after the end chunk.
+    pub fn decompress<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+        dict_size: u32,
+    ) -> error::Result<()> {
+        self.decompress_chunks(input, output, dict_size, false)
+    }
+
+    // Decompress chunks up to the end chunk, or up to the end of the input
+    // too with `until_eof`, as for a segment
+    fn decompress_chunks<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+        dict_size: u32,
+        until_eof: bool,
+    ) -> error::Result<()> {
+        let dict_len = lzma::dict_len(dict_size)?;
+        self.lzma.reset();
+        self.lzma.output.set_dict_size(dict_len)?;
+        let mut need_dict_reset = true;
+        let mut need_props = true;
+        loop {
+            if until_eof && util::is_eof(input)? {
+                break;
+            }
+            let control = input.read_u8()?;
+            lzma_debug!("LZMA2 control byte: {:02x}", control);
+            if control == 0x00 {
+                break;
+            }
+            if (0x03..0x80).contains(&control) {
+                return Err(Lzma2Error::InvalidControlByte { control }.into());
+            }
+
+            if resets_dictionary(control) {
+                // The dictionary reset also requires new properties
+                need_dict_reset = false;
+                need_props = true;
+                self.lzma.output.finish(output)?;
+                self.lzma.output.set_dict_size(dict_len)?;
+            } else if need_dict_reset {
+                return Err(Lzma2Error::MissingDictionaryReset { control }.into());
+            }
+
+            if control < 0x80 {
+                let size = input.read_u16::<BigEndian>()? as usize + 1;
+                self.copy_uncompressed(input, output, size)?;
+                continue;
+            }
+
+            let unpacked_size =
+                (((control & 0x1F) as u64) << 16) + input.read_u16::<BigEndian>()? as u64 + 1;
+            let packed_size = input.read_u16::<BigEndian>()? as u32 + 1;
+            if control >= 0xC0 {
+                need_props = false;
+                self.lzma.reset_keeping_dict();
+                self.set_properties(input, dict_size)?;
+            } else if need_props {
+                return Err(Lzma2Error::MissingProperties { control }.into());
+            } else if control >= 0xA0 {
+                self.lzma.reset_keeping_dict();
+            }
+            self.decode_chunk(input, output, unpacked_size, packed_size)?;
+        }
+        self.lzma.output.finish(output)?;
+        Ok(())
+    }
+
+    // Read the properties byte of an LZMA chunk
+    fn set_properties<R: BufRead>(&mut self, input: &mut R, dict_size: u32) -> error::Result<()> {
+        let (lc, lp, pb) = lzma::read_properties(input)?;
+        if lc + lp > 4 {
+            return Err(Lzma2Error::InvalidLiteralProperties { lc, lp }.into());
+        }
+        self.lzma
+            .set_params(LzmaParams::from_fields(lc, lp, pb, dict_size, None))
+    }
+
+    fn decode_chunk<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+        unpacked_size: u64,
+        packed_size: u32,
+    ) -> error::Result<()> {
+        // The decoder stops once the dictionary holds the chunk
+        let mut params = match &self.lzma.params {
+            Some(v) => v.clone(),
+            None => return Err(error::lzma::LzmaError::ParamsNotSet.into()),
+        };
+        params.unpacked_size = Some(self.lzma.output.len() as u64 + unpacked_size);
+        self.lzma.set_params(params)?;
+
+        // Each chunk starts a new range coder
+        let mut chunk = Read::take(&mut *input, packed_size as u64);
+        let mut rangecoder = RangeDecoder::new(&mut chunk)
+            .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
+        self.lzma.process(output, &mut rangecoder)?;
+        if !rangecoder.is_finished_ok()? {
+            return Err(Lzma2Error::ChunkSizeMismatch { packed_size }.into());
+        }
+        Ok(())
+    }
+
+    fn copy_uncompressed<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+        mut size: usize,
+    ) -> error::Result<()> {
+        while size > 0 {
+            let buf = util::fill_buf(input)?;
+            if buf.is_empty() {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            let count = size.min(buf.len());
+            for &byte in &buf[..count] {
+                self.lzma.output.append_literal(output, byte)?;
+            }
+            input.consume(count);
+            size -= count;
+        }
+        Ok(())
+    }
+}
+
+// Whether the chunk starting with `control` resets the dictionary
+fn resets_dictionary(control: u8) -> bool {
+    control >= 0xE0 || control == 0x01
+}
+
+// Parse the header of the chunk at `pos` and return its control byte, its
+// length, header included, and its unpacked size
+fn chunk_at(input: &[u8], pos: usize) -> error::Result<(u8, usize, u64)> {
+    let mut header = &input[pos..];
+    let control = header.read_u8()?;
+    let (len, unpacked_size) = match control {
+        0x00 => (1, 0),
+        0x01 | 0x02 => {
+            let size = header.read_u16::<BigEndian>()? as usize + 1;
+            (3 + size, size as u64)
+        }
+        0x03..=0x7F => return Err(Lzma2Error::InvalidControlByte { control }.into()),
+        _ => {
+            let unpacked_size =
+                (((control & 0x1F) as u64) << 16) + header.read_u16::<BigEndian>()? as u64 + 1;
+            let packed_size = header.read_u16::<BigEndian>()? as usize + 1;
+            let header_len = if control >= 0xC0 { 6 } else { 5 };
+            (header_len + packed_size, unpacked_size)
+        }
+    };
+    if len > input.len() - pos {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok((control, len, unpacked_size))
+}
+
+/// Iterator over the segments of an LZMA2 stream held in memory, for random
+/// access: each segment starts at a chunk resetting the dictionary, and
+/// runs up to the next one, so that it can be decoded on its own.
+///
+/// Only the chunk headers are parsed, the data is not decoded. Iteration
+/// stops after the end chunk, or after the first error.
+#[derive(Clone, Debug)]
+pub struct Lzma2Segments<'a> {
+    input: &'a [u8],
+    pos: usize,
+    dict_size: u32,
+    uncompressed_offset: u64,
+    done: bool,
+}
+
+impl<'a> Lzma2Segments<'a> {
+    /// Iterate over the segments of the LZMA2 stream at the start of
+    /// `input`, decoded with a dictionary of `dict_size` bytes.
+    pub fn new(input: &'a [u8], dict_size: u32) -> Self {
+        Self {
+            input,
+            pos: 0,
+            dict_size,
+            uncompressed_offset: 0,
+            done: false,
+        }
+    }
+
+    /// Number of bytes of the input parsed so far, i.e. the length of the
+    /// stream, end chunk included, once the iteration is over.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    fn next_segment(&mut self) -> error::Result<Option<Lzma2Segment<'a>>> {
+        let start = self.pos;
+        let uncompressed_offset = self.uncompressed_offset;
+        loop {
+            let (control, len, unpacked_size) = chunk_at(self.input, self.pos)?;
+            if control == 0x00 {
+                if self.pos == start {
+                    self.pos += len;
+                    return Ok(Option::None);
+                }
+                break;
+            }
+            if resets_dictionary(control) {
+                if self.pos > start {
+                    break;
+                }
+            } else if self.pos == start {
+                return Err(Lzma2Error::MissingDictionaryReset { control }.into());
+            }
+            self.pos += len;
+            self.uncompressed_offset += unpacked_size;
+        }
+        Ok(Option::Some(Lzma2Segment {
+            data: &self.input[start..self.pos],
+            dict_size: self.dict_size,
+            uncompressed_offset,
+            uncompressed_len: self.uncompressed_offset - uncompressed_offset,
+        }))
+    }
+}
+
+impl<'a> Iterator for Lzma2Segments<'a> {
+    type Item = error::Result<Lzma2Segment<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return Option::None;
+        }
+        let res = self.next_segment();
+        self.done = !matches!(res, Ok(Option::Some(_)));
+        res.transpose()
+    }
+}
+
+/// Segment of an LZMA2 stream yielded by [`Lzma2Segments`]: the chunks from
+/// a dictionary reset up to the next one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lzma2Segment<'a> {
+    data: &'a [u8],
+    dict_size: u32,
+    uncompressed_offset: u64,
+    uncompressed_len: u64,
+}
+
+impl<'a> Lzma2Segment<'a> {
+    /// The chunks of the segment, without an end chunk.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Offset of the output of the segment in the output of the stream.
+    pub fn uncompressed_offset(&self) -> u64 {
+        self.uncompressed_offset
+    }
+
+    /// Number of bytes the segment decodes to.
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// Decompress the segment on its own with `decoder`, from a fresh
+    /// dictionary.
+    pub fn decompress<LZB, const PROBS_MEM_LIMIT: usize>(
+        &self,
+        decoder: &mut Lzma2Decoder<LZB, PROBS_MEM_LIMIT>,
+        output: &mut dyn io::Write,
+    ) -> error::Result<()>
+    where
+        LZB: LzBuffer,
+    {
+        decoder.decompress_chunks(&mut &self.data[..], output, self.dict_size, true)
+    }
+}
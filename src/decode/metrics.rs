@@ -0,0 +1,80 @@
+//! Periodic throughput reports for long-running decodes.
+
+use crate::decode::util::Callback;
+use std::time::{Duration, Instant};
+
+/// Progress of a decode, reported to the callback set with
+/// [`DecoderState::set_metrics_callback`](super::lzma::DecoderState::set_metrics_callback).
+/// All values are counted since the decoder was last reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeMetrics {
+    /// Compressed bytes consumed by the range decoders.
+    pub input_bytes: u64,
+    /// Bytes decoded.
+    pub output_bytes: u64,
+    /// Time since decoding started.
+    pub elapsed: Duration,
+}
+
+// Number of decoded symbols between two reads of the clock, to keep the
+// overhead per symbol negligible.
+const CHECK_INTERVAL: u32 = 1024;
+
+pub(crate) struct MetricsReporter {
+    interval: Duration,
+    callback: Callback<dyn FnMut(DecodeMetrics) + Send>,
+    start: Option<Instant>,
+    last_report: Duration,
+    countdown: u32,
+}
+
+impl MetricsReporter {
+    pub(crate) fn new(
+        interval: Duration,
+        callback: impl FnMut(DecodeMetrics) + Send + 'static,
+    ) -> Self {
+        Self {
+            interval,
+            callback: Callback::new(Box::new(callback)),
+            start: None,
+            last_report: Duration::ZERO,
+            countdown: 0,
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.start = None;
+        self.last_report = Duration::ZERO;
+        self.countdown = 0;
+    }
+
+    // Called when decoding starts or resumes.
+    pub(crate) fn begin(&mut self) {
+        self.start.get_or_insert_with(Instant::now);
+    }
+
+    // Called before each symbol.
+    #[inline]
+    pub(crate) fn tick(&mut self, input_bytes: u64, output_bytes: u64) {
+        if self.countdown > 0 {
+            self.countdown -= 1;
+            return;
+        }
+        self.countdown = CHECK_INTERVAL;
+
+        let elapsed = match self.start {
+            Some(start) => start.elapsed(),
+            None => return,
+        };
+        if elapsed.saturating_sub(self.last_report) < self.interval {
+            return;
+        }
+        self.last_report = elapsed;
+        let metrics = DecodeMetrics {
+            input_bytes,
+            output_bytes,
+            elapsed,
+        };
+        (self.callback.get_mut())(metrics);
+    }
+}
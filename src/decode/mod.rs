@@ -1,10 +1,32 @@
 //! Decoding logic.
 
+#[cfg(feature = "tokio")]
+pub mod async_reader;
+#[cfg(feature = "serde")]
+pub mod checkpoint;
+#[cfg(feature = "std")]
+pub mod chunks;
+#[cfg(feature = "events")]
+pub mod events;
+pub mod filter;
+pub mod format;
 pub mod lzbuffer;
+pub mod lzip;
 pub mod lzma;
+pub mod lzma2;
+#[cfg(feature = "std")]
+pub mod metrics;
 pub mod options;
+pub mod probs;
 pub mod rangecoder;
+#[cfg(feature = "stream")]
+pub mod reader;
+mod sha256;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod trace;
 pub mod util;
+pub mod xz;
 
 #[cfg(feature = "stream")]
 pub mod stream;
@@ -1,5 +1,4 @@
 use crate::option::GuaranteedOption as Option;
-use crate::option::GuaranteedOption::*;
 /// Options to tweak decompression behavior.
 #[derive(Clone, Copy, Debug, PartialEq, Default)]
 pub struct Options {
@@ -8,6 +7,92 @@ pub struct Options {
     /// The default is
     /// [`UnpackedSize::ReadFromHeader`](enum.UnpackedSize.html#variant.ReadFromHeader).
     pub unpacked_size: UnpackedSize,
+    /// `(lc, lp, pb)` to decode with instead of the properties byte of the
+    /// header, e.g. to recover data from an encoder writing a wrong one. As
+    /// with [`UnpackedSize::ReadHeaderButUseProvided`], the byte is still
+    /// read, but otherwise ignored even when invalid. The provided values
+    /// take precedence over the header in every member, and are checked
+    /// when the decoder parameters are set: `lc <= 8`, `lp <= 4`, `pb <= 4`
+    /// and `1 << (lc + lp)` within `PROBS_MEM_LIMIT`.
+    ///
+    /// The default is `None`, i.e. the properties of the header.
+    pub properties: Option<(u32, u32, u32)>,
+    /// Whether to decode a sequence of concatenated `.lzma` members, each
+    /// with its own header, instead of a single one. Only supported by
+    /// [`lzma_decompress_with_options`](crate::lzma_decompress_with_options).
+    ///
+    /// The default is `false`.
+    pub concatenated: bool,
+    /// Whether to reject streams using constructs a canonical encoder would
+    /// not produce, such as a new distance equal to a rep distance. Such
+    /// streams are otherwise decoded normally.
+    ///
+    /// The default is `false`.
+    pub strict_canonical: bool,
+    /// Whether to check, once the declared unpacked size has been decoded,
+    /// that the stream ends there, either at the end of input or with an
+    /// end marker. Otherwise decoding simply stops at the declared size.
+    ///
+    /// The default is `false`.
+    pub verify_end_after_size: bool,
+    /// Whether to reject input left once the declared unpacked size has
+    /// been decoded, possibly followed by an end marker, with
+    /// [`LzmaError::TrailingData`](crate::error::lzma::LzmaError::TrailingData),
+    /// e.g. to validate that a file holds exactly one stream. Input after an
+    /// end marker is always rejected, and reported with the same error when
+    /// this is set. Only checked when decoding to the end,
+    /// as a `Stream` stops taking input at the end of the stream instead, and
+    /// ignored when decoding concatenated members.
+    ///
+    /// The default is `false`.
+    pub reject_trailing_data: bool,
+    /// Byte assumed to fill the dictionary before the start of the output,
+    /// for streams compressed against such a fill. With `Some(fill)`, the
+    /// first literal is decoded as if preceded by `fill`, and matches may
+    /// reach up to the dictionary size before the start of the output,
+    /// copying `fill`.
+    ///
+    /// The default is `None`, i.e. standard LZMA: the first literal is
+    /// decoded as if preceded by a zero byte, and matches reaching before
+    /// the start of the output are an error.
+    pub dict_fill: Option<u8>,
+    /// Largest distance in bytes matches may reach back, for formats
+    /// restricting references to a window smaller than the dictionary.
+    /// Longer matches are rejected with
+    /// [`LzmaError::InvalidBackReference`](crate::error::lzma::LzmaError::InvalidBackReference).
+    ///
+    /// The default is `None`, i.e. matches may reach back up to the
+    /// dictionary size.
+    pub max_reference_distance: Option<usize>,
+    /// Length of the compressed data following the header, e.g. the file
+    /// length minus the header length, to report the input progress of
+    /// streams of unknown unpacked size with
+    /// [`DecoderState::input_progress_fraction`](crate::decompress::raw::DecoderState::input_progress_fraction).
+    ///
+    /// The default is `None`, i.e. unknown.
+    pub total_input_len: Option<u64>,
+    /// Largest number of bytes to decode, to bound the memory and time
+    /// spent on untrusted data whatever unpacked size its header declares.
+    /// Decoding stops with
+    /// [`Error::OutputTooLarge`](crate::error::Error::OutputTooLarge) as
+    /// soon as the output exceeds it, checked once per symbol in every mode,
+    /// across the writes of a `Stream` too. The limit covers all members
+    /// when decoding concatenated members.
+    ///
+    /// The default is `None`, i.e. no limit.
+    #[doc(alias = "max_decompressed")]
+    pub max_output: Option<u64>,
+    /// Whether to stop cleanly when the input ends in the middle of the
+    /// stream, keeping the bytes decoded so far, as when recovering data
+    /// from a partial download. Only the end of the input is tolerated:
+    /// corrupt data still fails with an [`LzmaError`](crate::error::lzma::LzmaError).
+    /// The end of the input within the header of a member is tolerated by
+    /// [`lzma_decompress_with_options`](crate::lzma_decompress_with_options)
+    /// too. See
+    /// [`DecoderState::is_truncated`](crate::decompress::raw::DecoderState::is_truncated).
+    ///
+    /// The default is `false`.
+    pub allow_truncated: bool,
 }
 
 /// Alternatives for defining the unpacked size of the decoded data.
@@ -30,6 +115,10 @@ pub enum UnpackedSize {
     /// the header. Use the provided value.
     /// If the provided value is `None`, assume that there is an end-of-payload marker in the file.
     UseProvided(Option<u64>),
+    /// Assume that the 8 bytes used to specify the unpacked size are present in the header, but
+    /// ignore them and decode up to the end-of-payload marker. Then check that the unpacked size
+    /// is the provided value, e.g. known from a manifest, to detect truncated data.
+    VerifyAfterMarker(u64),
 }
 
 impl Default for UnpackedSize {
@@ -43,10 +132,74 @@ impl Options {
     pub const fn default() -> Self {
         Self {
             unpacked_size: UnpackedSize::default(),
+            properties: Option::None,
+            concatenated: false,
+            strict_canonical: false,
+            verify_end_after_size: false,
+            reject_trailing_data: false,
+            dict_fill: Option::None,
+            max_reference_distance: Option::None,
+            total_input_len: Option::None,
+            max_output: Option::None,
+            allow_truncated: false,
+        }
+    }
+
+    /// Start an [`OptionsBuilder`] from the default options.
+    pub const fn builder() -> OptionsBuilder {
+        OptionsBuilder {
+            options: Options::default(),
         }
     }
 }
 
+/// Chainable constructor of [`Options`], for the most common settings.
+/// Other fields keep their default value and can still be set on the built
+/// [`Options`].
+///
+/// ```
+/// use lzma_rs::decompress::{Options, UnpackedSize};
+///
+/// let options = Options::builder()
+///     .unpacked_size(UnpackedSize::ReadHeaderButUseProvided(Some(1024).into()))
+///     .memlimit(1 << 20)
+///     .allow_truncated(true)
+///     .build();
+/// assert_eq!(options.max_output, Some(1 << 20).into());
+/// assert!(options.allow_truncated);
+/// assert!(!options.concatenated);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    /// Set [`Options::unpacked_size`].
+    pub fn unpacked_size(mut self, unpacked_size: UnpackedSize) -> Self {
+        self.options.unpacked_size = unpacked_size;
+        self
+    }
+
+    /// Limit the output to `limit` bytes, and so the memory of the decoders
+    /// writing to memory, by setting [`Options::max_output`].
+    pub fn memlimit(mut self, limit: u64) -> Self {
+        self.options.max_output = Option::Some(limit);
+        self
+    }
+
+    /// Set [`Options::allow_truncated`].
+    pub fn allow_truncated(mut self, allow_truncated: bool) -> Self {
+        self.options.allow_truncated = allow_truncated;
+        self
+    }
+
+    /// Get the options.
+    pub fn build(self) -> Options {
+        self.options
+    }
+}
+
 impl UnpackedSize {
     /// Const replacement for [`Default::default`]
     pub const fn default() -> Self {
@@ -62,6 +215,16 @@ mod test {
         assert_eq!(
             Options {
                 unpacked_size: UnpackedSize::ReadFromHeader,
+                properties: Option::None,
+                concatenated: false,
+                strict_canonical: false,
+                verify_end_after_size: false,
+                reject_trailing_data: false,
+                dict_fill: Option::None,
+                max_reference_distance: Option::None,
+                total_input_len: Option::None,
+                max_output: Option::None,
+                allow_truncated: false,
             },
             Options::default()
         );
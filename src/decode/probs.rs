@@ -0,0 +1,216 @@
+//! Storage of the adaptive bit probabilities used by the LZMA decoder.
+
+/// Access to the probability tables of a
+/// [`DecoderState`](super::lzma::DecoderState).
+///
+/// Each method returns the probability (on 11 bits, initially `0x400`) the
+/// range decoder reads and updates for one coded bit, so implementations are
+/// free to choose their memory layout. `state` is below 12 and `pos_state`
+/// below 16.
+pub trait ProbModel {
+    /// Set the literal coder probabilities to `literal_init` and all other
+    /// probabilities to `init`. Standard LZMA uses `0x400` for both.
+    fn reset(&mut self, literal_init: u16, init: u16);
+    /// Number of literal coder contexts available, i.e. the largest
+    /// supported `1 << (lc + lp)`.
+    fn literal_states(&self) -> usize;
+    /// Whether the next symbol is a match rather than a literal.
+    fn is_match(&mut self, state: usize, pos_state: usize) -> &mut u16;
+    /// Whether a match reuses one of the last 4 distances.
+    fn is_rep(&mut self, state: usize) -> &mut u16;
+    /// Whether a rep match uses another distance than `rep[0]`.
+    fn is_rep_g0(&mut self, state: usize) -> &mut u16;
+    /// Whether a rep match uses another distance than `rep[1]`.
+    fn is_rep_g1(&mut self, state: usize) -> &mut u16;
+    /// Whether a rep match uses `rep[3]` rather than `rep[2]`.
+    fn is_rep_g2(&mut self, state: usize) -> &mut u16;
+    /// Whether a `rep[0]` match is longer than a single byte.
+    fn is_rep_0long(&mut self, state: usize, pos_state: usize) -> &mut u16;
+    /// The `0x300` probabilities of the literal coder for `lit_state`, below
+    /// [`literal_states`](ProbModel::literal_states).
+    fn literal_probs(&mut self, lit_state: usize) -> &mut [u16; 0x300];
+    /// The probabilities of the low bits of distances in slots 4 to 13.
+    fn pos_decoders(&mut self) -> &mut [u16; 115];
+}
+
+/// Number of literal coder contexts used by streams with the given `lc` and
+/// `lp`, i.e. `1 << (lc + lp)`: the exact `PROBS_MEM_LIMIT` for them.
+pub const fn probs_mem_limit(lc: u32, lp: u32) -> usize {
+    1 << (lc + lp)
+}
+
+// Fails to compile when evaluated with a `PROBS_MEM_LIMIT` not matching `LC`
+// and `LP`.
+pub(crate) struct AssertProps<const PROBS_MEM_LIMIT: usize, const LC: u32, const LP: u32>;
+
+impl<const PROBS_MEM_LIMIT: usize, const LC: u32, const LP: u32>
+    AssertProps<PROBS_MEM_LIMIT, LC, LP>
+{
+    pub(crate) const OK: () = assert!(
+        LC <= 8 && LP <= 4 && PROBS_MEM_LIMIT == probs_mem_limit(LC, LP),
+        "PROBS_MEM_LIMIT must be 1 << (LC + LP), with LC <= 8 and LP <= 4"
+    );
+}
+
+/// Default [`ProbModel`], storing each table in its own array.
+#[derive(Clone)]
+pub struct ArrayProbModel<const PROBS_MEM_LIMIT: usize> {
+    literal_probs: [[u16; 0x300]; PROBS_MEM_LIMIT],
+    pos_decoders: [u16; 115],
+    is_match: [u16; 192], // true = LZ, false = literal
+    is_rep: [u16; 12],
+    is_rep_g0: [u16; 12],
+    is_rep_g1: [u16; 12],
+    is_rep_g2: [u16; 12],
+    is_rep_0long: [u16; 192],
+}
+
+impl<const PROBS_MEM_LIMIT: usize> ArrayProbModel<PROBS_MEM_LIMIT> {
+    /// Create a model with zeroed probabilities; [`ProbModel::reset`] must
+    /// be called before use.
+    pub const fn new() -> Self {
+        Self {
+            literal_probs: [[0; 0x300]; PROBS_MEM_LIMIT],
+            pos_decoders: [0; 115],
+            is_match: [0; 192],
+            is_rep: [0; 12],
+            is_rep_g0: [0; 12],
+            is_rep_g1: [0; 12],
+            is_rep_g2: [0; 12],
+            is_rep_0long: [0; 192],
+        }
+    }
+}
+
+// Checkpoints hold the probabilities of the literal coder contexts in use,
+// and the other ones in declaration order
+#[cfg(feature = "serde")]
+impl<const PROBS_MEM_LIMIT: usize> ArrayProbModel<PROBS_MEM_LIMIT> {
+    pub(crate) fn save_probs(
+        &self,
+        literal_states: usize,
+        literal: &mut Vec<u16>,
+        other: &mut Vec<u16>,
+    ) {
+        self.literal_probs
+            .iter()
+            .take(literal_states)
+            .for_each(|v| literal.extend_from_slice(v));
+        for table in self.tables() {
+            other.extend_from_slice(table);
+        }
+    }
+
+    pub(crate) fn load_probs(
+        &mut self,
+        literal: &mut core::slice::Iter<u16>,
+        other: &mut core::slice::Iter<u16>,
+    ) {
+        self.literal_probs
+            .iter_mut()
+            .flatten()
+            .zip(literal)
+            .for_each(|(p, v)| *p = *v);
+        for table in self.tables_mut() {
+            table.iter_mut().zip(&mut *other).for_each(|(p, v)| *p = *v);
+        }
+    }
+
+    fn tables(&self) -> [&[u16]; 7] {
+        [
+            &self.pos_decoders,
+            &self.is_match,
+            &self.is_rep,
+            &self.is_rep_g0,
+            &self.is_rep_g1,
+            &self.is_rep_g2,
+            &self.is_rep_0long,
+        ]
+    }
+
+    fn tables_mut(&mut self) -> [&mut [u16]; 7] {
+        [
+            &mut self.pos_decoders,
+            &mut self.is_match,
+            &mut self.is_rep,
+            &mut self.is_rep_g0,
+            &mut self.is_rep_g1,
+            &mut self.is_rep_g2,
+            &mut self.is_rep_0long,
+        ]
+    }
+}
+
+impl<const PROBS_MEM_LIMIT: usize> Default for ArrayProbModel<PROBS_MEM_LIMIT> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const PROBS_MEM_LIMIT: usize> core::fmt::Debug for ArrayProbModel<PROBS_MEM_LIMIT> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("ArrayProbModel")
+            .field("literal_states", &PROBS_MEM_LIMIT)
+            .finish()
+    }
+}
+
+impl<const PROBS_MEM_LIMIT: usize> ProbModel for ArrayProbModel<PROBS_MEM_LIMIT> {
+    fn reset(&mut self, literal_init: u16, init: u16) {
+        self.literal_probs
+            .iter_mut()
+            .for_each(|v| v.iter_mut().for_each(|v| *v = literal_init));
+        self.pos_decoders.iter_mut().for_each(|v| *v = init);
+        self.is_match.iter_mut().for_each(|v| *v = init);
+        self.is_rep.iter_mut().for_each(|v| *v = init);
+        self.is_rep_g0.iter_mut().for_each(|v| *v = init);
+        self.is_rep_g1.iter_mut().for_each(|v| *v = init);
+        self.is_rep_g2.iter_mut().for_each(|v| *v = init);
+        self.is_rep_0long.iter_mut().for_each(|v| *v = init);
+    }
+
+    #[inline]
+    fn literal_states(&self) -> usize {
+        PROBS_MEM_LIMIT
+    }
+
+    #[inline]
+    fn is_match(&mut self, state: usize, pos_state: usize) -> &mut u16 {
+        &mut self.is_match[(state << 4) + pos_state]
+    }
+
+    #[inline]
+    fn is_rep(&mut self, state: usize) -> &mut u16 {
+        &mut self.is_rep[state]
+    }
+
+    #[inline]
+    fn is_rep_g0(&mut self, state: usize) -> &mut u16 {
+        &mut self.is_rep_g0[state]
+    }
+
+    #[inline]
+    fn is_rep_g1(&mut self, state: usize) -> &mut u16 {
+        &mut self.is_rep_g1[state]
+    }
+
+    #[inline]
+    fn is_rep_g2(&mut self, state: usize) -> &mut u16 {
+        &mut self.is_rep_g2[state]
+    }
+
+    #[inline]
+    fn is_rep_0long(&mut self, state: usize, pos_state: usize) -> &mut u16 {
+        &mut self.is_rep_0long[(state << 4) + pos_state]
+    }
+
+    #[inline]
+    fn literal_probs(&mut self, lit_state: usize) -> &mut [u16; 0x300] {
+        &mut self.literal_probs[lit_state]
+    }
+
+    #[inline]
+    fn pos_decoders(&mut self) -> &mut [u16; 115] {
+        &mut self.pos_decoders
+    }
+}
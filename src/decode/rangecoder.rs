@@ -1,57 +1,116 @@
 use crate::decode::util;
 use crate::error;
 use crate::io;
-use byteorder::BigEndian;
+use byteorder::{BigEndian, ByteOrder};
 use io::ReadBytesExt;
 
 // TODO: Replace generic RangeDecoder over `R` into `dyn io::BufRead`?
+/// Range decoder reading the compressed bit stream from a `BufRead`.
 pub struct RangeDecoder<'a, R>
 where
     R: 'a + io::BufRead,
 {
+    /// Underlying compressed input.
     pub stream: &'a mut R,
+    /// Current width of the coding interval.
     pub range: u32,
+    /// Current code value within the interval.
     pub code: u32,
+    /// Number of bytes pulled out of `stream` by this decoder.
+    consumed: u64,
+    /// Initial bytes read by `new`, replayed to a tee set afterwards.
+    init: [u8; 5],
+    /// Sink mirroring the bytes pulled out of `stream`.
+    tee: Option<&'a mut (dyn io::Write + Send)>,
 }
 
 impl<'a, R> RangeDecoder<'a, R>
 where
     R: io::BufRead,
 {
+    /// Create a decoder, reading the 5 initial bytes of the range coder from
+    /// `stream`.
     pub fn new(stream: &'a mut R) -> io::Result<Self> {
         let mut dec = Self {
             stream,
             range: 0xFFFF_FFFF,
             code: 0,
+            consumed: 0,
+            init: [0; 5],
+            tee: None,
         };
-        let _ = dec.stream.read_u8()?;
-        dec.code = dec.stream.read_u32::<BigEndian>()?;
-        lzma_debug!("0 {{ range: {:08x}, code: {:08x} }}", dec.range, dec.code);
+        dec.stream.read_exact(&mut dec.init)?;
+        dec.code = BigEndian::read_u32(&dec.init[1..]);
+        dec.consumed = 5;
+        lzma_debug!("0 {{ range: {:08x}, code: {:08x} }}", dec.range, dec.code);
         Ok(dec)
     }
 
+    /// Resume decoding from a previously saved `range` and `code`.
+    ///
+    /// The consumed byte counter of the returned decoder starts at 0.
     pub fn from_parts(stream: &'a mut R, range: u32, code: u32) -> Self {
         Self {
             stream,
             range,
             code,
+            consumed: 0,
+            init: [0; 5],
+            tee: None,
         }
     }
 
-    pub fn set(&mut self, range: u32, code: u32) {
+    /// Mirror every compressed byte this decoder pulls out of its stream
+    /// into `sink`, exactly as consumed and without read-ahead.
+    ///
+    /// If nothing but the initial bytes has been consumed since
+    /// [`new`](RangeDecoder::new), they are written to `sink` right away,
+    /// so that it receives the whole range coder input. `sink` must be
+    /// `Send`, so that the range decoder is `Send` whenever `R` is.
+    pub fn with_input_tee(&mut self, sink: &'a mut (dyn io::Write + Send)) -> io::Result<()> {
+        if self.consumed == self.init.len() as u64 {
+            sink.write_all(&self.init)?;
+        }
+        self.tee = Some(sink);
+        Ok(())
+    }
+
+    /// Current `(range, code)` pair, as defined by the LZMA specification
+    /// and kept by other implementations, e.g. to compare the state of
+    /// decoders at known points of a stream. This is the pair to pass to
+    /// [`from_parts`](RangeDecoder::from_parts) to resume decoding.
+    pub fn state(&self) -> (u32, u32) {
+        (self.range, self.code)
+    }
+
+    pub(crate) fn set(&mut self, range: u32, code: u32) {
         self.range = range;
         self.code = code;
     }
 
-    pub fn read_into(&mut self, dst: &mut [u8]) -> io::Result<usize> {
-        self.stream.read(dst)
+    pub(crate) fn read_into(&mut self, dst: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = util::read(self.stream, dst)?;
+        self.consumed += bytes_read as u64;
+        if let Some(tee) = self.tee.as_mut() {
+            tee.write_all(&dst[..bytes_read])?;
+        }
+        Ok(bytes_read)
+    }
+
+    /// Number of compressed bytes this decoder has pulled out of its stream,
+    /// including bytes buffered by the streaming decoder for later use.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.consumed
     }
 
+    /// Whether the range coder is in a valid final state and the input is
+    /// exhausted.
     #[inline]
     pub fn is_finished_ok(&mut self) -> io::Result<bool> {
         Ok(self.code == 0 && self.is_eof()?)
     }
 
+    /// Whether the underlying input is exhausted.
     #[inline]
     pub fn is_eof(&mut self) -> io::Result<bool> {
         util::is_eof(self.stream)
@@ -59,12 +118,17 @@ where
 
     #[inline]
     fn normalize(&mut self) -> io::Result<()> {
-        lzma_trace!("  {{ range: {:08x}, code: {:08x} }}", self.range, self.code);
+        lzma_trace!("  {{ range: {:08x}, code: {:08x} }}", self.range, self.code);
         if self.range < 0x0100_0000 {
+            let byte = self.stream.read_u8()?;
             self.range <<= 8;
-            self.code = (self.code << 8) ^ (self.stream.read_u8()? as u32);
+            self.code = (self.code << 8) ^ (byte as u32);
+            self.consumed += 1;
+            if let Some(tee) = self.tee.as_mut() {
+                tee.write_all(&[byte])?;
+            }
 
-            lzma_debug!("+ {{ range: {:08x}, code: {:08x} }}", self.range, self.code);
+            lzma_debug!("+ {{ range: {:08x}, code: {:08x} }}", self.range, self.code);
         }
         Ok(())
     }
@@ -82,7 +146,7 @@ where
         Ok(bit)
     }
 
-    pub fn get(&mut self, count: usize) -> error::Result<u32> {
+    pub(crate) fn get(&mut self, count: usize) -> error::Result<u32> {
         let mut result = 0u32;
         for _ in 0..count {
             result = (result << 1) ^ (self.get_bit()? as u32)
@@ -90,8 +154,11 @@ where
         Ok(result)
     }
 
+    // Both outcomes are computed and selected with a mask rather than a
+    // branch, which is mispredicted about half of the time on
+    // high-entropy data.
     #[inline]
-    pub fn decode_bit(&mut self, prob: &mut u16, update: bool) -> io::Result<bool> {
+    pub(crate) fn decode_bit(&mut self, prob: &mut u16, update: bool) -> io::Result<bool> {
         let bound: u32 = (self.range >> 11) * (*prob as u32);
 
         lzma_trace!(
@@ -100,24 +167,21 @@ where
             prob,
             (self.code > bound) as u8
         );
-        if self.code < bound {
-            if update {
-                *prob += (0x800_u16 - *prob) >> 5;
-            }
-            self.range = bound;
-
-            self.normalize()?;
-            Ok(false)
-        } else {
-            if update {
-                *prob -= *prob >> 5;
-            }
-            self.code -= bound;
-            self.range -= bound;
-
-            self.normalize()?;
-            Ok(true)
+        let bit = self.code >= bound;
+        // All ones if the bit is 1, zero otherwise
+        let mask = 0_u32.wrapping_sub(bit as u32);
+        if update {
+            let p = *prob as u32;
+            let if_zero = p + ((0x800 - p) >> 5);
+            let if_one = p - (p >> 5);
+            *prob = ((if_zero & !mask) | (if_one & mask)) as u16;
         }
+        // `bound` < `range` as probabilities are below 0x800
+        self.range = (bound & !mask) | ((self.range - bound) & mask);
+        self.code -= bound & mask;
+
+        self.normalize()?;
+        Ok(bit)
     }
 
     fn parse_bit_tree(
@@ -134,7 +198,7 @@ where
         Ok(tmp - (1 << num_bits))
     }
 
-    pub fn parse_reverse_bit_tree(
+    pub(crate) fn parse_reverse_bit_tree(
         &mut self,
         num_bits: usize,
         probs: &mut [u16],
@@ -152,6 +216,25 @@ where
     }
 }
 
+/// [`RangeDecoder`] over a byte slice held in memory, the fastest input: each
+/// byte is fetched with a bounds check and a copy, without going through
+/// [`BufRead`](io::BufRead).
+pub type SliceRangeDecoder<'a, 'b> = RangeDecoder<'a, io::SliceReader<'b>>;
+
+impl<'a, R> core::fmt::Debug for RangeDecoder<'a, R>
+where
+    R: io::BufRead,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("RangeDecoder")
+            .field("range", &self.range)
+            .field("code", &self.code)
+            .field("consumed", &self.consumed)
+            .field("tee", &self.tee.is_some())
+            .finish()
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct BitTree<const SIZE: usize> {
     num_bits: usize,
@@ -165,12 +248,12 @@ impl<const SIZE: usize> BitTree<SIZE> {
             probs: [0; SIZE],
         }
     }
-    pub fn reset(&mut self) {
+    pub fn reset(&mut self, init: u16) {
         self.num_bits = match util::exact_log2(SIZE) {
             Some(v) => v,
             None => panic!("BitTree<SIZE> where SIZE is not power of 2"),
         };
-        self.probs.iter_mut().for_each(|v| *v = 0x400);
+        self.probs.iter_mut().for_each(|v| *v = init);
     }
     pub fn parse<R: io::BufRead>(
         &mut self,
@@ -187,8 +270,19 @@ impl<const SIZE: usize> BitTree<SIZE> {
     ) -> io::Result<u32> {
         rangecoder.parse_reverse_bit_tree(self.num_bits, &mut self.probs, 0, update)
     }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn save_probs(&self, out: &mut Vec<u16>) {
+        out.extend_from_slice(&self.probs);
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn load_probs(&mut self, probs: &mut core::slice::Iter<u16>) {
+        self.probs.iter_mut().zip(probs).for_each(|(p, v)| *p = *v);
+    }
 }
 
+#[derive(Clone)]
 pub struct LenDecoder {
     choice: u16,
     choice2: u16,
@@ -207,12 +301,12 @@ impl LenDecoder {
             high_coder: BitTree::new(),
         }
     }
-    pub fn reset(&mut self) {
-            self.choice = 0x400;
-            self.choice2 = 0x400;
-            self.low_coder.iter_mut().for_each(|v| v.reset());
-            self.mid_coder.iter_mut().for_each(|v| v.reset());
-            self.high_coder.reset();
+    pub fn reset(&mut self, init: u16) {
+        self.choice = init;
+        self.choice2 = init;
+        self.low_coder.iter_mut().for_each(|v| v.reset(init));
+        self.mid_coder.iter_mut().for_each(|v| v.reset(init));
+        self.high_coder.reset(init);
     }
     pub fn decode<R: io::BufRead>(
         &mut self,
@@ -228,4 +322,28 @@ impl LenDecoder {
             Ok(self.high_coder.parse(rangecoder, update)? as usize + 16)
         }
     }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn save_probs(&self, out: &mut Vec<u16>) {
+        out.push(self.choice);
+        out.push(self.choice2);
+        self.low_coder
+            .iter()
+            .chain(self.mid_coder.iter())
+            .for_each(|v| v.save_probs(out));
+        self.high_coder.save_probs(out);
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn load_probs(&mut self, probs: &mut core::slice::Iter<u16>) {
+        if let (Some(choice), Some(choice2)) = (probs.next(), probs.next()) {
+            self.choice = *choice;
+            self.choice2 = *choice2;
+        }
+        self.low_coder
+            .iter_mut()
+            .chain(self.mid_coder.iter_mut())
+            .for_each(|v| v.load_probs(probs));
+        self.high_coder.load_probs(probs);
+    }
 }
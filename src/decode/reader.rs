@@ -0,0 +1,183 @@
+//! Pull-based decoding through [`Read`](crate::io::Read).
+
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{self, DecoderState, LzmaParams};
+use crate::decode::rangecoder::RangeDecoder;
+use crate::decode::util;
+use crate::decompress::Options;
+use crate::error;
+use crate::io::{self, BufRead, Read};
+use crate::option::GuaranteedOption as Option;
+use crate::option::GuaranteedOption::*;
+
+/// Longest match, i.e. the most a symbol can decode past the requested
+/// offset.
+const MAX_MATCH_LEN: usize = 273;
+
+/// A [`Read`] adapter decompressing an LZMA member on demand, e.g. to feed
+/// a parser that stops before the end of the data.
+///
+/// Each call to [`read`](Read::read) decodes only as many symbols as needed
+/// to fill the buffer, at most the dictionary size minus 273 bytes, and
+/// copies the bytes back from the dictionary. Nothing is buffered outside
+/// the decoder and no output is pending, so the reader can be dropped at any
+/// point to stop decoding: the rest of the input is left unread, apart from
+/// the few bytes of lookahead the range decoder already consumed. Use
+/// [`into_inner`](LzmaReader::into_inner) to get the input back instead.
+///
+/// Decoding errors fail the read with an I/O error of kind
+/// [`ErrorKind::InvalidData`](io::ErrorKind::InvalidData), the original
+/// error being kept aside (see [`take_error`](LzmaReader::take_error)), and
+/// I/O errors of the input are returned as is. After an error, the stream
+/// cannot be resumed and every read fails.
+///
+/// The reader holds the whole decoder state, about `DICT_MEM_LIMIT` bytes;
+/// box it on stack-limited targets. Only a single member is decoded:
+/// `options.concatenated` is ignored.
+pub struct LzmaReader<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
+    input: R,
+    decoder: DecoderState<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>,
+    range: u32,
+    code: u32,
+    // Largest number of bytes decoded per read, so that they all remain in
+    // the dictionary
+    chunk_len: usize,
+    // Decoded bytes not read yet, at the end of the dictionary
+    available: usize,
+    finished: bool,
+    failed: bool,
+    error: Option<error::Error>,
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    LzmaReader<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: BufRead,
+{
+    /// Read the header and the start of the range coder data from `input`,
+    /// ready to decode.
+    pub fn new(mut input: R, options: &Options) -> error::Result<Self> {
+        let params = LzmaParams::read_header(&mut input, options)?;
+        let chunk_len = lzma::dict_len(params.dict_size)? - MAX_MATCH_LEN;
+        // An empty output may be stored as a bare header, without the
+        // initial bytes of the range coder
+        let bare = params.unpacked_size == Some(0) && util::is_eof(&mut input)?;
+        let mut decoder = DecoderState::new();
+        decoder.reset();
+        decoder.set_params(params)?;
+        decoder.set_options(&Options {
+            concatenated: false,
+            ..*options
+        });
+        // Bytes are copied from the dictionary, never written to a sink
+        decoder.skip_output(u64::MAX);
+        let (range, code) = if bare {
+            (0xFFFF_FFFF, 0)
+        } else {
+            RangeDecoder::new(&mut input)
+                .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?
+                .state()
+        };
+        Ok(Self {
+            input,
+            decoder,
+            range,
+            code,
+            chunk_len,
+            available: 0,
+            finished: bare,
+            failed: false,
+            error: None,
+        })
+    }
+
+    /// Get a reference to the input.
+    pub fn get_ref(&self) -> &R {
+        &self.input
+    }
+
+    /// Unwrap the input, positioned wherever decoding stopped.
+    pub fn into_inner(self) -> R {
+        self.input
+    }
+
+    /// Number of bytes decoded so far, which may exceed the number of bytes
+    /// read by up to a dictionary size.
+    pub fn decoded_len(&self) -> u64 {
+        self.decoder.output.len() as u64
+    }
+
+    /// Take the decoding error that failed a read, if any.
+    pub fn take_error(&mut self) -> Option<error::Error> {
+        self.error.take()
+    }
+
+    fn read_decoded(&mut self, buf: &mut [u8]) -> error::Result<usize> {
+        if self.available == 0 && !self.finished {
+            let start = self.decoder.output.len();
+            let target = start + buf.len().min(self.chunk_len);
+            let mut rangecoder = RangeDecoder::from_parts(&mut self.input, self.range, self.code);
+            let mut sink: &mut [u8] = &mut [];
+            self.finished =
+                self.decoder
+                    .process_to_offset(&mut sink, &mut rangecoder, target as u64)?;
+            let (range, code) = rangecoder.state();
+            self.range = range;
+            self.code = code;
+            self.available = self.decoder.output.len() - start;
+        }
+        let count = buf.len().min(self.available);
+        for (i, byte) in buf[..count].iter_mut().enumerate() {
+            *byte = self.decoder.output.last_n(self.available - i)?;
+        }
+        self.available -= count;
+        Ok(count)
+    }
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Read
+    for LzmaReader<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+where
+    R: BufRead,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.failed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "LZMA decoding failed",
+            ));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.read_decoded(buf) {
+            Ok(count) => Ok(count),
+            Err(e) => {
+                self.failed = true;
+                match e {
+                    error::Error::IoError(e) => Err(e),
+                    e => {
+                        self.error = Some(e);
+                        Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "LZMA decoding failed",
+                        ))
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> core::fmt::Debug
+    for LzmaReader<R, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("LzmaReader")
+            .field("available", &self.available)
+            .field("finished", &self.finished)
+            .field("failed", &self.failed)
+            .field("error", &self.error)
+            .finish()
+    }
+}
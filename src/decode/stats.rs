@@ -0,0 +1,121 @@
+//! Statistics collected while decoding, for analysis of LZMA streams.
+
+/// Largest length of an LZMA match.
+pub const MAX_MATCH_LEN: usize = 273;
+
+/// Number of distance slots (`pos_slot` values) of the LZMA format.
+pub const NUM_DISTANCE_SLOTS: usize = 64;
+
+/// Histograms of the symbols decoded by a
+/// [`DecoderState`](super::lzma::DecoderState).
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchStats {
+    /// Number of decoded literals.
+    pub literals: u64,
+    /// Number of matches of each length, indexed by length. Short rep
+    /// matches have length 1; index 0 is never used.
+    pub lengths: [u64; MAX_MATCH_LEN + 1],
+    /// Number of matches with a newly coded distance, indexed by the
+    /// `pos_slot` of the distance. Matches repeating one of the last 4
+    /// distances are not counted here.
+    pub distance_slots: [u64; NUM_DISTANCE_SLOTS],
+    /// Number of matches repeating one of the last 4 distances, indexed by
+    /// the rank of the distance, short rep matches counting as rank 0.
+    pub reps: [u64; 4],
+}
+
+impl MatchStats {
+    /// Create empty statistics.
+    pub const fn new() -> Self {
+        Self {
+            literals: 0,
+            lengths: [0; MAX_MATCH_LEN + 1],
+            distance_slots: [0; NUM_DISTANCE_SLOTS],
+            reps: [0; 4],
+        }
+    }
+
+    /// Number of decoded matches, short rep matches included.
+    pub fn matches(&self) -> u64 {
+        self.lengths.iter().sum()
+    }
+
+    /// Number of bytes copied by matches.
+    pub fn matched_bytes(&self) -> u64 {
+        self.lengths
+            .iter()
+            .enumerate()
+            .map(|(len, count)| len as u64 * count)
+            .sum()
+    }
+
+    /// Number of matches repeating one of the last 4 distances.
+    pub fn rep_matches(&self) -> u64 {
+        self.reps.iter().sum()
+    }
+
+    /// Number of matches with a newly coded distance.
+    pub fn new_matches(&self) -> u64 {
+        self.distance_slots.iter().sum()
+    }
+
+    /// Clear all counters.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    pub(crate) fn record_literal(&mut self) {
+        self.literals += 1;
+    }
+
+    pub(crate) fn record_match(&mut self, len: usize) {
+        if let Some(count) = self.lengths.get_mut(len) {
+            *count += 1;
+        }
+    }
+
+    pub(crate) fn record_rep(&mut self, idx: usize) {
+        self.reps[idx] += 1;
+    }
+
+    // `rep_0` is the coded distance, i.e. the actual distance minus one
+    pub(crate) fn record_distance(&mut self, rep_0: usize) {
+        if let Some(count) = self.distance_slots.get_mut(distance_slot(rep_0)) {
+            *count += 1;
+        }
+    }
+}
+
+impl Default for MatchStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `pos_slot` under which a coded distance (actual distance minus one) is
+/// encoded: the position of its highest set bit, doubled, plus the bit below
+/// it.
+pub fn distance_slot(rep_0: usize) -> usize {
+    if rep_0 < 4 {
+        return rep_0;
+    }
+    let bits = (usize::BITS - rep_0.leading_zeros()) as usize;
+    ((bits - 1) << 1) | ((rep_0 >> (bits - 2)) & 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_distance_slot() {
+        assert_eq!(distance_slot(0), 0);
+        assert_eq!(distance_slot(3), 3);
+        assert_eq!(distance_slot(4), 4);
+        assert_eq!(distance_slot(5), 4);
+        assert_eq!(distance_slot(6), 5);
+        assert_eq!(distance_slot(7), 5);
+        assert_eq!(distance_slot(8), 6);
+        assert_eq!(distance_slot(0xFFFF_FFFE), 63);
+    }
+}
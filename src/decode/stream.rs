@@ -1,10 +1,9 @@
 use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
 use crate::decode::lzma::{DecoderState, LzmaParams};
 use crate::decode::rangecoder::RangeDecoder;
-use crate::decompress::Options;
+use crate::decompress::{Options, UnpackedSize};
 use crate::error;
 use crate::io::{self, BufRead, Cursor, Read, Write};
-use crate::option::GuaranteedOption as Option;
 use crate::option::GuaranteedOption::*;
 use core::fmt::Debug;
 
@@ -29,6 +28,7 @@ const MAX_TMP_LEN: usize = MAX_HEADER_LEN + START_BYTES;
 /// initialize the stream before processing any data.
 #[repr(C)]
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 enum State<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> {
     Uninitialized,
     InvalidState,
@@ -134,10 +134,18 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     pub fn finish(&mut self, output: &mut dyn Write) -> crate::error::Result<()> {
         let finish_status = match self.state.take() {
             State::Header => {
-                if self.tmp.position() > 0 {
-                    Err(error::stream::StreamError::FailedToReadLzmaHeader.into())
-                } else {
+                let mut header = &self.tmp.get_ref()[0..self.tmp.position() as usize];
+                if header.is_empty() {
                     Ok(())
+                } else {
+                    match LzmaParams::read_header(&mut header, &self.options) {
+                        // An empty output may be stored as a bare header,
+                        // without the initial bytes of the range coder
+                        Ok(params) if params.unpacked_size == Some(0) && header.is_empty() => {
+                            Ok(())
+                        }
+                        _ => Err(error::stream::StreamError::FailedToReadLzmaHeader.into()),
+                    }
                 }
             }
             State::Data(state) => {
@@ -146,13 +154,10 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                 let mut stream = Cursor::new(&self.tmp.get_ref()[0..self.tmp.position() as usize]);
                 let mut range_decoder =
                     RangeDecoder::from_parts(&mut stream, state.range, state.code);
-                self.decoder
-                    .process(output, &mut range_decoder)
-                    .and(self.decoder.output.finish(output).map_err(|e| e.into()))
-                    .and(Ok(()))
+                self.decoder.signal_eof(output, &mut range_decoder)
             }
             State::InvalidState => Err(error::stream::StreamError::InvalidState.into()),
-            State::Uninitialized => panic!("Stream is uninitialized; call `Stream::reset` first"),
+            State::Uninitialized => return Err(error::stream::StreamError::Uninitialized.into()),
         };
         self.reset();
         finish_status
@@ -173,6 +178,11 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                 // chunks of data.
                 if let Ok(rangecoder) = RangeDecoder::new(&mut input) {
                     decoder.set_params(params)?;
+                    if let UnpackedSize::VerifyAfterMarker(len) = options.unpacked_size {
+                        decoder.set_len_after_marker(Some(len));
+                    }
+                    decoder.set_max_output(options.max_output);
+                    decoder.add_input_consumed(rangecoder.bytes_consumed());
                     Ok(State::Data(RunState {
                         range: rangecoder.range,
                         code: rangecoder.code,
@@ -184,7 +194,7 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                 }
             }
             // Failed to read_header() because we need more data, try again later.
-            Err(error::Error::HeaderTooShort(_)) => Ok(State::Header),
+            Err(error::Error::HeaderTooShort { .. }) => Ok(State::Header),
             // Fatal error. Don't retry.
             Err(e) => Err(e),
         }
@@ -217,7 +227,7 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     /// data from `data` slice, use [`Stream::write_all`] function.
     pub fn write(&mut self, output: &mut dyn Write, data: &[u8]) -> crate::error::Result<usize> {
         if let StreamStatus::Uninitialized = self.get_stream_status() {
-            panic!("Stream is uninitialized; call `Stream::reset` first");
+            return Err(error::stream::StreamError::Uninitialized.into());
         }
         let mut input = Cursor::new(data);
 
@@ -231,11 +241,7 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                     let bytes_read = if bytes_read < u64::MAX as usize {
                         bytes_read as u64
                     } else {
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            "Failed to convert integer to u64.",
-                        )
-                        .into());
+                        return Err(io::other_error("Failed to convert integer to u64.").into());
                     };
                     self.tmp.set_position(position + bytes_read);
 
@@ -254,7 +260,7 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                         let tmp = *self.tmp.get_ref();
                         let end = self.tmp.position();
                         let new_len = end - position;
-                        (&mut self.tmp.get_mut()[0..new_len as usize])
+                        self.tmp.get_mut()[0..new_len as usize]
                             .copy_from_slice(&tmp[position as usize..end as usize]);
                         self.tmp.set_position(new_len);
                     }
@@ -274,11 +280,9 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                             let bytes_read = if bytes_read < u64::MAX as usize {
                                 bytes_read as u64
                             } else {
-                                return Err(io::Error::new(
-                                    io::ErrorKind::Other,
-                                    "Failed to convert integer to u64.",
-                                )
-                                .into());
+                                return Err(
+                                    io::other_error("Failed to convert integer to u64.").into()
+                                );
                             };
                             self.tmp.set_position(bytes_read);
                         }
@@ -292,7 +296,7 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                         return Err(error::stream::StreamError::InvalidState.into())
                     }
                     State::Uninitialized => {
-                        panic!("Stream is uninitialized; call `Stream::reset` first")
+                        return Err(error::stream::StreamError::Uninitialized.into())
                     }
                 }
             }
@@ -316,14 +320,15 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
                 )?)
             }
             State::InvalidState => return Err(error::stream::StreamError::InvalidState.into()),
-            State::Uninitialized => panic!("Stream is uninitialized; call `Stream::reset` first"),
+            State::Uninitialized => return Err(error::stream::StreamError::Uninitialized.into()),
         };
         self.state.replace(state);
 
         Ok(input.position() as usize)
     }
 
-    ///
+    /// Write the whole `buf` of compressed data into the stream, calling
+    /// [`Stream::write`] until every byte has been consumed.
     pub fn write_all(
         &mut self,
         output: &mut dyn Write,
@@ -358,9 +363,8 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
             Data(_) => {
                 let params = match &self.decoder.params {
                     Some(v) => v.clone(),
-                    None => panic!(
-                "DecoderState::params is not initialized; call `DecoderState::set_params` first"
-            ),
+                    // Parameters are always set before entering `Data`
+                    None => return StreamStatus::InvalidState,
                 };
                 let unpacked_size = params.unpacked_size;
                 // Temporary buffer in `Stream` must be checked; without `Stream::finish` call,
@@ -395,6 +399,14 @@ impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
     }
 }
 
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Debug
     for Stream<DICT_MEM_LIMIT, PROBS_MEM_LIMIT>
 {
@@ -537,11 +549,11 @@ mod test {
         stream.write_all(&mut sink, &input[..]).unwrap();
         stream.finish(&mut sink).unwrap();
         assert_eq!(expected, &sink[..]);
-        sink.truncate(0);
+        sink.clear();
         stream.write_all(&mut sink, &input[..]).unwrap();
         stream.finish(&mut sink).unwrap();
         assert_eq!(expected, &sink[..]);
-        sink.truncate(0);
+        sink.clear();
         let (first_half, second_half) = input.split_at(input.len() / 2);
         stream.write_all(&mut sink, first_half).unwrap();
         stream.write_all(&mut sink, second_half).unwrap();
@@ -549,6 +561,28 @@ mod test {
         assert_eq!(expected, &sink[..]);
     }
 
+    /// Test that the observed ratio accounts for the input of every write
+    #[test]
+    fn test_stream_observed_ratio() {
+        let input = include_bytes!("../../tests/files/foo.txt.lzma");
+        let expected = include_bytes!("../../tests/files/foo.txt");
+        let mut sink = Vec::new();
+        let mut stream = Stream::<4096, 8>::new();
+        stream.reset();
+        assert_eq!(stream.decoder.observed_ratio(), None);
+        for chunk in input.chunks(100) {
+            stream.write_all(&mut sink, chunk).unwrap();
+        }
+        // Everything past the 13-byte header has been consumed.
+        assert_eq!(stream.decoder.total_in(), input.len() as u64 - 13);
+        assert_eq!(
+            stream.decoder.observed_ratio(),
+            Some((input.len() - 13) as f32 / expected.len() as f32)
+        );
+        stream.finish(&mut sink).unwrap();
+        assert_eq!(expected, &sink[..]);
+    }
+
     /// Test processing only partial data
     #[test]
     fn test_stream_incomplete() {
@@ -0,0 +1,55 @@
+//! Runtime tracing of the decoded symbols.
+
+use crate::decode::lzma::LzmaParams;
+
+/// Receiver of the parameters and symbols of a
+/// [`DecoderState`](super::lzma::DecoderState), e.g. to visualize the
+/// literal and match stream of a release build.
+///
+/// The sink is a type parameter of the decoder, so every method has an empty
+/// default and the default [`NoTrace`] sink compiles to nothing. Methods are
+/// called once the symbol is appended to the dictionary.
+pub trait TraceSink {
+    /// Parameters given to
+    /// [`set_params`](super::lzma::DecoderState::set_params).
+    #[inline]
+    fn on_params(&mut self, params: &LzmaParams) {
+        let _ = params;
+    }
+
+    /// A literal `byte`, after the literal hook if any.
+    #[inline]
+    fn on_literal(&mut self, byte: u8) {
+        let _ = byte;
+    }
+
+    /// A copy of `len` bytes from `dist` bytes back, `dist` being 1 for the
+    /// last byte. Short reps are reported with a length of 1.
+    #[inline]
+    fn on_match(&mut self, len: usize, dist: usize) {
+        let _ = (len, dist);
+    }
+}
+
+/// Default [`TraceSink`], ignoring everything.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NoTrace;
+
+impl TraceSink for NoTrace {}
+
+impl<T: TraceSink + ?Sized> TraceSink for &mut T {
+    #[inline]
+    fn on_params(&mut self, params: &LzmaParams) {
+        (**self).on_params(params)
+    }
+
+    #[inline]
+    fn on_literal(&mut self, byte: u8) {
+        (**self).on_literal(byte)
+    }
+
+    #[inline]
+    fn on_match(&mut self, len: usize, dist: usize) {
+        (**self).on_match(len, dist)
+    }
+}
@@ -1,10 +1,56 @@
 use crate::io;
 
 pub fn is_eof<R: io::BufRead>(input: &mut R) -> io::Result<bool> {
-    let buf = input.fill_buf()?;
+    let buf = fill_buf(input)?;
     Ok(buf.is_empty())
 }
 
+/// [`io::BufRead::fill_buf`], retried as long as it is interrupted.
+pub fn fill_buf<R: io::BufRead>(input: &mut R) -> io::Result<&[u8]> {
+    // The borrow checker does not let the filled buffer escape the loop, but
+    // a non-empty buffer is returned again without reading
+    loop {
+        match input.fill_buf() {
+            Ok([]) => return Ok(&[]),
+            Ok(_) => break,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    input.fill_buf()
+}
+
+/// [`io::Read::read`], retried as long as it is interrupted.
+pub fn read<R: io::Read>(input: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match input.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            res => return res,
+        }
+    }
+}
+
+/// Boxed callback stored in a decoder.
+///
+/// It is only ever called through `&mut self`, so the mutex is never locked
+/// and merely keeps its owner `Sync` without requiring it of the callback.
+#[cfg(feature = "std")]
+pub(crate) struct Callback<F: ?Sized>(std::sync::Mutex<Box<F>>);
+
+#[cfg(feature = "std")]
+impl<F: ?Sized> Callback<F> {
+    pub(crate) fn new(f: Box<F>) -> Self {
+        Self(std::sync::Mutex::new(f))
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut F {
+        match self.0.get_mut() {
+            Ok(f) => f,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
 pub const fn exact_log2(mut value: usize) -> Option<usize> {
     if value == 0 {
         return None;
@@ -45,4 +91,3 @@ mod test {
         assert_eq!(None, exact_log2((1 << 31) + 1));
     }
 }
-
@@ -0,0 +1,599 @@
+//! XZ container: stream and block framing, checks and the block filter
+//! chain.
+
+use crate::check::{CheckValue, Crc32, Crc64};
+use crate::decode::filter::{BcjX86, DeltaFilter, Filter};
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+use crate::decode::lzma::{lzma2_dict_size_from_byte, DecoderState};
+use crate::decode::lzma2::Lzma2Decoder;
+use crate::decode::sha256::Sha256;
+use crate::decode::util;
+use crate::error::xz::XzError;
+use crate::error::{self, HeaderField};
+use crate::io::{self, BufRead, Read, Write};
+use crate::option::GuaranteedOption as Option;
+use crate::option::GuaranteedOption::*;
+use byteorder::{ByteOrder, LittleEndian};
+use io::ReadBytesExt;
+
+/// Magic bytes starting a stream header.
+pub(crate) const MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+/// Magic bytes ending a stream footer.
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+/// Filter ID of LZMA2 in a block header.
+const LZMA2_ID: u64 = 0x21;
+
+/// XZ decoder, parsing the stream and block framing and delegating the
+/// compressed data of each block to an [`Lzma2Decoder`].
+///
+/// Every header, the index and the footer are validated with their CRC32,
+/// and the index against the blocks actually decoded. The uncompressed data
+/// of each block is verified with the check of the stream: none, CRC32,
+/// CRC64 or SHA-256. Blocks may use LZMA2 alone or preceded by up to three
+/// filters among the x86 BCJ filter (see [`BcjX86`]) and the delta filter
+/// (see [`DeltaFilter`]).
+///
+/// Concatenated streams, separated by stream padding, are decoded into one
+/// output.
+pub struct XzDecoder<LZB, const PROBS_MEM_LIMIT: usize>
+where
+    LZB: LzBuffer,
+{
+    lzma2: Lzma2Decoder<LZB, PROBS_MEM_LIMIT>,
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>
+    XzDecoder<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    /// Create a decoder with an inline dictionary of `DICT_MEM_LIMIT` bytes.
+    pub const fn new() -> Self {
+        Self {
+            lzma2: Lzma2Decoder::new(),
+        }
+    }
+}
+
+impl<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize> Default
+    for XzDecoder<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize> core::fmt::Debug for XzDecoder<LZB, PROBS_MEM_LIMIT>
+where
+    LZB: LzBuffer + core::fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("XzDecoder")
+            .field("lzma2", &self.lzma2)
+            .finish()
+    }
+}
+
+impl<LZB, const PROBS_MEM_LIMIT: usize> XzDecoder<LZB, PROBS_MEM_LIMIT>
+where
+    LZB: LzBuffer,
+{
+    /// Create a decoder delegating the LZMA chunks to `lzma`, e.g. to use
+    /// another dictionary buffer.
+    pub fn with_decoder(lzma: DecoderState<LZB, PROBS_MEM_LIMIT>) -> Self {
+        Self {
+            lzma2: Lzma2Decoder::with_decoder(lzma),
+        }
+    }
+
+    /// Decompress XZ data made of one or more streams, up to the end of
+    /// `input`.
+    pub fn decompress<R: BufRead>(
+        &mut self,
+        input: &mut R,
+        output: &mut dyn io::Write,
+    ) -> error::Result<()> {
+        let mut input = CountingReader {
+            inner: input,
+            count: 0,
+        };
+        loop {
+            self.decompress_stream(&mut input, output)?;
+            // Stream padding, in groups of 4 null bytes, then the end or the
+            // next stream
+            loop {
+                let buf = util::fill_buf(&mut input)?;
+                if buf.is_empty() {
+                    return Ok(());
+                }
+                if buf[0] != 0x00 {
+                    break;
+                }
+                let mut padding = [0xFF; 4];
+                let _ = input.read_exact(&mut padding);
+                if padding != [0; 4] {
+                    return Err(XzError::InvalidPadding.into());
+                }
+            }
+        }
+    }
+
+    fn decompress_stream<R: BufRead>(
+        &mut self,
+        input: &mut CountingReader<R>,
+        output: &mut dyn io::Write,
+    ) -> error::Result<()> {
+        let flags = read_stream_header(input)?;
+        let mut blocks = IndexHash::default();
+        loop {
+            let header_size_byte = input.read_u8()?;
+            // A null byte instead of a block header starts the index
+            if header_size_byte == 0x00 {
+                break;
+            }
+            let (unpadded_size, uncompressed_size) =
+                self.decompress_block(input, output, header_size_byte, flags)?;
+            blocks.add(unpadded_size, uncompressed_size);
+        }
+        let index_size = read_index(input, &blocks)?;
+        read_stream_footer(input, flags, index_size)
+    }
+
+    // Returns the unpadded and uncompressed sizes of the block
+    fn decompress_block<R: BufRead>(
+        &mut self,
+        input: &mut CountingReader<R>,
+        output: &mut dyn io::Write,
+        header_size_byte: u8,
+        flags: u16,
+    ) -> error::Result<(u64, u64)> {
+        let header_size = (header_size_byte as usize + 1) * 4;
+        let mut header = [0u8; 1024];
+        header[0] = header_size_byte;
+        input.read_exact(&mut header[1..header_size])?;
+        let (header, crc) = header[..header_size].split_at(header_size - 4);
+        if Crc32::checksum(header) != LittleEndian::read_u32(crc) {
+            return Err(XzError::BlockHeaderCrcMismatch.into());
+        }
+        let block = BlockHeader::parse(&header[1..])?;
+
+        let start = input.count;
+        let mut sink = CheckWriter {
+            inner: output,
+            check: Check::new(flags as u8)?,
+            len: 0,
+        };
+        let mut filters = block.filters;
+        self.decompress_filtered(input, &mut sink, &mut filters, block.dict_size)?;
+        let compressed_size = input.count - start;
+        let uncompressed_size = sink.len;
+        let mismatch =
+            |declared: &Option<u64>, actual| matches!(declared, Some(size) if *size != actual);
+        if mismatch(&block.compressed_size, compressed_size)
+            || mismatch(&block.uncompressed_size, uncompressed_size)
+        {
+            return Err(XzError::BlockSizeMismatch.into());
+        }
+
+        let unpadded_size = header_size as u64 + compressed_size;
+        for _ in 0..(4 - unpadded_size % 4) % 4 {
+            if input.read_u8()? != 0x00 {
+                return Err(XzError::InvalidPadding.into());
+            }
+        }
+
+        let check_size = sink.check.size();
+        let mut stored = [0u8; 32];
+        input.read_exact(&mut stored[..check_size])?;
+        let expected = sink.check.stored_value(&stored[..check_size]);
+        let found = sink.check.value();
+        if expected != found {
+            return Err(XzError::CheckMismatch { expected, found }.into());
+        }
+        Ok((unpadded_size + check_size as u64, uncompressed_size))
+    }
+
+    // Decompress the LZMA2 data of a block through `filters`, in reverse
+    // order, i.e. with the first filter writing to `output`
+    fn decompress_filtered<R: BufRead>(
+        &mut self,
+        input: &mut CountingReader<R>,
+        output: &mut dyn io::Write,
+        filters: &mut [Option<BlockFilter>],
+        dict_size: u32,
+    ) -> error::Result<()> {
+        match filters {
+            [Some(filter), rest @ ..] => {
+                let mut writer = FilterWriter {
+                    filter,
+                    inner: output,
+                    buf: [0; 4096],
+                    len: 0,
+                };
+                self.decompress_filtered(input, &mut writer, rest, dict_size)?;
+                writer.finish()?;
+                Ok(())
+            }
+            _ => self.lzma2.decompress(input, output, dict_size),
+        }
+    }
+}
+
+// Returns the stream flags
+fn read_stream_header<R: BufRead>(input: &mut CountingReader<R>) -> error::Result<u16> {
+    let mut header = [0u8; 12];
+    input
+        .read_exact(&mut header)
+        .map_err(error::Error::header_too_short(HeaderField::StreamHeader))?;
+    if header[..6] != MAGIC {
+        return Err(XzError::InvalidMagic.into());
+    }
+    if Crc32::checksum(&header[6..8]) != LittleEndian::read_u32(&header[8..]) {
+        return Err(XzError::HeaderCrcMismatch.into());
+    }
+    let flags = u16::from_be_bytes([header[6], header[7]]);
+    if flags & 0xFFF0 != 0 {
+        return Err(XzError::InvalidStreamFlags { flags }.into());
+    }
+    Check::new(flags as u8)?;
+    lzma_info!("XZ stream flags: {:04x}", flags);
+    Ok(flags)
+}
+
+// Read the index, whose indicator was already read, and return its size
+fn read_index<R: BufRead>(input: &mut CountingReader<R>, blocks: &IndexHash) -> error::Result<u64> {
+    let start = input.count - 1;
+    let mut reader = Crc32Reader {
+        inner: &mut *input,
+        crc: Crc32::new(),
+    };
+    reader.crc.update(&[0x00]);
+    let count = read_multibyte_int(&mut reader)?;
+    let mut records = IndexHash::default();
+    for _ in 0..count {
+        if records.count == blocks.count {
+            return Err(XzError::IndexMismatch.into());
+        }
+        let unpadded_size = read_multibyte_int(&mut reader)?;
+        let uncompressed_size = read_multibyte_int(&mut reader)?;
+        records.add(unpadded_size, uncompressed_size);
+    }
+    if records != *blocks {
+        return Err(XzError::IndexMismatch.into());
+    }
+
+    let size = reader.inner.count - start;
+    for _ in 0..(4 - size % 4) % 4 {
+        if reader.read_u8()? != 0x00 {
+            return Err(XzError::InvalidPadding.into());
+        }
+    }
+    let crc = reader.crc.finalize();
+    if input.read_u32::<LittleEndian>()? != crc {
+        return Err(XzError::IndexCrcMismatch.into());
+    }
+    Ok(input.count - start)
+}
+
+fn read_stream_footer<R: BufRead>(
+    input: &mut CountingReader<R>,
+    flags: u16,
+    index_size: u64,
+) -> error::Result<()> {
+    let mut footer = [0u8; 12];
+    input.read_exact(&mut footer)?;
+    if footer[10..] != FOOTER_MAGIC {
+        return Err(XzError::InvalidFooterMagic.into());
+    }
+    if Crc32::checksum(&footer[4..10]) != LittleEndian::read_u32(&footer[..4]) {
+        return Err(XzError::FooterCrcMismatch.into());
+    }
+    let footer_flags = u16::from_be_bytes([footer[8], footer[9]]);
+    if footer_flags != flags {
+        return Err(XzError::StreamFlagsMismatch {
+            header: flags,
+            footer: footer_flags,
+        }
+        .into());
+    }
+    let backward_size = (LittleEndian::read_u32(&footer[4..8]) as u64 + 1) * 4;
+    if backward_size != index_size {
+        return Err(XzError::BackwardSizeMismatch.into());
+    }
+    Ok(())
+}
+
+/// Fields of a block header used to decode the block.
+struct BlockHeader {
+    compressed_size: Option<u64>,
+    uncompressed_size: Option<u64>,
+    dict_size: u32,
+    /// Filters preceding LZMA2, in encoding order
+    filters: [Option<BlockFilter>; 3],
+}
+
+/// A filter preceding LZMA2 in a block.
+// Boxing the delta history would need an allocator
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+enum BlockFilter {
+    BcjX86(BcjX86),
+    Delta(DeltaFilter),
+}
+
+impl Filter for BlockFilter {
+    fn apply(&mut self, buf: &mut [u8]) -> usize {
+        match self {
+            BlockFilter::BcjX86(filter) => filter.apply(buf),
+            BlockFilter::Delta(filter) => filter.apply(buf),
+        }
+    }
+}
+
+impl BlockHeader {
+    // Parse the header between its size byte and its CRC32
+    fn parse(mut fields: &[u8]) -> error::Result<Self> {
+        let truncated = |e: error::Error| match e {
+            error::Error::IoError(_) => XzError::InvalidBlockHeader.into(),
+            e => e,
+        };
+        let flags = fields.read_u8().map_err(|_| XzError::InvalidBlockHeader)?;
+        if flags & 0x3C != 0 {
+            return Err(XzError::InvalidBlockHeader.into());
+        }
+        let mut compressed_size = None;
+        if flags & 0x40 != 0 {
+            compressed_size = Some(read_multibyte_int(&mut fields).map_err(truncated)?);
+        }
+        let mut uncompressed_size = None;
+        if flags & 0x80 != 0 {
+            uncompressed_size = Some(read_multibyte_int(&mut fields).map_err(truncated)?);
+        }
+
+        // Filters are listed in encoding order, so LZMA2 comes last
+        let filters = (flags & 0x03) + 1;
+        let mut dict_size = 0;
+        let mut chain = [None, None, None];
+        for i in 0..filters {
+            let id = read_multibyte_int(&mut fields).map_err(truncated)?;
+            let props_size = read_multibyte_int(&mut fields).map_err(truncated)?;
+            if props_size > fields.len() as u64 {
+                return Err(XzError::InvalidBlockHeader.into());
+            }
+            let (props, rest) = fields.split_at(props_size as usize);
+            fields = rest;
+            let last = i + 1 == filters;
+            match id {
+                LZMA2_ID if last => {
+                    if props.len() != 1 {
+                        return Err(XzError::InvalidBlockHeader.into());
+                    }
+                    dict_size = lzma2_dict_size_from_byte(props[0])?;
+                }
+                BcjX86::ID if !last => {
+                    chain[i as usize] = Some(BlockFilter::BcjX86(match props.len() {
+                        0 => BcjX86::new(),
+                        4 => BcjX86::with_start_offset(LittleEndian::read_u32(props)),
+                        _ => return Err(XzError::InvalidBlockHeader.into()),
+                    }));
+                }
+                DeltaFilter::ID if !last => match props {
+                    [props] => {
+                        chain[i as usize] =
+                            Some(BlockFilter::Delta(DeltaFilter::from_props(*props)))
+                    }
+                    _ => return Err(XzError::InvalidBlockHeader.into()),
+                },
+                _ => return Err(XzError::UnsupportedFilter { id }.into()),
+            }
+        }
+        if fields.iter().any(|&b| b != 0x00) {
+            return Err(XzError::InvalidPadding.into());
+        }
+        Ok(Self {
+            compressed_size,
+            uncompressed_size,
+            dict_size,
+            filters: chain,
+        })
+    }
+}
+
+/// Read a variable-length integer: 7 bits per byte, least significant
+/// first, with the high bit set on all bytes but the last.
+fn read_multibyte_int<R: Read>(input: &mut R) -> error::Result<u64> {
+    let mut value = 0;
+    for i in 0..9 {
+        let byte = input.read_u8()?;
+        value |= ((byte & 0x7F) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            if i > 0 && byte == 0x00 {
+                break;
+            }
+            return Ok(value);
+        }
+    }
+    Err(XzError::InvalidMultibyteInteger.into())
+}
+
+/// Summary of the block sizes of a stream, to compare the blocks against
+/// the index without storing them.
+#[derive(Default, PartialEq)]
+struct IndexHash {
+    count: u64,
+    unpadded_sum: u64,
+    uncompressed_sum: u64,
+    // CRC32 of the sizes, to catch records swapped or changed in opposite
+    // directions
+    crc: Crc32,
+}
+
+impl IndexHash {
+    fn add(&mut self, unpadded_size: u64, uncompressed_size: u64) {
+        self.count += 1;
+        self.unpadded_sum = self.unpadded_sum.wrapping_add(unpadded_size);
+        self.uncompressed_sum = self.uncompressed_sum.wrapping_add(uncompressed_size);
+        let mut record = [0u8; 16];
+        LittleEndian::write_u64(&mut record[..8], unpadded_size);
+        LittleEndian::write_u64(&mut record[8..], uncompressed_size);
+        self.crc.update(&record);
+    }
+}
+
+/// Integrity check of the uncompressed data of a block.
+#[derive(Clone, Debug)]
+enum Check {
+    None,
+    Crc32(Crc32),
+    Crc64(Crc64),
+    Sha256(Sha256),
+}
+
+impl Check {
+    fn new(id: u8) -> error::Result<Self> {
+        match id {
+            0x00 => Ok(Check::None),
+            0x01 => Ok(Check::Crc32(Crc32::new())),
+            0x04 => Ok(Check::Crc64(Crc64::new())),
+            0x0A => Ok(Check::Sha256(Sha256::new())),
+            _ => Err(XzError::UnsupportedCheck { check: id }.into()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Check::None => {}
+            Check::Crc32(crc) => crc.update(data),
+            Check::Crc64(crc) => crc.update(data),
+            Check::Sha256(hasher) => hasher.update(data),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Check::None => 0,
+            Check::Crc32(_) => 4,
+            Check::Crc64(_) => 8,
+            Check::Sha256(_) => 32,
+        }
+    }
+
+    // Check of the data seen so far
+    fn value(&self) -> CheckValue {
+        match self {
+            Check::None => CheckValue::None,
+            Check::Crc32(crc) => CheckValue::Crc32(crc.finalize()),
+            Check::Crc64(crc) => CheckValue::Crc64(crc.finalize()),
+            Check::Sha256(hasher) => CheckValue::Sha256(hasher.clone().finish()),
+        }
+    }
+
+    // Check of the same kind read from the `size()` bytes stored after a
+    // block
+    fn stored_value(&self, stored: &[u8]) -> CheckValue {
+        match self {
+            Check::None => CheckValue::None,
+            Check::Crc32(_) => CheckValue::Crc32(LittleEndian::read_u32(stored)),
+            Check::Crc64(_) => CheckValue::Crc64(LittleEndian::read_u64(stored)),
+            Check::Sha256(_) => {
+                let mut hash = [0; 32];
+                hash.copy_from_slice(stored);
+                CheckValue::Sha256(hash)
+            }
+        }
+    }
+}
+
+/// A [`BufRead`] counting the bytes consumed from the inner reader.
+struct CountingReader<'a, R> {
+    inner: &'a mut R,
+    count: u64,
+}
+
+impl<'a, R: BufRead> Read for CountingReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+}
+
+impl<'a, R: BufRead> BufRead for CountingReader<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count += amt as u64;
+        self.inner.consume(amt);
+    }
+}
+
+/// A [`Read`] updating a CRC32 with the bytes read.
+struct Crc32Reader<'a, R> {
+    inner: &'a mut R,
+    crc: Crc32,
+}
+
+impl<'a, R: Read> Read for Crc32Reader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.crc.update(&buf[..count]);
+        Ok(count)
+    }
+}
+
+/// A [`Write`] computing the check and the length of the data written.
+struct CheckWriter<'a> {
+    inner: &'a mut dyn io::Write,
+    check: Check,
+    len: u64,
+}
+
+impl<'a> Write for CheckWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = self.inner.write(buf)?;
+        self.check.update(&buf[..count]);
+        self.len += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A [`Write`] running a filter on the data written, holding back the last
+/// few bytes until the filter can process them or
+/// [`finish`](FilterWriter::finish) is called.
+struct FilterWriter<'a, F> {
+    filter: &'a mut F,
+    inner: &'a mut dyn io::Write,
+    buf: [u8; 4096],
+    len: usize,
+}
+
+impl<'a, F: Filter> FilterWriter<'a, F> {
+    // Write the bytes held back as they are, at the end of the block
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.buf[..self.len])?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl<'a, F: Filter> Write for FilterWriter<'a, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // At most 4 bytes are held back, so there is always room
+        let count = buf.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&buf[..count]);
+        self.len += count;
+        let processed = self.filter.apply(&mut self.buf[..self.len]);
+        self.inner.write_all(&self.buf[..processed])?;
+        self.buf.copy_within(processed..self.len, 0);
+        self.len -= processed;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
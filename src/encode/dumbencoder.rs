@@ -62,6 +62,7 @@ where
         Ok(encoder)
     }
 
+    #[allow(clippy::unbuffered_bytes)]
     pub fn process<R>(mut self, input: R) -> io::Result<()>
     where
         R: io::Read,
@@ -0,0 +1,408 @@
+//! LZMA encoder with a hash chain match finder.
+
+use crate::compress::{Options, UnpackedSize};
+use crate::decode::lzma::LzmaParams;
+use crate::encode::rangecoder::{LenEncoder, RangeEncoder};
+use crate::io;
+
+const LC: u32 = 3;
+const LP: u32 = 0;
+const PB: u32 = 2;
+
+const MATCH_MIN_LEN: usize = 2;
+const MATCH_MAX_LEN: usize = 273;
+
+/// Number of bits of the hash of the next 3 bytes.
+const HASH_BITS: u32 = 14;
+/// Maximum number of candidates visited by the match finder per position.
+const MAX_CHAIN_DEPTH: usize = 32;
+
+/// LZMA encoder with a greedy parser over a hash chain match finder.
+///
+/// The probability model and state transitions mirror the decoder, with
+/// `lc = 3`, `lp = 0` and `pb = 2`. Matches reach at most `DICT_SIZE` bytes
+/// back, which is also the dictionary size written to the header. The match
+/// finder takes 4 bytes per dictionary byte on top of 64 KiB of hash heads;
+/// as [`LzmaEncoder::new`] is a `const fn`, the encoder can be placed in a
+/// `static` on stack-limited targets.
+pub struct LzmaEncoder<const DICT_SIZE: usize> {
+    literal_probs: [[u16; 0x300]; 1 << (LC + LP)],
+    is_match: [[u16; 1 << PB]; 12],
+    is_rep: [u16; 12],
+    is_rep_g0: [u16; 12],
+    is_rep_g1: [u16; 12],
+    is_rep_g2: [u16; 12],
+    is_rep_0long: [[u16; 1 << PB]; 12],
+    pos_slot: [[u16; 64]; 4],
+    pos_special: [u16; 115],
+    align: [u16; 16],
+    len_encoder: LenEncoder,
+    rep_len_encoder: LenEncoder,
+    state: usize,
+    rep: [usize; 4],
+    // Last position + 1 with a given hash, 0 for none
+    head: [u32; 1 << HASH_BITS],
+    // Previous position + 1 with the same hash, indexed by position modulo
+    // `DICT_SIZE`
+    chain: [u32; DICT_SIZE],
+}
+
+impl<const DICT_SIZE: usize> LzmaEncoder<DICT_SIZE> {
+    /// Create an encoder.
+    pub const fn new() -> Self {
+        Self {
+            literal_probs: [[0x400; 0x300]; 1 << (LC + LP)],
+            is_match: [[0x400; 1 << PB]; 12],
+            is_rep: [0x400; 12],
+            is_rep_g0: [0x400; 12],
+            is_rep_g1: [0x400; 12],
+            is_rep_g2: [0x400; 12],
+            is_rep_0long: [[0x400; 1 << PB]; 12],
+            pos_slot: [[0x400; 64]; 4],
+            pos_special: [0x400; 115],
+            align: [0x400; 16],
+            len_encoder: LenEncoder::new(),
+            rep_len_encoder: LenEncoder::new(),
+            state: 0,
+            rep: [0; 4],
+            head: [0; 1 << HASH_BITS],
+            chain: [0; DICT_SIZE],
+        }
+    }
+
+    /// Parameters written to the header for the given options.
+    pub fn params(options: &Options) -> LzmaParams {
+        let unpacked_size = match options.unpacked_size {
+            UnpackedSize::WriteToHeader(x) => x,
+            UnpackedSize::SkipWritingToHeader => None,
+        };
+        LzmaParams::from_fields(LC, LP, PB, DICT_SIZE as u32, unpacked_size.into())
+    }
+
+    /// Compress `input` as one LZMA member written to `output`, with the
+    /// header and end marker requested by `options`. The encoder is reset
+    /// first, so it can be reused.
+    pub fn compress<W>(&mut self, input: &[u8], output: &mut W, options: &Options) -> io::Result<()>
+    where
+        W: io::Write,
+    {
+        if DICT_SIZE == 0 || DICT_SIZE > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "DICT_SIZE must be in 1..=u32::MAX",
+            ));
+        }
+        // Positions are stored as u32 in the match finder
+        if input.len() as u64 >= u32::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "input must be shorter than 4 GiB",
+            ));
+        }
+        self.reset();
+
+        if let UnpackedSize::WriteToHeader(_) = options.unpacked_size {
+            Self::params(options).write_header(output)?;
+        }
+
+        let mut rangecoder = RangeEncoder::new(output);
+        let mut pos = 0;
+        while pos < input.len() {
+            let pos_state = pos & ((1 << PB) - 1);
+            let (len, distance) = self.find_match(input, pos);
+            let (rep_len, rep_idx) = self.find_rep(input, pos);
+
+            let advance = if rep_len >= MATCH_MIN_LEN && rep_len + 1 >= len {
+                self.encode_rep(&mut rangecoder, rep_idx, rep_len, pos_state)?;
+                rep_len
+            } else if len >= MATCH_MIN_LEN {
+                self.encode_match(&mut rangecoder, distance - 1, len, pos_state)?;
+                len
+            } else if pos > self.rep[0] && input[pos] == input[pos - self.rep[0] - 1] {
+                self.encode_short_rep(&mut rangecoder, pos_state)?;
+                1
+            } else {
+                self.encode_literal(&mut rangecoder, input, pos, pos_state)?;
+                1
+            };
+
+            for p in pos..pos + advance {
+                self.insert(input, p);
+            }
+            pos += advance;
+        }
+
+        if let UnpackedSize::WriteToHeader(None) = options.unpacked_size {
+            let pos_state = input.len() & ((1 << PB) - 1);
+            self.encode_match(&mut rangecoder, 0xFFFF_FFFF, MATCH_MIN_LEN, pos_state)?;
+        }
+
+        rangecoder.finish()
+    }
+
+    fn reset(&mut self) {
+        self.literal_probs
+            .iter_mut()
+            .for_each(|probs| probs.fill(0x400));
+        self.is_match.iter_mut().for_each(|probs| probs.fill(0x400));
+        self.is_rep.fill(0x400);
+        self.is_rep_g0.fill(0x400);
+        self.is_rep_g1.fill(0x400);
+        self.is_rep_g2.fill(0x400);
+        self.is_rep_0long
+            .iter_mut()
+            .for_each(|probs| probs.fill(0x400));
+        self.pos_slot.iter_mut().for_each(|probs| probs.fill(0x400));
+        self.pos_special.fill(0x400);
+        self.align.fill(0x400);
+        self.len_encoder = LenEncoder::new();
+        self.rep_len_encoder = LenEncoder::new();
+        self.state = 0;
+        self.rep = [0; 4];
+        // Chain entries are always written before being followed
+        self.head.fill(0);
+    }
+
+    fn hash(input: &[u8], pos: usize) -> usize {
+        let value =
+            input[pos] as u32 | (input[pos + 1] as u32) << 8 | (input[pos + 2] as u32) << 16;
+        (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, input: &[u8], pos: usize) {
+        if pos + 3 > input.len() {
+            return;
+        }
+        let hash = Self::hash(input, pos);
+        self.chain[pos % DICT_SIZE] = self.head[hash];
+        self.head[hash] = pos as u32 + 1;
+    }
+
+    fn match_len(input: &[u8], from: usize, pos: usize) -> usize {
+        let max_len = MATCH_MAX_LEN.min(input.len() - pos);
+        input[from..]
+            .iter()
+            .zip(&input[pos..pos + max_len])
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    /// Longest match of at least 3 bytes among the hash chain of `pos`, as
+    /// `(len, distance)`.
+    fn find_match(&self, input: &[u8], pos: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        if pos + 3 > input.len() {
+            return best;
+        }
+        let max_len = MATCH_MAX_LEN.min(input.len() - pos);
+        let mut candidate = self.head[Self::hash(input, pos)];
+        for _ in 0..MAX_CHAIN_DEPTH {
+            if candidate == 0 {
+                break;
+            }
+            let from = candidate as usize - 1;
+            let distance = pos - from;
+            // Older entries of the chain have been overwritten
+            if distance > DICT_SIZE {
+                break;
+            }
+            let len = Self::match_len(input, from, pos);
+            if len >= 3 && len > best.0 {
+                best = (len, distance);
+                if len == max_len {
+                    break;
+                }
+            }
+            let next = self.chain[from % DICT_SIZE];
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+
+    /// Longest match at one of the last distances, as `(len, index)`. Ties
+    /// go to the lowest index, as a canonical encoder would.
+    fn find_rep(&self, input: &[u8], pos: usize) -> (usize, usize) {
+        let mut best = (0, 0);
+        for (idx, &rep) in self.rep.iter().enumerate() {
+            if rep >= pos {
+                continue;
+            }
+            let len = Self::match_len(input, pos - rep - 1, pos);
+            if len > best.0 {
+                best = (len, idx);
+            }
+        }
+        best
+    }
+
+    fn encode_literal<W: io::Write>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        input: &[u8],
+        pos: usize,
+        pos_state: usize,
+    ) -> io::Result<()> {
+        rangecoder.encode_bit(&mut self.is_match[self.state][pos_state], false)?;
+
+        let prev_byte = if pos > 0 { input[pos - 1] } else { 0 } as usize;
+        let lit_state = ((pos & ((1 << LP) - 1)) << LC) + (prev_byte >> (8 - LC));
+        let probs = &mut self.literal_probs[lit_state];
+        let byte = input[pos] as usize;
+
+        let mut result: usize = 1;
+        let mut i = 8;
+        if self.state >= 7 {
+            let match_byte = input[pos - self.rep[0] - 1] as usize;
+            while i > 0 {
+                i -= 1;
+                let match_bit = (match_byte >> i) & 1;
+                let bit = (byte >> i) & 1;
+                rangecoder.encode_bit(&mut probs[((1 + match_bit) << 8) + result], bit != 0)?;
+                result = (result << 1) ^ bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+        while i > 0 {
+            i -= 1;
+            let bit = (byte >> i) & 1;
+            rangecoder.encode_bit(&mut probs[result], bit != 0)?;
+            result = (result << 1) ^ bit;
+        }
+
+        self.state = if self.state < 4 {
+            0
+        } else if self.state < 10 {
+            self.state - 3
+        } else {
+            self.state - 6
+        };
+        Ok(())
+    }
+
+    /// Encode a match with a new distance, given as `distance - 1`.
+    fn encode_match<W: io::Write>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        rep_0: usize,
+        len: usize,
+        pos_state: usize,
+    ) -> io::Result<()> {
+        rangecoder.encode_bit(&mut self.is_match[self.state][pos_state], true)?;
+        rangecoder.encode_bit(&mut self.is_rep[self.state], false)?;
+        self.rep[3] = self.rep[2];
+        self.rep[2] = self.rep[1];
+        self.rep[1] = self.rep[0];
+        self.rep[0] = rep_0;
+
+        let len = len - MATCH_MIN_LEN;
+        self.len_encoder.encode(rangecoder, len, pos_state)?;
+        self.state = if self.state < 7 { 7 } else { 10 };
+        self.encode_distance(rangecoder, rep_0 as u32, len.min(3))
+    }
+
+    fn encode_distance<W: io::Write>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        distance: u32,
+        len_state: usize,
+    ) -> io::Result<()> {
+        let pos_slot = pos_slot(distance);
+        rangecoder.encode_bit_tree(6, &mut self.pos_slot[len_state], pos_slot)?;
+        if pos_slot < 4 {
+            return Ok(());
+        }
+
+        let num_direct_bits = (pos_slot >> 1) as usize - 1;
+        let base = (2 | (pos_slot & 1)) << num_direct_bits;
+        let rem = distance - base;
+        if pos_slot < 14 {
+            rangecoder.encode_reverse_bit_tree(
+                num_direct_bits,
+                &mut self.pos_special,
+                (base - pos_slot) as usize,
+                rem,
+            )
+        } else {
+            rangecoder.encode_direct_bits(rem >> 4, num_direct_bits - 4)?;
+            rangecoder.encode_reverse_bit_tree(4, &mut self.align, 0, rem & 0xF)
+        }
+    }
+
+    fn encode_rep<W: io::Write>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        idx: usize,
+        len: usize,
+        pos_state: usize,
+    ) -> io::Result<()> {
+        rangecoder.encode_bit(&mut self.is_match[self.state][pos_state], true)?;
+        rangecoder.encode_bit(&mut self.is_rep[self.state], true)?;
+        if idx == 0 {
+            rangecoder.encode_bit(&mut self.is_rep_g0[self.state], false)?;
+            rangecoder.encode_bit(&mut self.is_rep_0long[self.state][pos_state], true)?;
+        } else {
+            rangecoder.encode_bit(&mut self.is_rep_g0[self.state], true)?;
+            if idx == 1 {
+                rangecoder.encode_bit(&mut self.is_rep_g1[self.state], false)?;
+            } else {
+                rangecoder.encode_bit(&mut self.is_rep_g1[self.state], true)?;
+                rangecoder.encode_bit(&mut self.is_rep_g2[self.state], idx == 3)?;
+            }
+            // Update LRU
+            let dist = self.rep[idx];
+            for i in (0..idx).rev() {
+                self.rep[i + 1] = self.rep[i];
+            }
+            self.rep[0] = dist;
+        }
+
+        self.rep_len_encoder
+            .encode(rangecoder, len - MATCH_MIN_LEN, pos_state)?;
+        self.state = if self.state < 7 { 8 } else { 11 };
+        Ok(())
+    }
+
+    fn encode_short_rep<W: io::Write>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        pos_state: usize,
+    ) -> io::Result<()> {
+        rangecoder.encode_bit(&mut self.is_match[self.state][pos_state], true)?;
+        rangecoder.encode_bit(&mut self.is_rep[self.state], true)?;
+        rangecoder.encode_bit(&mut self.is_rep_g0[self.state], false)?;
+        rangecoder.encode_bit(&mut self.is_rep_0long[self.state][pos_state], false)?;
+        self.state = if self.state < 7 { 9 } else { 11 };
+        Ok(())
+    }
+}
+
+impl<const DICT_SIZE: usize> Default for LzmaEncoder<DICT_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const DICT_SIZE: usize> core::fmt::Debug for LzmaEncoder<DICT_SIZE> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("LzmaEncoder")
+            .field("dict_size", &DICT_SIZE)
+            .field("state", &self.state)
+            .field("rep", &self.rep)
+            .finish()
+    }
+}
+
+/// Slot of a distance (minus 1), the inverse of the decoder's distance
+/// decoding.
+fn pos_slot(distance: u32) -> u32 {
+    if distance < 4 {
+        return distance;
+    }
+    let bits = 31 - distance.leading_zeros();
+    2 * bits + ((distance >> (bits - 1)) & 1)
+}
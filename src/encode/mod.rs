@@ -1,5 +1,7 @@
 //! Encoding logic.
 
+#[cfg(feature = "std")]
 pub mod dumbencoder;
+pub mod lzma;
 pub mod options;
-mod rangecoder;
+pub mod rangecoder;
@@ -1,6 +1,8 @@
 use crate::io;
 use io::WriteBytesExt;
 
+/// Range encoder writing the compressed bit stream to a `Write`, the
+/// counterpart of [`RangeDecoder`](crate::decompress::raw::RangeDecoder).
 pub struct RangeEncoder<'a, W>
 where
     W: 'a + io::Write,
@@ -16,6 +18,7 @@ impl<'a, W> RangeEncoder<'a, W>
 where
     W: io::Write,
 {
+    /// Create an encoder writing to `stream`.
     #[allow(clippy::let_and_return)]
     pub fn new(stream: &'a mut W) -> Self {
         let enc = Self {
@@ -50,6 +53,8 @@ where
         Ok(())
     }
 
+    /// Flush the pending bytes of the encoder. Must be called once, after
+    /// the last bit.
     pub fn finish(&mut self) -> io::Result<()> {
         for _ in 0..5 {
             self.write_low()?;
@@ -82,6 +87,8 @@ where
         Ok(())
     }
 
+    /// Encode `bit` with probability `prob` of it being 0, updating the
+    /// probability the same way as the decoder.
     pub fn encode_bit(&mut self, prob: &mut u16, bit: bool) -> io::Result<()> {
         let bound: u32 = (self.range >> 11) * (*prob as u32);
         lzma_trace!(
@@ -102,4 +109,108 @@ where
 
         self.normalize()
     }
+
+    /// Encode the low `count` bits of `value` with fixed probabilities,
+    /// most significant bit first.
+    pub fn encode_direct_bits(&mut self, value: u32, count: usize) -> io::Result<()> {
+        for i in (0..count).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 != 0 {
+                self.low += self.range as u64;
+            }
+            self.normalize()?;
+        }
+        Ok(())
+    }
+
+    /// Encode the low `num_bits` bits of `value` with the bit tree `probs`,
+    /// most significant bit first.
+    pub fn encode_bit_tree(
+        &mut self,
+        num_bits: usize,
+        probs: &mut [u16],
+        value: u32,
+    ) -> io::Result<()> {
+        let mut tmp: usize = 1;
+        for i in (0..num_bits).rev() {
+            let bit = (value >> i) & 1 != 0;
+            self.encode_bit(&mut probs[tmp], bit)?;
+            tmp = (tmp << 1) ^ (bit as usize);
+        }
+        Ok(())
+    }
+
+    /// Encode the low `num_bits` bits of `value` with the bit tree starting
+    /// at `probs[offset]`, least significant bit first.
+    pub fn encode_reverse_bit_tree(
+        &mut self,
+        num_bits: usize,
+        probs: &mut [u16],
+        offset: usize,
+        value: u32,
+    ) -> io::Result<()> {
+        let mut tmp: usize = 1;
+        for i in 0..num_bits {
+            let bit = (value >> i) & 1 != 0;
+            self.encode_bit(&mut probs[offset + tmp], bit)?;
+            tmp = (tmp << 1) ^ (bit as usize);
+        }
+        Ok(())
+    }
+}
+
+impl<'a, W> core::fmt::Debug for RangeEncoder<'a, W>
+where
+    W: io::Write,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("RangeEncoder")
+            .field("range", &self.range)
+            .field("low", &self.low)
+            .field("cache", &self.cache)
+            .field("cachesz", &self.cachesz)
+            .finish()
+    }
+}
+
+/// Counterpart of `LenDecoder`, for match lengths minus 2 (0..272).
+#[derive(Clone)]
+pub(crate) struct LenEncoder {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; 16],
+    mid: [[u16; 8]; 16],
+    high: [u16; 256],
+}
+
+impl LenEncoder {
+    pub(crate) const fn new() -> Self {
+        Self {
+            choice: 0x400,
+            choice2: 0x400,
+            low: [[0x400; 8]; 16],
+            mid: [[0x400; 8]; 16],
+            high: [0x400; 256],
+        }
+    }
+
+    pub(crate) fn encode<W: io::Write>(
+        &mut self,
+        rangecoder: &mut RangeEncoder<W>,
+        len: usize,
+        pos_state: usize,
+    ) -> io::Result<()> {
+        if len < 8 {
+            rangecoder.encode_bit(&mut self.choice, false)?;
+            rangecoder.encode_bit_tree(3, &mut self.low[pos_state], len as u32)
+        } else if len < 16 {
+            rangecoder.encode_bit(&mut self.choice, true)?;
+            rangecoder.encode_bit(&mut self.choice2, false)?;
+            rangecoder.encode_bit_tree(3, &mut self.mid[pos_state], (len - 8) as u32)
+        } else {
+            rangecoder.encode_bit(&mut self.choice, true)?;
+            rangecoder.encode_bit(&mut self.choice2, true)?;
+            rangecoder.encode_bit_tree(8, &mut self.high, (len - 16) as u32)
+        }
+    }
 }
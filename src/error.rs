@@ -2,11 +2,17 @@
 #![allow(missing_docs)]
 
 use crate::io;
+use crate::option::GuaranteedOption;
+use core::fmt;
 use core::result;
 
 pub mod lzma {
+    use core::fmt;
+
     #[derive(PartialEq, Debug)]
     pub enum LzmaError {
+        // The four following variants are no longer returned: out-of-range
+        // distances are reported as `InvalidMatchDistance`
         MatchDistanceIsBeyondDictionarySize {
             distance: usize,
             dict_size: usize,
@@ -23,21 +29,502 @@ pub mod lzma {
             distance: usize,
             output_len: usize,
         },
+        /// A match or literal context references a byte outside the
+        /// dictionary, `available` being the number of bytes it can reach:
+        /// the output so far and any preset dictionary, up to the dictionary
+        /// size
+        InvalidMatchDistance {
+            distance: usize,
+            available: usize,
+        },
         /// `properties` must be < 255
         InvalidHeader {
             invalid_properties: u32,
         },
-        EosFoundButMoreBytesAvailable,
+        /// `lc + lp` must be <= 4 in a header
+        InvalidLiteralContext {
+            lc: u32,
+            lp: u32,
+        },
+        /// `input_offset` is the number of compressed bytes taken from the
+        /// input, not counting the header, and `output_offset` the number of
+        /// decoded bytes when the end marker was found
+        EosFoundButMoreBytesAvailable {
+            input_offset: u64,
+            output_offset: u64,
+        },
+        /// `input_offset` is the number of compressed bytes taken from the
+        /// input, not counting the header, when the mismatch was detected
         ProcessedDataDoesNotMatchUnpackedSize {
             unpacked_size: u64,
             decompressed_data: usize,
+            input_offset: u64,
         },
-        /// When processing is done in `Finish`, standalone mode and `RangeDecoder` 
+        /// When processing is done in `Finish`, standalone mode and `RangeDecoder`
         DataStreamIsTooShort,
+        /// `DecoderState` was used before calling `DecoderState::reset`
+        DecoderUninitialized,
+        /// `DecoderState` was used before calling `DecoderState::set_params`
+        ParamsNotSet,
+        /// `lc` must be <= 8, `lp` and `pb` must be <= 4
+        InvalidProperties {
+            lc: u32,
+            lp: u32,
+            pb: u32,
+        },
+        /// The LZ buffer was used before its dictionary size was set
+        DictionaryUninitialized,
+        /// `dict_size` must be > 0
+        InvalidDictionarySize {
+            dict_size: usize,
+        },
+        /// `dict_size` does not fit in a `usize` on this target
+        DictionaryTooLarge {
+            dict_size: u64,
+        },
+        /// The stream goes on after the declared unpacked size, without an
+        /// end marker
+        TrailingDataAfterDeclaredSize {
+            unpacked_size: u64,
+        },
+        /// Input is left after the end of the stream, `bytes_remaining`
+        /// being its length
+        TrailingData {
+            bytes_remaining: u64,
+        },
+        /// A construct a canonical encoder would not produce, found in strict
+        /// canonical mode
+        NonCanonicalEncoding(NonCanonical),
+        /// A match reaches further back than the configured maximum
+        InvalidBackReference {
+            distance: usize,
+            max_distance: usize,
+        },
+        /// LZMA2 dictionary size bytes must be <= 40
+        InvalidDictionarySizeByte {
+            byte: u8,
+        },
+        /// Initial probabilities must be > 0 and < `0x800`
+        InvalidInitialProbability {
+            prob: u16,
+        },
+        /// A chunk ended before or after the next reset offset, or the reset
+        /// offsets are not strictly increasing
+        ResetOffsetMismatch {
+            reset_offset: u64,
+            consumed: u64,
+        },
+        /// The stream ended before or after the given compressed length
+        CompressedLengthMismatch {
+            compressed_len: u64,
+            consumed: u64,
+        },
+        /// A preset dictionary must be set before any byte is decoded
+        PresetDictionaryAfterOutput {
+            output_len: usize,
+        },
+        /// A checkpoint holds probabilities or a dictionary window that do
+        /// not match its parameters
+        InvalidCheckpoint,
     }
+
+    /// Kinds of non-canonical constructs, with distances given in bytes.
+    #[derive(PartialEq, Debug)]
+    pub enum NonCanonical {
+        /// A match with a new distance equal to `rep[index]`, which could
+        /// have been coded as a rep match
+        NewDistanceIsRep { distance: usize, index: usize },
+        /// A rep match using `rep[index]` while `rep[earlier]` holds the same
+        /// distance
+        RedundantRep {
+            distance: usize,
+            index: usize,
+            earlier: usize,
+        },
+    }
+    impl fmt::Display for LzmaError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                LzmaError::MatchDistanceIsBeyondDictionarySize {
+                    distance,
+                    dict_size,
+                } => write!(
+                    f,
+                    "match distance {} is beyond the dictionary size {}",
+                    distance, dict_size
+                ),
+                LzmaError::MatchDistanceIsBeyondOutputSize {
+                    distance,
+                    output_len,
+                } => write!(
+                    f,
+                    "match distance {} is beyond the output size {}",
+                    distance, output_len
+                ),
+                LzmaError::LzDistanceIsBeyondDictionarySize {
+                    distance,
+                    dict_size,
+                } => write!(
+                    f,
+                    "LZ distance {} is beyond the dictionary size {}",
+                    distance, dict_size
+                ),
+                LzmaError::LzDistanceIsBeyondOutputSize {
+                    distance,
+                    output_len,
+                } => write!(
+                    f,
+                    "LZ distance {} is beyond the output size {}",
+                    distance, output_len
+                ),
+                LzmaError::InvalidMatchDistance {
+                    distance,
+                    available,
+                } => write!(
+                    f,
+                    "match distance {} is beyond the {} available bytes",
+                    distance, available
+                ),
+                LzmaError::InvalidHeader { invalid_properties } => write!(
+                    f,
+                    "invalid LZMA properties byte {} (must be < 225)",
+                    invalid_properties
+                ),
+                LzmaError::InvalidLiteralContext { lc, lp } => write!(
+                    f,
+                    "invalid LZMA header properties lc={}, lp={} (lc + lp must be <= 4)",
+                    lc, lp
+                ),
+                LzmaError::EosFoundButMoreBytesAvailable {
+                    input_offset,
+                    output_offset,
+                } => write!(
+                    f,
+                    "end of stream marker found but more bytes are available \
+                     (input offset {}, output offset {})",
+                    input_offset, output_offset
+                ),
+                LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
+                    unpacked_size,
+                    decompressed_data,
+                    input_offset,
+                } => write!(
+                    f,
+                    "decompressed {} bytes but the unpacked size is {} (input offset {})",
+                    decompressed_data, unpacked_size, input_offset
+                ),
+                LzmaError::DataStreamIsTooShort => write!(f, "LZMA stream is too short"),
+                LzmaError::DecoderUninitialized => write!(f, "decoder state was not reset"),
+                LzmaError::ParamsNotSet => write!(f, "decoder parameters were not set"),
+                LzmaError::InvalidProperties { lc, lp, pb } => write!(
+                    f,
+                    "invalid properties lc={}, lp={}, pb={} (lc must be <= 8, lp and pb <= 4)",
+                    lc, lp, pb
+                ),
+                LzmaError::DictionaryUninitialized => {
+                    write!(f, "LZ buffer dictionary size was not set")
+                }
+                LzmaError::InvalidDictionarySize { dict_size } => {
+                    write!(f, "invalid dictionary size {}", dict_size)
+                }
+                LzmaError::DictionaryTooLarge { dict_size } => write!(
+                    f,
+                    "dictionary size {} exceeds the address space of the target",
+                    dict_size
+                ),
+                LzmaError::TrailingDataAfterDeclaredSize { unpacked_size } => write!(
+                    f,
+                    "stream goes on after the unpacked size {} without an end marker",
+                    unpacked_size
+                ),
+                LzmaError::TrailingData { bytes_remaining } => write!(
+                    f,
+                    "{} bytes of input left after the end of the stream",
+                    bytes_remaining
+                ),
+                LzmaError::NonCanonicalEncoding(e) => write!(f, "non-canonical encoding: {}", e),
+                LzmaError::InvalidBackReference {
+                    distance,
+                    max_distance,
+                } => write!(
+                    f,
+                    "match distance {} exceeds the maximum distance {}",
+                    distance, max_distance
+                ),
+                LzmaError::InvalidDictionarySizeByte { byte } => {
+                    write!(f, "invalid LZMA2 dictionary size byte {}", byte)
+                }
+                LzmaError::InvalidInitialProbability { prob } => {
+                    write!(f, "invalid initial probability {}", prob)
+                }
+                LzmaError::ResetOffsetMismatch {
+                    reset_offset,
+                    consumed,
+                } => write!(
+                    f,
+                    "chunk ended at offset {} instead of the reset offset {}",
+                    consumed, reset_offset
+                ),
+                LzmaError::CompressedLengthMismatch {
+                    compressed_len,
+                    consumed,
+                } => write!(
+                    f,
+                    "stream ended after {} bytes instead of the compressed length {}",
+                    consumed, compressed_len
+                ),
+                LzmaError::PresetDictionaryAfterOutput { output_len } => write!(
+                    f,
+                    "preset dictionary set after {} bytes were decoded",
+                    output_len
+                ),
+                LzmaError::InvalidCheckpoint => write!(f, "invalid decoder checkpoint"),
+            }
+        }
+    }
+
+    impl fmt::Display for NonCanonical {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                NonCanonical::NewDistanceIsRep { distance, index } => {
+                    write!(f, "new match distance {} equals rep[{}]", distance, index)
+                }
+                NonCanonical::RedundantRep {
+                    distance,
+                    index,
+                    earlier,
+                } => write!(
+                    f,
+                    "rep[{}] match distance {} equals rep[{}]",
+                    index, distance, earlier
+                ),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for LzmaError {}
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for NonCanonical {}
+}
+
+pub mod lzma2 {
+    use core::fmt;
+
+    #[derive(PartialEq, Debug)]
+    pub enum Lzma2Error {
+        /// Control bytes `0x03` to `0x7F` are invalid
+        InvalidControlByte { control: u8 },
+        /// The first chunk must reset the dictionary
+        MissingDictionaryReset { control: u8 },
+        /// The first LZMA chunk after a dictionary reset must set new
+        /// properties
+        MissingProperties { control: u8 },
+        /// `lc + lp` must be <= 4
+        InvalidLiteralProperties { lc: u32, lp: u32 },
+        /// The LZMA data of a chunk did not end exactly at its compressed
+        /// size
+        ChunkSizeMismatch { packed_size: u32 },
+    }
+    impl fmt::Display for Lzma2Error {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                Lzma2Error::InvalidControlByte { control } => {
+                    write!(f, "invalid LZMA2 control byte {:#04x}", control)
+                }
+                Lzma2Error::MissingDictionaryReset { control } => write!(
+                    f,
+                    "first LZMA2 chunk (control byte {:#04x}) does not reset the dictionary",
+                    control
+                ),
+                Lzma2Error::MissingProperties { control } => write!(
+                    f,
+                    "LZMA2 chunk (control byte {:#04x}) does not set new properties after a dictionary reset",
+                    control
+                ),
+                Lzma2Error::InvalidLiteralProperties { lc, lp } => {
+                    write!(f, "invalid LZMA2 properties lc={}, lp={} (lc + lp must be <= 4)", lc, lp)
+                }
+                Lzma2Error::ChunkSizeMismatch { packed_size } => write!(
+                    f,
+                    "LZMA data did not end at the chunk packed size {}",
+                    packed_size
+                ),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for Lzma2Error {}
+}
+
+pub mod xz {
+    use crate::check::CheckValue;
+    use core::fmt;
+
+    #[derive(PartialEq, Debug)]
+    pub enum XzError {
+        /// The stream header does not start with `FD 37 7A 58 5A 00`
+        InvalidMagic,
+        /// The stream footer does not end with `59 5A`
+        InvalidFooterMagic,
+        /// Reserved bits of the stream flags are set
+        InvalidStreamFlags {
+            flags: u16,
+        },
+        /// The stream flags of the header and the footer differ
+        StreamFlagsMismatch {
+            header: u16,
+            footer: u16,
+        },
+        /// Only the None, CRC32, CRC64 and SHA-256 checks are supported
+        UnsupportedCheck {
+            check: u8,
+        },
+        HeaderCrcMismatch,
+        BlockHeaderCrcMismatch,
+        IndexCrcMismatch,
+        FooterCrcMismatch,
+        /// The check stored after a block does not match the one computed
+        /// from its uncompressed data
+        CheckMismatch {
+            expected: CheckValue,
+            found: CheckValue,
+        },
+        /// Reserved bits or invalid filter properties in a block header
+        InvalidBlockHeader,
+        /// A variable-length integer longer than 9 bytes or not minimally
+        /// encoded
+        InvalidMultibyteInteger,
+        /// Only LZMA2, optionally preceded by up to three x86 BCJ (ID `0x04`)
+        /// or delta (ID `0x03`) filters, is supported
+        UnsupportedFilter {
+            id: u64,
+        },
+        /// The data of a block does not match the sizes in its header
+        BlockSizeMismatch,
+        /// Padding bytes must be zero, and stream padding a multiple of 4
+        /// bytes
+        InvalidPadding,
+        /// The index does not match the blocks of the stream
+        IndexMismatch,
+        /// The backward size in the stream footer does not match the size
+        /// of the index
+        BackwardSizeMismatch,
+        /// The distance of the delta filter must be between 1 and 256
+        InvalidDeltaDistance {
+            distance: usize,
+        },
+    }
+    impl fmt::Display for XzError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                XzError::InvalidMagic => write!(f, "invalid XZ stream header magic"),
+                XzError::InvalidFooterMagic => write!(f, "invalid XZ stream footer magic"),
+                XzError::InvalidStreamFlags { flags } => {
+                    write!(f, "invalid XZ stream flags {:#06x}", flags)
+                }
+                XzError::StreamFlagsMismatch { header, footer } => write!(
+                    f,
+                    "XZ stream flags differ between header ({:#06x}) and footer ({:#06x})",
+                    header, footer
+                ),
+                XzError::UnsupportedCheck { check } => {
+                    write!(f, "unsupported XZ check type {}", check)
+                }
+                XzError::HeaderCrcMismatch => write!(f, "XZ stream header CRC mismatch"),
+                XzError::BlockHeaderCrcMismatch => write!(f, "XZ block header CRC mismatch"),
+                XzError::IndexCrcMismatch => write!(f, "XZ index CRC mismatch"),
+                XzError::FooterCrcMismatch => write!(f, "XZ stream footer CRC mismatch"),
+                XzError::CheckMismatch { expected, found } => write!(
+                    f,
+                    "XZ block check mismatch: expected {}, found {}",
+                    expected, found
+                ),
+                XzError::InvalidBlockHeader => write!(f, "invalid XZ block header"),
+                XzError::InvalidMultibyteInteger => write!(f, "invalid XZ multibyte integer"),
+                XzError::UnsupportedFilter { id } => {
+                    write!(f, "unsupported XZ filter {:#x}", id)
+                }
+                XzError::BlockSizeMismatch => {
+                    write!(f, "XZ block data does not match its header sizes")
+                }
+                XzError::InvalidPadding => write!(f, "invalid XZ padding"),
+                XzError::IndexMismatch => write!(f, "XZ index does not match the blocks"),
+                XzError::BackwardSizeMismatch => {
+                    write!(f, "XZ backward size does not match the index size")
+                }
+                XzError::InvalidDeltaDistance { distance } => write!(
+                    f,
+                    "invalid delta filter distance {} (must be between 1 and 256)",
+                    distance
+                ),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for XzError {}
+}
+
+pub mod lzip {
+    use core::fmt;
+
+    #[derive(PartialEq, Debug)]
+    pub enum LzipError {
+        /// The member header does not start with `LZIP`
+        InvalidMagic,
+        /// Only version 1 of the format is supported
+        UnsupportedVersion { version: u8 },
+        /// The coded dictionary size must be between 4 KiB and 512 MiB
+        InvalidDictionarySize { byte: u8 },
+        /// The CRC32 stored in the trailer does not match the one computed
+        /// from the decompressed data
+        CrcMismatch { expected: u32, found: u32 },
+        /// The data size stored in the trailer does not match the length
+        /// of the decompressed data
+        DataSizeMismatch { expected: u64, found: u64 },
+        /// The member size stored in the trailer does not match the length
+        /// of the member, header and trailer included
+        MemberSizeMismatch { expected: u64, found: u64 },
+    }
+    impl fmt::Display for LzipError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                LzipError::InvalidMagic => write!(f, "invalid LZIP member header magic"),
+                LzipError::UnsupportedVersion { version } => {
+                    write!(f, "unsupported LZIP version {}", version)
+                }
+                LzipError::InvalidDictionarySize { byte } => {
+                    write!(f, "invalid LZIP dictionary size byte {:#04x}", byte)
+                }
+                LzipError::CrcMismatch { expected, found } => write!(
+                    f,
+                    "LZIP CRC mismatch: expected {:08x}, found {:08x}",
+                    expected, found
+                ),
+                LzipError::DataSizeMismatch { expected, found } => write!(
+                    f,
+                    "LZIP data size mismatch: expected {}, found {}",
+                    expected, found
+                ),
+                LzipError::MemberSizeMismatch { expected, found } => write!(
+                    f,
+                    "LZIP member size mismatch: expected {}, found {}",
+                    expected, found
+                ),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for LzipError {}
 }
 
 pub mod stream {
+    use core::fmt;
+
     #[derive(PartialEq, Debug)]
     pub enum StreamError {
         /// When `finish` is called and header parsing was never completed
@@ -45,6 +532,49 @@ pub mod stream {
         /// When `finish` is called but previous errors corrupted the stream
         /// state
         InvalidState,
+        /// `Stream` was used before calling `Stream::reset`
+        Uninitialized,
+    }
+    impl fmt::Display for StreamError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self {
+                StreamError::FailedToReadLzmaHeader => {
+                    write!(f, "stream finished before the LZMA header was read")
+                }
+                StreamError::InvalidState => {
+                    write!(f, "stream state was corrupted by a previous error")
+                }
+                StreamError::Uninitialized => write!(f, "stream was not reset"),
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    impl std::error::Error for StreamError {}
+}
+
+/// Part of a header that could not be read entirely.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeaderField {
+    /// The `lc`, `lp` and `pb` properties byte.
+    Properties,
+    /// The dictionary size, or its coded byte in a compact header.
+    DictSize,
+    /// The unpacked size of an `.lzma` header.
+    UnpackedSize,
+    /// The stream header of an `.xz` file or the member header of an `.lz`
+    /// file, which are read at once.
+    StreamHeader,
+}
+
+impl fmt::Display for HeaderField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderField::Properties => write!(f, "properties"),
+            HeaderField::DictSize => write!(f, "dictionary size"),
+            HeaderField::UnpackedSize => write!(f, "unpacked size"),
+            HeaderField::StreamHeader => write!(f, "stream header"),
+        }
     }
 }
 
@@ -61,11 +591,105 @@ pub enum Error {
     },
     /// I/O error.
     IoError(io::Error),
-    /// Not enough bytes to complete header
-    HeaderTooShort(io::Error),
+    /// Not enough bytes to complete header, `source` being the error
+    /// returned when reading `field`
+    HeaderTooShort {
+        field: HeaderField,
+        source: io::Error,
+    },
     /// LZMA error.
     LzmaError(lzma::LzmaError),
+    /// LZMA2 error.
+    Lzma2Error(lzma2::Lzma2Error),
+    /// XZ error.
+    XzError(xz::XzError),
+    /// LZIP error.
+    LzipError(lzip::LzipError),
     StreamError(stream::StreamError),
+    /// More bytes were decoded than the configured maximum output
+    #[doc(alias = "DecompressedSizeLimitExceeded")]
+    OutputTooLarge {
+        limit: u64,
+    },
+    /// The input does not look like any supported format
+    UnknownFormat,
+    /// The decompressed data does not fit in the output buffer, which must
+    /// hold at least `needed_at_least` bytes
+    OutputBufferFull {
+        needed_at_least: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DictionaryBufferTooSmall { needed, available } => write!(
+                f,
+                "dictionary buffer too small: {} bytes needed, {} available",
+                needed, available
+            ),
+            Error::ProbabilitiesBufferTooSmall { needed, available } => write!(
+                f,
+                "probabilities buffer too small: {} entries needed, {} available",
+                needed, available
+            ),
+            Error::IoError(_) => write!(f, "I/O error"),
+            Error::HeaderTooShort { field, .. } => {
+                write!(f, "header too short, reading the {}", field)
+            }
+            Error::LzmaError(e) => e.fmt(f),
+            Error::Lzma2Error(e) => e.fmt(f),
+            Error::XzError(e) => e.fmt(f),
+            Error::LzipError(e) => e.fmt(f),
+            Error::StreamError(e) => e.fmt(f),
+            Error::OutputTooLarge { limit } => {
+                write!(f, "output exceeds the limit of {} bytes", limit)
+            }
+            Error::UnknownFormat => write!(f, "unknown compression format"),
+            Error::OutputBufferFull { needed_at_least } => write!(
+                f,
+                "output buffer full, at least {} bytes needed",
+                needed_at_least
+            ),
+        }
+    }
+}
+
+// The wrapped `io::Error`s are exposed as the source, while the other wrapped
+// errors are displayed as is.
+#[cfg(feature = "std")]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(e) | Error::HeaderTooShort { source: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Error {
+    // Wrap the error returned when reading `field` of a header
+    pub(crate) fn header_too_short(field: HeaderField) -> impl FnOnce(io::Error) -> Error {
+        move |source| Error::HeaderTooShort { field, source }
+    }
+
+    /// Offsets in the compressed data, after the header, and in the decoded
+    /// data where the error was detected, as `(input_offset, output_offset)`,
+    /// for the errors that record them.
+    pub fn offset(&self) -> GuaranteedOption<(u64, u64)> {
+        match self {
+            Error::LzmaError(lzma::LzmaError::EosFoundButMoreBytesAvailable {
+                input_offset,
+                output_offset,
+            }) => GuaranteedOption::Some((*input_offset, *output_offset)),
+            Error::LzmaError(lzma::LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
+                decompressed_data,
+                input_offset,
+                ..
+            }) => GuaranteedOption::Some((*input_offset, *decompressed_data as u64)),
+            _ => GuaranteedOption::None,
+        }
+    }
 }
 
 /// Library result alias.
@@ -77,6 +701,24 @@ impl From<lzma::LzmaError> for Error {
     }
 }
 
+impl From<lzma2::Lzma2Error> for Error {
+    fn from(e: lzma2::Lzma2Error) -> Self {
+        Error::Lzma2Error(e)
+    }
+}
+
+impl From<xz::XzError> for Error {
+    fn from(e: xz::XzError) -> Self {
+        Error::XzError(e)
+    }
+}
+
+impl From<lzip::LzipError> for Error {
+    fn from(e: lzip::LzipError) -> Self {
+        Error::LzipError(e)
+    }
+}
+
 impl From<stream::StreamError> for Error {
     fn from(e: stream::StreamError) -> Self {
         Error::StreamError(e)
@@ -1,5 +1,29 @@
-mod io_ext;
+mod buf_take;
 mod cursor;
-pub use io_ext::*;
-pub use cursor::Cursor;
+mod io_ext;
+mod iter_reader;
+mod slice_reader;
+mod span;
+mod transform;
+mod utf8;
+pub(crate) use buf_take::BufTake;
 pub use core2::io::*;
+pub use cursor::Cursor;
+pub use io_ext::*;
+pub use iter_reader::IterReader;
+pub use slice_reader::SliceReader;
+pub use span::SpanWriter;
+pub use transform::TransformWriter;
+pub use utf8::Utf8ValidatingWriter;
+
+/// An error of kind [`ErrorKind::Other`] carrying `msg`.
+pub(crate) fn other_error(msg: &'static str) -> Error {
+    #[cfg(feature = "std")]
+    {
+        Error::other(msg)
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        Error::new(ErrorKind::Other, msg)
+    }
+}
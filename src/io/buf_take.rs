@@ -0,0 +1,77 @@
+use super::{BufRead, Read, Result};
+
+/// A [`BufRead`] over a bare [`Read`], pulling at most `limit` bytes out of
+/// it through a buffer of `S` bytes.
+///
+/// Unlike a plain buffered reader, it never reads ahead past the limit, so
+/// the bytes following it are left in the inner reader.
+#[derive(Debug)]
+pub(crate) struct BufTake<'a, R, const S: usize> {
+    inner: &'a mut R,
+    limit: u64,
+    buf: [u8; S],
+    pos: usize,
+    cap: usize,
+}
+
+impl<'a, R: Read, const S: usize> BufTake<'a, R, S> {
+    /// Create a reader pulling at most `limit` bytes out of `inner`.
+    pub(crate) fn new(inner: &'a mut R, limit: u64) -> Self {
+        Self {
+            inner,
+            limit,
+            buf: [0; S],
+            pos: 0,
+            cap: 0,
+        }
+    }
+}
+
+impl<'a, R: Read, const S: usize> Read for BufTake<'a, R, S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let available = self.fill_buf()?;
+        let count = buf.len().min(available.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<'a, R: Read, const S: usize> BufRead for BufTake<'a, R, S> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos == self.cap && self.limit > 0 {
+            let max = (S as u64).min(self.limit) as usize;
+            let count = self.inner.read(&mut self.buf[..max])?;
+            self.limit -= count as u64;
+            self.pos = 0;
+            self.cap = count;
+        }
+        Ok(&self.buf[self.pos..self.cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.cap);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stops_at_limit() {
+        let mut inner: &[u8] = b"hello world";
+        let mut reader = BufTake::<_, 4>::new(&mut inner, 7);
+        assert_eq!(reader.fill_buf().unwrap(), b"hell");
+        reader.consume(1);
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"ell");
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"o w");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert_eq!(reader.fill_buf().unwrap(), b"");
+        // The bytes past the limit are left in the inner reader
+        assert_eq!(inner, b"orld");
+    }
+}
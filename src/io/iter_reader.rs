@@ -0,0 +1,87 @@
+use super::{BufRead, Read, Result};
+
+/// Number of bytes taken from the iterator at once.
+const ITER_BUF_LEN: usize = 64;
+
+/// A [`BufRead`] over an iterator of bytes, e.g. a generator or a lazily
+/// unpacked transport, to decode from sources that are not readers.
+///
+/// Bytes are taken from the iterator a few at a time into a small internal
+/// buffer, so that [`fill_buf`](BufRead::fill_buf) can return a slice. The
+/// end of the iterator is the end of the input.
+#[derive(Clone, Debug)]
+pub struct IterReader<I> {
+    iter: I,
+    buf: [u8; ITER_BUF_LEN],
+    pos: usize,
+    filled: usize,
+}
+
+impl<I: Iterator<Item = u8>> IterReader<I> {
+    /// Create a reader over the bytes of `iter`.
+    pub fn new<T: IntoIterator<IntoIter = I>>(iter: T) -> Self {
+        Self {
+            iter: iter.into_iter(),
+            buf: [0; ITER_BUF_LEN],
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    /// Unwrap the iterator. Bytes taken from it but not consumed yet are
+    /// lost.
+    pub fn into_inner(self) -> I {
+        self.iter
+    }
+}
+
+impl<I: Iterator<Item = u8>> Read for IterReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let available = self.fill_buf()?;
+        let count = buf.len().min(available.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl<I: Iterator<Item = u8>> BufRead for IterReader<I> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        if self.pos == self.filled {
+            // `buf` comes first so that no byte is taken once it is full
+            self.filled = 0;
+            for (slot, byte) in self.buf.iter_mut().zip(&mut self.iter) {
+                *slot = byte;
+                self.filled += 1;
+            }
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt.min(self.filled - self.pos);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn read_past_buffer() {
+        let mut reader = IterReader::new((0..=255u8).cycle().take(200));
+        assert_eq!(reader.fill_buf().unwrap().len(), ITER_BUF_LEN);
+        reader.consume(ITER_BUF_LEN - 1);
+        // A read stops at the end of the buffer
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], ITER_BUF_LEN as u8 - 1);
+
+        let mut buf = [0; 136];
+        reader.read_exact(&mut buf).unwrap();
+        assert!(buf.iter().copied().eq(ITER_BUF_LEN as u8..200));
+        assert_eq!(reader.fill_buf().unwrap(), b"");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,117 @@
+use super::{BufRead, Read, Result};
+
+/// A [`BufRead`] over a byte slice, keeping track of how much of it was
+/// consumed.
+///
+/// Unlike [`Cursor`](super::Cursor), it only supports reading and never goes
+/// past the end of the slice, which makes it a simple input source for
+/// [`RangeDecoder::new`](crate::decompress::raw::RangeDecoder::new) in
+/// `no_std` builds. [`read_exact`](Read::read_exact) is a single bounds
+/// check and copy, which keeps the byte fetches of the range decoder cheap.
+#[derive(Clone, Debug)]
+pub struct SliceReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceReader<'a> {
+    /// Create a reader starting at the beginning of `data`.
+    pub const fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Number of bytes consumed so far.
+    pub const fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Number of bytes left to read.
+    pub const fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+}
+
+impl<'a> Read for SliceReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = buf.len().min(self.remaining());
+        buf[..count].copy_from_slice(&self.data[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+
+    #[inline]
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        match self.data.get(self.pos..self.pos + buf.len()) {
+            Some(src) => {
+                buf.copy_from_slice(src);
+                self.pos += buf.len();
+                Ok(())
+            }
+            None => Err(super::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+impl<'a> BufRead for SliceReader<'a> {
+    fn fill_buf(&mut self) -> Result<&[u8]> {
+        Ok(&self.data[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt.min(self.remaining());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn fill_buf_and_consume() {
+        let mut reader = SliceReader::new(b"hello");
+        assert_eq!(reader.fill_buf().unwrap(), b"hello");
+        // Filling does not consume
+        assert_eq!(reader.fill_buf().unwrap(), b"hello");
+        assert_eq!(reader.position(), 0);
+
+        reader.consume(2);
+        assert_eq!(reader.fill_buf().unwrap(), b"llo");
+        assert_eq!((reader.position(), reader.remaining()), (2, 3));
+
+        // Consuming past the end stops at the end of the slice
+        reader.consume(10);
+        assert_eq!(reader.fill_buf().unwrap(), b"");
+        assert_eq!((reader.position(), reader.remaining()), (5, 0));
+        reader.consume(1);
+        assert_eq!(reader.position(), 5);
+    }
+
+    #[test]
+    fn read_across_end() {
+        let mut reader = SliceReader::new(b"hello");
+        let mut buf = [0; 3];
+        assert_eq!(reader.read(&mut buf).unwrap(), 3);
+        assert_eq!(&buf, b"hel");
+        assert_eq!(reader.read(&mut buf).unwrap(), 2);
+        assert_eq!(&buf[..2], b"lo");
+        assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        assert!(reader.read_exact(&mut buf).is_err());
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn read_exact() {
+        let mut reader = SliceReader::new(b"hello");
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"he");
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ll");
+        // A short read fails without consuming anything
+        let err = reader.read_exact(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), crate::io::ErrorKind::UnexpectedEof);
+        assert_eq!(reader.position(), 4);
+        reader.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(buf[0], b'o');
+    }
+}
@@ -0,0 +1,95 @@
+use super::{other_error, Result, Write};
+use crate::error;
+use crate::option::GuaranteedOption as Option;
+use crate::option::GuaranteedOption::*;
+
+/// A [`Write`] adapter batching everything written through it into spans of
+/// `SPAN` bytes handed to a callback, e.g. to feed a DMA engine.
+///
+/// Spans are passed in output order and cover the output exactly once.
+/// Every span holds exactly `SPAN` bytes and starts at an output offset that
+/// is a multiple of `SPAN`, except the last one, which may be shorter and is
+/// only passed on [`flush`](Write::flush). No alignment of the span's memory
+/// address is guaranteed. With `SPAN == 0`, writes are passed through as
+/// they come.
+///
+/// If the callback fails, its error is kept aside (see
+/// [`take_error`](SpanWriter::take_error)) and the write fails with an I/O
+/// error of kind [`ErrorKind::Other`](super::ErrorKind::Other).
+pub struct SpanWriter<F, const SPAN: usize> {
+    callback: F,
+    buf: [u8; SPAN],
+    len: usize,
+    error: Option<error::Error>,
+}
+
+impl<F, const SPAN: usize> SpanWriter<F, SPAN>
+where
+    F: FnMut(&[u8]) -> error::Result<()>,
+{
+    /// Create a writer passing spans to `callback`.
+    pub fn new(callback: F) -> Self {
+        Self {
+            callback,
+            buf: [0; SPAN],
+            len: 0,
+            error: None,
+        }
+    }
+
+    /// Take the error returned by the callback, if any.
+    pub fn take_error(&mut self) -> Option<error::Error> {
+        self.error.take()
+    }
+}
+
+fn emit<F>(callback: &mut F, error: &mut Option<error::Error>, span: &[u8]) -> Result<()>
+where
+    F: FnMut(&[u8]) -> error::Result<()>,
+{
+    match callback(span) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            *error = Some(e);
+            Err(other_error("span callback failed"))
+        }
+    }
+}
+
+impl<F, const SPAN: usize> Write for SpanWriter<F, SPAN>
+where
+    F: FnMut(&[u8]) -> error::Result<()>,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if SPAN == 0 {
+            emit(&mut self.callback, &mut self.error, buf)?;
+            return Ok(buf.len());
+        }
+        let count = buf.len().min(SPAN - self.len);
+        self.buf[self.len..self.len + count].copy_from_slice(&buf[..count]);
+        self.len += count;
+        if self.len == SPAN {
+            emit(&mut self.callback, &mut self.error, &self.buf)?;
+            self.len = 0;
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.len > 0 {
+            emit(&mut self.callback, &mut self.error, &self.buf[..self.len])?;
+            self.len = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<F, const SPAN: usize> core::fmt::Debug for SpanWriter<F, SPAN> {
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("SpanWriter")
+            .field("span", &SPAN)
+            .field("len", &self.len)
+            .field("error", &self.error)
+            .finish()
+    }
+}
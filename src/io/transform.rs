@@ -0,0 +1,70 @@
+use super::{Result, Write};
+
+/// A [`Write`] adapter applying a byte-wise transform to everything written
+/// through it before forwarding it to the inner writer.
+///
+/// Used as the output sink of a decoder, the transform sees every decoded
+/// byte exactly once, whether it came from a literal or a match, while the
+/// dictionary keeps the untransformed data.
+pub struct TransformWriter<W, F> {
+    inner: W,
+    transform: F,
+}
+
+impl<W, F> TransformWriter<W, F>
+where
+    W: Write,
+    F: FnMut(u8) -> u8,
+{
+    /// Wrap `inner`, applying `transform` to each byte written.
+    pub fn new(inner: W, transform: F) -> Self {
+        Self { inner, transform }
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W, F> Write for TransformWriter<W, F>
+where
+    W: Write,
+    F: FnMut(u8) -> u8,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut chunk = [0u8; 256];
+        let len = buf.len().min(chunk.len());
+        for (dst, src) in chunk.iter_mut().zip(&buf[..len]) {
+            *dst = (self.transform)(*src);
+        }
+        // Transformed bytes cannot be handed back, so write them all
+        self.inner.write_all(&chunk[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W, F> core::fmt::Debug for TransformWriter<W, F>
+where
+    W: core::fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("TransformWriter")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
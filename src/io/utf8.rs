@@ -0,0 +1,196 @@
+use super::{Error, ErrorKind, Result, Write};
+
+/// A [`Write`] adapter checking that everything written through it is valid
+/// UTF-8, failing on the first invalid sequence instead of after the whole
+/// output has been decoded.
+///
+/// Valid bytes are forwarded to the inner writer as they come, except for
+/// an incomplete sequence at the end of a write, which is held back until
+/// the following writes complete it. Writes fail with an I/O error of kind
+/// [`ErrorKind::InvalidData`] from the first invalid sequence on; call
+/// [`finish`](Utf8ValidatingWriter::finish) at the end of the output to
+/// also reject a truncated sequence.
+pub struct Utf8ValidatingWriter<W> {
+    inner: W,
+    pending: [u8; 4],
+    pending_len: usize,
+    valid_len: u64,
+    invalid: bool,
+}
+
+fn invalid_data() -> Error {
+    Error::new(ErrorKind::InvalidData, "stream did not contain valid UTF-8")
+}
+
+impl<W> Utf8ValidatingWriter<W>
+where
+    W: Write,
+{
+    /// Wrap `inner`, forwarding it the bytes written once validated.
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: [0; 4],
+            pending_len: 0,
+            valid_len: 0,
+            invalid: false,
+        }
+    }
+
+    /// Number of bytes validated and forwarded to the inner writer, i.e. the
+    /// offset of the first invalid sequence after an error.
+    pub fn valid_len(&self) -> u64 {
+        self.valid_len
+    }
+
+    /// Get a reference to the inner writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner writer.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Check that the output does not end with an incomplete sequence,
+    /// flush and unwrap the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.invalid || self.pending_len > 0 {
+            return Err(invalid_data());
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn forward(&mut self, buf: &[u8]) -> Result<()> {
+        self.inner.write_all(buf)?;
+        self.valid_len += buf.len() as u64;
+        Ok(())
+    }
+
+    // Complete the pending sequence with bytes from `buf`, returning how
+    // many were used.
+    fn complete_pending(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut used = 0;
+        while self.pending_len > 0 && used < buf.len() {
+            self.pending[self.pending_len] = buf[used];
+            self.pending_len += 1;
+            used += 1;
+            match core::str::from_utf8(&self.pending[..self.pending_len]) {
+                Ok(_) => {
+                    let pending = self.pending;
+                    self.forward(&pending[..self.pending_len])?;
+                    self.pending_len = 0;
+                }
+                Err(e) if e.error_len().is_some() => {
+                    self.invalid = true;
+                    return Err(invalid_data());
+                }
+                Err(_) => {}
+            }
+        }
+        Ok(used)
+    }
+}
+
+impl<W> Write for Utf8ValidatingWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.invalid {
+            return Err(invalid_data());
+        }
+        let used = self.complete_pending(buf)?;
+        let rest = &buf[used..];
+        match core::str::from_utf8(rest) {
+            Ok(_) => self.forward(rest)?,
+            Err(e) => {
+                let (valid, tail) = rest.split_at(e.valid_up_to());
+                self.forward(valid)?;
+                if e.error_len().is_some() {
+                    self.invalid = true;
+                    return Err(invalid_data());
+                }
+                // An incomplete sequence, shorter than 4 bytes
+                self.pending[..tail.len()].copy_from_slice(tail);
+                self.pending_len = tail.len();
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W> core::fmt::Debug for Utf8ValidatingWriter<W>
+where
+    W: core::fmt::Debug,
+{
+    fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::fmt::Result {
+        fmt.debug_struct("Utf8ValidatingWriter")
+            .field("inner", &self.inner)
+            .field("pending", &&self.pending[..self.pending_len])
+            .field("valid_len", &self.valid_len)
+            .field("invalid", &self.invalid)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+
+    // Write `data` in chunks of `chunk` bytes.
+    fn validate(data: &[u8], chunk: usize) -> (Result<Vec<u8>>, u64) {
+        let mut writer = Utf8ValidatingWriter::new(Vec::new());
+        for part in data.chunks(chunk) {
+            if let Err(e) = writer.write_all(part) {
+                return (Err(e), writer.valid_len());
+            }
+        }
+        let valid_len = writer.valid_len();
+        (writer.finish(), valid_len)
+    }
+
+    #[test]
+    fn sequences_split_across_writes() {
+        let text = "a\u{e9}\u{20ac}\u{1f600}z \u{1f980}\u{7ff}\u{10ffff}";
+        for chunk in 1..=text.len() {
+            let (res, valid_len) = validate(text.as_bytes(), chunk);
+            assert_eq!(res.unwrap(), text.as_bytes(), "chunk {}", chunk);
+            assert_eq!(valid_len, text.len() as u64);
+        }
+    }
+
+    #[test]
+    fn invalid_sequences() {
+        // Stray continuation byte, overlong encoding, surrogate, truncated
+        // sequence followed by ASCII, and code point above U+10FFFF
+        for (data, offset) in [
+            (&b"ab\x80cd"[..], 2),
+            (b"ab\xc0\xafcd", 2),
+            (b"\xe2\x82\xac\xed\xa0\x80", 3),
+            (b"\xf0\x9f\x98a", 0),
+            (b"ab\xf4\x90\x80\x80", 2),
+        ] {
+            for chunk in 1..=data.len() {
+                let (res, valid_len) = validate(data, chunk);
+                let e = res.unwrap_err();
+                assert_eq!(e.kind(), ErrorKind::InvalidData);
+                assert_eq!(valid_len, offset, "{:x?}, chunk {}", data, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_at_end() {
+        let mut writer = Utf8ValidatingWriter::new(Vec::new());
+        writer.write_all(b"ok\xe2\x82").unwrap();
+        assert_eq!(writer.get_ref(), b"ok");
+        assert_eq!(writer.finish().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,5 +1,10 @@
-//! lzma-rs fork containing only no_std based LZMA decoder (standalone function
-//! & stream based)
+//! lzma-rs fork providing no_std based decoders for LZMA (standalone function
+//! & stream based), LZMA2, XZ and LZIP data, along with a greedy LZMA encoder
+//! ([`compress::LzmaEncoder`]).
+//!
+//! No public API panics, whatever the input or the order of calls:
+//! malformed streams and misuse are reported as an [`error::Error`].
+//! Without the `std` feature the crate does not allocate.
 
 #![cfg_attr(not(feature = "std"), no_std)]
 #![deny(missing_docs)]
@@ -9,8 +14,10 @@
 #[macro_use]
 mod macros;
 
+use crate::decode::lzbuffer::{LzBuffer, LzCircularBuffer};
+
+pub mod check;
 mod decode;
-#[cfg(feature = "std")]
 mod encode;
 pub mod error;
 
@@ -18,18 +25,226 @@ pub mod error;
 pub mod io;
 
 /// Compression helpers.
-#[cfg(feature = "std")]
 pub mod compress {
+    pub use crate::encode::lzma::LzmaEncoder;
     pub use crate::encode::options::*;
+
+    /// Low-level building blocks of the LZMA encoder.
+    pub mod raw {
+        pub use crate::encode::rangecoder::RangeEncoder;
+    }
 }
 
 /// Decompression helpers.
 pub mod decompress {
+    #[cfg(feature = "tokio")]
+    pub use crate::decode::async_reader::AsyncLzmaReader;
+    pub use crate::decode::format::{detect_format, Format};
+    pub use crate::decode::lzma::{max_expansion, MemberInfo};
     pub use crate::decode::options::*;
     #[cfg(feature = "stream")]
+    pub use crate::decode::reader::LzmaReader;
+    #[cfg(feature = "stream")]
     pub use crate::decode::stream::Stream;
     #[cfg(feature = "stream")]
     pub use crate::decode::stream::StreamStatus;
+
+    /// Low-level building blocks of the LZMA decoder, for callers driving
+    /// the decoding loop themselves.
+    pub mod raw {
+        #[cfg(feature = "serde")]
+        pub use crate::decode::checkpoint::Checkpoint;
+        #[cfg(feature = "std")]
+        pub use crate::decode::chunks::DecoderChunks;
+        #[cfg(feature = "events")]
+        pub use crate::decode::events::{Event, EventKind, EventRing, EVENT_RING_LEN};
+        pub use crate::decode::lzbuffer::{
+            CircularBuffer, LzBuffer, LzCircularBuffer, LzSliceBuffer,
+        };
+        pub use crate::decode::lzma::{
+            enumerate_properties, lzma2_dict_size_from_byte, DecoderState, LzmaParams, SymbolKind,
+            MAX_REQUIRED_INPUT,
+        };
+        pub use crate::decode::lzma2::{Lzma2Decoder, Lzma2Segment, Lzma2Segments};
+        #[cfg(feature = "std")]
+        pub use crate::decode::metrics::DecodeMetrics;
+        pub use crate::decode::probs::{probs_mem_limit, ArrayProbModel, ProbModel};
+        pub use crate::decode::rangecoder::{RangeDecoder, SliceRangeDecoder};
+        #[cfg(feature = "stats")]
+        pub use crate::decode::stats::{distance_slot, MatchStats};
+        pub use crate::decode::trace::{NoTrace, TraceSink};
+    }
+
+    /// Decompress LZMA data held in memory with the provided options,
+    /// returning the decoded bytes.
+    ///
+    /// The output is reserved up front for the
+    /// [expected size](raw::LzmaParams::expected_output_size) of the
+    /// stream, capped by [`max_output`](Options::max_output) and by the
+    /// [`max_expansion`] of `input`, so that a bogus header cannot cause a
+    /// huge allocation. Set `max_output` to bound the memory used on
+    /// untrusted data, as the output grows past the reserved size when the
+    /// data decodes to more.
+    #[cfg(feature = "std")]
+    pub fn decompress_to_vec<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+        input: &[u8],
+        options: &Options,
+    ) -> crate::error::Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(output_size_hint(input, options));
+        crate::lzma_decompress_with_options::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
+            &mut &input[..],
+            &mut output,
+            options,
+        )?;
+        Ok(output)
+    }
+
+    // Bytes to reserve for the output of `decompress_to_vec`: the expected
+    // size of the first member, capped by what the options and the input
+    // allow, or 0 if unknown or if the header is invalid
+    #[cfg(feature = "std")]
+    fn output_size_hint(input: &[u8], options: &Options) -> usize {
+        use crate::option::GuaranteedOption::*;
+        use core::convert::TryFrom;
+        let size = match raw::LzmaParams::read_header(&mut &input[..], options) {
+            Ok(params) => match params.expected_output_size() {
+                Some(size) => size,
+                None => return 0,
+            },
+            Err(_) => return 0,
+        };
+        let size = match options.max_output {
+            Some(limit) => size.min(limit),
+            None => size,
+        };
+        let size = size.min(max_expansion(input.len() as u64));
+        usize::try_from(size).unwrap_or(usize::MAX)
+    }
+
+    /// Decompress every `.lzma` member of `input`, as produced by
+    /// concatenating files, into a single output: the same as
+    /// [`lzma_decompress_with_options`](crate::lzma_decompress_with_options)
+    /// with [`concatenated`](Options::concatenated) set.
+    ///
+    /// Each member ends at its end marker or after its unpacked size, and
+    /// the next one starts right after, until the end of `input`.
+    #[cfg(feature = "std")]
+    pub fn decode_all<R, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+        input: &mut R,
+        options: &Options,
+    ) -> crate::error::Result<Vec<u8>>
+    where
+        R: crate::io::BufRead,
+    {
+        let mut output = Vec::new();
+        crate::lzma_decompress_with_options::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
+            input,
+            &mut output,
+            &Options {
+                concatenated: true,
+                ..*options
+            },
+        )?;
+        Ok(output)
+    }
+
+    /// Decompress LZMA data held in memory with the provided options into
+    /// `output`, without allocating, returning the number of bytes written.
+    ///
+    /// Fails with
+    /// [`Error::OutputBufferFull`](crate::error::Error::OutputBufferFull)
+    /// soon after the decompressed data exceeds `output`, whose content is
+    /// then unspecified. A smaller [`max_output`](Options::max_output) still
+    /// applies.
+    pub fn decode_into<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+        input: &[u8],
+        output: &mut [u8],
+        options: &Options,
+    ) -> crate::error::Result<usize> {
+        use crate::option::GuaranteedOption::*;
+        let capacity = output.len();
+        let mut options = *options;
+        // Stop decoding once the output no longer fits, rather than when the
+        // dictionary is flushed
+        let limited = match options.max_output {
+            Some(limit) => limit >= capacity as u64,
+            None => true,
+        };
+        if limited {
+            options.max_output = Some(capacity as u64);
+        }
+        let mut sink = crate::SliceSink {
+            buf: output,
+            pos: 0,
+            needed: 0,
+        };
+        let res = crate::lzma_decompress_with_options::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
+            &mut &input[..],
+            &mut sink,
+            &options,
+        );
+        match res {
+            Ok(()) => Ok(sink.pos),
+            Err(crate::error::Error::OutputTooLarge { .. }) if limited => {
+                Err(crate::error::Error::OutputBufferFull {
+                    needed_at_least: sink.needed.max(capacity.saturating_add(1)),
+                })
+            }
+            Err(crate::error::Error::IoError(_)) if sink.needed > capacity => {
+                Err(crate::error::Error::OutputBufferFull {
+                    needed_at_least: sink.needed,
+                })
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decompress `input` with the decoder matching its
+    /// [detected](detect_format) format, returning that format. Raw LZMA2
+    /// data is decoded with a dictionary of `DICT_MEM_LIMIT` bytes.
+    pub fn decompress_auto<W, const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+        input: &[u8],
+        output: &mut W,
+    ) -> crate::error::Result<Format>
+    where
+        W: crate::io::Write,
+    {
+        let format = match detect_format(input) {
+            crate::option::GuaranteedOption::Some(format) => format,
+            crate::option::GuaranteedOption::None => {
+                return Err(crate::error::Error::UnknownFormat)
+            }
+        };
+        let mut input = input;
+        match format {
+            Format::Lzma => {
+                crate::lzma_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(&mut input, output)?
+            }
+            Format::Lzma2 => crate::lzma2_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
+                &mut input,
+                output,
+                DICT_MEM_LIMIT.min(u32::MAX as usize) as u32,
+            )?,
+            Format::Xz => {
+                crate::xz_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(&mut input, output)?
+            }
+            Format::Lzip => {
+                crate::lzip_decompress::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(&mut input, output)?
+            }
+        }
+        Ok(format)
+    }
+
+    /// Building blocks of the LZIP format.
+    pub mod lzip {
+        pub use crate::decode::lzip::{dict_size_from_byte, LzipDecoder};
+    }
+
+    /// Building blocks of the XZ format.
+    pub mod xz {
+        pub use crate::decode::filter::{BcjX86, DeltaFilter, Filter};
+        pub use crate::decode::xz::XzDecoder;
+    }
 }
 
 /// Decompress LZMA data with default
@@ -51,6 +266,9 @@ pub fn lzma_decompress<
 }
 
 /// Decompress LZMA data with the provided options.
+///
+/// The decoder state is kept on the stack; see
+/// [`DecoderState`](decompress::raw::DecoderState) for its size.
 pub fn lzma_decompress_with_options<
     R: io::BufRead,
     W: io::Write,
@@ -61,19 +279,479 @@ pub fn lzma_decompress_with_options<
     output: &mut W,
     options: &decompress::Options,
 ) -> error::Result<()> {
-    use crate::decode::lzbuffer::LzBuffer;
-    use crate::decode::lzbuffer::LzCircularBuffer;
-    let params = decode::lzma::LzmaParams::read_header(input, options)?;
     let mut decoder =
         decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    let mut options = *options;
+    loop {
+        let (_, _, unpacked_len) =
+            match decompress_member(&mut decoder, input, output, &options, false) {
+                Err(e) if options.allow_truncated && is_truncated_member(&e) => return Ok(()),
+                res => res?,
+            };
+        if decoder.is_truncated() || !options.concatenated || decode::util::is_eof(input)? {
+            return Ok(());
+        }
+        lower_max_output(&mut options, unpacked_len)?;
+    }
+}
+
+// Sink of `decompress::decode_into`, failing writes that do not fit and
+// recording the length the output would need
+struct SliceSink<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    needed: usize,
+}
+
+impl<'a> io::Write for SliceSink<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.pos.saturating_add(buf.len());
+        if end > self.buf.len() {
+            self.needed = self.needed.max(end);
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "output buffer full",
+            ));
+        }
+        self.buf[self.pos..end].copy_from_slice(buf);
+        self.pos = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Whether a member failed because the input ended within its header or the
+// initial bytes of its range coder
+fn is_truncated_member(e: &error::Error) -> bool {
+    match e {
+        error::Error::HeaderTooShort { source, .. } => {
+            source.kind() == io::ErrorKind::UnexpectedEof
+        }
+        error::Error::LzmaError(error::lzma::LzmaError::DataStreamIsTooShort) => true,
+        _ => false,
+    }
+}
+
+/// Decompress LZMA data with the provided options, passing the output to
+/// `callback` in spans of `SPAN` bytes instead of writing it to a sink.
+///
+/// See [`io::SpanWriter`] for the batching guarantees. An error returned by
+/// `callback` aborts decoding and is returned as is.
+pub fn lzma_decompress_with_callback<
+    R: io::BufRead,
+    F: FnMut(&[u8]) -> error::Result<()>,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+    const SPAN: usize,
+>(
+    input: &mut R,
+    options: &decompress::Options,
+    callback: F,
+) -> error::Result<()> {
+    use crate::io::Write;
+    let mut sink = io::SpanWriter::<F, SPAN>::new(callback);
+    let res = lzma_decompress_with_options::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
+        input, &mut sink, options,
+    )
+    .and_then(|()| Ok(sink.flush()?));
+    match (res, sink.take_error()) {
+        (Err(error::Error::IoError(_)), option::GuaranteedOption::Some(e)) => Err(e),
+        (res, _) => res,
+    }
+}
+
+/// Decompress concatenated LZMA members, writing each one to a fresh sink
+/// obtained from `new_sink`. Returns the number of members.
+///
+/// `new_sink` is called at the start of each member. The input must hold at
+/// least one member; `options.concatenated` is ignored.
+pub fn lzma_decompress_members<
+    R: io::BufRead,
+    W: io::Write,
+    F: FnMut() -> W,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    options: &decompress::Options,
+    mut new_sink: F,
+) -> error::Result<usize> {
+    let mut decoder =
+        decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    let mut options = decompress::Options {
+        concatenated: true,
+        ..*options
+    };
+    let mut members = 0;
+    loop {
+        let (_, _, unpacked_len) =
+            decompress_member(&mut decoder, input, &mut new_sink(), &options, false)?;
+        lower_max_output(&mut options, unpacked_len)?;
+        members += 1;
+        if decode::util::is_eof(input)? {
+            return Ok(members);
+        }
+    }
+}
+
+/// Index concatenated LZMA members, passing the header and compressed byte
+/// range of each one to `callback`. Returns the number of members.
+///
+/// Finding the end of a member requires decoding it, so the members are
+/// fully decoded, but their output is discarded. The input must hold at
+/// least one member; `options.concatenated` is ignored. An error returned
+/// by `callback` aborts indexing and is returned as is.
+pub fn lzma_index_members<
+    R: io::BufRead,
+    F: FnMut(decompress::MemberInfo) -> error::Result<()>,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    options: &decompress::Options,
+    mut callback: F,
+) -> error::Result<usize> {
+    let mut decoder =
+        decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    let mut options = decompress::Options {
+        concatenated: true,
+        ..*options
+    };
+    // All output is skipped, so nothing is ever written to the sink
+    let mut sink: &mut [u8] = &mut [];
+    let mut members = 0;
+    let mut start = 0;
+    loop {
+        let (params, len, unpacked_len) =
+            decompress_member(&mut decoder, input, &mut sink, &options, true)?;
+        lower_max_output(&mut options, unpacked_len)?;
+        callback(decompress::MemberInfo {
+            params,
+            start,
+            end: start + len,
+        })?;
+        start += len;
+        members += 1;
+        if decode::util::is_eof(input)? {
+            return Ok(members);
+        }
+    }
+}
+
+/// Decompress the payload of an LZMA member whose header was read
+/// beforehand, e.g. once for many members sharing the same parameters.
+///
+/// `input` starts right after the header, and the unpacked size is taken
+/// from `params`, so `options.unpacked_size` is ignored except for
+/// [`VerifyAfterMarker`](decompress::UnpackedSize::VerifyAfterMarker).
+/// Calls share no state, so members can be decoded on separate threads.
+pub fn lzma_decompress_payload<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    output: &mut W,
+    params: &decompress::raw::LzmaParams,
+    options: &decompress::Options,
+) -> error::Result<()> {
+    let mut decoder =
+        decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    decompress_payload(&mut decoder, input, output, params.clone(), options, false)?;
+    Ok(())
+}
+
+/// Decompress a batch of LZMA payloads sharing the parameters of one
+/// `header`, in parallel, and return their outputs in order.
+///
+/// The header is parsed once with `options`, then each payload is decoded
+/// by its own decoder as with [`lzma_decompress_payload`]. The payloads
+/// share the unpacked size of the header, so members of different sizes
+/// must end with an end marker, e.g. by passing
+/// [`UnpackedSize::ReadHeaderButUseProvided(None)`](decompress::UnpackedSize::ReadHeaderButUseProvided).
+/// The first error found is returned.
+#[cfg(feature = "rayon")]
+pub fn lzma_decompress_batch<const DICT_MEM_LIMIT: usize, const PROBS_MEM_LIMIT: usize>(
+    header: &[u8],
+    payloads: &[&[u8]],
+    options: &decompress::Options,
+) -> error::Result<Vec<Vec<u8>>> {
+    use rayon::prelude::*;
+
+    let params = decompress::raw::LzmaParams::read_header(&mut &header[..], options)?;
+    payloads
+        .par_iter()
+        .map(|payload| {
+            let mut output = Vec::new();
+            lzma_decompress_payload::<_, _, DICT_MEM_LIMIT, PROBS_MEM_LIMIT>(
+                &mut &payload[..],
+                &mut output,
+                &params,
+                options,
+            )?;
+            Ok(output)
+        })
+        .collect()
+}
+
+/// Decompress exactly `compressed_len` bytes of LZMA payload from a bare
+/// [`Read`](io::Read), for tightly-framed protocols where the header was
+/// read beforehand and no [`BufRead`](io::BufRead) is available.
+///
+/// The input is buffered internally, but never past `compressed_len`, so
+/// the bytes following the payload are left in `input`. The unpacked size
+/// is taken from `params`, and the payload must end exactly at
+/// `compressed_len`, otherwise
+/// [`LzmaError::CompressedLengthMismatch`](error::lzma::LzmaError::CompressedLengthMismatch)
+/// is returned.
+pub fn lzma_decompress_exact<
+    R: io::Read,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    output: &mut W,
+    compressed_len: u64,
+    params: &decompress::raw::LzmaParams,
+) -> error::Result<()> {
+    let mut decoder =
+        decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    let options = decompress::Options {
+        unpacked_size: decompress::UnpackedSize::UseProvided(params.unpacked_size),
+        ..Default::default()
+    };
+    let mut input = io::BufTake::<_, 64>::new(input, compressed_len);
+    let (consumed, _) = decompress_payload(
+        &mut decoder,
+        &mut input,
+        output,
+        params.clone(),
+        &options,
+        false,
+    )?;
+    if consumed != compressed_len {
+        return Err(error::lzma::LzmaError::CompressedLengthMismatch {
+            compressed_len,
+            consumed,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Decompress an LZMA member whose compressed data is split into
+/// independent chunks starting at `reset_offsets`, for custom containers
+/// chunking LZMA without the LZMA2 framing.
+///
+/// Offsets are counted from the end of the header and must be strictly
+/// increasing. At each offset, the decoder is reset to a fresh state and
+/// dictionary, and a new range coder starts with its 5 initial bytes. Every
+/// chunk but the last must end exactly at the next offset, with an end
+/// marker or at the end of its range coder data, otherwise
+/// [`LzmaError::ResetOffsetMismatch`](error::lzma::LzmaError::ResetOffsetMismatch)
+/// is returned. The unpacked size, if known, covers the output of all
+/// chunks.
+pub fn lzma_decompress_with_resets<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+    reset_offsets: &[u64],
+) -> error::Result<()> {
+    let mut decoder =
+        decode::lzma::DecoderState::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    let params = decode::lzma::LzmaParams::read_header(input, options)?;
+    // Sizes apply to the whole output, not to chunks
+    let chunk_options = decompress::Options {
+        unpacked_size: decompress::UnpackedSize::ReadFromHeader,
+        ..*options
+    };
+    let mut start = 0;
+    let mut written = 0;
+    for &reset_offset in reset_offsets {
+        if reset_offset <= start {
+            return Err(error::lzma::LzmaError::ResetOffsetMismatch {
+                reset_offset,
+                consumed: start,
+            }
+            .into());
+        }
+        let mut chunk = io::Read::take(&mut *input, reset_offset - start);
+        let mut chunk_params = params.clone();
+        chunk_params.unpacked_size = option::GuaranteedOption::None;
+        let (consumed, unpacked_len) = decompress_payload(
+            &mut decoder,
+            &mut chunk,
+            output,
+            chunk_params,
+            &chunk_options,
+            false,
+        )?;
+        if start + consumed != reset_offset {
+            return Err(error::lzma::LzmaError::ResetOffsetMismatch {
+                reset_offset,
+                consumed: start + consumed,
+            }
+            .into());
+        }
+        start = reset_offset;
+        written += unpacked_len;
+    }
+
+    // The last chunk holds whatever remains of the unpacked size
+    let remaining = |size: u64| {
+        size.checked_sub(written).ok_or(
+            error::lzma::LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
+                unpacked_size: size,
+                decompressed_data: written as usize,
+                input_offset: start,
+            },
+        )
+    };
+    let mut last_params = params;
+    if let option::GuaranteedOption::Some(size) = last_params.unpacked_size {
+        last_params.unpacked_size = option::GuaranteedOption::Some(remaining(size)?);
+    }
+    let mut last_options = *options;
+    if let decompress::UnpackedSize::VerifyAfterMarker(len) = options.unpacked_size {
+        last_options.unpacked_size = decompress::UnpackedSize::VerifyAfterMarker(remaining(len)?);
+    }
+    decompress_payload(
+        &mut decoder,
+        input,
+        output,
+        last_params,
+        &last_options,
+        false,
+    )?;
+    Ok(())
+}
+
+/// Decompress a raw LZMA2 stream, made of chunks up to an end chunk, with a
+/// dictionary of `dict_size` bytes as given by its container.
+pub fn lzma2_decompress<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    output: &mut W,
+    dict_size: u32,
+) -> error::Result<()> {
+    let mut decoder =
+        decode::lzma2::Lzma2Decoder::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    decoder.decompress(input, output, dict_size)
+}
+
+/// Decompress XZ data, made of one or more streams, as produced by the `xz`
+/// tool. Blocks may use LZMA2, optionally preceded by up to three filters
+/// among the x86 BCJ filter and the delta filter, and their dictionary size
+/// must fit in `DICT_MEM_LIMIT`.
+pub fn xz_decompress<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    output: &mut W,
+) -> error::Result<()> {
+    let mut decoder =
+        decode::xz::XzDecoder::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    decoder.decompress(input, output)
+}
+
+/// Decompress LZIP data, made of one or more members, as produced by the
+/// `lzip` and `plzip` tools. The dictionary size of every member must fit
+/// in `DICT_MEM_LIMIT`.
+pub fn lzip_decompress<
+    R: io::BufRead,
+    W: io::Write,
+    const DICT_MEM_LIMIT: usize,
+    const PROBS_MEM_LIMIT: usize,
+>(
+    input: &mut R,
+    output: &mut W,
+) -> error::Result<()> {
+    let mut decoder =
+        decode::lzip::LzipDecoder::<LzCircularBuffer<DICT_MEM_LIMIT>, PROBS_MEM_LIMIT>::new();
+    decoder.decompress(input, output)
+}
+
+// Returns the header of the member, its compressed length and its unpacked
+// length
+fn decompress_member<R: io::BufRead, W: io::Write, const PROBS_MEM_LIMIT: usize>(
+    decoder: &mut decode::lzma::DecoderState<impl LzBuffer, PROBS_MEM_LIMIT>,
+    input: &mut R,
+    output: &mut W,
+    options: &decompress::Options,
+    discard_output: bool,
+) -> error::Result<(decode::lzma::LzmaParams, u64, u64)> {
+    let params = decode::lzma::LzmaParams::read_header(input, options)?;
+    let header_len = match options.unpacked_size {
+        decompress::UnpackedSize::UseProvided(_) => 5,
+        _ => 13,
+    };
+    let (payload_len, unpacked_len) = decompress_payload(
+        decoder,
+        input,
+        output,
+        params.clone(),
+        options,
+        discard_output,
+    )?;
+    Ok((params, header_len + payload_len, unpacked_len))
+}
+
+// Share the output limit among members: the next ones may only decode what
+// the previous ones left
+fn lower_max_output(options: &mut decompress::Options, unpacked_len: u64) -> error::Result<()> {
+    if let option::GuaranteedOption::Some(limit) = &mut options.max_output {
+        *limit = limit
+            .checked_sub(unpacked_len)
+            .ok_or(error::Error::OutputTooLarge { limit: *limit })?;
+    }
+    Ok(())
+}
+
+// Returns the compressed and unpacked lengths of the payload
+fn decompress_payload<R: io::BufRead, W: io::Write, const PROBS_MEM_LIMIT: usize>(
+    decoder: &mut decode::lzma::DecoderState<impl LzBuffer, PROBS_MEM_LIMIT>,
+    input: &mut R,
+    output: &mut W,
+    params: decode::lzma::LzmaParams,
+    options: &decompress::Options,
+    discard_output: bool,
+) -> error::Result<(u64, u64)> {
+    let empty = params.unpacked_size == option::GuaranteedOption::Some(0);
     decoder.reset();
     decoder.set_params(params)?;
+    if discard_output {
+        decoder.skip_output(u64::MAX);
+    }
+    decoder.set_options(options);
 
+    // An empty output may be stored as a bare header, without the initial
+    // bytes of the range coder
+    if empty && decode::util::is_eof(input)? {
+        return Ok((0, 0));
+    }
     let mut rangecoder = decode::rangecoder::RangeDecoder::new(input)
         .map_err(|_| error::lzma::LzmaError::DataStreamIsTooShort)?;
     decoder.process(output, &mut rangecoder)?;
+    let unpacked_len = decoder.output.len() as u64;
     decoder.output.finish(output)?;
-    Ok(())
+    Ok((rangecoder.bytes_consumed(), unpacked_len))
 }
 
 /// Compresses data with LZMA and default
@@ -105,9 +783,11 @@ pub mod option {
     /// allows to achieve guaranteed 0-initializable
     /// [`crate::decompress::Stream`] with `Option::None` variant being 0
     #[repr(C)]
-    #[derive(PartialEq, PartialOrd, Eq, Ord, Debug, Hash)]
+    #[derive(PartialEq, PartialOrd, Eq, Ord, Debug, Hash, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub enum GuaranteedOption<T> {
         /// No value
+        #[default]
         None,
         /// Some value `T`
         Some(T),
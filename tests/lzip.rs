@@ -0,0 +1,143 @@
+#![cfg(feature = "std")]
+
+use lzma_rs::check::Crc32;
+use lzma_rs::error::lzip::LzipError;
+use lzma_rs::error::{Error, HeaderField};
+use std::io::Read;
+
+/// Utility function to read a file into memory
+fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::fs::File::open(filename).and_then(|mut file| file.read_to_end(&mut data))?;
+    Ok(data)
+}
+
+fn decompress(input: &[u8]) -> lzma_rs::error::Result<Vec<u8>> {
+    let mut decomp = Vec::new();
+    lzma_rs::lzip_decompress::<_, _, 4096, 8>(&mut &input[..], &mut decomp)?;
+    Ok(decomp)
+}
+
+fn assert_lzip_error(input: &[u8], expected: LzipError) {
+    match decompress(input) {
+        Err(Error::LzipError(e)) => assert_eq!(e, expected),
+        res => panic!("expected {:?}, got {:?}", expected, res),
+    }
+}
+
+/// Wrap the LZMA stream of a `.lzma` file with an end marker into an LZIP
+/// member with a 4 KiB dictionary.
+fn lzip_member(lzma_file: &str, data: &[u8]) -> Vec<u8> {
+    let lzma = read_all_file(lzma_file).unwrap();
+    let mut member = b"LZIP\x01\x0C".to_vec();
+    member.extend_from_slice(&lzma[13..]);
+    member.extend_from_slice(&Crc32::checksum(data).to_le_bytes());
+    member.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    member.extend_from_slice(&(member.len() as u64 + 8).to_le_bytes());
+    member
+}
+
+#[test]
+fn decompress_file() {
+    // Checked with `xz --format=lzip -d`
+    let compressed = read_all_file("tests/files/foo.txt.lz").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    assert_eq!(decompress(&compressed).unwrap(), expected);
+    assert_eq!(
+        lzip_member("tests/files/foo.txt.lzma", &expected),
+        compressed
+    );
+}
+
+#[test]
+fn decompress_members() {
+    let foo = read_all_file("tests/files/foo.txt").unwrap();
+    let hello = read_all_file("tests/files/hello.txt").unwrap();
+    let mut compressed = lzip_member("tests/files/foo.txt.lzma", &foo);
+    compressed.extend(lzip_member("tests/files/hello.txt.lzma", &hello));
+    compressed.extend(lzip_member("tests/files/foo.txt.lzma", &foo));
+    assert_eq!(
+        decompress(&compressed).unwrap(),
+        [&foo[..], &hello[..], &foo[..]].concat()
+    );
+}
+
+#[test]
+fn invalid_header() {
+    let hello = read_all_file("tests/files/hello.txt").unwrap();
+    let member = lzip_member("tests/files/hello.txt.lzma", &hello);
+    assert_eq!(decompress(&member).unwrap(), hello);
+
+    let mut corrupted = member.clone();
+    corrupted[3] = b'Z';
+    assert_lzip_error(&corrupted, LzipError::InvalidMagic);
+    let mut corrupted = member.clone();
+    corrupted[4] = 0;
+    assert_lzip_error(&corrupted, LzipError::UnsupportedVersion { version: 0 });
+    let mut corrupted = member.clone();
+    corrupted[5] = 0x2C;
+    assert_lzip_error(&corrupted, LzipError::InvalidDictionarySize { byte: 0x2C });
+    // A dictionary larger than the buffer
+    let mut corrupted = member;
+    corrupted[5] = 0x0D;
+    assert!(matches!(
+        decompress(&corrupted),
+        Err(Error::DictionaryBufferTooSmall {
+            needed: 8192,
+            available: 4096
+        })
+    ));
+
+    assert!(matches!(
+        decompress(b"LZIP\x01"),
+        Err(Error::HeaderTooShort {
+            field: HeaderField::StreamHeader,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn invalid_trailer() {
+    let hello = read_all_file("tests/files/hello.txt").unwrap();
+    let member = lzip_member("tests/files/hello.txt.lzma", &hello);
+    let trailer = member.len() - 20;
+    let crc = Crc32::checksum(&hello);
+
+    let mut corrupted = member.clone();
+    corrupted[trailer] ^= 1;
+    assert_lzip_error(
+        &corrupted,
+        LzipError::CrcMismatch {
+            expected: crc ^ 1,
+            found: crc,
+        },
+    );
+    let mut corrupted = member.clone();
+    corrupted[trailer + 4] += 1;
+    assert_lzip_error(
+        &corrupted,
+        LzipError::DataSizeMismatch {
+            expected: 13,
+            found: 12,
+        },
+    );
+    let mut corrupted = member.clone();
+    corrupted[trailer + 12] -= 1;
+    assert_lzip_error(
+        &corrupted,
+        LzipError::MemberSizeMismatch {
+            expected: member.len() as u64 - 1,
+            found: member.len() as u64,
+        },
+    );
+
+    // Truncated trailer, and trailing garbage
+    assert!(matches!(
+        decompress(&member[..member.len() - 1]),
+        Err(Error::IoError(_))
+    ));
+    let mut garbage = member;
+    garbage.extend_from_slice(b"garbage");
+    assert_lzip_error(&garbage, LzipError::InvalidMagic);
+}
@@ -3,7 +3,6 @@ extern crate lzma;
 
 #[cfg(feature = "log")]
 use log::{debug, info};
-use lzma_rs::option::GuaranteedOption as Option;
 use lzma_rs::option::GuaranteedOption::*;
 use std::io::Read;
 
@@ -164,6 +163,41 @@ fn decompress_short_header() {
     lzma_rs::lzma_decompress::<_, _, 4096, 66>(&mut (b"" as &[u8]), &mut decomp).unwrap();
 }
 
+#[test]
+fn error_display_and_source() {
+    use std::error::Error as _;
+
+    let err = lzma_rs::lzma_decompress::<_, _, 4096, 66>(&mut (b"" as &[u8]), &mut Vec::new())
+        .unwrap_err();
+    assert_eq!(err.to_string(), "header too short, reading the properties");
+    let source = err.source().expect("io::Error source");
+    assert_eq!(
+        source.downcast_ref::<std::io::Error>().unwrap().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+
+    let err: lzma_rs::error::Error = lzma_rs::error::lzma::LzmaError::InvalidProperties {
+        lc: 9,
+        lp: 0,
+        pb: 2,
+    }
+    .into();
+    assert_eq!(
+        err.to_string(),
+        "invalid properties lc=9, lp=0, pb=2 (lc must be <= 8, lp and pb <= 4)"
+    );
+    assert!(err.source().is_none());
+
+    // Works with `?` in functions returning a boxed error
+    fn boxed() -> Result<(), Box<dyn std::error::Error>> {
+        Err(lzma_rs::error::Error::UnknownFormat)?
+    }
+    assert_eq!(
+        boxed().unwrap_err().to_string(),
+        "unknown compression format"
+    );
+}
+
 #[test]
 fn round_trip_basics() {
     #[cfg(feature = "log")]
@@ -189,6 +223,69 @@ fn round_trip_files() {
     round_trip_file("tests/files/range-coder-edge-case");
 }
 
+fn lzma_encode<const DICT_SIZE: usize>(x: &[u8], options: &lzma_rs::compress::Options) -> Vec<u8> {
+    let mut encoder = Box::new(lzma_rs::compress::LzmaEncoder::<DICT_SIZE>::new());
+    let mut compressed = Vec::new();
+    encoder.compress(x, &mut compressed, options).unwrap();
+    compressed
+}
+
+#[test]
+fn lzma_encoder_round_trip() {
+    #[cfg(feature = "log")]
+    let _ = env_logger::try_init();
+    let pattern: Vec<u8> = (0..100_000u64).map(|i| (i * i / 7 % 251) as u8).collect();
+    let inputs = [
+        Vec::new(),
+        b"a".to_vec(),
+        b"Hello world".to_vec(),
+        vec![0x00; 100_000],
+        pattern,
+        read_all_file("tests/files/foo.txt").unwrap(),
+        read_all_file("tests/files/range-coder-edge-case").unwrap(),
+        read_all_file("tests/files/dict-wraparound").unwrap(),
+    ];
+    for x in &inputs {
+        let compressed = lzma_encode::<4096>(x, &lzma_rs::compress::Options::default());
+        assert_decomp_eq::<4096>(&compressed, x, /* compare_to_liblzma */ true);
+
+        // Without the end marker, a canonical encoding
+        let encode_options = lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
+                Some(x.len() as u64).into(),
+            ),
+        };
+        let compressed = lzma_encode::<1000>(x, &encode_options);
+        assert_eq!(compressed[1..5], 1000u32.to_le_bytes());
+        let decode_options = lzma_rs::decompress::Options {
+            strict_canonical: true,
+            max_reference_distance: Some(1000),
+            ..Default::default()
+        };
+        let decomp =
+            lzma_rs::decompress::decompress_to_vec::<4096, 8>(&compressed, &decode_options)
+                .unwrap();
+        assert_eq!(&decomp, x);
+    }
+
+    // Matches make the output much smaller than with literals only
+    let x = read_all_file("tests/files/foo.txt").unwrap();
+    let mut literals = Vec::new();
+    lzma_rs::lzma_compress(&mut x.as_slice(), &mut literals).unwrap();
+    let compressed = lzma_encode::<65536>(&x, &Default::default());
+    assert!(compressed.len() < literals.len() * 3 / 4);
+}
+
+#[test]
+fn lzma_encoder_write_header() {
+    use lzma_rs::decompress::raw::LzmaParams;
+    let compressed = read_all_file("tests/files/hello.txt.lzma").unwrap();
+    let params = LzmaParams::read_header(&mut compressed.as_slice(), &Default::default()).unwrap();
+    let mut header = Vec::new();
+    params.write_header(&mut header).unwrap();
+    assert_eq!(header, compressed[..13]);
+}
+
 #[test]
 fn decompress_big_file() {
     #[cfg(feature = "log")]
@@ -216,6 +313,211 @@ fn decompress_range_coder_edge_case() {
     assert_decomp_eq::<4096>(&compressed, &expected, /* compare_to_liblzma */ true);
 }
 
+#[test]
+fn decompress_dict_wraparound() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::option::GuaranteedOption;
+
+    // Counts the matches whose source or destination range crosses the end
+    // of the ring
+    struct Wraparounds {
+        inner: LzCircularBuffer<4096>,
+        count: usize,
+    }
+
+    impl LzBuffer for Wraparounds {
+        fn set_dict_size(&mut self, dict_size: usize) -> lzma_rs::error::Result<()> {
+            self.inner.set_dict_size(dict_size)
+        }
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+        fn last_or(&self, lit: u8) -> u8 {
+            self.inner.last_or(lit)
+        }
+        fn last_n(&self, dist: usize) -> lzma_rs::error::Result<u8> {
+            self.inner.last_n(dist)
+        }
+        fn append_literal(
+            &mut self,
+            stream: &mut dyn std::io::Write,
+            lit: u8,
+        ) -> lzma_rs::error::Result<()> {
+            self.inner.append_literal(stream, lit)
+        }
+        fn append_lz(
+            &mut self,
+            stream: &mut dyn std::io::Write,
+            len: usize,
+            dist: usize,
+        ) -> lzma_rs::error::Result<()> {
+            let dst = self.inner.len() % 4096;
+            let src = (dst + 4096 - dist % 4096) % 4096;
+            if dst + len > 4096 || src + len > 4096 {
+                self.count += 1;
+            }
+            self.inner.append_lz(stream, len, dist)
+        }
+        fn discard_next(&mut self, count: usize) {
+            self.inner.discard_next(count)
+        }
+        fn finish(&mut self, stream: &mut dyn std::io::Write) -> std::io::Result<()> {
+            self.inner.finish(stream)
+        }
+        fn set_fill(&mut self, fill: GuaranteedOption<u8>) {
+            self.inner.set_fill(fill)
+        }
+        fn set_preset(&mut self, dict: &[u8]) -> lzma_rs::error::Result<usize> {
+            self.inner.set_preset(dict)
+        }
+        fn reset(&mut self) {
+            self.inner.reset()
+        }
+    }
+
+    // Generated with liblzma and a 4096-byte dictionary from data repeating
+    // itself at distances up to the dictionary size, see the README
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    assert_decomp_eq::<4096>(&compressed, &expected, /* compare_to_liblzma */ true);
+
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = Box::new(DecoderState::<_, 8, _>::with_prob_model(
+        Wraparounds {
+            inner: LzCircularBuffer::new(),
+            count: 0,
+        },
+        lzma_rs::decompress::raw::ArrayProbModel::<8>::new(),
+    ));
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    assert!(decoder.output.count > 0);
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn decompress_conformance_vectors() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::option::GuaranteedOption;
+
+    // Records the longest match distance and the literals decoded right
+    // after a match, i.e. with the matched literal coder
+    struct Coverage {
+        inner: LzCircularBuffer<65536>,
+        max_dist: usize,
+        matched_literals: usize,
+        after_match: bool,
+    }
+
+    impl LzBuffer for Coverage {
+        fn set_dict_size(&mut self, dict_size: usize) -> lzma_rs::error::Result<()> {
+            self.inner.set_dict_size(dict_size)
+        }
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+        fn last_or(&self, lit: u8) -> u8 {
+            self.inner.last_or(lit)
+        }
+        fn last_n(&self, dist: usize) -> lzma_rs::error::Result<u8> {
+            self.inner.last_n(dist)
+        }
+        fn append_literal(
+            &mut self,
+            stream: &mut dyn std::io::Write,
+            lit: u8,
+        ) -> lzma_rs::error::Result<()> {
+            if self.after_match {
+                self.matched_literals += 1;
+                self.after_match = false;
+            }
+            self.inner.append_literal(stream, lit)
+        }
+        fn append_lz(
+            &mut self,
+            stream: &mut dyn std::io::Write,
+            len: usize,
+            dist: usize,
+        ) -> lzma_rs::error::Result<()> {
+            self.max_dist = self.max_dist.max(dist);
+            self.after_match = true;
+            self.inner.append_lz(stream, len, dist)
+        }
+        fn discard_next(&mut self, count: usize) {
+            self.inner.discard_next(count)
+        }
+        fn finish(&mut self, stream: &mut dyn std::io::Write) -> std::io::Result<()> {
+            self.inner.finish(stream)
+        }
+        fn set_fill(&mut self, fill: GuaranteedOption<u8>) {
+            self.inner.set_fill(fill)
+        }
+        fn set_preset(&mut self, dict: &[u8]) -> lzma_rs::error::Result<usize> {
+            self.inner.set_preset(dict)
+        }
+        fn reset(&mut self) {
+            self.inner.reset()
+        }
+    }
+
+    // Returns the header and the longest match distance and matched literal
+    // count
+    fn decode(name: &str) -> (LzmaParams, usize, usize) {
+        let compressed = read_all_file(&format!("tests/files/{}.lzma", name)).unwrap();
+        let expected = read_all_file(&format!("tests/files/{}", name)).unwrap();
+        let options = lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::ReadFromHeader,
+            ..Default::default()
+        };
+
+        let mut input = compressed.as_slice();
+        let params = LzmaParams::read_header(&mut input, &options).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decoder = Box::new(DecoderState::<_, 8, _>::with_prob_model(
+            Coverage {
+                inner: LzCircularBuffer::new(),
+                max_dist: 0,
+                matched_literals: 0,
+                after_match: false,
+            },
+            lzma_rs::decompress::raw::ArrayProbModel::<8>::new(),
+        ));
+        decoder.reset();
+        decoder.set_params(params.clone()).unwrap();
+        let mut decomp = Vec::new();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+        assert!(input.is_empty(), "{}: trailing input", name);
+        assert_eq!(decomp, expected, "{}", name);
+
+        (
+            params,
+            decoder.output.max_dist,
+            decoder.output.matched_literals,
+        )
+    }
+
+    // Reference vectors produced by liblzma, see the README
+    let (params, _, matched_literals) = decode("conformance-sized");
+    assert!(matches!(params.unpacked_size, Some(20245)));
+    assert!(matched_literals > 0);
+
+    let (params, max_dist, matched_literals) = decode("conformance-marker");
+    assert!(matches!(params.unpacked_size, None));
+    assert_eq!(max_dist, params.dict_size as usize);
+    assert!(matched_literals > 0);
+}
+
 #[test]
 fn decompress_empty_world() {
     #[cfg(feature = "log")]
@@ -228,6 +530,56 @@ fn decompress_empty_world() {
     );
 }
 
+#[test]
+fn decompress_empty_sized() {
+    // Unpacked size 0, with and without an end marker, and as a bare header
+    let sized = b"\x5d\x00\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+    let marker = b"\x00\x83\xff\xfb\xff\xff\xc0\x00\x00\x00";
+    for payload in [&b"\x00\x00\x00\x00\x00"[..], &marker[..], &[][..]] {
+        let compressed = [&sized[..], payload].concat();
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut &compressed[..], &mut decomp).unwrap();
+        assert!(decomp.is_empty());
+        // liblzma needs the initial bytes of the range coder
+        if !payload.is_empty() {
+            assert!(lzma::decompress(&compressed).unwrap().is_empty());
+        }
+
+        let options = lzma_rs::decompress::Options {
+            verify_end_after_size: true,
+            ..Default::default()
+        };
+        let decomp =
+            lzma_rs::decompress::decompress_to_vec::<4096, 8>(&compressed, &options).unwrap();
+        assert!(decomp.is_empty());
+
+        #[cfg(feature = "stream")]
+        {
+            let mut reader =
+                lzma_rs::decompress::LzmaReader::<_, 4096, 8>::new(&compressed[..], &options)
+                    .unwrap();
+            let mut decomp = Vec::new();
+            reader.read_to_end(&mut decomp).unwrap();
+            assert!(decomp.is_empty());
+        }
+    }
+    // A `Stream` stops at the unpacked size, so the end marker would be left
+    // unread
+    assert_decomp_eq::<4096>(&[&sized[..], &[0; 5]].concat(), b"", true);
+    assert_decomp_eq::<4096>(sized, b"", false);
+
+    // Without a known size, the range coder is needed to find the end marker
+    assert!(matches!(
+        lzma_rs::lzma_decompress::<_, _, 4096, 8>(
+            &mut &b"\x5d\x00\x10\x00\x00\xff\xff\xff\xff\xff\xff\xff\xff"[..],
+            &mut Vec::new()
+        ),
+        Err(lzma_rs::error::Error::LzmaError(
+            lzma_rs::error::lzma::LzmaError::DataStreamIsTooShort
+        ))
+    ));
+}
+
 #[test]
 fn decompress_hello_world() {
     #[cfg(feature = "log")]
@@ -328,6 +680,50 @@ fn unpacked_size_write_none_to_header_and_use_provided_none_on_read() {
     assert_round_trip_with_options(&data[..], &encode_options, &decode_options);
 }
 
+#[test]
+fn properties_provided() {
+    let expected = read_all_file("tests/files/hello.txt").unwrap();
+    let mut compressed = read_all_file("tests/files/hello.txt.lzma").unwrap();
+    assert_eq!(compressed[0], 0x5D);
+    // The output fits a smaller dictionary
+    compressed[1..5].copy_from_slice(&[0x00, 0x10, 0x00, 0x00]);
+    let options = lzma_rs::decompress::Options {
+        properties: Some((3, 0, 2)),
+        ..Default::default()
+    };
+    // Wrong and invalid properties bytes are read but ignored
+    for props in [0x5D, 0x00, 225, 0xFF] {
+        compressed[0] = props;
+        let decomp = lzma_rs::decompress::decompress_to_vec::<4096, 8>(&compressed, &options);
+        assert_eq!(decomp.unwrap(), expected);
+    }
+    assert!(matches!(
+        lzma_rs::decompress::decompress_to_vec::<4096, 8>(&compressed, &Default::default()),
+        Err(lzma_rs::error::Error::LzmaError(
+            lzma_rs::error::lzma::LzmaError::InvalidHeader { .. }
+        ))
+    ));
+
+    // Provided properties are still checked
+    let options = lzma_rs::decompress::Options {
+        properties: Some((9, 0, 2)),
+        ..Default::default()
+    };
+    assert!(matches!(
+        lzma_rs::decompress::decompress_to_vec::<4096, 8>(&compressed, &options),
+        Err(lzma_rs::error::Error::LzmaError(
+            lzma_rs::error::lzma::LzmaError::InvalidProperties { lc: 9, .. }
+        ))
+    ));
+    assert!(matches!(
+        lzma_rs::decompress::decompress_to_vec::<4096, 8>(&compressed[..0], &options),
+        Err(lzma_rs::error::Error::HeaderTooShort {
+            field: lzma_rs::error::HeaderField::Properties,
+            ..
+        })
+    ));
+}
+
 #[test]
 fn memlimit() {
     let data = b"Some data";
@@ -420,3 +816,3149 @@ fn memlimit() {
         }
     }
 }
+
+#[test]
+fn observed_ratio() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    assert_eq!(decoder.observed_ratio(), None);
+
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    // Everything past the 13-byte header has been consumed.
+    assert_eq!(rangecoder.bytes_consumed(), compressed.len() as u64 - 13);
+    assert_eq!(
+        decoder.observed_ratio(),
+        Some((compressed.len() - 13) as f32 / expected.len() as f32)
+    );
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn skip_output() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    // Skip less than, exactly and more than one dictionary worth of output.
+    for &skip in &[0, 1, 1000, 4096, 10_000, expected.len(), expected.len() + 1] {
+        let mut input = compressed.as_slice();
+        let params =
+            LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+        let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        decoder.skip_output(skip as u64);
+
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+        assert_eq!(decomp, &expected[skip.min(expected.len())..]);
+    }
+}
+
+#[test]
+fn api_misuse_is_an_error() {
+    use lzma_rs::decompress::raw::{DecoderState, LzCircularBuffer, LzmaParams, RangeDecoder};
+    use lzma_rs::error::lzma::LzmaError;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    match decoder.set_params(params.clone()) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::DecoderUninitialized)) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+    match decoder.process(&mut decomp, &mut rangecoder) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::DecoderUninitialized)) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+
+    decoder.reset();
+    match decoder.process(&mut decomp, &mut rangecoder) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::ParamsNotSet)) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+    let mut invalid = params.clone();
+    invalid.lc = 9;
+    match decoder.set_params(invalid) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::InvalidProperties { lc: 9, .. })) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+    assert!(decomp.is_empty());
+
+    #[cfg(feature = "stream")]
+    {
+        let mut stream = lzma_rs::decompress::Stream::<4096, 8>::new();
+        match stream.write_all(&mut decomp, &compressed) {
+            Err(lzma_rs::error::Error::StreamError(
+                lzma_rs::error::stream::StreamError::Uninitialized,
+            )) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn match_stats() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    let stats = decoder.match_stats();
+    assert!(stats.literals > 0);
+    assert_eq!(stats.lengths[0], 0);
+    let matched: u64 = stats
+        .lengths
+        .iter()
+        .enumerate()
+        .map(|(len, count)| len as u64 * count)
+        .sum();
+    assert_eq!(stats.literals + matched, expected.len() as u64);
+    assert_eq!(stats.matched_bytes(), matched);
+    assert!(stats.new_matches() > 0);
+    assert_eq!(stats.new_matches() + stats.rep_matches(), stats.matches());
+    assert!(stats.lengths[1] <= stats.reps[0]);
+
+    decoder.reset();
+    assert_eq!(decoder.match_stats(), &Default::default());
+}
+
+#[cfg(feature = "stats")]
+#[test]
+fn match_stats_counts() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    // "abc" repeated 100 times, as compressed by xz with a 4 KiB dictionary:
+    // 3 literals, a match of 273 bytes at distance 3, and a rep match of the
+    // last 24 bytes
+    let compressed = [
+        0x5d, 0x00, 0x10, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x30,
+        0x98, 0x88, 0xad, 0x4b, 0x36, 0xed, 0x2f, 0x80, 0x7b, 0xff, 0xff, 0xf6, 0xb0, 0x40, 0x00,
+    ];
+    let mut input = &compressed[..];
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, b"abc".repeat(100));
+
+    let stats = decoder.match_stats();
+    assert_eq!(stats.literals, 3);
+    assert_eq!(stats.matches(), 2);
+    assert_eq!(stats.lengths[273], 1);
+    assert_eq!(stats.lengths[24], 1);
+    assert_eq!(stats.matched_bytes(), 297);
+    assert_eq!(stats.new_matches(), 1);
+    // Distance 3 is coded as 2, in slot 2
+    assert_eq!(stats.distance_slots[2], 1);
+    assert_eq!(stats.reps, [1, 0, 0, 0]);
+}
+
+#[test]
+fn decompress_with_small_stack() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    // The decoding path does not recurse and keeps no large locals; only the
+    // decoder state itself is big, so it is allocated outside the thread.
+    const STACK_SIZE: usize = 16 * 1024;
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    let handle = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            for file in &["tests/files/foo.txt", "tests/files/range-coder-edge-case"] {
+                let compressed = read_all_file(&format!("{}.lzma", file)).unwrap();
+                let expected = read_all_file(file).unwrap();
+                let mut input = compressed.as_slice();
+                let params =
+                    LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default())
+                        .unwrap();
+                let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+                let mut decomp = Vec::new();
+                decoder.reset();
+                decoder.set_params(params).unwrap();
+                decoder.process(&mut decomp, &mut rangecoder).unwrap();
+                decoder.output.finish(&mut decomp).unwrap();
+                assert_eq!(decomp, expected);
+            }
+        })
+        .unwrap();
+    handle.join().unwrap();
+}
+
+#[test]
+fn decompress_with_transform() {
+    use lzma_rs::io::TransformWriter;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut sink = TransformWriter::new(Vec::new(), |b| b ^ 0x5A);
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut sink).unwrap();
+    let scrambled = sink.into_inner();
+    assert_eq!(scrambled.len(), expected.len());
+    assert_ne!(scrambled, expected);
+
+    // XOR is its own inverse
+    let mut sink = TransformWriter::new(Vec::new(), |b| b ^ 0x5A);
+    std::io::Write::write_all(&mut sink, &scrambled).unwrap();
+    assert_eq!(sink.into_inner(), expected);
+}
+
+#[test]
+fn decompress_validating_utf8() {
+    use lzma_rs::io::Utf8ValidatingWriter;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut sink = Utf8ValidatingWriter::new(Vec::new());
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut sink).unwrap();
+    assert_eq!(sink.finish().unwrap(), expected);
+
+    // Decoding stops at the first invalid byte
+    let mut data = b"valid text, then ".repeat(100);
+    let invalid_at = data.len() as u64;
+    data.extend_from_slice(b"\xff garbage");
+    data.resize(data.len() + 100_000, b'x');
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut data.as_slice(), &mut compressed).unwrap();
+    let mut sink = Utf8ValidatingWriter::new(Vec::new());
+    let res = lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut sink);
+    assert!(matches!(res, Err(lzma_rs::error::Error::IoError(e))
+        if e.kind() == std::io::ErrorKind::InvalidData));
+    assert_eq!(sink.valid_len(), invalid_at);
+}
+
+#[test]
+fn eos_marker_offset() {
+    use lzma_rs::decompress::raw::{DecoderState, LzCircularBuffer, LzmaParams, RangeDecoder};
+
+    for file in &[
+        "tests/files/foo.txt.lzma",
+        "tests/files/range-coder-edge-case.lzma",
+    ] {
+        let compressed = read_all_file(file).unwrap();
+        let mut input = compressed.as_slice();
+        let params =
+            LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+
+        let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        assert_eq!(decoder.eos_marker_offset(), None);
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        // The marker is the last symbol of these files
+        assert_eq!(
+            decoder.eos_marker_offset(),
+            Some(compressed.len() as u64 - 13)
+        );
+
+        decoder.reset();
+        assert_eq!(decoder.eos_marker_offset(), None);
+    }
+}
+
+#[test]
+fn error_offsets() {
+    use lzma_rs::error::lzma::LzmaError;
+
+    let mut compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    // The end marker is the last symbol, followed here by garbage
+    let marker_end = compressed.len() as u64 - 13;
+    compressed.extend_from_slice(b"garbage");
+
+    let err =
+        lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut Vec::new())
+            .unwrap_err();
+    assert!(
+        matches!(
+            err,
+            lzma_rs::error::Error::LzmaError(LzmaError::EosFoundButMoreBytesAvailable { .. })
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.offset(), Some((marker_end, expected.len() as u64)));
+
+    #[cfg(feature = "stream")]
+    for chunk_size in [7, 100, compressed.len()] {
+        let mut stream = lzma_rs::decompress::Stream::<4096, 8>::new();
+        stream.reset();
+        let mut sink = Vec::new();
+        let err = compressed
+            .chunks(chunk_size)
+            .try_for_each(|chunk| stream.write_all(&mut sink, chunk))
+            .and_then(|()| stream.finish(&mut sink))
+            .unwrap_err();
+        assert_eq!(
+            err.offset(),
+            Some((marker_end, expected.len() as u64)),
+            "chunk size {}: {:?}",
+            chunk_size,
+            err
+        );
+    }
+
+    // The output offset is the decoded length when it does not match
+    let err = lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut read_all_file("tests/files/foo.txt.lzma")
+            .unwrap()
+            .as_slice(),
+        &mut Vec::new(),
+        &lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::VerifyAfterMarker(1),
+            ..Default::default()
+        },
+    )
+    .unwrap_err();
+    assert_eq!(err.offset(), Some((marker_end, expected.len() as u64)));
+    assert!(err.to_string().contains("input offset"), "{}", err);
+}
+
+#[test]
+fn decompress_concatenated() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct SharedSink(Rc<RefCell<Vec<u8>>>);
+
+    impl std::io::Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let foo = read_all_file("tests/files/foo.txt").unwrap();
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    compressed.extend(read_all_file("tests/files/foo.txt.lzma").unwrap());
+
+    // Without the option, the second member is trailing garbage
+    let mut decomp = Vec::new();
+    assert!(
+        lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut decomp).is_err()
+    );
+
+    let options = lzma_rs::decompress::Options {
+        concatenated: true,
+        ..Default::default()
+    };
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(decomp, [&b"Hello world"[..], &foo].concat());
+
+    // Members ending at their unpacked size, with or without end marker
+    let encode_options = lzma_rs::compress::Options {
+        unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(Some(3).into()),
+    };
+    let mut all = compressed.clone();
+    lzma_rs::lzma_compress_with_options(&mut &b"abc"[..], &mut all, &encode_options).unwrap();
+    all.extend_from_slice(b"\x5d\x00\x10\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+    all.extend_from_slice(&[0; 5]);
+    all.extend(read_all_file("tests/files/foo.txt.lzma").unwrap());
+    let decomp = lzma_rs::decompress::decode_all::<_, 4096, 8>(
+        &mut all.as_slice(),
+        &lzma_rs::decompress::Options::default(),
+    )
+    .unwrap();
+    assert_eq!(decomp, [&b"Hello world"[..], &foo, b"abc", &foo].concat());
+
+    let outputs = RefCell::new(Vec::new());
+    let members = lzma_rs::lzma_decompress_members::<_, _, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &lzma_rs::decompress::Options::default(),
+        || {
+            let sink = Rc::new(RefCell::new(Vec::new()));
+            outputs.borrow_mut().push(sink.clone());
+            SharedSink(sink)
+        },
+    )
+    .unwrap();
+    assert_eq!(members, 2);
+    let outputs = outputs.into_inner();
+    assert_eq!(outputs.len(), 2);
+    assert_eq!(*outputs[0].borrow(), b"Hello world");
+    assert_eq!(*outputs[1].borrow(), foo);
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn pending_input_len() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    assert_eq!(decoder.pending_input_len(), 0);
+
+    // Feed the range coder data one byte at a time: incomplete symbols are
+    // held back in the partial input buffer
+    let (init, rest) = input.split_at(5);
+    let mut init = init;
+    let rangecoder = RangeDecoder::new(&mut init).unwrap();
+    let (mut range, mut code) = (rangecoder.range, rangecoder.code);
+    let mut decomp = Vec::new();
+    let mut max_pending = 0;
+    for byte in rest.chunks(1) {
+        let mut chunk = byte;
+        let mut rangecoder = RangeDecoder::from_parts(&mut chunk, range, code);
+        decoder
+            .process_stream(&mut decomp, &mut rangecoder)
+            .unwrap();
+        range = rangecoder.range;
+        code = rangecoder.code;
+        assert!(decoder.pending_input_len() <= 20);
+        max_pending = max_pending.max(decoder.pending_input_len());
+    }
+    assert!(max_pending > 0);
+    assert_eq!(decoder.pending_input_len(), 0);
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn input_buf_size() {
+    use lzma_rs::decompress::raw::{
+        ArrayProbModel, DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, NoTrace,
+        RangeDecoder, MAX_REQUIRED_INPUT,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder =
+        DecoderState::<LzCircularBuffer<4096>, 8, ArrayProbModel<8>, NoTrace, 64>::with_trace_sink(
+            NoTrace,
+        );
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+
+    // Chunks straddling the partial input buffer in both directions
+    let (init, rest) = input.split_at(5);
+    let mut init = init;
+    let rangecoder = RangeDecoder::new(&mut init).unwrap();
+    let (mut range, mut code) = (rangecoder.range, rangecoder.code);
+    let mut decomp = Vec::new();
+    for chunk in rest.chunks(MAX_REQUIRED_INPUT + 3) {
+        for mut chunk in [&chunk[..1], &chunk[1..]] {
+            let mut rangecoder = RangeDecoder::from_parts(&mut chunk, range, code);
+            decoder
+                .process_stream(&mut decomp, &mut rangecoder)
+                .unwrap();
+            range = rangecoder.range;
+            code = rangecoder.code;
+            assert!(decoder.pending_input_len() <= 64);
+        }
+    }
+    assert_eq!(decoder.pending_input_len(), 0);
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn total_in_out() {
+    use lzma_rs::decompress::raw::{DecoderState, LzCircularBuffer, LzmaParams, RangeDecoder};
+
+    // A stream with an end marker embedded in a larger container
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut container = compressed.clone();
+    container.extend_from_slice(&[0; 5]);
+    container.extend_from_slice(b"trailer");
+    let mut input = container.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    // Accept the bytes following the end marker
+    decoder.set_concatenated(true);
+    assert_eq!((decoder.total_in(), decoder.total_out()), (0, 0));
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    decoder.process(&mut Vec::new(), &mut rangecoder).unwrap();
+    assert_eq!(decoder.total_in(), compressed.len() as u64 - 13);
+    assert_eq!(decoder.total_out(), expected.len() as u64);
+    assert_eq!(
+        &container[13 + decoder.total_in() as usize..],
+        b"\0\0\0\0\0trailer"
+    );
+
+    // In streaming mode, the counters accumulate across calls and include
+    // the bytes held back. The initial bytes are read by a range decoder
+    // never passed to the decoder, so they are not counted.
+    #[cfg(feature = "stream")]
+    {
+        let mut input = compressed.as_slice();
+        let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        let (init, rest) = input.split_at(5);
+        let mut init = init;
+        let rangecoder = RangeDecoder::new(&mut init).unwrap();
+        let (mut range, mut code) = (rangecoder.range, rangecoder.code);
+        let mut fed = 0;
+        for chunk in rest.chunks(7) {
+            fed += chunk.len() as u64;
+            let mut chunk = chunk;
+            let mut rangecoder = RangeDecoder::from_parts(&mut chunk, range, code);
+            decoder
+                .process_stream(&mut Vec::new(), &mut rangecoder)
+                .unwrap();
+            range = rangecoder.range;
+            code = rangecoder.code;
+            assert_eq!(decoder.total_in(), fed);
+        }
+        assert_eq!(decoder.total_in(), rest.len() as u64);
+        assert_eq!(decoder.total_out(), expected.len() as u64);
+    }
+}
+
+#[test]
+fn decompress_strict_canonical() {
+    use lzma_rs::error::lzma::{LzmaError, NonCanonical};
+
+    let options = lzma_rs::decompress::Options {
+        strict_canonical: true,
+        ..Default::default()
+    };
+
+    // The dumb encoder only emits literals
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(decomp, b"Hello world");
+
+    // xz sometimes codes a rep distance as a new distance, which decodes fine
+    // unless strict mode is on
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut compressed.as_slice(), &mut decomp).unwrap();
+    match lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut Vec::new(),
+        &options,
+    ) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::NonCanonicalEncoding(
+            NonCanonical::NewDistanceIsRep {
+                distance: 11,
+                index: 2,
+            },
+        ))) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn decompress_with_callback() {
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut spans = Vec::new();
+    lzma_rs::lzma_decompress_with_callback::<_, _, 4096, 8, 1000>(
+        &mut compressed.as_slice(),
+        &lzma_rs::decompress::Options::default(),
+        |span| {
+            spans.push(span.to_vec());
+            Ok(())
+        },
+    )
+    .unwrap();
+    let (last, full) = spans.split_last().unwrap();
+    assert!(full.iter().all(|span| span.len() == 1000));
+    assert!(!last.is_empty() && last.len() <= 1000);
+    assert_eq!(spans.concat(), expected);
+
+    // Errors from the callback are returned as is
+    let mut calls = 0;
+    let res = lzma_rs::lzma_decompress_with_callback::<_, _, 4096, 8, 1000>(
+        &mut compressed.as_slice(),
+        &lzma_rs::decompress::Options::default(),
+        |_| {
+            calls += 1;
+            Err(lzma_rs::error::stream::StreamError::InvalidState.into())
+        },
+    );
+    match res {
+        Err(lzma_rs::error::Error::StreamError(
+            lzma_rs::error::stream::StreamError::InvalidState,
+        )) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn decoder_chunks() {
+    use lzma_rs::decompress::raw::{
+        DecoderChunks, DecoderState, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let decoder_for = |input: &mut &[u8]| {
+        let params =
+            LzmaParams::read_header(input, &lzma_rs::decompress::Options::default()).unwrap();
+        let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        decoder
+    };
+
+    // Chunks are capped to the dictionary size minus a longest match
+    for (chunk_len, expected_len) in [(1, 1), (1000, 1000), (usize::MAX, 4096 - 273)] {
+        let mut input = compressed.as_slice();
+        let mut decoder = decoder_for(&mut input);
+        let rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let chunks = DecoderChunks::new(&mut decoder, rangecoder, chunk_len).unwrap();
+        assert_eq!(chunks.chunk_len(), expected_len);
+        let chunks = chunks.collect::<Result<Vec<_>, _>>().unwrap();
+        let (last, full) = chunks.split_last().unwrap();
+        assert!(full.iter().all(|chunk| chunk.len() == expected_len));
+        assert!(!last.is_empty() && last.len() <= expected_len);
+        assert_eq!(chunks.concat(), expected);
+    }
+
+    // An error is yielded once
+    let truncated = &compressed[..compressed.len() / 2];
+    let mut input = truncated;
+    let mut decoder = decoder_for(&mut input);
+    let rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut chunks = DecoderChunks::new(&mut decoder, rangecoder, 1000).unwrap();
+    let mut decomp = Vec::new();
+    let err = loop {
+        match chunks
+            .next()
+            .expect("truncated input decoded without error")
+        {
+            Ok(chunk) => decomp.extend_from_slice(&chunk),
+            Err(e) => break e,
+        }
+    };
+    assert!(
+        matches!(err, lzma_rs::error::Error::IoError(_)),
+        "{:?}",
+        err
+    );
+    assert!(chunks.next().is_none());
+    assert_eq!(decomp, expected[..decomp.len()]);
+
+    // Parameters must be set
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    let mut input = &compressed[13..];
+    let rangecoder = RangeDecoder::new(&mut input).unwrap();
+    assert!(DecoderChunks::new(&mut decoder, rangecoder, 1000).is_err());
+}
+
+#[test]
+fn decoder_clone() {
+    use lzma_rs::decompress::raw::RangeDecoder;
+    use lzma_rs::decompress::raw::{DecoderState, LzBuffer, LzCircularBuffer, LzmaParams};
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+    decoder
+        .process_to_offset(&mut decomp, &mut rangecoder, 50_000)
+        .unwrap();
+    let (range, code) = rangecoder.state();
+    let offset = compressed.len() - input.len();
+    let (flushed, total_out) = (decomp.len(), decoder.total_out());
+
+    let mut saved = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    saved.clone_from(&decoder);
+
+    // A failed branch leaves the saved decoder untouched
+    let mut corrupt = compressed[offset..].to_vec();
+    corrupt.iter_mut().for_each(|b| *b = !*b);
+    let mut input = corrupt.as_slice();
+    let mut rangecoder = RangeDecoder::from_parts(&mut input, range, code);
+    assert!(decoder.process(&mut decomp, &mut rangecoder).is_err());
+    assert_eq!(saved.total_out(), total_out);
+
+    // Fall back to it, in place or through a clone
+    for in_place in [true, false] {
+        if in_place {
+            decoder.clone_from(&saved);
+        } else {
+            *decoder = saved.as_ref().clone();
+        }
+        decomp.truncate(flushed);
+        let mut input = &compressed[offset..];
+        let mut rangecoder = RangeDecoder::from_parts(&mut input, range, code);
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+        assert_eq!(decomp, expected);
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn checkpoint_restore() {
+    use lzma_rs::decompress::raw::{
+        Checkpoint, DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::error::{lzma::LzmaError, Error};
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::decompress::Options::default();
+
+    // Before and after the dictionary first wraps around, and near the end
+    for offset in [0, 1000, 4096, 50_000, expected.len() as u64 - 10] {
+        let mut input = &compressed[..];
+        let params = LzmaParams::read_header(&mut input, &options).unwrap();
+        let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        if offset > 0 {
+            decoder
+                .process_to_offset(&mut decomp, &mut rangecoder, offset)
+                .unwrap();
+        }
+        let checkpoint = decoder.checkpoint(&rangecoder).unwrap();
+        assert_eq!(checkpoint.total_in(), rangecoder.bytes_consumed());
+        assert_eq!(checkpoint.total_out(), decoder.total_out());
+        assert_eq!(checkpoint.flushed_out(), decomp.len() as u64);
+
+        let json = serde_json::to_string(&checkpoint).unwrap();
+        let checkpoint: Checkpoint = serde_json::from_str(&json).unwrap();
+
+        let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+        decoder.restore(&checkpoint).unwrap();
+        let mut input = &compressed[13 + checkpoint.total_in() as usize..];
+        let mut rangecoder = checkpoint.range_decoder(&mut input);
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        assert_eq!(decoder.total_in(), compressed.len() as u64 - 13);
+        decoder.output.finish(&mut decomp).unwrap();
+        assert_eq!(decomp, expected, "offset {}", offset);
+    }
+
+    // Parameters must be set, and fit the restored decoder
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    let mut input = &compressed[13..];
+    let rangecoder = RangeDecoder::new(&mut input).unwrap();
+    assert!(matches!(
+        decoder.checkpoint(&rangecoder),
+        Err(Error::LzmaError(LzmaError::ParamsNotSet))
+    ));
+    let mut input = &compressed[..];
+    let params = LzmaParams::read_header(&mut input, &options).unwrap();
+    decoder.set_params(params).unwrap();
+    let checkpoint = decoder.checkpoint(&rangecoder).unwrap();
+    let mut small = Box::new(DecoderState::<LzCircularBuffer<1024>, 8>::new());
+    assert!(matches!(
+        small.restore(&checkpoint),
+        Err(Error::DictionaryBufferTooSmall { .. })
+    ));
+
+    // Inconsistent checkpoints are rejected
+    let mut json: serde_json::Value = serde_json::to_value(&checkpoint).unwrap();
+    json["literal_probs"].as_array_mut().unwrap().pop();
+    let truncated: Checkpoint = serde_json::from_value(json).unwrap();
+    assert!(matches!(
+        decoder.restore(&truncated),
+        Err(Error::LzmaError(LzmaError::InvalidCheckpoint))
+    ));
+}
+
+#[test]
+fn decompress_from_slice_reader() {
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut input = lzma_rs::io::SliceReader::new(&compressed);
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut input, &mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+    assert_eq!(input.position(), compressed.len());
+    assert_eq!(input.remaining(), 0);
+
+    // Driving the decoder with a range decoder over the slice
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, SliceRangeDecoder,
+    };
+    let mut input = lzma_rs::io::SliceReader::new(&compressed);
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut rangecoder = SliceRangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+    assert_eq!(rangecoder.bytes_consumed() + 13, compressed.len() as u64);
+}
+
+#[test]
+fn decompress_from_iterator() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut input = lzma_rs::io::IterReader::new(compressed.clone());
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut input, &mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+    assert_eq!(input.into_inner().len(), 0);
+
+    // Feeding a range decoder directly
+    let mut input = lzma_rs::io::IterReader::new(compressed.iter().copied());
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn input_tee() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    // Stop before the end to check that no more than needed is teed
+    let unpacked_size = 1000;
+    let options = lzma_rs::decompress::Options {
+        unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(
+            unpacked_size,
+        )),
+        ..Default::default()
+    };
+    for &size in &[Some(unpacked_size), None] {
+        let options = match size {
+            Some(_) => options,
+            None => Default::default(),
+        };
+        let mut input = compressed.as_slice();
+        let params = LzmaParams::read_header(&mut input, &options).unwrap();
+        let mut teed = Vec::new();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        rangecoder.with_input_tee(&mut teed).unwrap();
+        let mut decomp = Vec::new();
+
+        let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+
+        let consumed = rangecoder.bytes_consumed() as usize;
+        assert_eq!(teed, &compressed[13..13 + consumed]);
+        assert_eq!(input, &compressed[13 + consumed..]);
+        match size {
+            Some(size) => {
+                assert_eq!(decomp, &expected[..size as usize]);
+                assert!(consumed < compressed.len() - 13);
+            }
+            None => {
+                assert_eq!(decomp, expected);
+                assert_eq!(consumed, compressed.len() - 13);
+            }
+        }
+    }
+}
+
+#[test]
+fn custom_prob_model() {
+    use lzma_rs::decompress::raw::{
+        ArrayProbModel, DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, ProbModel,
+        RangeDecoder,
+    };
+
+    // Delegates to the default model, counting literal table lookups
+    #[derive(Default)]
+    struct Counting {
+        inner: ArrayProbModel<8>,
+        literal_lookups: usize,
+    }
+
+    impl ProbModel for Counting {
+        fn reset(&mut self, literal_init: u16, init: u16) {
+            self.inner.reset(literal_init, init)
+        }
+        fn literal_states(&self) -> usize {
+            self.inner.literal_states()
+        }
+        fn is_match(&mut self, state: usize, pos_state: usize) -> &mut u16 {
+            self.inner.is_match(state, pos_state)
+        }
+        fn is_rep(&mut self, state: usize) -> &mut u16 {
+            self.inner.is_rep(state)
+        }
+        fn is_rep_g0(&mut self, state: usize) -> &mut u16 {
+            self.inner.is_rep_g0(state)
+        }
+        fn is_rep_g1(&mut self, state: usize) -> &mut u16 {
+            self.inner.is_rep_g1(state)
+        }
+        fn is_rep_g2(&mut self, state: usize) -> &mut u16 {
+            self.inner.is_rep_g2(state)
+        }
+        fn is_rep_0long(&mut self, state: usize, pos_state: usize) -> &mut u16 {
+            self.inner.is_rep_0long(state, pos_state)
+        }
+        fn literal_probs(&mut self, lit_state: usize) -> &mut [u16; 0x300] {
+            self.literal_lookups += 1;
+            self.inner.literal_probs(lit_state)
+        }
+        fn pos_decoders(&mut self) -> &mut [u16; 115] {
+            self.inner.pos_decoders()
+        }
+    }
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+
+    let mut decoder = DecoderState::<_, 8, _>::with_prob_model(
+        LzCircularBuffer::<4096>::new(),
+        Counting::default(),
+    );
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+    assert!(decoder.prob_model().literal_lookups > 0);
+}
+
+#[test]
+fn verify_end_after_size() {
+    use lzma_rs::error::lzma::LzmaError;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let decode = |unpacked_size: u64, verify_end_after_size: bool| {
+        let options = lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::ReadHeaderButUseProvided(Some(
+                unpacked_size,
+            )),
+            verify_end_after_size,
+            ..Default::default()
+        };
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+            &mut compressed.as_slice(),
+            &mut decomp,
+            &options,
+        )
+        .map(|()| decomp)
+    };
+
+    // A declared size shorter than the data is only caught when verifying
+    assert_eq!(decode(1000, false).unwrap(), &expected[..1000]);
+    match decode(1000, true) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::TrailingDataAfterDeclaredSize {
+            unpacked_size: 1000,
+        })) => {}
+        res => panic!("Unexpected result: {:?}", res),
+    }
+
+    // The exact size is followed by the end marker
+    assert_eq!(decode(expected.len() as u64, true).unwrap(), expected);
+
+    // Streams without end marker end with the input
+    let data = b"Hello world, hello world";
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress_with_options(
+        &mut &data[..],
+        &mut compressed,
+        &lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
+                Some(data.len() as u64).into(),
+            ),
+        },
+    )
+    .unwrap();
+    let options = lzma_rs::decompress::Options {
+        verify_end_after_size: true,
+        ..Default::default()
+    };
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(decomp, data);
+}
+
+#[test]
+fn reject_trailing_data() {
+    use lzma_rs::decompress::{Options, UnpackedSize};
+    use lzma_rs::error::lzma::LzmaError;
+    use lzma_rs::error::Error;
+
+    let expected = b"Hello world, hello world";
+    let mut sized = Vec::new();
+    lzma_rs::lzma_compress_with_options(
+        &mut &expected[..],
+        &mut sized,
+        &lzma_rs::compress::Options {
+            unpacked_size: lzma_rs::compress::UnpackedSize::WriteToHeader(
+                Some(expected.len() as u64).into(),
+            ),
+        },
+    )
+    .unwrap();
+    let decode = |input: &[u8], options: &Options| {
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+            &mut &input[..],
+            &mut decomp,
+            options,
+        )
+        .map(|()| decomp)
+    };
+    let strict = Options {
+        reject_trailing_data: true,
+        ..Default::default()
+    };
+
+    // Trailing data is ignored by default
+    let mut garbage = sized.clone();
+    garbage.extend_from_slice(b"garbage");
+    assert_eq!(decode(&garbage, &Default::default()).unwrap(), expected);
+    assert_eq!(decode(&sized, &strict).unwrap(), expected);
+    assert!(matches!(
+        decode(&garbage, &strict),
+        Err(Error::LzmaError(LzmaError::TrailingData {
+            bytes_remaining: 7
+        }))
+    ));
+
+    // The end marker may follow the declared size
+    let marker = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let provided = |size: u64| Options {
+        unpacked_size: UnpackedSize::ReadHeaderButUseProvided(Some(size)),
+        ..strict
+    };
+    assert_eq!(
+        decode(&marker, &provided(expected.len() as u64)).unwrap(),
+        expected
+    );
+    assert!(matches!(
+        decode(&marker, &provided(1000)),
+        Err(Error::LzmaError(LzmaError::TrailingDataAfterDeclaredSize {
+            unpacked_size: 1000
+        }))
+    ));
+
+    // Input after an end marker is reported as trailing data too, whether
+    // the stream ends at the declared size or at the marker alone
+    let mut garbage = marker.clone();
+    garbage.push(0);
+    assert!(matches!(
+        decode(&garbage, &provided(expected.len() as u64)),
+        Err(Error::LzmaError(LzmaError::TrailingData {
+            bytes_remaining: 1
+        }))
+    ));
+    assert_eq!(decode(&marker, &strict).unwrap(), expected);
+    assert!(matches!(
+        decode(&garbage, &strict),
+        Err(Error::LzmaError(LzmaError::TrailingData {
+            bytes_remaining: 1
+        }))
+    ));
+    assert!(matches!(
+        decode(&garbage, &Default::default()),
+        Err(Error::LzmaError(
+            LzmaError::EosFoundButMoreBytesAvailable { .. }
+        ))
+    ));
+}
+
+#[test]
+fn raw_params() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::error::lzma::LzmaError;
+
+    // Decode the data after the header with the parameters given out of band
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let header = LzmaParams::read_header(
+        &mut &compressed[..],
+        &lzma_rs::decompress::Options::default(),
+    )
+    .unwrap();
+    let params = LzmaParams::new(3, 0, 2, header.provided_dict_size(), None).unwrap();
+    assert_eq!(params, header);
+
+    let mut input = &compressed[13..];
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<65536>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    let params = LzmaParams::new(0, 4, 4, 1, Some(10)).unwrap();
+    assert_eq!(
+        (params.provided_dict_size(), params.effective_dict_size()),
+        (1, 0x1000)
+    );
+    for (lc, lp, pb) in [(9, 0, 0), (0, 5, 0), (0, 0, 5)] {
+        match LzmaParams::new(lc, lp, pb, 0x1000, None) {
+            Err(lzma_rs::error::Error::LzmaError(LzmaError::InvalidProperties { .. })) => {}
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+    match LzmaParams::new(3, 0, 2, 0, None) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::InvalidDictionarySize {
+            dict_size: 0,
+        })) => {}
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn header_literal_context() {
+    use lzma_rs::decompress::raw::LzmaParams;
+    use lzma_rs::error::{lzma::LzmaError, Error};
+
+    // Properties byte (pb * 5 + lp) * 9 + lc, dictionary and unpacked sizes
+    let header = |lc: u8, lp: u8| {
+        let mut header = [0xff; 13];
+        header[..5].copy_from_slice(&[lp * 9 + lc, 0x00, 0x10, 0x00, 0x00]);
+        header
+    };
+    for (lc, lp) in [(4, 0), (0, 4), (2, 2)] {
+        let params = LzmaParams::peek_header(&header(lc, lp)).unwrap();
+        assert_eq!((params.lc, params.lp), (lc as u32, lp as u32));
+    }
+    for (lc, lp) in [(5, 0), (4, 1), (8, 4)] {
+        let header = header(lc, lp);
+        assert!(matches!(
+            LzmaParams::peek_header(&header),
+            Err(Error::LzmaError(LzmaError::InvalidLiteralContext { lc: l, lp: p }))
+                if (l, p) == (lc as u32, lp as u32)
+        ));
+        assert!(matches!(
+            LzmaParams::read_compact_header(&mut &[header[0], 0][..], None),
+            Err(Error::LzmaError(LzmaError::InvalidLiteralContext { .. }))
+        ));
+    }
+}
+
+#[test]
+fn peek_header() {
+    use lzma_rs::decompress::raw::LzmaParams;
+    use lzma_rs::error::{lzma::LzmaError, Error, HeaderField};
+
+    let compressed = read_all_file("tests/files/hello.txt.lzma").unwrap();
+    let params = LzmaParams::peek_header(&compressed).unwrap();
+    let expected =
+        LzmaParams::read_header(&mut compressed.as_slice(), &Default::default()).unwrap();
+    assert_eq!(params, expected);
+
+    // Dictionary sizes are raised to 0x1000 and the marker is detected
+    let header = [
+        0x5d, 0x00, 0x01, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    ];
+    let params = LzmaParams::peek_header(&header).unwrap();
+    assert_eq!(
+        (params.provided_dict_size(), params.dict_size),
+        (0x100, 0x1000)
+    );
+    assert_eq!(params.unpacked_size, None);
+
+    assert!(matches!(
+        LzmaParams::peek_header(&[225; 13]),
+        Err(Error::LzmaError(LzmaError::InvalidHeader { .. }))
+    ));
+    // Truncated headers report the field being read
+    for (len, expected) in [
+        (0, HeaderField::Properties),
+        (3, HeaderField::DictSize),
+        (12, HeaderField::UnpackedSize),
+    ] {
+        match LzmaParams::peek_header(&compressed[..len]) {
+            Err(Error::HeaderTooShort { field, source }) => {
+                assert_eq!(field, expected);
+                assert_eq!(source.kind(), std::io::ErrorKind::UnexpectedEof);
+            }
+            res => panic!("expected a short {:?}, got {:?}", expected, res),
+        }
+    }
+}
+
+#[test]
+fn memory_required() {
+    use lzma_rs::decompress::raw::{DecoderState, LzCircularBuffer, LzmaParams};
+
+    // A decoder sized exactly for the stream
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let params = LzmaParams::peek_header(&compressed).unwrap();
+    assert_eq!((params.lc, params.lp, params.dict_size), (3, 0, 4096));
+    assert_eq!(
+        params.memory_required(),
+        DecoderState::<LzCircularBuffer<4096>, 8>::memory_footprint()
+    );
+
+    let small = LzmaParams::new(0, 0, 0, 0x1000, None).unwrap();
+    let large = LzmaParams::new(8, 4, 0, 0x10_0000, None).unwrap();
+    assert_eq!(
+        large.memory_required() - small.memory_required(),
+        (0x10_0000 - 0x1000) + ((1 << 12) - 1) * 0x600
+    );
+}
+
+#[test]
+fn enumerate_properties() {
+    use lzma_rs::decompress::raw::{enumerate_properties, LzmaParams};
+
+    let all: Vec<_> = enumerate_properties().collect();
+    // 15 pairs with lc + lp <= 4 for each pb
+    assert_eq!(all.len(), 75);
+    assert_eq!(all[0], (0, 0, 0, 0));
+    // The most common properties: lc=3, lp=0, pb=2
+    assert!(all.contains(&(0x5D, 3, 0, 2)));
+    assert_eq!(all[74], (216, 0, 4, 4));
+
+    for (props, lc, lp, pb) in all {
+        let header = [
+            props, 0, 0x10, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        let params =
+            LzmaParams::read_header(&mut &header[..], &lzma_rs::decompress::Options::default())
+                .unwrap();
+        assert_eq!((params.lc, params.lp, params.pb), (lc, lp, pb));
+    }
+    for props in [5, 224, 225] {
+        let header = [
+            props, 0, 0x10, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+        ];
+        assert!(LzmaParams::read_header(
+            &mut &header[..],
+            &lzma_rs::decompress::Options::default()
+        )
+        .is_err());
+    }
+}
+
+/// Reader failing with `Interrupted` on every other call.
+struct InterruptingReader<R> {
+    inner: R,
+    interrupt: bool,
+}
+
+impl<R: std::io::Read> std::io::Read for InterruptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.interrupt = !self.interrupt;
+        if self.interrupt {
+            return Err(std::io::ErrorKind::Interrupted.into());
+        }
+        self.inner.read(buf)
+    }
+}
+
+#[test]
+fn decompress_interrupted_input() {
+    for filename in ["tests/files/foo.txt", "tests/files/range-coder-edge-case"] {
+        let compressed = read_all_file(&format!("{}.lzma", filename)).unwrap();
+        let expected = read_all_file(filename).unwrap();
+
+        // A small buffer forces many reads, each of which is first interrupted
+        let mut input = std::io::BufReader::with_capacity(
+            3,
+            InterruptingReader {
+                inner: compressed.as_slice(),
+                interrupt: false,
+            },
+        );
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut input, &mut decomp).unwrap();
+        assert_eq!(decomp, expected);
+    }
+}
+
+#[test]
+fn max_expansion() {
+    use lzma_rs::decompress::max_expansion;
+
+    for filename in [
+        "tests/files/empty.txt",
+        "tests/files/foo.txt",
+        "tests/files/hello.txt",
+        "tests/files/range-coder-edge-case",
+    ] {
+        let compressed = read_all_file(&format!("{}.lzma", filename)).unwrap();
+        let expected = read_all_file(filename).unwrap();
+        assert!(expected.len() as u64 <= max_expansion(compressed.len() as u64));
+    }
+
+    assert_eq!(max_expansion(0), 0);
+    assert_eq!(max_expansion(18), 18 * 7098);
+    assert_eq!(max_expansion(u64::MAX), u64::MAX);
+}
+
+#[test]
+fn reset_with_initial_probs() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::error::lzma::LzmaError;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let decode = |literal_init, init| {
+        let mut input = compressed.as_slice();
+        let params =
+            LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+        decoder.reset_with_initial_probs(literal_init, init)?;
+        decoder.set_params(params)?;
+        decoder.process(&mut decomp, &mut rangecoder)?;
+        decoder.output.finish(&mut decomp)?;
+        Ok::<_, lzma_rs::error::Error>(decomp)
+    };
+
+    // The standard initialization
+    assert_eq!(decode(0x400, 0x400).unwrap(), expected);
+    // Another initialization decodes the same stream differently
+    assert!(!matches!(decode(0x200, 0x400), Ok(ref d) if *d == expected));
+    assert!(!matches!(decode(0x400, 0x600), Ok(ref d) if *d == expected));
+
+    for (literal_init, init, prob) in [(0, 0x400, 0), (0x400, 0x800, 0x800)] {
+        match decode(literal_init, init) {
+            Err(lzma_rs::error::Error::LzmaError(LzmaError::InvalidInitialProbability {
+                prob: p,
+            })) => assert_eq!(p, prob),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
+
+#[test]
+fn reset_keeping_dict() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    // Two members with end markers decoded one after the other into the
+    // same dictionary, resetting only the coder state in between
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut decomp = Vec::new();
+    for _ in 0..2 {
+        let mut body = input;
+        let mut rangecoder = RangeDecoder::new(&mut body).unwrap();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.reset_keeping_dict();
+    }
+    // The dictionary and the parameters are kept
+    assert_eq!(decoder.output.len(), 2 * expected.len());
+    assert_eq!(
+        decoder.output.last_n(1).unwrap(),
+        expected[expected.len() - 1]
+    );
+    assert!(matches!(decoder.params, Some(_)));
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, [expected.as_slice(), expected.as_slice()].concat());
+}
+
+#[test]
+fn decoder_with_props() {
+    use lzma_rs::decompress::raw::{
+        probs_mem_limit, DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::error::Error;
+
+    type Lc3Lp0 = DecoderState<LzCircularBuffer<4096>, { probs_mem_limit(3, 0) }>;
+    type Lc0Lp0 = DecoderState<LzCircularBuffer<4096>, { probs_mem_limit(0, 0) }>;
+    assert_eq!(
+        std::mem::size_of::<Lc3Lp0>() - std::mem::size_of::<Lc0Lp0>(),
+        7 * 0x300 * 2
+    );
+
+    // foo.txt.lzma uses lc = 3 and lp = 0
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut input = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(Lc3Lp0::with_props::<3, 0>());
+    decoder.reset();
+    decoder.set_params(params.clone()).unwrap();
+    let mut decomp = Vec::new();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, read_all_file("tests/files/foo.txt").unwrap());
+
+    let mut decoder = Box::new(Lc0Lp0::with_props::<0, 0>());
+    decoder.reset();
+    assert!(matches!(
+        decoder.set_params(params),
+        Err(Error::ProbabilitiesBufferTooSmall {
+            needed: 8,
+            available: 1
+        })
+    ));
+}
+
+#[test]
+fn index_members() {
+    let hello = {
+        let mut compressed = Vec::new();
+        lzma_rs::lzma_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+        compressed
+    };
+    let foo = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let edge_case = read_all_file("tests/files/range-coder-edge-case.lzma").unwrap();
+    let members = [hello.as_slice(), foo.as_slice(), edge_case.as_slice()];
+    let compressed = members.concat();
+
+    let mut index = Vec::new();
+    let count = lzma_rs::lzma_index_members::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &lzma_rs::decompress::Options::default(),
+        |info| {
+            index.push(info);
+            Ok(())
+        },
+    )
+    .unwrap();
+    assert_eq!(count, 3);
+
+    let mut start = 0;
+    for (member, info) in members.iter().zip(&index) {
+        let params = lzma_rs::decompress::raw::LzmaParams::read_header(
+            &mut &member[..],
+            &lzma_rs::decompress::Options::default(),
+        )
+        .unwrap();
+        assert_eq!(info.params, params);
+        assert_eq!((info.start, info.end), (start, start + member.len() as u64));
+        start = info.end;
+    }
+
+    // Errors from the callback are returned as is
+    let res = lzma_rs::lzma_index_members::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &lzma_rs::decompress::Options::default(),
+        |_| {
+            Err(lzma_rs::error::Error::LzmaError(
+                lzma_rs::error::lzma::LzmaError::ParamsNotSet,
+            ))
+        },
+    );
+    assert!(matches!(
+        res,
+        Err(lzma_rs::error::Error::LzmaError(
+            lzma_rs::error::lzma::LzmaError::ParamsNotSet
+        ))
+    ));
+}
+
+#[test]
+fn append_lz_runs_and_wraparound() {
+    use lzma_rs::decompress::raw::{LzBuffer, LzCircularBuffer};
+
+    // Compare with copying byte by byte from the whole history
+    let mut buffer = LzCircularBuffer::<64>::new();
+    buffer.set_dict_size(50).unwrap();
+    let mut output = Vec::new();
+    let mut expected: Vec<u8> = Vec::new();
+    let mut seed = 1u32;
+    let mut next = || {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (seed >> 16) as usize
+    };
+    for round in 0..2000 {
+        let lit = next() as u8;
+        buffer.append_literal(&mut output, lit).unwrap();
+        expected.push(lit);
+        // Long runs, distances up to the dictionary size, which often
+        // straddle the end of the ring
+        let dist = match round % 4 {
+            0 => 1,
+            1 => 50,
+            _ => 1 + next() % expected.len().min(50),
+        };
+        if dist > expected.len() {
+            continue;
+        }
+        let len = 1 + next() % 273;
+        buffer.append_lz(&mut output, len, dist).unwrap();
+        for _ in 0..len {
+            expected.push(expected[expected.len() - dist]);
+        }
+    }
+    buffer.finish(&mut output).unwrap();
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn invalid_match_distance() {
+    use lzma_rs::decompress::raw::{LzBuffer, LzCircularBuffer};
+    use lzma_rs::error::lzma::LzmaError;
+    use lzma_rs::error::Error;
+
+    let mut buffer = LzCircularBuffer::<16>::new();
+    buffer.set_dict_size(8).unwrap();
+    let mut output = Vec::new();
+    buffer.append_literal(&mut output, b'a').unwrap();
+    for distance in [0, 2, usize::MAX] {
+        let err = LzmaError::InvalidMatchDistance {
+            distance,
+            available: 1,
+        };
+        assert!(matches!(buffer.last_n(distance), Err(Error::LzmaError(e)) if e == err));
+        assert!(matches!(
+            buffer.append_lz(&mut output, 1, distance),
+            Err(Error::LzmaError(e)) if e == err
+        ));
+    }
+
+    // Streams whose first match reaches one byte past the output, every bit
+    // being coded with a probability not used before
+    let decode = |bits: &[u8]| {
+        let mut input = vec![0x5D, 0x00, 0x10, 0x00, 0x00, 3, 0, 0, 0, 0, 0, 0, 0];
+        let mut encoder = lzma_rs::compress::raw::RangeEncoder::new(&mut input);
+        for &bit in bits {
+            encoder.encode_bit(&mut 0x400, bit == 1).unwrap();
+        }
+        encoder.finish().unwrap();
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress::<_, _, 4096, 8>(&mut input.as_slice(), &mut decomp)
+            .map(|()| decomp)
+    };
+    // Literal `a`, then a match of length 2 at distance `slot + 1`
+    let literal_then_match = |slot: u8| {
+        let mut bits = vec![0, 0, 1, 1, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0];
+        bits.extend_from_slice(&[0, 0, 0, 0, 0, slot]);
+        decode(&bits)
+    };
+    assert_eq!(literal_then_match(0).unwrap(), b"aaa");
+    assert!(matches!(
+        literal_then_match(1),
+        Err(Error::LzmaError(LzmaError::InvalidMatchDistance {
+            distance: 2,
+            available: 1,
+        }))
+    ));
+    // A short rep (distance 1) before any output
+    assert!(matches!(
+        decode(&[1, 1, 0, 0]),
+        Err(Error::LzmaError(LzmaError::InvalidMatchDistance {
+            distance: 1,
+            available: 0,
+        }))
+    ));
+}
+
+#[test]
+fn dict_fill() {
+    use lzma_rs::decompress::raw::{LzBuffer, LzCircularBuffer};
+
+    let mut buffer = LzCircularBuffer::<16>::new();
+    buffer.set_dict_size(8).unwrap();
+    let mut output = Vec::new();
+    // Without a fill, the dictionary is empty
+    assert!(buffer.append_lz(&mut output, 3, 4).is_err());
+    assert_eq!(buffer.last_or(b'y'), b'y');
+
+    buffer.set_fill(Some(b'x'));
+    buffer.append_literal(&mut output, b'a').unwrap();
+    buffer.append_lz(&mut output, 3, 3).unwrap();
+    assert_eq!(buffer.last_n(8).unwrap(), b'x');
+    assert!(buffer.last_n(9).is_err());
+    buffer.finish(&mut output).unwrap();
+    assert_eq!(output, b"axxa");
+
+    // A zero fill does not change the decoding of standard streams
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let options = lzma_rs::decompress::Options {
+        dict_fill: Some(0),
+        ..Default::default()
+    };
+    let mut decomp = Vec::new();
+    lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &options,
+    )
+    .unwrap();
+    assert_eq!(decomp, expected);
+
+    // But another fill changes the context of the first literal
+    let options = lzma_rs::decompress::Options {
+        dict_fill: Some(0xFF),
+        ..Default::default()
+    };
+    let mut decomp = Vec::new();
+    let res = lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+        &mut compressed.as_slice(),
+        &mut decomp,
+        &options,
+    );
+    assert!(res.is_err() || decomp != expected);
+}
+
+#[test]
+fn preset_dict() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use lzma_rs::error::lzma::LzmaError;
+    use lzma_rs::error::Error;
+
+    let dict = read_all_file("tests/files/preset-dict.dict").unwrap();
+    let expected = read_all_file("tests/files/preset-dict").unwrap();
+    let decode = |compressed: &[u8], dict: &[u8]| {
+        let mut input = compressed;
+        let params = LzmaParams::read_header(&mut input, &Default::default())?;
+        let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 16>::new());
+        decoder.reset();
+        decoder.set_params(params)?;
+        decoder.set_preset_dict(dict)?;
+        let mut decomp = Vec::new();
+        let mut rangecoder = RangeDecoder::new(&mut input)?;
+        decoder.process(&mut decomp, &mut rangecoder)?;
+        decoder.output.finish(&mut decomp)?;
+        Ok::<_, Error>(decomp)
+    };
+
+    // Produced by liblzma with a preset dictionary longer than the 4096-byte
+    // dictionary, of which only the tail is used
+    let compressed = read_all_file("tests/files/preset-dict.lzma").unwrap();
+    assert_eq!(decode(&compressed, &dict).unwrap(), expected);
+    assert_eq!(decode(&compressed, &dict[120..]).unwrap(), expected);
+    assert!(!matches!(decode(&compressed, &[]), Ok(v) if v == expected));
+
+    // A preset of 4001 bytes and lp = 1: its length counts in the position
+    // bits
+    let compressed = read_all_file("tests/files/preset-dict-odd.lzma").unwrap();
+    assert_eq!(decode(&compressed, &dict[..4001]).unwrap(), expected);
+
+    // The preset must come after the parameters and before any output
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 16>::new();
+    assert!(matches!(
+        decoder.set_preset_dict(&dict),
+        Err(Error::LzmaError(LzmaError::DecoderUninitialized))
+    ));
+    decoder.reset();
+    assert!(matches!(
+        decoder.set_preset_dict(&dict),
+        Err(Error::LzmaError(LzmaError::ParamsNotSet))
+    ));
+    let mut input = compressed.as_slice();
+    decoder
+        .set_params(LzmaParams::read_header(&mut input, &Default::default()).unwrap())
+        .unwrap();
+    decoder
+        .output
+        .append_literal(&mut Vec::new(), b'a')
+        .unwrap();
+    assert!(matches!(
+        decoder.set_preset_dict(&dict),
+        Err(Error::LzmaError(LzmaError::PresetDictionaryAfterOutput {
+            output_len: 1
+        }))
+    ));
+
+    // Distances reach into the preset, but not before it
+    let mut buffer = LzCircularBuffer::<16>::new();
+    buffer.set_dict_size(8).unwrap();
+    assert_eq!(buffer.set_preset(b"0123456789").unwrap(), 8);
+    assert_eq!(buffer.last_or(b'y'), b'9');
+    let mut output = Vec::new();
+    buffer.append_lz(&mut output, 3, 8).unwrap();
+    assert_eq!(buffer.last_n(8).unwrap(), b'5');
+    assert!(buffer.last_n(9).is_err());
+    buffer.finish(&mut output).unwrap();
+    assert_eq!(output, b"234");
+    let mut buffer = LzCircularBuffer::<16>::new();
+    buffer.set_dict_size(8).unwrap();
+    buffer.set_preset(b"ab").unwrap();
+    assert!(buffer.last_n(3).is_err());
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn signal_eof() {
+    use lzma_rs::decompress::raw::{DecoderState, LzCircularBuffer, LzmaParams, RangeDecoder};
+
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello world, hello world"[..], &mut compressed).unwrap();
+    let decode = |compressed: &[u8]| {
+        let mut input = compressed;
+        let params = LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default())?;
+        let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+        decoder.reset();
+        decoder.set_params(params)?;
+
+        let rangecoder = RangeDecoder::new(&mut input)?;
+        let (mut range, mut code) = (rangecoder.range, rangecoder.code);
+        let mut decomp = Vec::new();
+        for chunk in input.chunks(3) {
+            let mut chunk = chunk;
+            let mut rangecoder = RangeDecoder::from_parts(&mut chunk, range, code);
+            decoder.process_stream(&mut decomp, &mut rangecoder)?;
+            range = rangecoder.range;
+            code = rangecoder.code;
+        }
+        // No data for now, but more could come
+        let mut empty: &[u8] = &[];
+        let mut rangecoder = RangeDecoder::from_parts(&mut empty, range, code);
+        decoder.process_stream(&mut decomp, &mut rangecoder)?;
+
+        decoder.signal_eof(&mut decomp, &mut rangecoder)?;
+        Ok::<_, lzma_rs::error::Error>(decomp)
+    };
+
+    assert_eq!(decode(&compressed).unwrap(), b"Hello world, hello world");
+    // A truncated stream fails the end of stream checks
+    for len in [compressed.len() - 3, compressed.len() - 1] {
+        match decode(&compressed[..len]) {
+            Err(lzma_rs::error::Error::IoError(e))
+                if e.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            res => panic!("{}: unexpected result: {:?}", len, res),
+        }
+    }
+}
+
+#[test]
+fn decompress_into_slice_buffer() {
+    use lzma_rs::decompress::raw::{
+        ArrayProbModel, DecoderState, LzBuffer, LzSliceBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    // One region holds the dictionary, with room to spare for other uses
+    let mut arena = vec![0xAA; 6000];
+    let (dict, _rest) = arena.split_at_mut(4096);
+
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = Box::new(DecoderState::<_, 8, _>::with_prob_model(
+        LzSliceBuffer::from_slice(dict),
+        ArrayProbModel::<8>::new(),
+    ));
+    decoder.reset();
+    decoder.set_params(params.clone()).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    // The dictionary must fit in the slice
+    let mut small = [0; 1024];
+    let mut decoder = Box::new(DecoderState::<_, 8, _>::with_prob_model(
+        LzSliceBuffer::from_slice(&mut small),
+        ArrayProbModel::<8>::new(),
+    ));
+    decoder.reset();
+    match decoder.set_params(params) {
+        Err(lzma_rs::error::Error::DictionaryBufferTooSmall { needed, available }) => {
+            assert_eq!((needed, available), (4096, 1024))
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn read_compact_header() {
+    use lzma_rs::decompress::raw::{
+        lzma2_dict_size_from_byte, DecoderState, LzBuffer, LzCircularBuffer, LzmaParams,
+        RangeDecoder,
+    };
+    use lzma_rs::error::lzma::LzmaError;
+
+    assert_eq!(lzma2_dict_size_from_byte(0).unwrap(), 4096);
+    assert_eq!(lzma2_dict_size_from_byte(1).unwrap(), 6144);
+    assert_eq!(lzma2_dict_size_from_byte(16).unwrap(), 1 << 20);
+    assert_eq!(lzma2_dict_size_from_byte(39).unwrap(), 3 << 30);
+    assert_eq!(lzma2_dict_size_from_byte(40).unwrap(), 0xFFFF_FFFF);
+    assert!(matches!(
+        lzma2_dict_size_from_byte(41),
+        Err(lzma_rs::error::Error::LzmaError(
+            LzmaError::InvalidDictionarySizeByte { byte: 41 }
+        ))
+    ));
+
+    // Swap the header of foo.txt.lzma for the compact form
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let params = LzmaParams::read_header(
+        &mut &compressed[..],
+        &lzma_rs::decompress::Options::default(),
+    )
+    .unwrap();
+    assert_eq!(params.dict_size, 4096);
+    let mut input = [&[compressed[0], 0][..], &compressed[13..]].concat();
+
+    let mut reader = input.as_slice();
+    let compact = LzmaParams::read_compact_header(&mut reader, params.unpacked_size).unwrap();
+    assert_eq!(compact, params);
+
+    let mut rangecoder = RangeDecoder::new(&mut reader).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(compact).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    // Invalid properties and truncated headers are rejected
+    input[0] = 225;
+    assert!(matches!(
+        LzmaParams::read_compact_header(&mut input.as_slice(), None),
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::InvalidHeader {
+            invalid_properties: 225
+        }))
+    ));
+    assert!(matches!(
+        LzmaParams::read_compact_header(&mut &compressed[..1], None),
+        Err(lzma_rs::error::Error::HeaderTooShort {
+            field: lzma_rs::error::HeaderField::DictSize,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn process_to_offset() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+
+    let mut decomp = Vec::new();
+    let mut offset = 0;
+    loop {
+        offset += 100;
+        let finished = decoder
+            .process_to_offset(&mut decomp, &mut rangecoder, offset)
+            .unwrap();
+        let len = decoder.output.len() as u64;
+        if finished {
+            assert!(len < offset + 273);
+            break;
+        }
+        assert!((offset..offset + 273).contains(&len));
+    }
+    assert_eq!(decoder.output.len(), expected.len());
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[cfg(feature = "events")]
+#[test]
+fn decode_events() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, EventKind, LzCircularBuffer, LzmaParams, RangeDecoder, EVENT_RING_LEN,
+    };
+
+    let decode = |compressed: &[u8]| {
+        let mut input = compressed;
+        let params =
+            LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        let res = decoder.process(&mut Vec::new(), &mut rangecoder);
+        (res, decoder.events().iter().copied().collect::<Vec<_>>())
+    };
+
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello"[..], &mut compressed).unwrap();
+    let (res, events) = decode(&compressed);
+    res.unwrap();
+    let kinds: Vec<_> = events.iter().map(|e| e.kind).collect();
+    assert_eq!(
+        kinds,
+        [
+            EventKind::Literal(b'H'),
+            EventKind::Literal(b'e'),
+            EventKind::Literal(b'l'),
+            EventKind::Literal(b'l'),
+            EventKind::Literal(b'o'),
+            EventKind::EndMarker,
+        ]
+    );
+    assert_eq!(events[5].offset, 5);
+    assert_eq!(events[5].state, 0);
+
+    // A truncated stream ends with an error, after the last symbols decoded
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let (res, events) = decode(&compressed[..compressed.len() / 2]);
+    assert!(res.is_err());
+    assert_eq!(events.len(), EVENT_RING_LEN);
+    assert_eq!(events.last().unwrap().kind, EventKind::Error);
+    assert!(events
+        .windows(2)
+        .all(|pair| pair[0].offset < pair[1].offset));
+    assert!(events.iter().all(|e| e.state < 12));
+}
+
+#[test]
+fn max_reference_distance() {
+    use lzma_rs::error::lzma::LzmaError;
+
+    // Matches in this file reach back up to the dictionary size of 4096
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    let decode = |max_distance| {
+        let options = lzma_rs::decompress::Options {
+            max_reference_distance: max_distance,
+            ..Default::default()
+        };
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+            &mut compressed.as_slice(),
+            &mut decomp,
+            &options,
+        )
+        .map(|()| decomp)
+    };
+
+    assert_eq!(decode(None).unwrap(), expected);
+    assert_eq!(decode(Some(4096)).unwrap(), expected);
+    match decode(Some(4000)) {
+        Err(lzma_rs::error::Error::LzmaError(LzmaError::InvalidBackReference {
+            distance,
+            max_distance,
+        })) => {
+            assert!(distance > 4000);
+            assert_eq!(max_distance, 4000);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+}
+
+#[test]
+fn decoders_are_send_and_sync() {
+    use lzma_rs::decompress::lzip::LzipDecoder;
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzCircularBuffer, LzSliceBuffer, Lzma2Decoder, RangeDecoder,
+    };
+    use lzma_rs::decompress::xz::XzDecoder;
+    #[cfg(feature = "stream")]
+    use lzma_rs::decompress::LzmaReader;
+
+    fn assert_send<T: Send>() {}
+    fn assert_sync<T: Sync>() {}
+
+    assert_send::<DecoderState<LzCircularBuffer<4096>, 8>>();
+    assert_sync::<DecoderState<LzCircularBuffer<4096>, 8>>();
+    assert_send::<DecoderState<LzSliceBuffer<'static>, 8>>();
+    assert_sync::<DecoderState<LzSliceBuffer<'static>, 8>>();
+    assert_send::<Lzma2Decoder<LzCircularBuffer<4096>, 8>>();
+    assert_sync::<Lzma2Decoder<LzCircularBuffer<4096>, 8>>();
+    assert_send::<XzDecoder<LzCircularBuffer<4096>, 8>>();
+    assert_sync::<XzDecoder<LzCircularBuffer<4096>, 8>>();
+    assert_send::<LzipDecoder<LzCircularBuffer<4096>, 8>>();
+    assert_sync::<LzipDecoder<LzCircularBuffer<4096>, 8>>();
+    #[cfg(feature = "stream")]
+    assert_send::<LzmaReader<&'static [u8], 4096, 8>>();
+    assert_send::<RangeDecoder<'static, &'static [u8]>>();
+    #[cfg(feature = "stream")]
+    assert_send::<lzma_rs::decompress::Stream<4096, 8>>();
+    assert_send::<lzma_rs::error::Error>();
+    assert_sync::<lzma_rs::error::Error>();
+
+    // A configured decoder moves to a worker thread
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        lzma_rs::decompress::raw::LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let data = input.to_vec();
+    let decomp = std::thread::spawn(move || {
+        use lzma_rs::decompress::raw::LzBuffer;
+        let mut input = data.as_slice();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+        decomp
+    })
+    .join()
+    .unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn decode_into_slice() {
+    use lzma_rs::decompress::{decode_into, Options};
+    use lzma_rs::error::Error;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut output = vec![0; expected.len() + 100];
+    let len = decode_into::<4096, 8>(&compressed, &mut output, &Default::default()).unwrap();
+    assert_eq!(output[..len], expected);
+    let len = decode_into::<4096, 8>(
+        &compressed,
+        &mut output[..expected.len()],
+        &Default::default(),
+    )
+    .unwrap();
+    assert_eq!(output[..len], expected);
+
+    // Whether the overflow is found on a flush of the dictionary or not
+    for capacity in [0, 10, 4096, 4097, expected.len() - 1] {
+        let mut output = vec![0; capacity];
+        match decode_into::<4096, 8>(&compressed, &mut output, &Default::default()) {
+            Err(Error::OutputBufferFull { needed_at_least }) => {
+                assert!(needed_at_least > capacity && needed_at_least <= expected.len())
+            }
+            res => panic!("unexpected result for {}: {:?}", capacity, res),
+        }
+    }
+
+    // A lower limit is reported as such
+    let options = Options {
+        max_output: Some(1000),
+        ..Default::default()
+    };
+    assert!(matches!(
+        decode_into::<4096, 8>(&compressed, &mut output, &options),
+        Err(Error::OutputTooLarge { limit: 1000 })
+    ));
+}
+
+#[test]
+fn decompress_allow_truncated() {
+    use lzma_rs::decompress::raw::{DecoderState, LzBuffer, LzCircularBuffer, LzmaParams};
+    use lzma_rs::decompress::{decompress_to_vec, Options};
+    use lzma_rs::error::Error;
+
+    let options = Options {
+        allow_truncated: true,
+        ..Default::default()
+    };
+    // With a declared size and with an end marker
+    for filename in ["tests/files/foo.txt", "tests/files/conformance-marker"] {
+        let compressed = read_all_file(&format!("{}.lzma", filename)).unwrap();
+        let expected = read_all_file(filename).unwrap();
+        let mut prev_len = 0;
+        for len in (0..compressed.len()).step_by(compressed.len() / 16 + 1) {
+            let decomp = decompress_to_vec::<65536, 8>(&compressed[..len], &options).unwrap();
+            assert!(expected.starts_with(&decomp), "{} {}", filename, len);
+            assert!(decomp.len() >= prev_len);
+            prev_len = decomp.len();
+            assert!(
+                decompress_to_vec::<65536, 8>(&compressed[..len], &Default::default()).is_err()
+            );
+        }
+        assert!(prev_len > 0);
+        assert_eq!(
+            decompress_to_vec::<65536, 8>(&compressed, &options).unwrap(),
+            expected
+        );
+    }
+
+    // Reported by the decoder
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let mut input = &compressed[..compressed.len() / 2];
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    decoder.set_allow_truncated(true);
+    let mut rangecoder = lzma_rs::decompress::raw::RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    assert!(decoder.is_truncated());
+    decoder.output.finish(&mut decomp).unwrap();
+    assert!(!decomp.is_empty());
+
+    // Format errors are still reported
+    let mut corrupt = compressed[..100].to_vec();
+    corrupt[0] = 225;
+    assert!(matches!(
+        decompress_to_vec::<4096, 8>(&corrupt, &options),
+        Err(Error::LzmaError(_))
+    ));
+}
+
+#[test]
+fn decompress_to_vec_max_output() {
+    use lzma_rs::decompress::{decompress_to_vec, Options};
+    use lzma_rs::error::Error;
+
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    let len = expected.len() as u64;
+    let decode = |input: &[u8], max_output, concatenated| {
+        let options = Options {
+            max_output,
+            concatenated,
+            ..Default::default()
+        };
+        decompress_to_vec::<4096, 8>(input, &options)
+    };
+
+    assert_eq!(decode(&compressed, None, false).unwrap(), expected);
+    assert_eq!(decode(&compressed, Some(len), false).unwrap(), expected);
+    assert!(matches!(
+        decode(&compressed, Some(len - 1), false),
+        Err(Error::OutputTooLarge { limit }) if limit == len - 1
+    ));
+
+    // The limit covers all members
+    let twice = [compressed.as_slice(), compressed.as_slice()].concat();
+    assert_eq!(
+        decode(&twice, Some(2 * len), true).unwrap().len() as u64,
+        2 * len
+    );
+    assert!(matches!(
+        decode(&twice, Some(2 * len - 1), true),
+        Err(Error::OutputTooLarge { .. })
+    ));
+
+    // A header claiming 10 GB stops at the limit, long before the end of
+    // the data
+    let mut compressed = read_all_file("tests/files/conformance-sized.lzma").unwrap();
+    compressed[5..13].copy_from_slice(&10_000_000_000u64.to_le_bytes());
+    let options = Options {
+        max_output: Some(1000),
+        ..Default::default()
+    };
+    assert!(matches!(
+        decompress_to_vec::<65536, 8>(&compressed, &options),
+        Err(Error::OutputTooLarge { limit: 1000 })
+    ));
+}
+
+#[test]
+fn decompress_to_vec_capacity() {
+    use lzma_rs::decompress::decompress_to_vec;
+    use lzma_rs::decompress::raw::LzmaParams;
+
+    let compressed = read_all_file("tests/files/conformance-sized.lzma").unwrap();
+    let expected = read_all_file("tests/files/conformance-sized").unwrap();
+    let params = LzmaParams::peek_header(&compressed).unwrap();
+    assert_eq!(params.expected_output_size(), Some(expected.len() as u64));
+
+    // The output is reserved up front for the declared size
+    let decomp = decompress_to_vec::<65536, 8>(&compressed, &Default::default()).unwrap();
+    assert_eq!(decomp, expected);
+    assert_eq!(decomp.capacity(), expected.len());
+
+    // Streams with an end marker have no expected size
+    let compressed = read_all_file("tests/files/conformance-marker.lzma").unwrap();
+    let params = LzmaParams::peek_header(&compressed).unwrap();
+    assert_eq!(params.expected_output_size(), None);
+
+    // A bogus size is capped by the input length before allocating
+    let mut compressed = read_all_file("tests/files/conformance-sized.lzma").unwrap();
+    compressed[5..13].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+    compressed.truncate(100);
+    let params = LzmaParams::peek_header(&compressed).unwrap();
+    assert_eq!(params.expected_output_size(), Some(u64::MAX - 1));
+    assert!(decompress_to_vec::<65536, 8>(&compressed, &Default::default()).is_err());
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn stream_max_output() {
+    use lzma_rs::decompress::{Options, Stream};
+    use lzma_rs::error::Error;
+
+    // The limit holds across writes of small chunks of a stream with an end
+    // marker
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let len = read_all_file("tests/files/dict-wraparound").unwrap().len() as u64;
+    let decode = |max_output| {
+        let options = Options {
+            max_output,
+            ..Default::default()
+        };
+        let mut stream = Stream::<4096, 8>::new_with_options(&options);
+        stream.reset();
+        let mut decomp = Vec::new();
+        for chunk in compressed.chunks(100) {
+            stream.write_all(&mut decomp, chunk)?;
+        }
+        stream.finish(&mut decomp)?;
+        Ok::<_, Error>(decomp.len() as u64)
+    };
+
+    assert_eq!(decode(Some(len)).unwrap(), len);
+    for limit in [0, 1000, len - 1] {
+        match decode(Some(limit)) {
+            Err(Error::OutputTooLarge { limit: l }) => assert_eq!(l, limit),
+            res => panic!("limit {}: {:?}", limit, res),
+        }
+    }
+}
+
+#[test]
+fn verify_len_after_marker() {
+    use lzma_rs::error::lzma::LzmaError;
+
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut &b"Hello world"[..], &mut compressed).unwrap();
+    let decode = |len| {
+        let options = lzma_rs::decompress::Options {
+            unpacked_size: lzma_rs::decompress::UnpackedSize::VerifyAfterMarker(len),
+            ..Default::default()
+        };
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_with_options::<_, _, 4096, 8>(
+            &mut compressed.as_slice(),
+            &mut decomp,
+            &options,
+        )?;
+        #[cfg(feature = "stream")]
+        {
+            let mut sink = Vec::new();
+            let mut stream = lzma_rs::decompress::Stream::<4096, 8>::new_with_options(&options);
+            stream.reset();
+            stream.write_all(&mut sink, &compressed)?;
+            stream.finish(&mut sink)?;
+            assert_eq!(sink, decomp);
+        }
+        Ok::<_, lzma_rs::error::Error>(decomp)
+    };
+
+    assert_eq!(decode(11).unwrap(), b"Hello world");
+    for len in [0, 10, 12] {
+        match decode(len) {
+            Err(lzma_rs::error::Error::LzmaError(
+                LzmaError::ProcessedDataDoesNotMatchUnpackedSize {
+                    unpacked_size,
+                    decompressed_data,
+                    ..
+                },
+            )) => assert_eq!((unpacked_size, decompressed_data), (len, 11)),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
+
+#[test]
+fn decompress_payloads_in_parallel() {
+    use lzma_rs::decompress::raw::LzmaParams;
+
+    let inputs: Vec<Vec<u8>> = (0..8)
+        .map(|i| format!("member {}: {}", i, "abc".repeat(i * 100)).into_bytes())
+        .collect();
+    let members: Vec<Vec<u8>> = inputs
+        .iter()
+        .map(|input| {
+            let mut compressed = Vec::new();
+            lzma_rs::lzma_compress(&mut input.as_slice(), &mut compressed).unwrap();
+            compressed
+        })
+        .collect();
+
+    // All members share the same header, parsed once
+    let options = lzma_rs::decompress::Options::default();
+    let params = LzmaParams::read_header(&mut members[0].as_slice(), &options).unwrap();
+    assert!(members.iter().all(|m| m[..13] == members[0][..13]));
+
+    let outputs: Vec<Vec<u8>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = members
+            .iter()
+            .map(|member| {
+                let params = &params;
+                let options = &options;
+                scope.spawn(move || {
+                    let mut decomp = Vec::new();
+                    lzma_rs::lzma_decompress_payload::<_, _, 4096, 8>(
+                        &mut &member[13..],
+                        &mut decomp,
+                        params,
+                        options,
+                    )
+                    .unwrap();
+                    decomp
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(outputs, inputs);
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn decompress_batch() {
+    let inputs: Vec<Vec<u8>> = (0..32)
+        .map(|i| format!("member {}: {}", i, "abc".repeat(i * 100)).into_bytes())
+        .collect();
+    let members: Vec<Vec<u8>> = inputs
+        .iter()
+        .map(|input| {
+            let mut compressed = Vec::new();
+            lzma_rs::lzma_compress(&mut input.as_slice(), &mut compressed).unwrap();
+            compressed
+        })
+        .collect();
+    assert!(members.iter().all(|m| m[..13] == members[0][..13]));
+    let payloads: Vec<&[u8]> = members.iter().map(|m| &m[13..]).collect();
+
+    // Outputs come back in the order of the payloads
+    let options = lzma_rs::decompress::Options::default();
+    let outputs =
+        lzma_rs::lzma_decompress_batch::<4096, 8>(&members[0][..13], &payloads, &options).unwrap();
+    assert_eq!(outputs, inputs);
+    assert!(
+        lzma_rs::lzma_decompress_batch::<4096, 8>(&members[0][..13], &[], &options)
+            .unwrap()
+            .is_empty()
+    );
+
+    // A corrupt member fails the batch, and so does a short header
+    let mut corrupt = payloads[5].to_vec();
+    corrupt.truncate(corrupt.len() / 2);
+    let mut payloads = payloads;
+    payloads[5] = &corrupt;
+    assert!(
+        lzma_rs::lzma_decompress_batch::<4096, 8>(&members[0][..13], &payloads, &options).is_err()
+    );
+    assert!(matches!(
+        lzma_rs::lzma_decompress_batch::<4096, 8>(&members[0][..12], &payloads, &options),
+        Err(lzma_rs::error::Error::HeaderTooShort { .. })
+    ));
+}
+
+#[test]
+fn decompress_exact() {
+    use lzma_rs::decompress::raw::LzmaParams;
+    use lzma_rs::error::lzma::LzmaError;
+
+    let options = lzma_rs::decompress::Options::default();
+    for name in ["conformance-sized", "conformance-marker", "foo.txt"] {
+        let compressed = read_all_file(&format!("tests/files/{}.lzma", name)).unwrap();
+        let expected = read_all_file(&format!("tests/files/{}", name)).unwrap();
+        let params = LzmaParams::read_header(&mut &compressed[..13], &options).unwrap();
+        let compressed_len = compressed.len() as u64 - 13;
+
+        // A bare reader, with trailing bytes after the payload
+        let mut framed = compressed[13..].to_vec();
+        framed.extend_from_slice(b"next frame");
+        let mut input = InterruptingReader {
+            inner: framed.as_slice(),
+            interrupt: false,
+        };
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_exact::<_, _, 65536, 8>(
+            &mut input,
+            &mut decomp,
+            compressed_len,
+            &params,
+        )
+        .unwrap();
+        assert_eq!(decomp, expected, "{}", name);
+        assert_eq!(input.inner, b"next frame", "{}", name);
+
+        // A length past the end of the payload is detected, without reading
+        // more than that length
+        let mut input = framed.as_slice();
+        let res = lzma_rs::lzma_decompress_exact::<_, _, 65536, 8>(
+            &mut input,
+            &mut Vec::new(),
+            compressed_len + 5,
+            &params,
+        );
+        assert!(
+            matches!(
+                res,
+                Err(lzma_rs::error::Error::LzmaError(
+                    LzmaError::CompressedLengthMismatch { .. }
+                        | LzmaError::EosFoundButMoreBytesAvailable { .. }
+                ))
+            ),
+            "{}: {:?}",
+            name,
+            res
+        );
+        assert!(input.len() >= b"frame".len(), "{}", name);
+
+        // A length cutting the payload short fails
+        let res = lzma_rs::lzma_decompress_exact::<_, _, 65536, 8>(
+            &mut framed.as_slice(),
+            &mut Vec::new(),
+            compressed_len - 1,
+            &params,
+        );
+        assert!(res.is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn decode_prefix_sample() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params.clone()).unwrap();
+
+    let mut decomp = Vec::new();
+    let mut sample = [0; 100];
+    let count = decoder
+        .decode_prefix_sample_into(&mut decomp, &mut rangecoder, &mut sample)
+        .unwrap();
+    assert_eq!(count, 100);
+    assert_eq!(&sample[..], &expected[..100]);
+    let len = decoder.output.len();
+    assert!((100..100 + 273).contains(&len));
+
+    // Sampling goes on from there, across dictionary wraps, and stops at the
+    // end of the stream
+    let mut sample = vec![0; expected.len()];
+    let count = decoder
+        .decode_prefix_sample_into(&mut decomp, &mut rangecoder, &mut sample)
+        .unwrap();
+    assert_eq!(count, expected.len() - len);
+    assert_eq!(&sample[..count], &expected[len..]);
+
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    // A sample longer than the dictionary
+    let mut input = &compressed[13..];
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let sample = decoder
+        .decode_prefix_sample(&mut rangecoder, 10_000)
+        .unwrap();
+    assert_eq!(sample, &expected[..10_000]);
+}
+
+#[test]
+fn metrics_callback() {
+    use lzma_rs::decompress::raw::{
+        DecodeMetrics, DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let reports = Arc::new(Mutex::new(Vec::<DecodeMetrics>::new()));
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    let sink = reports.clone();
+    decoder.set_metrics_callback(Duration::ZERO, move |m| sink.lock().unwrap().push(m));
+
+    for _ in 0..2 {
+        reports.lock().unwrap().clear();
+        decoder.reset();
+        let mut input = compressed.as_slice();
+        let params =
+            LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+        decoder.set_params(params).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+        assert_eq!(decomp, expected);
+
+        // Counts restart on reset and only grow from there
+        let reports = reports.lock().unwrap();
+        assert!(reports.len() > 1);
+        assert!(reports[0].output_bytes < 1024 * 273);
+        for pair in reports.windows(2) {
+            assert!(pair[0].input_bytes <= pair[1].input_bytes);
+            assert!(pair[0].output_bytes < pair[1].output_bytes);
+            assert!(pair[0].elapsed <= pair[1].elapsed);
+        }
+        let last = reports.last().unwrap();
+        assert!(last.input_bytes <= compressed.len() as u64);
+        assert!(last.output_bytes <= expected.len() as u64);
+    }
+
+    decoder.clear_metrics_callback();
+    reports.lock().unwrap().clear();
+    decoder.reset();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    decoder.set_params(params).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    decoder
+        .process(&mut std::io::sink(), &mut rangecoder)
+        .unwrap();
+    assert!(reports.lock().unwrap().is_empty());
+}
+
+#[cfg(feature = "literal-hook")]
+#[test]
+fn literal_hook() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn decode_with_hook(
+        compressed: &[u8],
+        hook: impl FnMut(u8) -> u8 + Send + 'static,
+    ) -> lzma_rs::error::Result<Vec<u8>> {
+        let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+        decoder.set_literal_hook(hook);
+        decoder.reset();
+        let mut input = compressed;
+        let params = LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default())?;
+        decoder.set_params(params)?;
+        let mut rangecoder = RangeDecoder::new(&mut input)?;
+        let mut decomp = Vec::new();
+        decoder.process(&mut decomp, &mut rangecoder)?;
+        decoder.output.finish(&mut decomp)?;
+        Ok(decomp)
+    }
+
+    // The hook sees every literal, but not bytes copied by matches
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let literals = Arc::new(AtomicUsize::new(0));
+    let counter = literals.clone();
+    let decomp = decode_with_hook(&compressed, move |byte| {
+        counter.fetch_add(1, Ordering::Relaxed);
+        byte
+    })
+    .unwrap();
+    assert_eq!(decomp, expected);
+    let literals = literals.load(Ordering::Relaxed);
+    assert!(literals > 0 && literals < expected.len());
+
+    // The encoder of this crate only emits literals, with lc = 3, so
+    // rewriting the low bits of each byte keeps the stream decodable
+    let mut compressed = Vec::new();
+    lzma_rs::lzma_compress(&mut expected.as_slice(), &mut compressed).unwrap();
+    let decomp = decode_with_hook(&compressed, |byte| byte ^ 1).unwrap();
+    let rewritten: Vec<u8> = expected.iter().map(|byte| byte ^ 1).collect();
+    assert_eq!(decomp, rewritten);
+}
+
+#[test]
+fn input_progress_fraction() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    assert_eq!(decoder.input_progress_fraction(), None);
+    decoder.set_total_input_len(Some(compressed.len() as u64 - 13));
+    assert_eq!(decoder.input_progress_fraction(), Some(0.0));
+
+    let mut decomp = Vec::new();
+    let mut last = 0.0;
+    let mut offset = 0;
+    while !decoder
+        .process_to_offset(&mut decomp, &mut rangecoder, offset)
+        .unwrap()
+    {
+        let fraction = match decoder.input_progress_fraction() {
+            Some(fraction) => fraction,
+            None => panic!("no progress"),
+        };
+        assert!(fraction >= last && fraction < 1.0);
+        last = fraction;
+        offset += 10_000;
+    }
+    assert!(last > 0.5);
+    assert_eq!(decoder.input_progress_fraction(), Some(1.0));
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    decoder.reset();
+    assert_eq!(decoder.input_progress_fraction(), None);
+}
+
+#[test]
+fn process_timed() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use std::time::Duration;
+
+    let compressed = read_all_file("tests/files/range-coder-edge-case.lzma").unwrap();
+    let expected = read_all_file("tests/files/range-coder-edge-case").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+
+    // Each call makes progress, even without any budget
+    let mut decomp = Vec::new();
+    let mut calls = 0;
+    let mut len = 0;
+    while !decoder
+        .process_timed(&mut decomp, &mut rangecoder, Duration::ZERO)
+        .unwrap()
+    {
+        assert!(decoder.output.len() > len);
+        len = decoder.output.len();
+        calls += 1;
+    }
+    assert!(calls > 1);
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+
+    // A large enough budget decodes everything at once
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut decomp = Vec::new();
+    assert!(decoder
+        .process_timed(&mut decomp, &mut rangecoder, Duration::from_secs(3600))
+        .unwrap());
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn range_decoder_state() {
+    use lzma_rs::decompress::raw::{
+        ArrayProbModel, DecoderState, LzSliceBuffer, LzmaParams, RangeDecoder,
+    };
+
+    // Reference values, computed by an independent decoder following the
+    // LZMA specification, after each of the literals "Hel" starting
+    // hello.txt.lzma
+    let expected = [
+        (0xFFFF_FFFF, 0x2419_4998),
+        (0x8000_0000, 0x194D_986F),
+        (0x4000_0000, 0x0D98_6F10),
+        (0x2000_0000, 0x186F_1019),
+    ];
+
+    let compressed = read_all_file("tests/files/hello.txt.lzma").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut dict = vec![0; params.dict_size as usize];
+    let mut decoder = Box::new(DecoderState::<_, 8, _>::with_prob_model(
+        LzSliceBuffer::from_slice(&mut dict),
+        ArrayProbModel::<8>::new(),
+    ));
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+
+    assert_eq!(rangecoder.state(), expected[0]);
+    let mut sink = std::io::sink();
+    for (offset, &state) in expected.iter().enumerate().skip(1) {
+        let ended = decoder
+            .process_to_offset(&mut sink, &mut rangecoder, offset as u64)
+            .unwrap();
+        assert!(!ended);
+        assert_eq!(rangecoder.state(), state, "after {} literals", offset);
+        assert_eq!(rangecoder.bytes_consumed(), 5 + offset as u64);
+    }
+}
+
+#[test]
+fn peek_next_symbol_kind() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder, SymbolKind,
+    };
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut input = compressed.as_slice();
+    let params =
+        LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+
+    let mut decomp = Vec::new();
+    let mut seen = [false; 4];
+    loop {
+        let state = rangecoder.state();
+        let consumed = rangecoder.bytes_consumed();
+        let kind = decoder.peek_next_symbol_kind(&mut rangecoder).unwrap();
+        // Peeking again gives the same answer, and consumes nothing
+        assert_eq!(
+            decoder.peek_next_symbol_kind(&mut rangecoder).unwrap(),
+            kind
+        );
+        assert_eq!(rangecoder.state(), state);
+        assert_eq!(rangecoder.bytes_consumed(), consumed);
+
+        // Decode exactly one symbol
+        let len = decoder.output.len();
+        let ended = decoder
+            .process_to_offset(&mut decomp, &mut rangecoder, len as u64 + 1)
+            .unwrap();
+        let symbol_len = decoder.output.len() - len;
+        match kind {
+            SymbolKind::Literal | SymbolKind::ShortRep => assert_eq!(symbol_len, 1),
+            SymbolKind::Match | SymbolKind::Rep(_) => assert!(symbol_len >= 2 || ended),
+        }
+        seen[match kind {
+            SymbolKind::Literal => 0,
+            SymbolKind::Match => 1,
+            SymbolKind::Rep(_) => 2,
+            SymbolKind::ShortRep => 3,
+        }] = true;
+        if ended {
+            break;
+        }
+    }
+    assert_eq!(seen, [true; 4]);
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn decompress_with_resets() {
+    use lzma_rs::compress::{Options, UnpackedSize};
+
+    // Compress each chunk on its own, and keep the header of the first one
+    fn compress(data: &[u8], unpacked_size: UnpackedSize) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        let options = Options { unpacked_size };
+        lzma_rs::lzma_compress_with_options(&mut &data[..], &mut compressed, &options).unwrap();
+        compressed
+    }
+    let first = b"The first chunk, ".repeat(50);
+    let second = b"then a second one, unrelated to the first. ".repeat(30);
+    let mut expected = first.clone();
+    expected.extend_from_slice(&second);
+
+    // The first chunk ends at the end of its data, the second with a marker
+    let chunk1 = compress(&first, UnpackedSize::SkipWritingToHeader);
+    let chunk2 = compress(&second, UnpackedSize::SkipWritingToHeader);
+    let marked = compress(&second, UnpackedSize::default());
+    for (header_size, last_chunk) in [
+        (expected.len() as u64, &chunk2[5..]),
+        (u64::MAX, &marked[13..]),
+    ] {
+        let mut stream = chunk1[..5].to_vec();
+        stream.extend_from_slice(&header_size.to_le_bytes());
+        stream.extend_from_slice(&chunk1[5..]);
+        stream.extend_from_slice(last_chunk);
+        let reset_offsets = [chunk1.len() as u64 - 5];
+
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress_with_resets::<_, _, 4096, 8>(
+            &mut stream.as_slice(),
+            &mut decomp,
+            &Default::default(),
+            &reset_offsets,
+        )
+        .unwrap();
+        assert_eq!(decomp, expected);
+
+        // A reset in the middle of a chunk is detected
+        for reset_offset in [reset_offsets[0] - 1, reset_offsets[0] + 1, 0] {
+            let res = lzma_rs::lzma_decompress_with_resets::<_, _, 4096, 8>(
+                &mut stream.as_slice(),
+                &mut Vec::new(),
+                &Default::default(),
+                &[reset_offset],
+            );
+            assert!(res.is_err(), "reset at {}", reset_offset);
+        }
+    }
+}
+
+#[test]
+fn provided_and_effective_dict_size() {
+    use lzma_rs::decompress::raw::LzmaParams;
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    for (provided, effective) in [
+        (0, 0x1000),
+        (0x10, 0x1000),
+        (0x1000, 0x1000),
+        (0x1001, 0x1001),
+    ] {
+        let mut input = compressed.clone();
+        input[1..5].copy_from_slice(&u32::to_le_bytes(provided));
+        let params = LzmaParams::read_header(
+            &mut input.as_slice(),
+            &lzma_rs::decompress::Options::default(),
+        )
+        .unwrap();
+        assert_eq!(params.provided_dict_size(), provided);
+        assert_eq!(params.effective_dict_size(), effective);
+        assert_eq!(params.dict_size, effective);
+
+        // The effective size is used for decoding
+        let mut decomp = Vec::new();
+        lzma_rs::lzma_decompress::<_, _, 0x2000, 8>(&mut input.as_slice(), &mut decomp).unwrap();
+        assert_eq!(decomp, expected);
+    }
+}
+
+#[test]
+fn dict_wrap_callback() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+    use std::sync::{Arc, Mutex};
+
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    let wraps = Arc::new(Mutex::new(Vec::new()));
+    let mut decoder = DecoderState::<LzCircularBuffer<4096>, 8>::new();
+    let sink = wraps.clone();
+    decoder
+        .output
+        .set_wrap_callback(move |len| sink.lock().unwrap().push(len));
+
+    for _ in 0..2 {
+        wraps.lock().unwrap().clear();
+        decoder.reset();
+        let mut input = compressed.as_slice();
+        let params =
+            LzmaParams::read_header(&mut input, &lzma_rs::decompress::Options::default()).unwrap();
+        assert_eq!(params.dict_size, 4096);
+        decoder.set_params(params).unwrap();
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        decoder.process(&mut decomp, &mut rangecoder).unwrap();
+        decoder.output.finish(&mut decomp).unwrap();
+        assert_eq!(decomp, expected);
+
+        let expected_wraps: Vec<u64> = (1..=expected.len() as u64 / 4096)
+            .map(|n| n * 4096)
+            .collect();
+        assert_eq!(*wraps.lock().unwrap(), expected_wraps);
+    }
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn lzma_reader() {
+    use lzma_rs::decompress::LzmaReader;
+    use lzma_rs::error::lzma::LzmaError;
+
+    // A member with an end marker and one with its size in the header, read
+    // in chunks of various sizes. The long matches of conformance-marker are
+    // split across reads.
+    for (name, chunk) in [
+        ("dict-wraparound", 1),
+        ("dict-wraparound", 1000),
+        ("dict-wraparound", 100_000),
+        ("conformance-marker", 100),
+        ("conformance-marker", 3000),
+        ("conformance-sized", 7),
+        ("conformance-sized", 100_000),
+    ] {
+        let compressed = read_all_file(&format!("tests/files/{}.lzma", name)).unwrap();
+        let expected = read_all_file(&format!("tests/files/{}", name)).unwrap();
+        let mut reader = Box::new(
+            LzmaReader::<_, 65536, 8>::new(compressed.as_slice(), &Default::default()).unwrap(),
+        );
+        let mut decomp = Vec::new();
+        let mut buf = vec![0; chunk];
+        loop {
+            let count = reader.read(&mut buf).unwrap();
+            if count == 0 {
+                break;
+            }
+            decomp.extend_from_slice(&buf[..count]);
+        }
+        assert_eq!(decomp, expected, "{}, chunk {}", name, chunk);
+        assert!(reader.get_ref().is_empty());
+    }
+
+    // Stopping early decodes little more than what was read, and leaves the
+    // rest of the input unread
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    let mut reader =
+        LzmaReader::<_, 4096, 8>::new(compressed.as_slice(), &Default::default()).unwrap();
+    let mut prefix = [0; 100];
+    reader.read_exact(&mut prefix).unwrap();
+    assert_eq!(prefix[..], expected[..100]);
+    assert!(reader.decoded_len() < 100 + 273);
+    assert!(reader.get_ref().len() > compressed.len() / 2);
+    drop(reader);
+
+    // Decoding errors are kept aside, input errors are returned as is
+    let options = lzma_rs::decompress::Options {
+        max_reference_distance: Some(16),
+        ..Default::default()
+    };
+    let mut reader = LzmaReader::<_, 4096, 8>::new(compressed.as_slice(), &options).unwrap();
+    let e = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+    assert!(matches!(
+        reader.take_error(),
+        Some(lzma_rs::error::Error::LzmaError(
+            LzmaError::InvalidBackReference { .. }
+        ))
+    ));
+    assert!(reader.read(&mut [0; 16]).is_err());
+
+    let truncated = &compressed[..compressed.len() / 2];
+    let mut reader = LzmaReader::<_, 4096, 8>::new(truncated, &Default::default()).unwrap();
+    let e = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(matches!(reader.take_error(), None));
+}
+
+#[test]
+fn trace_sink() {
+    use lzma_rs::decompress::raw::RangeDecoder;
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, TraceSink,
+    };
+
+    // Rebuilds the output from the symbols
+    #[derive(Default)]
+    struct Rebuild {
+        params: usize,
+        matches: usize,
+        out: Vec<u8>,
+    }
+
+    impl TraceSink for Rebuild {
+        fn on_params(&mut self, _params: &LzmaParams) {
+            self.params += 1;
+        }
+
+        fn on_literal(&mut self, byte: u8) {
+            self.out.push(byte);
+        }
+
+        fn on_match(&mut self, len: usize, dist: usize) {
+            self.matches += 1;
+            for _ in 0..len {
+                self.out.push(self.out[self.out.len() - dist]);
+            }
+        }
+    }
+
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+    let mut sink = Rebuild::default();
+    let mut input = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let mut decoder =
+        Box::new(DecoderState::<LzCircularBuffer<4096>, 8, _, _>::with_trace_sink(&mut sink));
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut decomp = Vec::new();
+    decoder.process(&mut decomp, &mut rangecoder).unwrap();
+    decoder.output.finish(&mut decomp).unwrap();
+    assert_eq!(decoder.trace_sink().params, 1);
+    drop(decoder);
+
+    assert_eq!(decomp, expected);
+    assert_eq!(sink.out, expected);
+    assert!(sink.matches > 0);
+}
+
+#[test]
+fn process_with() {
+    use lzma_rs::decompress::raw::{
+        DecoderState, LzBuffer, LzCircularBuffer, LzmaParams, RangeDecoder,
+    };
+
+    fn decode(compressed: &[u8], skip: u64, with: bool) -> Vec<u8> {
+        let mut input = compressed;
+        let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+        let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+        decoder.reset();
+        decoder.set_params(params).unwrap();
+        decoder.skip_output(skip);
+        let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+        let mut decomp = Vec::new();
+        if with {
+            decoder
+                .process_with(&mut rangecoder, |bytes| decomp.extend_from_slice(bytes))
+                .unwrap();
+        } else {
+            decoder.process(&mut decomp, &mut rangecoder).unwrap();
+            decoder.output.finish(&mut decomp).unwrap();
+        }
+        decomp
+    }
+
+    for filename in [
+        "tests/files/foo.txt.lzma",
+        "tests/files/dict-wraparound.lzma",
+    ] {
+        let compressed = read_all_file(filename).unwrap();
+        for skip in [0, 100, 5000] {
+            assert_eq!(
+                decode(&compressed, skip, true),
+                decode(&compressed, skip, false),
+                "{} skipping {}",
+                filename,
+                skip
+            );
+        }
+    }
+
+    // Bytes are handed over a dictionary at a time, skipped ones left out
+    let compressed = read_all_file("tests/files/dict-wraparound.lzma").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    let mut input = compressed.as_slice();
+    let params = LzmaParams::read_header(&mut input, &Default::default()).unwrap();
+    let dict_size = params.dict_size as usize;
+    let mut decoder = Box::new(DecoderState::<LzCircularBuffer<4096>, 8>::new());
+    decoder.reset();
+    decoder.set_params(params).unwrap();
+    decoder.skip_output(10);
+    let mut rangecoder = RangeDecoder::new(&mut input).unwrap();
+    let mut calls = 0;
+    let mut decomp = Vec::new();
+    decoder
+        .process_with(&mut rangecoder, |bytes| {
+            assert!(!bytes.is_empty() && bytes.len() <= dict_size);
+            calls += 1;
+            decomp.extend_from_slice(bytes);
+        })
+        .unwrap();
+    assert_eq!(decomp, &expected[10..]);
+    assert!(calls <= expected.len() / dict_size + 2);
+}
@@ -0,0 +1,213 @@
+#![cfg(feature = "std")]
+
+use lzma_rs::decompress::raw::lzma2_dict_size_from_byte;
+use lzma_rs::error::lzma2::Lzma2Error;
+use lzma_rs::error::Error;
+use std::io::Read;
+
+/// Utility function to read a file into memory
+fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::fs::File::open(filename).and_then(|mut file| file.read_to_end(&mut data))?;
+    Ok(data)
+}
+
+/// Extract the LZMA2 data and dictionary size of a single-block XZ file
+/// whose block header has no optional sizes and a single LZMA2 filter.
+fn lzma2_body(xz: &[u8]) -> (&[u8], u32) {
+    // Stream header, then block header size, flags, filter ID and
+    // properties size
+    assert_eq!(xz[12..16], [0x02, 0x00, 0x21, 0x01]);
+    let dict_size = lzma2_dict_size_from_byte(xz[16]).unwrap();
+    (&xz[24..], dict_size)
+}
+
+fn decompress(input: &[u8], dict_size: u32) -> lzma_rs::error::Result<Vec<u8>> {
+    let mut decomp = Vec::new();
+    lzma_rs::lzma2_decompress::<_, _, 65536, 16>(&mut &input[..], &mut decomp, dict_size)?;
+    Ok(decomp)
+}
+
+#[test]
+fn decompress_xz_block_bodies() {
+    // Chunks with new properties, state resets, uncompressed chunks and
+    // dictionary resets
+    for i in 1..=4 {
+        let filename = format!("tests/files/good-1-lzma2-{}", i);
+        let xz = read_all_file(&format!("{}.xz", filename)).unwrap();
+        let expected = read_all_file(&filename).unwrap();
+        let (body, dict_size) = lzma2_body(&xz);
+        assert_eq!(dict_size, 65536);
+
+        let mut input = body;
+        let mut decomp = Vec::new();
+        lzma_rs::lzma2_decompress::<_, _, 65536, 16>(&mut input, &mut decomp, dict_size).unwrap();
+        assert_eq!(decomp, expected, "{}", filename);
+        // The block padding and check follow the end chunk
+        assert!(!input.is_empty() && input.len() < body.len());
+    }
+}
+
+#[test]
+fn decompress_uncompressed_chunks() {
+    // A chunk resetting the dictionary, a chunk keeping it, then the end
+    let input = b"\x01\x00\x02abc\x02\x00\x01de\x00";
+    assert_eq!(decompress(input, 4096).unwrap(), b"abcde");
+    assert_eq!(decompress(b"\x00", 4096).unwrap(), b"");
+}
+
+#[test]
+fn decompress_invalid_chunks() {
+    let xz = read_all_file("tests/files/good-1-lzma2-1.xz").unwrap();
+    let (body, dict_size) = lzma2_body(&xz);
+
+    // The first chunk resets the dictionary and sets the properties
+    assert_eq!(body[0], 0xE0);
+    let mut stream = body.to_vec();
+    for (control, expected) in [
+        (0x03, Lzma2Error::InvalidControlByte { control: 0x03 }),
+        (0x7F, Lzma2Error::InvalidControlByte { control: 0x7F }),
+        (0x02, Lzma2Error::MissingDictionaryReset { control: 0x02 }),
+        (0xC0, Lzma2Error::MissingDictionaryReset { control: 0xC0 }),
+    ] {
+        stream[0] = control;
+        match decompress(&stream, dict_size) {
+            Err(Error::Lzma2Error(e)) => assert_eq!(e, expected),
+            res => panic!("control {:02x}: {:?}", control, res),
+        }
+    }
+
+    // A dictionary reset requires new properties in the next LZMA chunk
+    let mut stream = b"\x01\x00\x00a".to_vec();
+    stream.extend_from_slice(body);
+    stream[4] = 0xA0;
+    match decompress(&stream, dict_size) {
+        Err(Error::Lzma2Error(e)) => {
+            assert_eq!(e, Lzma2Error::MissingProperties { control: 0xA0 })
+        }
+        res => panic!("{:?}", res),
+    }
+
+    // Properties with lc + lp > 4
+    let mut stream = body.to_vec();
+    stream[5] = 3 + 9 * 2;
+    match decompress(&stream, dict_size) {
+        Err(Error::Lzma2Error(e)) => {
+            assert_eq!(e, Lzma2Error::InvalidLiteralProperties { lc: 3, lp: 2 })
+        }
+        res => panic!("{:?}", res),
+    }
+
+    // A compressed size past the end of the LZMA data of the chunk
+    let mut stream = body.to_vec();
+    stream[4] += 1;
+    assert!(decompress(&stream, dict_size).is_err());
+
+    // Truncated streams
+    for len in [0, 3, 6, 100] {
+        assert!(decompress(&body[..len], dict_size).is_err(), "{}", len);
+    }
+    assert!(decompress(b"\x01\x00\x02ab", 4096).is_err());
+}
+
+#[test]
+fn dictionary_too_small() {
+    let xz = read_all_file("tests/files/good-1-lzma2-1.xz").unwrap();
+    let (body, _) = lzma2_body(&xz);
+    let res = lzma_rs::lzma2_decompress::<_, _, 4096, 16>(&mut &body[..], &mut Vec::new(), 65536);
+    assert!(matches!(
+        res,
+        Err(Error::DictionaryBufferTooSmall {
+            needed: 65536,
+            available: 4096
+        })
+    ));
+}
+
+#[test]
+fn segments() {
+    use lzma_rs::decompress::raw::{LzCircularBuffer, Lzma2Decoder, Lzma2Segments};
+
+    for i in 1..=4 {
+        let filename = format!("tests/files/good-1-lzma2-{}", i);
+        let xz = read_all_file(&format!("{}.xz", filename)).unwrap();
+        let expected = read_all_file(&filename).unwrap();
+        let (body, dict_size) = lzma2_body(&xz);
+
+        // Each segment decodes on its own to its part of the output
+        let mut segments = Lzma2Segments::new(body, dict_size);
+        let mut decoder = Box::new(Lzma2Decoder::<LzCircularBuffer<65536>, 16>::new());
+        let mut offset = 0;
+        let mut count = 0;
+        for segment in &mut segments {
+            let segment = segment.unwrap();
+            assert_eq!(segment.uncompressed_offset(), offset);
+            let mut decomp = Vec::new();
+            segment.decompress(&mut decoder, &mut decomp).unwrap();
+            assert_eq!(decomp.len() as u64, segment.uncompressed_len());
+            assert_eq!(
+                decomp,
+                &expected[offset as usize..][..decomp.len()],
+                "{}",
+                filename
+            );
+            offset += segment.uncompressed_len();
+            count += 1;
+        }
+        assert_eq!(offset, expected.len() as u64);
+        // Only the last file resets the dictionary within the stream
+        assert_eq!(count, if i == 4 { 2 } else { 1 }, "{}", filename);
+
+        // The stream ends with its end chunk
+        let mut input = body;
+        lzma_rs::lzma2_decompress::<_, _, 65536, 16>(&mut input, &mut Vec::new(), dict_size)
+            .unwrap();
+        assert_eq!(segments.position(), body.len() - input.len());
+    }
+}
+
+#[test]
+fn segments_of_uncompressed_chunks() {
+    use lzma_rs::decompress::raw::{LzCircularBuffer, Lzma2Decoder, Lzma2Segments};
+
+    // Two segments, the first one of two chunks
+    let input = b"\x01\x00\x02abc\x02\x00\x01de\x01\x00\x00f\x00garbage";
+    let segments = Lzma2Segments::new(input, 4096)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].data(), b"\x01\x00\x02abc\x02\x00\x01de");
+    assert_eq!(
+        (
+            segments[1].uncompressed_offset(),
+            segments[1].uncompressed_len()
+        ),
+        (5, 1)
+    );
+    let mut decoder = Lzma2Decoder::<LzCircularBuffer<4096>, 16>::new();
+    let mut decomp = Vec::new();
+    segments[1].decompress(&mut decoder, &mut decomp).unwrap();
+    assert_eq!(decomp, b"f");
+
+    // Iteration stops after an error
+    for (input, expected) in [
+        (
+            &b"\x02\x00\x00a\x00"[..],
+            Some(Lzma2Error::MissingDictionaryReset { control: 0x02 }),
+        ),
+        (
+            &b"\x01\x00\x00a\x03"[..],
+            Some(Lzma2Error::InvalidControlByte { control: 0x03 }),
+        ),
+        (&b"\x01\x00\x01a"[..], None),
+    ] {
+        let mut segments = Lzma2Segments::new(input, 4096);
+        let err = segments.find_map(Result::err).unwrap();
+        match (err, expected) {
+            (Error::Lzma2Error(e), Some(expected)) => assert_eq!(e, expected),
+            (Error::IoError(_), None) => (),
+            (err, _) => panic!("{:?}", err),
+        }
+        assert!(segments.next().is_none());
+    }
+}
@@ -0,0 +1,94 @@
+#![cfg(feature = "tokio")]
+
+use lzma_rs::decompress::AsyncLzmaReader;
+use std::io::Read;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+/// Utility function to read a file into memory
+fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::fs::File::open(filename).and_then(|mut file| file.read_to_end(&mut data))?;
+    Ok(data)
+}
+
+/// Source returning `Pending` before every byte.
+struct TrickleReader<'a> {
+    data: &'a [u8],
+    ready: bool,
+}
+
+impl AsyncRead for TrickleReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if !self.ready {
+            self.ready = true;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        self.ready = false;
+        if let Some((&byte, rest)) = self.data.split_first() {
+            buf.put_slice(&[byte]);
+            self.data = rest;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn async_reader() {
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let mut reader = AsyncLzmaReader::<_, 4096, 8>::new(compressed.as_slice(), &Default::default());
+    let mut decomp = Vec::new();
+    reader.read_to_end(&mut decomp).await.unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[tokio::test]
+async fn async_reader_pending_source() {
+    let compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let expected = read_all_file("tests/files/foo.txt").unwrap();
+
+    let source = TrickleReader {
+        data: &compressed,
+        ready: false,
+    };
+    let mut reader = Box::new(AsyncLzmaReader::<_, 4096, 8>::new(
+        source,
+        &Default::default(),
+    ));
+    let mut decomp = Vec::new();
+    reader.read_to_end(&mut decomp).await.unwrap();
+    assert_eq!(decomp, expected);
+    assert!(reader.get_ref().data.is_empty());
+}
+
+#[tokio::test]
+async fn async_reader_error() {
+    let mut compressed = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    compressed.truncate(compressed.len() / 2);
+
+    let mut reader = AsyncLzmaReader::<_, 4096, 8>::new(compressed.as_slice(), &Default::default());
+    let mut decomp = Vec::new();
+    let err = reader.read_to_end(&mut decomp).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(reader.read(&mut [0; 16]).await.is_err());
+
+    // Corrupt data is reported aside
+    compressed[0] = 0xFF;
+    let mut reader = AsyncLzmaReader::<_, 4096, 8>::new(compressed.as_slice(), &Default::default());
+    let err = reader.read_to_end(&mut Vec::new()).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(matches!(
+        reader.take_error(),
+        Some(lzma_rs::error::Error::LzmaError(
+            lzma_rs::error::lzma::LzmaError::InvalidHeader { .. }
+        ))
+    ));
+}
@@ -0,0 +1,234 @@
+#![cfg(feature = "std")]
+
+use lzma_rs::check::{CheckValue, Crc32};
+use lzma_rs::decompress::raw::{ArrayProbModel, DecoderState, LzSliceBuffer};
+use lzma_rs::decompress::xz::XzDecoder;
+use lzma_rs::error::xz::XzError;
+use lzma_rs::error::Error;
+use std::io::Read;
+
+/// Utility function to read a file into memory
+fn read_all_file(filename: &str) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    std::fs::File::open(filename).and_then(|mut file| file.read_to_end(&mut data))?;
+    Ok(data)
+}
+
+/// Decompress with a dictionary of `dict_mem` bytes on the heap, as files
+/// produced with the default presets need 8 MiB.
+fn decompress(input: &[u8], dict_mem: usize) -> lzma_rs::error::Result<Vec<u8>> {
+    let mut dict = vec![0; dict_mem];
+    let lzma = DecoderState::with_prob_model(
+        LzSliceBuffer::from_slice(&mut dict),
+        ArrayProbModel::<16>::new(),
+    );
+    let mut decoder = XzDecoder::with_decoder(lzma);
+    let mut decomp = Vec::new();
+    decoder.decompress(&mut &input[..], &mut decomp)?;
+    Ok(decomp)
+}
+
+fn assert_xz_error(input: &[u8], expected: XzError) {
+    match decompress(input, 65536) {
+        Err(Error::XzError(e)) => assert_eq!(e, expected),
+        res => panic!("expected {:?}, got {:?}", expected, res),
+    }
+}
+
+#[test]
+fn decompress_files() {
+    // Checks: none (bcj-x86.bin), CRC32, CRC64 and SHA-256
+    // (dict-wraparound). Blocks with and without sizes in their header,
+    // with the x86 BCJ or the delta filter (delta-pcm.bin).
+    for (filename, dict_mem) in [
+        ("tests/files/empty.txt", 4096),
+        ("tests/files/hello.txt", 8 << 20),
+        ("tests/files/foo.txt", 8 << 20),
+        ("tests/files/block-check-crc32.txt", 1 << 17),
+        ("tests/files/good-1-lzma2-1", 65536),
+        ("tests/files/good-1-lzma2-2", 65536),
+        ("tests/files/good-1-lzma2-3", 65536),
+        ("tests/files/good-1-lzma2-4", 65536),
+        ("tests/files/dict-wraparound", 4096),
+        ("tests/files/bcj-x86.bin", 4096),
+        ("tests/files/delta-pcm.bin", 4096),
+    ] {
+        let compressed = read_all_file(&format!("{}.xz", filename)).unwrap();
+        let expected = read_all_file(filename).unwrap();
+        let decomp = decompress(&compressed, dict_mem).unwrap();
+        assert!(decomp == expected, "{}", filename);
+    }
+}
+
+#[test]
+fn decompress_filter_chain() {
+    let expected = read_all_file("tests/files/bcj-x86.bin").unwrap();
+    let compressed = read_all_file("tests/files/bcj-x86-delta.xz").unwrap();
+    assert_eq!(decompress(&compressed, 4096).unwrap(), expected);
+}
+
+#[test]
+fn xz_decompress_inline_dict() {
+    let compressed = read_all_file("tests/files/dict-wraparound.xz").unwrap();
+    let expected = read_all_file("tests/files/dict-wraparound").unwrap();
+    let mut decomp = Vec::new();
+    lzma_rs::xz_decompress::<_, _, 4096, 16>(&mut compressed.as_slice(), &mut decomp).unwrap();
+    assert_eq!(decomp, expected);
+}
+
+#[test]
+fn decompress_concatenated_streams() {
+    let mut compressed = read_all_file("tests/files/good-1-lzma2-1.xz").unwrap();
+    let mut expected = read_all_file("tests/files/good-1-lzma2-1").unwrap();
+    compressed.extend_from_slice(&[0; 8]);
+    compressed.extend_from_slice(&read_all_file("tests/files/empty.txt.xz").unwrap());
+    compressed.extend_from_slice(&read_all_file("tests/files/good-1-lzma2-4.xz").unwrap());
+    expected.extend_from_slice(&read_all_file("tests/files/good-1-lzma2-4").unwrap());
+    compressed.extend_from_slice(&[0; 4]);
+    assert_eq!(decompress(&compressed, 65536).unwrap(), expected);
+
+    // Stream padding must be a multiple of 4 bytes
+    compressed.push(0);
+    assert_xz_error(&compressed, XzError::InvalidPadding);
+    compressed.truncate(compressed.len() - 3);
+    assert_xz_error(&compressed, XzError::InvalidPadding);
+}
+
+#[test]
+fn decompress_invalid_framing() {
+    let compressed = read_all_file("tests/files/good-1-lzma2-1.xz").unwrap();
+    let corrupt = |offset: usize, value: u8| {
+        let mut data = compressed.clone();
+        data[offset] = value;
+        data
+    };
+    let len = compressed.len();
+
+    assert_xz_error(&corrupt(1, b'8'), XzError::InvalidMagic);
+    assert_xz_error(&corrupt(7, 0x04), XzError::HeaderCrcMismatch);
+    assert_xz_error(&corrupt(16, 0x09), XzError::BlockHeaderCrcMismatch);
+    assert_xz_error(&corrupt(len - 1, b'A'), XzError::InvalidFooterMagic);
+    assert_xz_error(&corrupt(len - 4, 0x01), XzError::FooterCrcMismatch);
+    // Index CRC32, then the last byte of the block check
+    assert_xz_error(&corrupt(len - 13, 0x00), XzError::IndexCrcMismatch);
+    let check = |data: &[u8]| {
+        let mut crc = [0; 4];
+        crc.copy_from_slice(&data[len - 28..len - 24]);
+        CheckValue::Crc32(u32::from_le_bytes(crc))
+    };
+    let found = CheckValue::Crc32(Crc32::checksum(
+        &read_all_file("tests/files/good-1-lzma2-1").unwrap(),
+    ));
+    assert_eq!(check(&compressed), found);
+    let corrupted = corrupt(len - 25, 0x00);
+    assert_xz_error(
+        &corrupted,
+        XzError::CheckMismatch {
+            expected: check(&corrupted),
+            found,
+        },
+    );
+
+    // An LZMA2 error inside a block
+    match decompress(&corrupt(24, 0x03), 65536) {
+        Err(Error::Lzma2Error(_)) => {}
+        res => panic!("{:?}", res),
+    }
+
+    // Truncated data
+    for len in [0, 6, 12, 30, len / 2, len - 1] {
+        assert!(decompress(&compressed[..len], 65536).is_err(), "{}", len);
+    }
+}
+
+#[test]
+fn decompress_invalid_index() {
+    // Record the uncompressed size of the block one byte larger, and patch
+    // the CRC32 of the index to isolate the mismatch
+    let mut compressed = read_all_file("tests/files/good-1-lzma2-1.xz").unwrap();
+    let index = compressed.len() - 12 - 12;
+    assert_eq!(compressed[index..index + 2], [0x00, 0x01]);
+    compressed[index + 4] += 1;
+    let crc = Crc32::checksum(&compressed[index..index + 8]);
+    compressed[index + 8..index + 12].copy_from_slice(&crc.to_le_bytes());
+    assert_xz_error(&compressed, XzError::IndexMismatch);
+}
+
+#[test]
+fn decompress_mismatched_flags() {
+    // A footer claiming CRC32 in a CRC64 stream, with a valid CRC32
+    let mut compressed = read_all_file("tests/files/empty.txt.xz").unwrap();
+    let footer = compressed.len() - 12;
+    compressed[footer + 9] = 0x01;
+    let crc = Crc32::checksum(&compressed[footer + 4..footer + 10]);
+    compressed[footer..footer + 4].copy_from_slice(&crc.to_le_bytes());
+    assert_xz_error(
+        &compressed,
+        XzError::StreamFlagsMismatch {
+            header: 0x0004,
+            footer: 0x0001,
+        },
+    );
+}
+
+#[test]
+fn dictionary_too_small() {
+    let compressed = read_all_file("tests/files/hello.txt.xz").unwrap();
+    assert!(matches!(
+        decompress(&compressed, 65536),
+        Err(Error::DictionaryBufferTooSmall {
+            needed: 0x80_0000,
+            available: 65536
+        })
+    ));
+}
+
+#[test]
+fn decompress_auto_formats() {
+    use lzma_rs::decompress::{decompress_auto, detect_format, Format};
+    use lzma_rs::option::GuaranteedOption;
+
+    let xz = read_all_file("tests/files/good-1-lzma2-1.xz").unwrap();
+    // The LZMA2 data of the first block, after the stream header and the
+    // block header
+    let lzma2 = &xz[12 + (xz[12] as usize + 1) * 4..];
+    let lzma = read_all_file("tests/files/foo.txt.lzma").unwrap();
+    let lzip = read_all_file("tests/files/foo.txt.lz").unwrap();
+    for (compressed, filename, format) in [
+        (&xz[..], "tests/files/good-1-lzma2-1", Format::Xz),
+        (lzma2, "tests/files/good-1-lzma2-1", Format::Lzma2),
+        (&lzma, "tests/files/foo.txt", Format::Lzma),
+        (&lzip, "tests/files/foo.txt", Format::Lzip),
+    ] {
+        assert_eq!(detect_format(compressed), GuaranteedOption::Some(format));
+        let mut decomp = Vec::new();
+        assert_eq!(
+            decompress_auto::<_, 65536, 16>(compressed, &mut decomp).unwrap(),
+            format
+        );
+        assert_eq!(decomp, read_all_file(filename).unwrap(), "{:?}", format);
+    }
+
+    // Every `.lzma`, `.xz` and `.lz` test file is recognized
+    for entry in std::fs::read_dir("tests/files").unwrap() {
+        let path = entry.unwrap().path();
+        let expected = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("lzma") => Format::Lzma,
+            Some("xz") => Format::Xz,
+            Some("lz") => Format::Lzip,
+            _ => continue,
+        };
+        let compressed = read_all_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            detect_format(&compressed),
+            GuaranteedOption::Some(expected),
+            "{:?}",
+            path
+        );
+    }
+
+    assert!(matches!(
+        decompress_auto::<_, 4096, 16>(b"plain text", &mut Vec::new()),
+        Err(Error::UnknownFormat)
+    ));
+}